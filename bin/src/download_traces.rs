@@ -0,0 +1,373 @@
+//! Download the traces of a block range from l2geth, e.g. to backfill months of
+//! blocks for `utilization` or the regression corpus.
+//!
+//! Requests are sent `--concurrency` at a time and at most `--rps` per second.
+//! 429 and 5xx responses, timeouts and connection errors are retried with an
+//! exponential backoff, honoring `Retry-After`. Traces are written to
+//! `<dir>/<block>.json.zst`, zstd compressed, and the sha256 of every file
+//! written is appended to `<dir>/manifest.jsonl`. A rerun skips the blocks
+//! whose file matches its checksum in the manifest and downloads the others.
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, StatusCode};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// RPC endpoint of l2geth serving `scroll_getBlockTraceByNumberOrHash`.
+    #[clap(long = "l2geth")]
+    l2geth_api_url: String,
+    /// First block to download.
+    #[clap(long = "first")]
+    first: u64,
+    /// Last block to download, inclusive.
+    #[clap(long = "last")]
+    last: u64,
+    /// Dir of the traces and the manifest.
+    #[clap(long = "dir")]
+    dir: PathBuf,
+    /// Requests in flight.
+    #[clap(long = "concurrency", default_value_t = 8)]
+    concurrency: usize,
+    /// Requests per second, unlimited if 0.
+    #[clap(long = "rps", default_value_t = 0.0)]
+    rps: f64,
+    /// Retries of a request before the block is given up.
+    #[clap(long = "max-retries", default_value_t = 8)]
+    max_retries: u32,
+    /// Delay before the first retry, doubled on every retry up to a minute.
+    #[clap(long = "backoff-ms", default_value_t = 500)]
+    backoff_ms: u64,
+    /// Timeout of a request.
+    #[clap(long = "timeout-secs", default_value_t = 120)]
+    timeout_secs: u64,
+    /// zstd level of the traces written.
+    #[clap(long = "level", default_value_t = 3)]
+    level: i32,
+    /// Write plain `<block>.json` traces.
+    #[clap(long = "no-compress")]
+    no_compress: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    let args = Args::parse();
+    if args.first > args.last {
+        bail!(
+            "first block {} is after last block {}",
+            args.first,
+            args.last
+        );
+    }
+    fs::create_dir_all(&args.dir)?;
+
+    let client = Arc::new(RpcClient::new(&args)?);
+    let manifest = Arc::new(Manifest::open(&args.dir.join("manifest.jsonl"))?);
+    let level = (!args.no_compress).then_some(args.level);
+
+    let mut report = Report::default();
+    let mut tasks = JoinSet::new();
+    for number in args.first..=args.last {
+        if tasks.len() >= args.concurrency.max(1) {
+            report.record(tasks.join_next().await.unwrap()?);
+        }
+        let (client, manifest, dir) = (client.clone(), manifest.clone(), args.dir.clone());
+        tasks.spawn(async move {
+            let outcome = download_block(&client, &manifest, &dir, number, level).await;
+            (number, outcome)
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        report.record(result?);
+    }
+
+    log::info!(
+        "blocks {}..={}: {} downloaded, {} verified, {} failed",
+        args.first,
+        args.last,
+        report.downloaded,
+        report.verified,
+        report.failed.len()
+    );
+    if !report.failed.is_empty() {
+        report.failed.sort_unstable();
+        bail!(
+            "failed to download blocks {:?}, rerun to retry them",
+            report.failed
+        );
+    }
+    Ok(())
+}
+
+enum Outcome {
+    Downloaded,
+    /// Already in the dir, matching its checksum.
+    Verified,
+}
+
+#[derive(Default)]
+struct Report {
+    downloaded: usize,
+    verified: usize,
+    failed: Vec<u64>,
+}
+
+impl Report {
+    fn record(&mut self, (number, outcome): (u64, Result<Outcome>)) {
+        match outcome {
+            Ok(Outcome::Downloaded) => self.downloaded += 1,
+            Ok(Outcome::Verified) => self.verified += 1,
+            Err(e) => {
+                log::error!("block {number}: {e:#}");
+                self.failed.push(number);
+            }
+        }
+        let done = self.downloaded + self.verified + self.failed.len();
+        if done % 1000 == 0 {
+            log::info!("{done} blocks done");
+        }
+    }
+}
+
+async fn download_block(
+    client: &RpcClient,
+    manifest: &Manifest,
+    dir: &Path,
+    number: u64,
+    level: Option<i32>,
+) -> Result<Outcome> {
+    if let Some(entry) = manifest.get(number) {
+        let path = dir.join(&entry.file);
+        let matches = tokio::task::spawn_blocking(move || {
+            fs::read(path).map_or(false, |data| sha256_hex(&data) == entry.sha256)
+        })
+        .await?;
+        if matches {
+            return Ok(Outcome::Verified);
+        }
+        log::warn!("block {number}: checksum mismatch, downloading it again");
+    }
+
+    let trace = client
+        .call(
+            "scroll_getBlockTraceByNumberOrHash",
+            json!([format!("{number:#x}")]),
+        )
+        .await?;
+    if trace.is_null() {
+        bail!("no such block");
+    }
+    let file = match level {
+        Some(_) => format!("{number}.json.zst"),
+        None => format!("{number}.json"),
+    };
+    let path = dir.join(&file);
+    let entry = tokio::task::spawn_blocking(move || -> Result<ManifestEntry> {
+        let json = serde_json::to_vec(&trace)?;
+        let data = match level {
+            Some(level) => zstd::encode_all(json.as_slice(), level)?,
+            None => json,
+        };
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, &data)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(ManifestEntry {
+            block: number,
+            file,
+            sha256: sha256_hex(&data),
+            bytes: data.len() as u64,
+        })
+    })
+    .await??;
+    manifest.append(entry)?;
+    Ok(Outcome::Downloaded)
+}
+
+/// JSON-RPC client of l2geth, retrying transient failures.
+struct RpcClient {
+    url: String,
+    http: reqwest::Client,
+    max_retries: u32,
+    backoff: Duration,
+    rate_limit: Option<RateLimiter>,
+}
+
+impl RpcClient {
+    fn new(args: &Args) -> Result<Self> {
+        Ok(Self {
+            url: args.l2geth_api_url.clone(),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(args.timeout_secs))
+                .build()?,
+            max_retries: args.max_retries,
+            backoff: Duration::from_millis(args.backoff_ms),
+            rate_limit: (args.rps > 0.0).then(|| RateLimiter::new(args.rps)),
+        })
+    }
+
+    /// The `result` of the call. JSON-RPC errors aren't retried.
+    async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let mut attempt = 0;
+        loop {
+            if let Some(rate_limit) = &self.rate_limit {
+                rate_limit.acquire().await;
+            }
+            let (reason, retry_after) = match self.http.post(&self.url).json(&body).send().await {
+                Ok(resp) if resp.status().is_success() => match resp.bytes().await {
+                    Ok(bytes) => {
+                        let mut response: Value = serde_json::from_slice(&bytes)
+                            .with_context(|| format!("{method}: invalid response"))?;
+                        if let Some(error) = response.get("error") {
+                            bail!("{method}: {error}");
+                        }
+                        return Ok(response
+                            .get_mut("result")
+                            .map(Value::take)
+                            .unwrap_or_default());
+                    }
+                    Err(e) => (e.to_string(), None),
+                },
+                Ok(resp)
+                    if resp.status() == StatusCode::TOO_MANY_REQUESTS
+                        || resp.status().is_server_error() =>
+                {
+                    let retry_after = resp
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse().ok())
+                        .map(Duration::from_secs);
+                    (resp.status().to_string(), retry_after)
+                }
+                Ok(resp) => bail!("{method}: HTTP {}", resp.status()),
+                Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => {
+                    (e.to_string(), None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            attempt += 1;
+            if attempt > self.max_retries {
+                bail!(
+                    "{method}: {reason}, gave up after {} retries",
+                    self.max_retries
+                );
+            }
+            let delay = retry_after.unwrap_or_else(|| self.backoff_delay(attempt));
+            log::warn!("{method}: {reason}, retry {attempt} in {delay:?}");
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// `backoff * 2^(attempt - 1)`, capped, with a jitter of up to half of it so
+    /// that the requests failed together aren't retried together.
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff
+            .saturating_mul(1u32 << (attempt - 1).min(16))
+            .min(MAX_BACKOFF);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// Spaces the requests evenly at a rate.
+struct RateLimiter {
+    interval: Duration,
+    next: tokio::sync::Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(per_sec: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / per_sec),
+            next: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn acquire(&self) {
+        let at = {
+            let mut next = self.next.lock().await;
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        tokio::time::sleep_until(at).await;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ManifestEntry {
+    block: u64,
+    /// Relative to the dir of the manifest.
+    file: String,
+    sha256: String,
+    bytes: u64,
+}
+
+/// `manifest.jsonl`, one entry per file written, the last entry of a block wins.
+struct Manifest {
+    entries: Mutex<HashMap<u64, ManifestEntry>>,
+    file: Mutex<File>,
+}
+
+impl Manifest {
+    fn open(path: &Path) -> Result<Self> {
+        let mut entries = HashMap::new();
+        if path.exists() {
+            for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // a line torn by a crash, its block is downloaded again
+                match serde_json::from_str::<ManifestEntry>(&line) {
+                    Ok(entry) => {
+                        entries.insert(entry.block, entry);
+                    }
+                    Err(e) => log::warn!("{}:{}: skipped, {e}", path.display(), i + 1),
+                }
+            }
+        }
+        log::info!("{} blocks in {}", entries.len(), path.display());
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            entries: Mutex::new(entries),
+            file: Mutex::new(file),
+        })
+    }
+
+    fn get(&self, block: u64) -> Option<ManifestEntry> {
+        self.entries.lock().unwrap().get(&block).cloned()
+    }
+
+    fn append(&self, entry: ManifestEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file.lock().unwrap().write_all(&line)?;
+        self.entries.lock().unwrap().insert(entry.block, entry);
+        Ok(())
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
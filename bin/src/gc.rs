@@ -0,0 +1,37 @@
+use clap::Parser;
+use zkevm::artifact::{ArtifactStore, RetentionPolicy};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Root dir of the artifact store.
+    #[clap(long = "root")]
+    root: String,
+    /// Remove the artifacts of all kinds older than this, overrides the env.
+    #[clap(long = "max-age-hours")]
+    max_age_hours: Option<u64>,
+    /// Keep at most this much of each kind of artifacts, overrides the env.
+    #[clap(long = "max-gb")]
+    max_gb: Option<u64>,
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    let args = Args::parse();
+    let store = ArtifactStore::new(&args.root).expect("failed to open artifact store");
+    let report = store
+        .gc(|kind| {
+            let mut policy = RetentionPolicy::from_env(kind);
+            if let Some(hours) = args.max_age_hours {
+                policy.max_age = Some(std::time::Duration::from_secs(hours * 3600));
+            }
+            if let Some(gb) = args.max_gb {
+                policy.max_bytes = Some(gb << 30);
+            }
+            policy
+        })
+        .expect("artifact gc failed");
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+}
@@ -0,0 +1,49 @@
+use clap::Parser;
+use zkevm::instance::diff_instances;
+use zkevm::prover::AggCircuitProof;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Instance of an agg proof: the proof json, the serialized instance, or
+    /// EVM calldata as raw bytes or hex.
+    #[clap(long = "left")]
+    left: String,
+    /// The instance to compare with, in any of the formats of `--left`.
+    #[clap(long = "right")]
+    right: String,
+    /// Print the diff as json.
+    #[clap(long = "json")]
+    json: bool,
+}
+
+fn load_instance(path: &str) -> Vec<u8> {
+    let buf = std::fs::read(path).unwrap_or_else(|e| panic!("failed to read {path}: {e}"));
+    if let Ok(proof) = serde_json::from_slice::<AggCircuitProof>(&buf) {
+        return proof.instance;
+    }
+    let text = String::from_utf8_lossy(&buf);
+    let text = text.trim();
+    match hex::decode(text.strip_prefix("0x").unwrap_or(text)) {
+        Ok(bytes) if !text.is_empty() => bytes,
+        _ => buf,
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let diffs = diff_instances(&load_instance(&args.left), &load_instance(&args.right))
+        .expect("failed to decode instances");
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diffs).unwrap());
+    } else if diffs.is_empty() {
+        println!("instances are equal");
+    } else {
+        for diff in &diffs {
+            println!("{diff}");
+        }
+    }
+    if !diffs.is_empty() {
+        std::process::exit(1);
+    }
+}
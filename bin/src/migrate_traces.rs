@@ -0,0 +1,81 @@
+use clap::Parser;
+use std::fs;
+use std::path::{Path, PathBuf};
+use types::migrate::{migrate_trace, TraceVersion};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Trace file, or dir searched recursively for `.json` files.
+    #[clap(long = "input")]
+    input: PathBuf,
+    /// Dir of the upgraded traces, with the layout of the input.
+    /// The input is rewritten in place if unset.
+    #[clap(long = "output")]
+    output: Option<PathBuf>,
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    let args = Args::parse();
+    let root = if args.input.is_dir() {
+        args.input.clone()
+    } else {
+        args.input.parent().unwrap_or(Path::new("")).to_path_buf()
+    };
+    let mut files = vec![];
+    collect_json_files(&args.input, &mut files).expect("failed to list input");
+
+    let (mut upgraded, mut failed) = (0, 0);
+    for file in files {
+        let out = match &args.output {
+            Some(dir) => dir.join(file.strip_prefix(&root).unwrap()),
+            None => file.clone(),
+        };
+        match migrate_file(&file, &out) {
+            Ok(TraceVersion::CURRENT) => log::debug!("{:?} is up to date", file),
+            Ok(version) => {
+                log::info!("{:?}: upgraded from {:?}", file, version);
+                upgraded += 1;
+            }
+            Err(e) => {
+                log::error!("{:?}: {}", file, e);
+                failed += 1;
+            }
+        }
+    }
+    log::info!("upgraded {} traces, {} failed", upgraded, failed);
+    if failed != 0 {
+        std::process::exit(1);
+    }
+}
+
+fn migrate_file(path: &Path, out: &Path) -> anyhow::Result<TraceVersion> {
+    let (trace, version) = migrate_trace(serde_json::from_slice(&fs::read(path)?)?)?;
+    if version != TraceVersion::CURRENT || path != out {
+        if let Some(dir) = out.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = out.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&trace)?)?;
+        fs::rename(&tmp_path, out)?;
+    }
+    Ok(version)
+}
+
+fn collect_json_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            collect_json_files(&entry, files)?;
+        }
+    } else if path.extension().map_or(false, |ext| ext == "json") {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
@@ -18,6 +18,7 @@ const DEFAULT_END_BATCH: i64 = i64::MAX;
 async fn main() {
     dotenv::dotenv().ok();
     env_logger::init();
+    zkevm::tune::apply_tuned_threads();
 
     log::info!("mock-testnet: begin");
 
@@ -0,0 +1,147 @@
+//! Inspect, verify, convert and truncate params files.
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use halo2_proofs::SerdeFormat;
+use zkevm::utils::{
+    check_params_powers, convert_params, detect_params_format, load_params_any_format,
+    params_file_len, read_params_degree, verify_params_digest,
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the degree, format, point counts and size of a params file.
+    Inspect {
+        /// Params file.
+        path: String,
+    },
+    /// Check the params against their sha256 and that their points are powers
+    /// of the same secret.
+    Verify {
+        /// Params file.
+        path: String,
+        /// Consecutive points checked, with a pairing each.
+        #[clap(long = "samples", default_value_t = 16)]
+        samples: usize,
+        /// Fail if there is no sha256 next to the params.
+        #[clap(long = "require-digest")]
+        require_digest: bool,
+    },
+    /// Rewrite the params in another format, `raw` or `compressed`.
+    Convert {
+        /// Params file.
+        src: String,
+        /// Params file written, with its sha256, may be `src`.
+        dst: String,
+        #[clap(long = "format", parse(try_from_str = parse_format))]
+        format: SerdeFormat,
+    },
+    /// Write the params downsized to a smaller degree.
+    Truncate {
+        /// Params file.
+        src: String,
+        /// Params file written, with its sha256.
+        dst: String,
+        #[clap(long = "degree")]
+        degree: usize,
+        /// Format written, the one of `src` by default.
+        #[clap(long = "format", parse(try_from_str = parse_format))]
+        format: Option<SerdeFormat>,
+    },
+}
+
+fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    match Args::parse().command {
+        Command::Inspect { path } => {
+            let degree = read_params_degree(&path)?;
+            let len = std::fs::metadata(&path)?.len();
+            println!("path:       {path}");
+            println!("degree:     {degree}");
+            println!("g points:   {}", 1u64 << degree);
+            println!("lagrange:   {}", 1u64 << degree);
+            println!("size:       {len} bytes");
+            match detect_params_format(&path, degree)? {
+                Some(format) => println!("format:     {}", format_name(format)),
+                None => println!(
+                    "format:     unknown, raw params of degree {degree} are {} bytes, compressed {}",
+                    params_file_len(degree, SerdeFormat::RawBytes),
+                    params_file_len(degree, SerdeFormat::Processed)
+                ),
+            }
+            match std::fs::read_to_string(format!("{path}.sha256")) {
+                Ok(digest) => println!("sha256:     {}", digest.trim()),
+                Err(_) => println!("sha256:     none"),
+            }
+        }
+        Command::Verify {
+            path,
+            samples,
+            require_digest,
+        } => {
+            if verify_params_digest(&path)? {
+                println!("sha256 matches");
+            } else if require_digest {
+                bail!("no sha256 next to {path}");
+            } else {
+                println!("no sha256, skipped");
+            }
+            let params = load_params_any_format(&path, read_params_degree(&path)?)?;
+            if !check_params_powers(&params, samples) {
+                bail!("the points of {path} aren't successive powers of a secret");
+            }
+            println!("{samples} points checked");
+        }
+        Command::Convert { src, dst, format } => {
+            convert_params(&src, &dst, None, format)?;
+            println!("{src} written to {dst} {}", format_name(format));
+        }
+        Command::Truncate {
+            src,
+            dst,
+            degree,
+            format,
+        } => {
+            let file_degree = read_params_degree(&src)?;
+            if degree > file_degree {
+                bail!("{src} are of degree {file_degree}, can't truncate to {degree}");
+            }
+            let format = match format {
+                Some(format) => format,
+                None => match detect_params_format(&src, file_degree)? {
+                    Some(format) => format,
+                    None => bail!("unknown format of {src}, give --format"),
+                },
+            };
+            convert_params(&src, &dst, Some(degree), format)?;
+            println!("{src} truncated to degree {degree} in {dst}");
+        }
+    }
+    Ok(())
+}
+
+fn parse_format(s: &str) -> Result<SerdeFormat, String> {
+    match s {
+        "raw" => Ok(SerdeFormat::RawBytesUnchecked),
+        "compressed" => Ok(SerdeFormat::Processed),
+        _ => Err(format!(
+            "unknown params format {s}, expected raw or compressed"
+        )),
+    }
+}
+
+fn format_name(format: SerdeFormat) -> &'static str {
+    match format {
+        SerdeFormat::Processed => "compressed",
+        SerdeFormat::RawBytes | SerdeFormat::RawBytesUnchecked => "raw",
+    }
+}
@@ -0,0 +1,179 @@
+//! Fetch the traces of a block range from l2geth, prove them as one chunk,
+//! verify the proof natively and in revm, then write the calldata of the
+//! verifier contract. Every stage writes its output into `--dir` and is skipped
+//! when the output is already there, so a failed run is resumed by rerunning it.
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use ethers_providers::{Http, Provider};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use types::eth::BlockTrace;
+use zkevm::{
+    chunk::ChunkInfo,
+    circuit::{AGG_DEGREE, DEGREE},
+    instance::decode_column,
+    prover::{derive_rng, AggCircuitProof, Prover},
+    utils::{load_or_create_params, load_or_create_seed, read_block_trace_from_file},
+    verifier::Verifier,
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// RPC endpoint of l2geth serving `scroll_getBlockTraceByNumberOrHash`.
+    #[clap(long = "l2geth")]
+    l2geth_api_url: String,
+    /// First block of the chunk.
+    #[clap(long = "first")]
+    first: u64,
+    /// Last block of the chunk, inclusive.
+    #[clap(long = "last")]
+    last: u64,
+    /// Get params and write into file.
+    #[clap(short, long = "params")]
+    params_path: String,
+    /// Get seed and write into file.
+    #[clap(long = "seed")]
+    seed_path: String,
+    /// Dir of the outputs of every stage, `pipeline_<first>_<last>` by default.
+    #[clap(long = "dir")]
+    dir: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    env_logger::init();
+    zkevm::tune::apply_tuned_threads();
+
+    let args = Args::parse();
+    if args.first > args.last {
+        bail!(
+            "first block {} is after last block {}",
+            args.first,
+            args.last
+        );
+    }
+    let dir = args
+        .dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("pipeline_{}_{}", args.first, args.last)));
+    fs::create_dir_all(&dir)?;
+
+    let traces = stage("fetch", || fetch_traces(&args, &dir.join("traces")))?;
+
+    let params = load_or_create_params(&args.params_path, *DEGREE)?;
+    let agg_params = load_or_create_params(&args.params_path, *AGG_DEGREE)?;
+
+    let proof_path = dir.join("agg_proof.json");
+    let proof: AggCircuitProof = if proof_path.exists() {
+        log::info!("prove: skipped, {:?} exists", proof_path);
+        serde_json::from_reader(File::open(&proof_path)?)?
+    } else {
+        let proof = stage("prove", || {
+            let seed = load_or_create_seed(&args.seed_path)?;
+            let mut prover = Prover::from_params_and_seed(params.clone(), agg_params.clone(), seed);
+            let resume_dir = dir.join("agg_resume");
+            prover.set_resume_dir(Some(resume_dir.clone()));
+            let resumable = Prover::load_agg_resume_state(&resume_dir)?
+                .map_or(false, |state| state.is_of(&traces));
+            let proof = if resumable {
+                prover.resume_agg_from_dir(&resume_dir, &traces)?
+            } else {
+                let mut rng = derive_rng(&mut prover.rng);
+                prover.create_agg_circuit_proof_batch(&traces, &mut rng)?
+            };
+            if proof.total_proved_block_count < traces.len() {
+                bail!(
+                    "only {} of {} blocks fit into the chunk, split the range",
+                    proof.total_proved_block_count,
+                    traces.len()
+                );
+            }
+            Ok(proof)
+        })?;
+        write_atomically(&proof_path, &serde_json::to_vec(&proof)?)?;
+        proof
+    };
+
+    let verified_path = dir.join("verified");
+    if verified_path.exists() {
+        log::info!("verify: skipped, {:?} exists", verified_path);
+    } else {
+        stage("verify", || {
+            let verifier = Verifier::from_params(params, agg_params, Some(proof.vk.clone()))?;
+            // the native verifier consumes the proof
+            let owned: AggCircuitProof = serde_json::from_reader(File::open(&proof_path)?)?;
+            if !verifier.verify_agg_circuit_proof(owned)? {
+                bail!("native verification failed");
+            }
+            verifier.check_instances_against_blocks(&proof, &traces)?;
+            let instances = decode_column(&proof.instance)?;
+            let bytecode = verifier.agg_evm_verifier_bytecode(instances.len())?;
+            if !Verifier::evm_verify_calldata(bytecode, proof.encode_calldata()?) {
+                bail!("evm verification failed");
+            }
+            Ok(())
+        })?;
+        fs::write(&verified_path, [])?;
+    }
+
+    let finalize_path = dir.join("finalize.json");
+    if finalize_path.exists() {
+        log::info!("calldata: skipped, {:?} exists", finalize_path);
+    } else {
+        stage("calldata", || {
+            let calldata = hex::encode(proof.encode_calldata()?);
+            write_atomically(&dir.join("calldata.hex"), calldata.as_bytes())?;
+            let chunk = ChunkInfo::from_block_traces(&traces)?;
+            let finalize = serde_json::json!({
+                "first_block": args.first,
+                "last_block": args.last,
+                "public_input_hash": chunk.public_input_hash(),
+                "chunk": chunk,
+                "calldata": format!("0x{calldata}"),
+            });
+            write_atomically(&finalize_path, &serde_json::to_vec_pretty(&finalize)?)
+        })?;
+    }
+
+    log::info!("pipeline of blocks {}..={} done", args.first, args.last);
+    Ok(())
+}
+
+fn stage<T>(name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    log::info!("{name}: begin");
+    let now = Instant::now();
+    let result = f().with_context(|| format!("{name} failed"))?;
+    log::info!("{name}: done, elapsed: {:?}", now.elapsed());
+    Ok(result)
+}
+
+/// Traces of the range in `dir`, fetching the ones not there yet.
+fn fetch_traces(args: &Args, dir: &Path) -> Result<Vec<BlockTrace>> {
+    fs::create_dir_all(dir)?;
+    let provider = Provider::<Http>::try_from(args.l2geth_api_url.as_str())?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut traces = Vec::with_capacity((args.last - args.first + 1) as usize);
+    for number in args.first..=args.last {
+        let path = dir.join(format!("{number}.json"));
+        if !path.exists() {
+            log::info!("fetch: requesting trace of block {number}");
+            let trace: serde_json::Value = runtime.block_on(provider.request(
+                "scroll_getBlockTraceByNumberOrHash",
+                [format!("{number:#x}")],
+            ))?;
+            write_atomically(&path, &serde_json::to_vec(&trace)?)?;
+        }
+        traces.push(read_block_trace_from_file(&path)?);
+    }
+    Ok(traces)
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
@@ -1,17 +1,22 @@
 use clap::Parser;
 use log::info;
-use rand::{RngCore, SeedableRng};
-use rand_xorshift::XorShiftRng;
-use std::collections::HashMap;
+use serde_derive::Serialize;
+use std::ffi::OsStr;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::Instant;
+use types::eth::BlockTrace;
 use zkevm::{
     circuit::{SuperCircuit, AGG_DEGREE, DEGREE},
-    prover::Prover,
-    utils::{get_block_trace_from_file, load_or_create_params, load_or_create_seed},
+    prover::{derive_rng, prover_rng, AggConfig, MemoryGate, Prover, ProverRng},
+    tune::TUNED_SETTINGS,
+    utils::{
+        discover_block_traces, estimate_proving_memory, load_or_create_params, load_or_create_seed,
+        params_memory,
+    },
 };
 
 #[derive(Parser, Debug)]
@@ -23,7 +28,8 @@ struct Args {
     /// Get seed and write into file.
     #[clap(long = "seed")]
     seed_path: Option<String>,
-    /// Get BlockTrace from file or dir.
+    /// Get BlockTrace from file, dir (searched recursively) or quoted glob
+    /// pattern, proved in block order.
     #[clap(short, long = "trace")]
     trace_path: Option<String>,
     /// Option means if generates super circuit proof.
@@ -34,87 +40,299 @@ struct Args {
     /// Boolean means if output agg circuit proof.
     #[clap(long = "agg")]
     agg_proof: Option<bool>,
+    /// Split blocks exceeding the super circuit capacity into several
+    /// super circuits before aggregation.
+    #[clap(long = "split")]
+    split_block: Option<bool>,
+    /// JSON config of the aggregation circuit, instead of the one at `VERIFY_CONFIG`.
+    #[clap(long = "agg-config")]
+    agg_config_path: Option<String>,
+    /// Persist the inner proofs of each agg proof into `<trace>/agg_resume`, and
+    /// finish the aggregation found there by a previous run instead of proving again.
+    #[clap(long = "resume")]
+    resume: bool,
+    /// Traces proved in parallel, each by a prover of its own. A trace waits for
+    /// its estimated memory to fit into what the traces being proved leave. The
+    /// tuned jobs by default, see `tune`, else 1.
+    #[clap(long = "jobs")]
+    jobs: Option<usize>,
+    /// Memory budget in GiB of the traces proved in parallel, the tuned one or
+    /// else the memory available on the host if 0.
+    #[clap(long = "max-memory-gb", default_value_t = 0)]
+    max_memory_gb: u64,
+    /// Output format of the agg circuit proof.
+    #[clap(long = "format", value_enum, default_value = "default")]
+    format: ProofFormat,
+    /// Write the timings of the run into `<dir>/run_report.json`.
+    #[clap(long = "report")]
+    report_dir: Option<PathBuf>,
+    /// Sample the run and write a flamegraph and a pprof profile next to the
+    /// report, needs the `profile` feature.
+    #[clap(long = "profile", requires = "report-dir")]
+    profile: bool,
+}
+
+/// Timings of a run, in milliseconds.
+#[derive(Serialize, Default)]
+struct RunReport {
+    traces: Vec<TraceReport>,
+    total_ms: u128,
+    /// Files of the profile, relative to the report.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    profile: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct TraceReport {
+    name: String,
+    super_ms: Option<u128>,
+    agg_ms: Option<u128>,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum ProofFormat {
+    /// Files of proof, instance and vk, plus the full proof JSON.
+    Default,
+    /// The JSON schema consumed by the coordinator.
+    Coordinator,
 }
 
 fn main() {
     dotenv::dotenv().ok();
     env_logger::init();
+    zkevm::tune::apply_tuned_threads();
 
     let args = Args::parse();
-    let params = load_or_create_params(&args.params_path.clone().unwrap(), *DEGREE)
-        .expect("failed to load or create params");
-    let agg_params = load_or_create_params(&args.params_path.unwrap(), *AGG_DEGREE)
-        .expect("failed to load or create params");
-    let seed =
-        load_or_create_seed(&args.seed_path.unwrap()).expect("failed to load or create seed");
-
-    let (local_rng1, mut local_rng2) = {
-        let mut rng = XorShiftRng::from_seed(seed);
-        let mut seed1 = [0u8; 16];
-        rng.fill_bytes(&mut seed1);
-        let local_rng1 = XorShiftRng::from_seed(seed1);
-        let mut seed2 = [0u8; 16];
-        rng.fill_bytes(&mut seed2);
-        let local_rng2 = XorShiftRng::from_seed(seed2);
-        (local_rng1, local_rng2)
+    // params loading included
+    let profiler = args.profile.then(profiler::start);
+    let params_path = args.params_path.as_deref().unwrap();
+    let params =
+        load_or_create_params(params_path, *DEGREE).expect("failed to load or create params");
+    let agg_params =
+        load_or_create_params(params_path, *AGG_DEGREE).expect("failed to load or create params");
+    let seed = load_or_create_seed(args.seed_path.as_deref().unwrap())
+        .expect("failed to load or create seed");
+
+    let tuned = TUNED_SETTINGS.as_ref();
+    let jobs = args.jobs.or(tuned.map(|s| s.jobs)).unwrap_or(1).max(1);
+    let mut rng = prover_rng(Some(seed));
+    let mut params = Some((params, agg_params));
+    let provers: Vec<_> = (0..jobs)
+        .map(|i| {
+            // the last prover takes the params, the others a copy
+            let (params, agg_params) = if i + 1 == jobs {
+                params.take().unwrap()
+            } else {
+                let (params, agg_params) = params.as_ref().unwrap();
+                (params.clone(), agg_params.clone())
+            };
+            let mut prover = Prover::from_params_and_rng(params, agg_params, derive_rng(&mut rng));
+            if let Some(path) = &args.agg_config_path {
+                let config = AggConfig::from_file(path).expect("failed to read agg config");
+                prover
+                    .set_agg_config(Some(config))
+                    .expect("invalid agg config");
+            }
+            (prover, derive_rng(&mut rng))
+        })
+        .collect();
+
+    let traces = discover_block_traces(args.trace_path.as_deref().unwrap())
+        .unwrap_or_else(|e| panic!("{}", e))
+        .into_iter()
+        .map(|(path, trace)| (path.file_stem().unwrap().to_os_string(), trace));
+    // the copies of the params are held until the end, out of a budget given;
+    // MemAvailable is read once they are made
+    let params_copies = (jobs as u64 - 1) * (params_memory(*DEGREE) + params_memory(*AGG_DEGREE));
+    let budget_left = |budget: u64| budget.saturating_sub(params_copies).max(1);
+    let gate = (jobs > 1).then(|| match (args.max_memory_gb, tuned) {
+        (0, Some(tuned)) => MemoryGate::new(budget_left(tuned.max_memory)),
+        (0, None) => MemoryGate::from_available_memory(),
+        (gb, _) => MemoryGate::new(budget_left(gb << 30)),
+    });
+    if let Some(gate) = &gate {
+        info!(
+            "proving {} traces at a time, within {}GB",
+            jobs,
+            gate.budget() >> 30
+        );
+    }
+
+    let outer_now = Instant::now();
+    let queue = Mutex::new(traces);
+    let reports = Mutex::new(vec![]);
+    std::thread::scope(|scope| {
+        let (args, queue, reports, gate) = (&args, &queue, &reports, &gate);
+        for (mut prover, mut rng) in provers {
+            scope.spawn(move || loop {
+                let next = queue.lock().unwrap().next();
+                let (trace_name, trace) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                // the traces left are queued until the memory of this one fits
+                let _permit = gate.as_ref().map(|gate| {
+                    gate.acquire(estimate_proving_memory(std::slice::from_ref(&trace)))
+                });
+                let report = prove_trace(args, &mut prover, &mut rng, &trace_name, &trace);
+                reports.lock().unwrap().push(report);
+            });
+        }
+    });
+    let mut report = RunReport {
+        traces: reports.into_inner().unwrap(),
+        ..Default::default()
     };
+    info!("finish generating all, elapsed: {:?}", outer_now.elapsed());
+    report.total_ms = outer_now.elapsed().as_millis();
 
-    let mut prover = Prover::from_params_and_rng(params, agg_params, local_rng1);
+    if let Some(dir) = &args.report_dir {
+        fs::create_dir_all(dir).unwrap();
+        if let Some(profiler) = profiler {
+            report.profile = profiler::write(profiler, dir);
+        }
+        write_report(&report, dir);
+    }
+}
 
-    let mut traces = HashMap::new();
-    let trace_path = PathBuf::from(&args.trace_path.unwrap());
-    if trace_path.is_dir() {
-        for entry in fs::read_dir(trace_path).unwrap() {
-            let path = entry.unwrap().path();
-            if path.is_file() && path.to_str().unwrap().ends_with(".json") {
-                let block_trace = get_block_trace_from_file(path.to_str().unwrap());
-                traces.insert(path.file_stem().unwrap().to_os_string(), block_trace);
-            }
+fn prove_trace(
+    args: &Args,
+    prover: &mut Prover,
+    rng: &mut ProverRng,
+    trace_name: &OsStr,
+    trace: &BlockTrace,
+) -> TraceReport {
+    let mut trace_report = TraceReport {
+        name: trace_name.to_string_lossy().to_string(),
+        super_ms: None,
+        agg_ms: None,
+    };
+    if args.super_proof.is_some() {
+        let proof_path = PathBuf::from(trace_name).join("super.proof");
+
+        let now = Instant::now();
+        let super_proof = prover
+            .create_target_circuit_proof::<SuperCircuit>(trace, rng)
+            .expect("cannot generate evm_proof");
+        info!(
+            "finish generating evm proof of {}, elapsed: {:?}",
+            &trace.header.hash.unwrap(),
+            now.elapsed()
+        );
+        trace_report.super_ms = Some(now.elapsed().as_millis());
+
+        if args.super_proof.unwrap() {
+            let mut f = File::create(&proof_path).unwrap();
+            f.write_all(super_proof.snark.proof.as_slice()).unwrap();
         }
-    } else {
-        let block_trace = get_block_trace_from_file(trace_path.to_str().unwrap());
-        traces.insert(trace_path.file_stem().unwrap().to_os_string(), block_trace);
     }
 
-    let outer_now = Instant::now();
-    for (trace_name, trace) in traces {
-        if args.super_proof.is_some() {
-            let proof_path = PathBuf::from(&trace_name).join("super.proof");
-
-            let now = Instant::now();
-            let super_proof = prover
-                .create_target_circuit_proof::<SuperCircuit>(&trace, &mut local_rng2)
-                .expect("cannot generate evm_proof");
-            info!(
-                "finish generating evm proof of {}, elapsed: {:?}",
-                &trace.header.hash.unwrap(),
-                now.elapsed()
-            );
+    if args.agg_proof.is_some() {
+        let mut proof_path = PathBuf::from(trace_name).join("agg.proof");
 
-            if args.super_proof.unwrap() {
-                let mut f = File::create(&proof_path).unwrap();
-                f.write_all(super_proof.snark.proof.as_slice()).unwrap();
+        let now = Instant::now();
+        let resume_dir = PathBuf::from(trace_name).join("agg_resume");
+        let resumable = args.resume && !args.split_block.unwrap_or_default();
+        prover.set_resume_dir(resumable.then(|| resume_dir.clone()));
+        let agg_proof = if args.split_block.unwrap_or_default() {
+            prover.create_agg_circuit_proof_split(trace, rng)
+        } else if resumable && resume_state_of(&resume_dir, trace) {
+            prover.resume_agg_from_dir(&resume_dir, std::slice::from_ref(trace))
+        } else {
+            prover.create_agg_circuit_proof(trace, rng)
+        }
+        .expect("cannot generate agg_proof");
+        info!(
+            "finish generating agg proof of {}, elapsed: {:?}",
+            &trace.header.hash.unwrap(),
+            now.elapsed()
+        );
+        trace_report.agg_ms = Some(now.elapsed().as_millis());
+
+        if args.agg_proof.unwrap() {
+            fs::create_dir_all(&proof_path).unwrap();
+            match args.format {
+                ProofFormat::Default => agg_proof.write_to_dir(&mut proof_path),
+                ProofFormat::Coordinator => {
+                    agg_proof.write_coordinator_json_to_dir(&mut proof_path)
+                }
             }
         }
+    }
+    trace_report
+}
 
-        if args.agg_proof.is_some() {
-            let mut proof_path = PathBuf::from(&trace_name).join("agg.proof");
-
-            let now = Instant::now();
-            let agg_proof = prover
-                .create_agg_circuit_proof(&trace, &mut local_rng2)
-                .expect("cannot generate agg_proof");
-            info!(
-                "finish generating agg proof of {}, elapsed: {:?}",
-                &trace.header.hash.unwrap(),
-                now.elapsed()
+/// Whether the unfinished aggregation in `dir` is the one of `trace`. A state left
+/// by another trace is proved again, and replaced.
+fn resume_state_of(dir: &Path, trace: &BlockTrace) -> bool {
+    match Prover::load_agg_resume_state(dir).expect("failed to read agg resume state") {
+        Some(state) if state.is_of(std::slice::from_ref(trace)) => true,
+        Some(_) => {
+            log::warn!(
+                "agg resume state in {} is of another trace, proving again",
+                dir.display()
             );
-
-            if args.agg_proof.unwrap() {
-                fs::create_dir_all(&proof_path).unwrap();
-                agg_proof.write_to_dir(&mut proof_path);
-            }
+            false
         }
+        None => false,
+    }
+}
+
+fn write_report(report: &RunReport, dir: &Path) {
+    let path = dir.join("run_report.json");
+    let f = File::create(&path).expect("failed to create run report");
+    serde_json::to_writer_pretty(f, report).expect("failed to write run report");
+    info!("run report written to {}", path.display());
+}
+
+/// Sampling of the whole process, worker threads included.
+#[cfg(feature = "profile")]
+mod profiler {
+    use pprof::protos::Message;
+    use pprof::ProfilerGuard;
+    use std::fs::{self, File};
+    use std::path::Path;
+
+    /// Samples per second.
+    const FREQUENCY: i32 = 99;
+
+    pub fn start() -> ProfilerGuard<'static> {
+        pprof::ProfilerGuardBuilder::default()
+            .frequency(FREQUENCY)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .expect("failed to start profiler")
+    }
+
+    /// Write the flamegraph and the pprof profile into the dir, returns their file names.
+    pub fn write(guard: ProfilerGuard<'static>, dir: &Path) -> Vec<String> {
+        let report = guard.report().build().expect("failed to build profile");
+        let flamegraph = File::create(dir.join("profile.svg")).unwrap();
+        report
+            .flamegraph(flamegraph)
+            .expect("failed to write flamegraph");
+        let mut buf = Vec::new();
+        report
+            .pprof()
+            .expect("failed to build pprof profile")
+            .encode(&mut buf)
+            .expect("failed to encode pprof profile");
+        fs::write(dir.join("profile.pb"), buf).unwrap();
+        vec!["profile.svg".to_string(), "profile.pb".to_string()]
+    }
+}
+
+#[cfg(not(feature = "profile"))]
+mod profiler {
+    use std::path::Path;
+
+    pub struct Disabled;
+
+    pub fn start() -> Disabled {
+        panic!("--profile needs the bin to be built with the `profile` feature");
+    }
+
+    pub fn write(_guard: Disabled, _dir: &Path) -> Vec<String> {
+        vec![]
     }
-    info!("finish generating all, elapsed: {:?}", outer_now.elapsed());
 }
@@ -0,0 +1,1231 @@
+use anyhow::anyhow;
+use clap::Parser;
+use ethers_core::abi::{self, ParamType, Token};
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{Address, BlockNumber, Filter, TransactionRequest, U256};
+use ethers_core::utils::keccak256;
+use ethers_providers::{Middleware, Provider};
+use ethers_signers::{LocalWallet, Signer};
+use hyper::body::HttpBody;
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_LENGTH, RETRY_AFTER};
+use hyper::server::conn::Http;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_derive::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+use types::eth::BlockTrace;
+use types::migrate::traces_from_slice;
+use zkevm::artifact::publish::publisher_from_env;
+use zkevm::artifact::ArtifactStore;
+use zkevm::prover::{AggCircuitProof, AggConfig, Prover};
+use zkevm::service::auth::{Access, AuthConfig, AuthError, Authenticator, Identity, Scope};
+use zkevm::service::dispatch::{
+    run_worker, Coordinator, DispatchError, WitnessLease, WORKER_POLL_SECS,
+};
+use zkevm::service::relayer::{L1Client, L1Receipt, L1Transaction, H256};
+use zkevm::service::telemetry::SpanExporter;
+use zkevm::service::watcher::{
+    CommittedBatch, L1Watcher, RollupEvents, TraceSource, WatcherConfig,
+};
+use zkevm::service::{
+    AdmissionError, JobFilter, JobId, JobStatus, ProverService, ReloadError, ServiceConfig,
+};
+use zkevm::trie_repair::{AccountTrieProof, TrieProofSource};
+use zkevm::tune::TUNED_SETTINGS;
+use zkevm::verifier::Verifier;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Interval of the comments sent on idle event streams, so that proxies don't
+/// close them during hours-long jobs.
+const EVENTS_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Get params from the file or dir.
+    #[clap(short, long = "params")]
+    params_path: Option<String>,
+    /// Get seed from the file, not needed by a coordinator.
+    #[clap(long = "seed")]
+    seed_path: Option<String>,
+    /// A `prover` proves the jobs it takes, a `coordinator` only generates their
+    /// witnesses, which its `worker`s prove, see `zkevm::service::dispatch`.
+    #[clap(long = "role", value_enum, default_value = "prover")]
+    role: Role,
+    /// Agg vk a coordinator verifies the proofs of its workers with.
+    #[clap(long = "agg-vk", required_if_eq("role", "coordinator"))]
+    agg_vk_path: Option<String>,
+    /// URL of the coordinator of a worker.
+    #[clap(long = "coordinator", required_if_eq("role", "worker"))]
+    coordinator_url: Option<String>,
+    /// Name of a worker in the status of the jobs it proves, the hostname by default.
+    #[clap(long = "worker-name")]
+    worker_name: Option<String>,
+    /// Address to listen on.
+    #[clap(long = "listen", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+    /// Root dir of the artifact store the proofs are written into.
+    #[clap(long = "output", default_value = "./service_output")]
+    output_dir: String,
+    /// Run the artifact gc with the retention policies from the env every so
+    /// many seconds, 0 to disable.
+    #[clap(long = "gc-interval-secs", default_value = "3600")]
+    gc_interval_secs: u64,
+    /// Number of worker threads, the tuned jobs by default, see `tune`, else 1.
+    #[clap(long = "workers")]
+    workers: Option<usize>,
+    /// Max number of jobs waiting in the queue, 0 for no limit.
+    #[clap(long = "max-queued-jobs", default_value = "64")]
+    max_queued_jobs: usize,
+    /// Memory budget in GiB of the jobs in flight, 0 for no limit. The tuned one
+    /// by default, else no limit.
+    #[clap(long = "max-memory-gb")]
+    max_memory_gb: Option<u64>,
+    /// Max size in MiB of a request body, 413 above it.
+    #[clap(long = "max-body-mb", default_value = "256")]
+    max_body_mb: u64,
+    /// Fail jobs still proving after so many seconds, 0 for no limit.
+    #[clap(long = "job-timeout-secs", default_value = "0")]
+    job_timeout_secs: u64,
+    /// JSON file of the API keys, JWT secret and rate limits.
+    /// The API is open to anyone who can reach it if unset.
+    #[clap(long = "auth")]
+    auth_path: Option<String>,
+    /// PEM certificate chain of the server, enables TLS.
+    #[clap(long = "tls-cert", requires = "tls-key")]
+    tls_cert: Option<String>,
+    /// PEM PKCS#8 private key of the server.
+    #[clap(long = "tls-key", requires = "tls-cert")]
+    tls_key: Option<String>,
+    /// PEM CA certificates to verify client certificates against, enables mTLS.
+    #[clap(long = "tls-client-ca", requires = "tls-cert")]
+    tls_client_ca: Option<String>,
+    /// JSON config of the aggregation circuit, instead of the one at `VERIFY_CONFIG`.
+    #[clap(long = "agg-config")]
+    agg_config_path: Option<String>,
+    /// Generate the proving keys before listening.
+    #[clap(long = "warm-up")]
+    warm_up: bool,
+    /// Also run an aggregation proof of an empty batch before listening.
+    #[clap(long = "warm-up-proof", requires = "warm-up")]
+    warm_up_proof: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    Prover,
+    Coordinator,
+    Worker,
+}
+
+struct App {
+    service: ProverService,
+    auth: Option<Authenticator>,
+    max_body_bytes: u64,
+}
+
+#[derive(Deserialize)]
+struct ReloadRequest {
+    params_path: String,
+    seed_path: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct WorkerFailure {
+    error: String,
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    env_logger::init();
+    zkevm::tune::apply_tuned_threads();
+
+    let args = Args::parse();
+    if args.role == Role::Worker {
+        run_worker_node(&args).await;
+        return;
+    }
+    let auth = match &args.auth_path {
+        Some(path) => {
+            let config = AuthConfig::from_file(path).expect("failed to load auth config");
+            Some(Authenticator::new(config))
+        }
+        None => {
+            log::warn!("service: no --auth given, the API is not authenticated");
+            None
+        }
+    };
+    let tls = args.tls_cert.as_ref().map(|cert| {
+        tls_acceptor(
+            cert,
+            args.tls_key.as_ref().unwrap(),
+            args.tls_client_ca.as_deref(),
+        )
+        .expect("failed to load tls config")
+    });
+
+    let artifacts = ArtifactStore::new(&args.output_dir).expect("failed to open artifact store");
+    if args.gc_interval_secs != 0 {
+        let artifacts = artifacts.clone();
+        let period = Duration::from_secs(args.gc_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let artifacts = artifacts.clone();
+                match tokio::task::spawn_blocking(move || artifacts.gc_from_env()).await {
+                    Ok(Ok(report)) => log::info!(
+                        "service: artifact gc reclaimed {} bytes",
+                        report.reclaimed_bytes()
+                    ),
+                    Ok(Err(e)) => log::error!("service: artifact gc failed: {}", e),
+                    Err(e) => log::error!("service: artifact gc panicked: {}", e),
+                }
+            }
+        });
+    }
+
+    let tuned = TUNED_SETTINGS.as_ref();
+    let config = ServiceConfig {
+        artifacts,
+        workers: args.workers.or(tuned.map(|s| s.jobs)).unwrap_or(1),
+        max_queued_jobs: args.max_queued_jobs,
+        max_memory: args
+            .max_memory_gb
+            .map(|gb| gb << 30)
+            .or(tuned.map(|s| s.max_memory))
+            .unwrap_or(0),
+        job_timeout: (args.job_timeout_secs != 0)
+            .then(|| Duration::from_secs(args.job_timeout_secs)),
+        publisher: publisher_from_env(),
+        exporter: OtlpExporter::from_env().map(|e| Arc::new(e) as Arc<dyn SpanExporter>),
+        relayer: EthL1Client::from_env()
+            .await
+            .map(|c| Arc::new(c) as Arc<dyn L1Client>),
+    };
+    let mut service = match args.role {
+        Role::Coordinator => ProverService::coordinator(config, load_verifier(&args)),
+        _ => ProverService::new(load_prover(&args), config),
+    };
+    if let Some(events) = EthRollupEvents::from_env() {
+        let traces = RpcTraceSource::from_env().expect("L1_WATCH_L2_RPC_URL is not set");
+        let watcher = L1Watcher::new(
+            Arc::new(events),
+            Arc::new(traces),
+            WatcherConfig::default(),
+            service.artifacts().root().join("l1_watcher.json"),
+        )
+        .expect("failed to load the l1 watcher state");
+        service.watch_l1(watcher);
+    }
+    let app = Arc::new(App {
+        service,
+        auth,
+        max_body_bytes: args.max_body_mb << 20,
+    });
+
+    log::info!("service: listening on {}", args.listen);
+    let result = match tls {
+        Some(acceptor) => serve_tls(app, args.listen, acceptor).await,
+        None => {
+            let make_svc = make_service_fn(move |_conn| {
+                let app = app.clone();
+                async move { Ok::<_, Infallible>(service_fn(move |req| handle(app.clone(), req))) }
+            });
+            Server::bind(&args.listen)
+                .serve(make_svc)
+                .await
+                .map_err(Into::into)
+        }
+    };
+    if let Err(e) = result {
+        log::error!("service: server error: {}", e);
+    }
+}
+
+fn load_verifier(args: &Args) -> Verifier {
+    let agg_vk = std::fs::read(args.agg_vk_path.as_ref().unwrap()).expect("failed to read agg vk");
+    Verifier::from_fpath(
+        args.params_path.as_ref().expect("--params is required"),
+        Some(agg_vk),
+    )
+    .expect("failed to init verifier")
+}
+
+fn load_prover(args: &Args) -> Prover {
+    let mut prover = Prover::from_fpath(
+        args.params_path.as_ref().expect("--params is required"),
+        args.seed_path.as_ref().expect("--seed is required"),
+    );
+    prover.set_trie_proof_source(
+        RpcTrieProofSource::from_env().map(|s| Arc::new(s) as Arc<dyn TrieProofSource>),
+    );
+    if let Some(path) = &args.agg_config_path {
+        let config = AggConfig::from_file(path).expect("failed to read agg config");
+        prover
+            .set_agg_config(Some(config))
+            .expect("invalid agg config");
+    }
+    if args.warm_up {
+        let report = prover
+            .warm_up(args.warm_up_proof)
+            .expect("failed to warm up prover");
+        log::info!(
+            "service: warmed up in {:?}: {:?}",
+            report.total(),
+            report.steps
+        );
+    }
+    prover
+}
+
+/// Prove the witnesses of the coordinator until interrupted, the job in flight
+/// being finished first.
+async fn run_worker_node(args: &Args) {
+    let mut prover = load_prover(args);
+    let coordinator = HttpCoordinator::new(args.coordinator_url.clone().unwrap());
+    let name = args
+        .worker_name
+        .clone()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| format!("worker-{}", std::process::id()));
+    log::info!("service: worker {} proving for {}", name, coordinator.url);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            tokio::signal::ctrl_c().await.ok();
+            log::info!("service: stopping once the job in flight is done");
+            stop.store(true, Ordering::SeqCst);
+        });
+    }
+    tokio::task::spawn_blocking(move || {
+        let poll_interval = Duration::from_secs(*WORKER_POLL_SECS);
+        run_worker(&mut prover, &coordinator, &name, poll_interval, &stop)
+    })
+    .await
+    .expect("worker panicked");
+}
+
+async fn serve_tls(app: Arc<App>, listen: SocketAddr, acceptor: TlsAcceptor) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::warn!("service: accept failed: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("service: tls handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let svc = service_fn(move |req| handle(app.clone(), req));
+            if let Err(e) = Http::new().serve_connection(stream, svc).await {
+                log::warn!("service: connection with {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+fn tls_acceptor(
+    cert_path: &str,
+    key_path: &str,
+    client_ca_path: Option<&str>,
+) -> anyhow::Result<TlsAcceptor> {
+    let certs = read_certs(cert_path)?;
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .next()
+        .map(rustls::PrivateKey)
+        .ok_or_else(|| anyhow!("no PKCS#8 private key in {}", key_path))?;
+
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca_path {
+        Some(path) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots
+                    .add(&cert)
+                    .map_err(|e| anyhow!("bad client CA in {}: {:?}", path, e))?;
+            }
+            builder
+                .with_client_cert_verifier(rustls::server::AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn read_certs(path: &str) -> anyhow::Result<Vec<rustls::Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+/// Routes:
+/// - `POST /v1/prove`: body is a JSON array of block traces, returns the job id,
+///   or 429 when the queue is full and 413 when the job is above the memory budget.
+///   A body above `--max-body-mb` is refused with 413 on every route.
+/// - `GET /v1/status/{id}`: returns the job status, with the timeline of its phases.
+/// - `GET /v1/jobs?state=..&block=..&limit=..`: returns the job history, latest
+///   first, optionally only the jobs in the state or proving the block.
+/// - `POST /v1/reload`: body is `{"params_path": .., "seed_path": ..}`, swaps in a
+///   new prover once it is loaded, 400 if the params or seed can't be loaded (none
+///   are created), 409 on a coordinator.
+/// - `POST /v1/worker/lease?worker=..`: on a coordinator, returns the binary
+///   witness of a job with its id in `x-job-id`, 204 if none waits.
+/// - `POST /v1/worker/jobs/{id}/proof?worker=..`: body is the agg proof of the job
+///   leased, `POST /v1/worker/jobs/{id}/failure?worker=..` is `{"error": ..}`, 403
+///   if the worker doesn't hold the lease. A proof failing to verify against the
+///   traces of the job fails it. With `--auth` the worker is the identity of the
+///   credentials, `worker` is ignored.
+///
+/// With `--auth`, every request needs an API key or a JWT, see `zkevm::service::auth`;
+/// 401 is returned without valid credentials, 403 without the scope of the route,
+/// `admin` for `/v1/reload`, `worker` for the worker routes and `submit` for the
+/// others, and 429 past the rate limit of the key.
+async fn handle(app: Arc<App>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let mut identity = None;
+    if let Some(auth) = &app.auth {
+        match auth.authorize(
+            header(&req, AUTHORIZATION.as_str()),
+            header(&req, "x-api-key"),
+            route_access(req.method(), req.uri().path()),
+        ) {
+            Ok(authorized) => {
+                log::debug!(
+                    "service: {} {} by {}",
+                    req.method(),
+                    req.uri(),
+                    authorized.name
+                );
+                identity = Some(authorized);
+            }
+            Err(e) => return Ok(auth_error_response(e)),
+        }
+    }
+    let service = &app.service;
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let response = match (&method, path.as_str()) {
+        (&Method::POST, "/v1/prove") => {
+            let traceparent = header(&req, "traceparent").map(str::to_string);
+            match read_traces(req, app.max_body_bytes).await {
+                Ok(block_traces) => {
+                    match service.submit_traced(block_traces, traceparent.as_deref()) {
+                        Ok(id) => json_response(StatusCode::OK, &serde_json::json!({ "id": id })),
+                        Err(e @ AdmissionError::QueueFull { .. }) => {
+                            error_response(StatusCode::TOO_MANY_REQUESTS, e)
+                        }
+                        Err(e @ AdmissionError::MemoryExceeded { .. }) => {
+                            error_response(StatusCode::PAYLOAD_TOO_LARGE, e)
+                        }
+                    }
+                }
+                Err(e) => body_error_response(e),
+            }
+        }
+        (&Method::GET, path) if path.starts_with("/v1/status/") => {
+            match path["/v1/status/".len()..].parse() {
+                Ok(id) => match service.status(id) {
+                    Some(status) => json_response(StatusCode::OK, &status),
+                    None => error_response(StatusCode::NOT_FOUND, format!("no job {id}")),
+                },
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+            }
+        }
+        (&Method::GET, "/v1/events") => events_response(service, None),
+        (&Method::GET, path) if path.starts_with("/v1/events/") => {
+            match path["/v1/events/".len()..].parse() {
+                Ok(id) if service.status(id).is_some() => events_response(service, Some(id)),
+                Ok(id) => error_response(StatusCode::NOT_FOUND, format!("no job {id}")),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+            }
+        }
+        (&Method::GET, path) if path.starts_with("/v1/artifacts/") => {
+            let digest = &path["/v1/artifacts/".len()..];
+            match service.artifacts().content().get(digest) {
+                Ok(Some(data)) => Response::builder()
+                    .header("content-type", "application/octet-stream")
+                    .body(Body::from(data))
+                    .unwrap(),
+                Ok(None) => error_response(StatusCode::NOT_FOUND, format!("no artifact {digest}")),
+                Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+            }
+        }
+        (&Method::GET, "/v1/jobs") => match parse_job_filter(req.uri().query()) {
+            Ok(filter) => json_response(StatusCode::OK, &service.jobs(&filter)),
+            Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+        },
+        (&Method::POST, "/v1/reload") => {
+            match read_json::<ReloadRequest>(req, app.max_body_bytes).await {
+                Ok(reload) => {
+                    let app = app.clone();
+                    let handle = tokio::task::spawn_blocking(move || {
+                        app.service
+                            .reload_from_fpath(&reload.params_path, &reload.seed_path)
+                    });
+                    match handle.await {
+                        Ok(Ok(generation)) => json_response(
+                            StatusCode::OK,
+                            &serde_json::json!({ "generation": generation }),
+                        ),
+                        Ok(Err(ReloadError::Dispatch(e))) => dispatch_error_response(e),
+                        Ok(Err(e @ ReloadError::Load(_))) => {
+                            error_response(StatusCode::BAD_REQUEST, e)
+                        }
+                        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+                    }
+                }
+                Err(e) => body_error_response(e),
+            }
+        }
+        (&Method::POST, "/v1/worker/lease") => match worker_of(identity, req.uri().query()) {
+            Ok(worker) => match service.lease_witness(&worker) {
+                Ok(Some(lease)) => Response::builder()
+                    .header("content-type", "application/octet-stream")
+                    .header("x-job-id", lease.id)
+                    .body(Body::from(lease.witness))
+                    .unwrap(),
+                Ok(None) => Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Body::empty())
+                    .unwrap(),
+                Err(e) => dispatch_error_response(e),
+            },
+            Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+        },
+        (&Method::POST, path) if path.starts_with("/v1/worker/jobs/") => {
+            let route = &path["/v1/worker/jobs/".len()..];
+            match worker_of(identity, req.uri().query()) {
+                Ok(worker) => match complete_witness(app.clone(), req, route, worker).await {
+                    Ok(response) => response,
+                    Err(e) => body_error_response(e),
+                },
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, format!("no route {method} {path}")),
+    };
+    Ok(response)
+}
+
+/// The scope of a route, see `zkevm::service::auth::Scope`. Event streams and the
+/// worker routes, which workers poll, aren't charged to the rate limit.
+fn route_access(method: &Method, path: &str) -> Access {
+    match (method, path) {
+        (&Method::POST, "/v1/reload") => Access::limited(Scope::Admin),
+        (&Method::POST, path) if path.starts_with("/v1/worker/") => {
+            Access::unlimited(Scope::Worker)
+        }
+        (&Method::GET, path) if path.starts_with("/v1/events") => Access::unlimited(Scope::Submit),
+        _ => Access::limited(Scope::Submit),
+    }
+}
+
+/// Server-sent events of the status of the job on every change, or of all jobs.
+/// The stream of a job starts with its current status and ends once it is over.
+fn events_response(service: &ProverService, id: Option<JobId>) -> Response<Body> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<JobStatus>();
+    let initial_tx = tx.clone();
+    service.subscribe(Box::new(move |status| {
+        if id.map_or(true, |id| id == status.id) {
+            tx.send(status.clone()).is_ok()
+        } else {
+            !tx.is_closed()
+        }
+    }));
+    // after subscribing, so that no change is missed
+    if let Some(status) = id.and_then(|id| service.status(id)) {
+        initial_tx.send(status).ok();
+    }
+    drop(initial_tx);
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut keep_alive = tokio::time::interval(EVENTS_KEEP_ALIVE);
+        loop {
+            let (event, done) = tokio::select! {
+                status = rx.recv() => match status {
+                    Some(status) => {
+                        let data = serde_json::to_string(&status).unwrap();
+                        let done = id.is_some() && status.state.is_final();
+                        (format!("event: status\nid: {}\ndata: {}\n\n", status.id, data), done)
+                    }
+                    None => break,
+                },
+                _ = keep_alive.tick() => (": keep-alive\n\n".to_string(), false),
+            };
+            if sender.send_data(event.into()).await.is_err() || done {
+                break;
+            }
+        }
+    });
+    Response::builder()
+        .header("content-type", "text/event-stream")
+        .header("cache-control", "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+/// `{id}/proof` or `{id}/failure` from a worker.
+async fn complete_witness(
+    app: Arc<App>,
+    req: Request<Body>,
+    route: &str,
+    worker: String,
+) -> anyhow::Result<Response<Body>> {
+    let limit = app.max_body_bytes;
+    let (id, result) = match route.split_once('/') {
+        Some((id, "proof")) => (
+            id.parse()?,
+            Ok(read_json::<AggCircuitProof>(req, limit).await?),
+        ),
+        Some((id, "failure")) => (
+            id.parse()?,
+            Err(read_json::<WorkerFailure>(req, limit).await?.error),
+        ),
+        _ => {
+            return Ok(error_response(
+                StatusCode::NOT_FOUND,
+                format!("no route {route}"),
+            ))
+        }
+    };
+    // the proof is stored and published before answering
+    let completed =
+        tokio::task::spawn_blocking(move || app.service.complete_witness(id, &worker, result))
+            .await?;
+    Ok(match completed {
+        Ok(()) => json_response(StatusCode::OK, &serde_json::json!({ "id": id })),
+        Err(e) => dispatch_error_response(e),
+    })
+}
+
+/// The worker calling a worker route: the identity authenticated with `--auth`,
+/// so that a worker can't take or complete the lease of another by naming it,
+/// else the `worker` of the query.
+fn worker_of(identity: Option<Identity>, query: Option<&str>) -> anyhow::Result<String> {
+    if let Some(identity) = identity {
+        return Ok(identity.name);
+    }
+    query
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("worker="))
+        .filter(|worker| !worker.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("no worker in the query"))
+}
+
+fn parse_job_filter(query: Option<&str>) -> anyhow::Result<JobFilter> {
+    let mut filter = JobFilter::default();
+    for pair in query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|p| !p.is_empty())
+    {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match key {
+            "state" => filter.state = Some(serde_json::from_value(value.into())?),
+            "block" => filter.block_number = Some(value.parse()?),
+            "limit" => filter.limit = value.parse()?,
+            _ => anyhow::bail!("unknown query parameter {}", key),
+        }
+    }
+    Ok(filter)
+}
+
+/// Posts the spans of the jobs to an OTLP/HTTP collector, at
+/// `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` or at `/v1/traces` under
+/// `OTEL_EXPORTER_OTLP_ENDPOINT`.
+#[derive(Debug)]
+struct OtlpExporter {
+    url: String,
+    service_name: String,
+    client: reqwest::Client,
+    // the exports are run from the worker threads, on the runtime of the server
+    runtime: tokio::runtime::Handle,
+}
+
+impl OtlpExporter {
+    fn from_env() -> Option<Self> {
+        let url = match std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT") {
+            Ok(url) => url,
+            Err(_) => format!(
+                "{}/v1/traces",
+                std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                    .ok()?
+                    .trim_end_matches('/')
+            ),
+        };
+        log::info!("service: exporting spans to {}", url);
+        Some(Self {
+            url,
+            service_name: std::env::var("OTEL_SERVICE_NAME")
+                .unwrap_or_else(|_| "zkevm-prover".to_string()),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("failed to build otlp client"),
+            runtime: tokio::runtime::Handle::current(),
+        })
+    }
+}
+
+impl SpanExporter for OtlpExporter {
+    fn export(&self, request: &serde_json::Value) -> anyhow::Result<()> {
+        self.runtime.block_on(async {
+            self.client
+                .post(&self.url)
+                .json(request)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+
+    fn service_name(&self) -> &str {
+        &self.service_name
+    }
+}
+
+/// Signs the submissions of the relayer with the key at `RELAYER_PRIVATE_KEY`,
+/// and sends them to the verifier contract at `RELAYER_CONTRACT` through the L1
+/// node at `RELAYER_L1_RPC_URL`. Legacy transactions, so that a replacement
+/// only has to bump the gas price.
+#[derive(Debug)]
+struct EthL1Client {
+    provider: Provider<ethers_providers::Http>,
+    wallet: LocalWallet,
+    contract: Address,
+    // the relayer thread runs on the runtime of the server
+    runtime: tokio::runtime::Handle,
+}
+
+impl EthL1Client {
+    async fn from_env() -> Option<Self> {
+        let url = std::env::var("RELAYER_L1_RPC_URL").ok()?;
+        let provider = Provider::try_from(url.as_str()).expect("invalid RELAYER_L1_RPC_URL");
+        let chain_id = provider
+            .get_chainid()
+            .await
+            .expect("failed to get the chain id of L1");
+        let wallet = std::env::var("RELAYER_PRIVATE_KEY")
+            .expect("RELAYER_PRIVATE_KEY is not set")
+            .parse::<LocalWallet>()
+            .expect("invalid RELAYER_PRIVATE_KEY")
+            .with_chain_id(chain_id.as_u64());
+        let contract = std::env::var("RELAYER_CONTRACT")
+            .expect("RELAYER_CONTRACT is not set")
+            .parse()
+            .expect("invalid RELAYER_CONTRACT");
+        log::info!(
+            "service: relaying proofs to {:?} on chain {} from {:?}",
+            contract,
+            chain_id,
+            wallet.address()
+        );
+        Some(Self {
+            provider,
+            wallet,
+            contract,
+            runtime: tokio::runtime::Handle::current(),
+        })
+    }
+}
+
+impl L1Client for EthL1Client {
+    fn pending_nonce(&self) -> anyhow::Result<u64> {
+        self.runtime.block_on(async {
+            let nonce = self
+                .provider
+                .get_transaction_count(self.wallet.address(), Some(BlockNumber::Pending.into()))
+                .await?;
+            Ok(nonce.as_u64())
+        })
+    }
+
+    fn gas_price(&self) -> anyhow::Result<u128> {
+        self.runtime
+            .block_on(async { Ok(self.provider.get_gas_price().await?.as_u128()) })
+    }
+
+    fn block_number(&self) -> anyhow::Result<u64> {
+        self.runtime
+            .block_on(async { Ok(self.provider.get_block_number().await?.as_u64()) })
+    }
+
+    fn send(&self, tx: &L1Transaction) -> anyhow::Result<H256> {
+        self.runtime.block_on(async {
+            let mut request: TypedTransaction = TransactionRequest::new()
+                .from(self.wallet.address())
+                .to(self.contract)
+                .data(tx.calldata.clone())
+                .nonce(tx.nonce)
+                .gas_price(U256::from(tx.gas_price))
+                .chain_id(self.wallet.chain_id())
+                .into();
+            let gas = self.provider.estimate_gas(&request, None).await?;
+            // margin for the state changing until the transaction is included
+            request.set_gas(gas * 6 / 5);
+            let signature = self.wallet.sign_transaction(&request).await?;
+            let pending = self
+                .provider
+                .send_raw_transaction(request.rlp_signed(&signature))
+                .await?;
+            Ok(H256(pending.tx_hash().0))
+        })
+    }
+
+    fn receipt(&self, tx_hash: H256) -> anyhow::Result<Option<L1Receipt>> {
+        self.runtime.block_on(async {
+            let receipt = self
+                .provider
+                .get_transaction_receipt(ethers_core::types::H256(tx_hash.0))
+                .await?;
+            Ok(receipt.and_then(|r| {
+                Some(L1Receipt {
+                    tx_hash,
+                    block_number: r.block_number?.as_u64(),
+                    gas_used: r.gas_used.unwrap_or_default().as_u64(),
+                    success: r.status.map_or(false, |status| status.as_u64() == 1),
+                })
+            }))
+        })
+    }
+}
+
+/// Follows the `CommitBatch` events of the rollup contract at
+/// `L1_WATCH_ROLLUP_CONTRACT` through the L1 node at `L1_WATCH_RPC_URL`, the
+/// chunks of a batch are decoded from the calldata of its `commitBatch`.
+#[derive(Debug)]
+struct EthRollupEvents {
+    provider: Provider<ethers_providers::Http>,
+    contract: Address,
+    // the watcher thread runs on the runtime of the server
+    runtime: tokio::runtime::Handle,
+}
+
+impl EthRollupEvents {
+    fn from_env() -> Option<Self> {
+        let url = std::env::var("L1_WATCH_RPC_URL").ok()?;
+        let provider = Provider::try_from(url.as_str()).expect("invalid L1_WATCH_RPC_URL");
+        let contract = std::env::var("L1_WATCH_ROLLUP_CONTRACT")
+            .expect("L1_WATCH_ROLLUP_CONTRACT is not set")
+            .parse()
+            .expect("invalid L1_WATCH_ROLLUP_CONTRACT");
+        log::info!("service: watching the batches committed to {:?}", contract);
+        Some(Self {
+            provider,
+            contract,
+            runtime: tokio::runtime::Handle::current(),
+        })
+    }
+}
+
+/// The chunks of the `commitBatch(uint8,bytes,bytes[],bytes)` calldata.
+fn commit_batch_chunks(calldata: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let selector = &keccak256("commitBatch(uint8,bytes,bytes[],bytes)")[..4];
+    if calldata.len() < 4 || &calldata[..4] != selector {
+        return Err(anyhow!("not a call of commitBatch"));
+    }
+    let params = [
+        ParamType::Uint(8),
+        ParamType::Bytes,
+        ParamType::Array(Box::new(ParamType::Bytes)),
+        ParamType::Bytes,
+    ];
+    match abi::decode(&params, &calldata[4..])?.swap_remove(2) {
+        Token::Array(chunks) => Ok(chunks.into_iter().filter_map(Token::into_bytes).collect()),
+        token => Err(anyhow!("expected the chunks, got {:?}", token)),
+    }
+}
+
+impl RollupEvents for EthRollupEvents {
+    fn block_number(&self) -> anyhow::Result<u64> {
+        self.runtime
+            .block_on(async { Ok(self.provider.get_block_number().await?.as_u64()) })
+    }
+
+    fn committed_batches(&self, from: u64, to: u64) -> anyhow::Result<Vec<CommittedBatch>> {
+        self.runtime.block_on(async {
+            let filter = Filter::new()
+                .address(self.contract)
+                .topic0(ethers_core::types::H256(keccak256(
+                    "CommitBatch(uint256,bytes32)",
+                )))
+                .from_block(from)
+                .to_block(to);
+            let mut batches = vec![];
+            for log in self.provider.get_logs(&filter).await? {
+                if log.removed == Some(true) || log.topics.len() < 3 {
+                    continue;
+                }
+                let batch_index = U256::from_big_endian(log.topics[1].as_bytes()).as_u64();
+                let tx_hash = log
+                    .transaction_hash
+                    .ok_or_else(|| anyhow!("log of batch {} without transaction", batch_index))?;
+                let tx = self
+                    .provider
+                    .get_transaction(tx_hash)
+                    .await?
+                    .ok_or_else(|| anyhow!("transaction {:?} not found", tx_hash))?;
+                // e.g. committed through another contract, left to the orchestrator
+                let chunks = match commit_batch_chunks(&tx.input) {
+                    Ok(chunks) => chunks,
+                    Err(e) => {
+                        log::warn!(
+                            "service: batch {} of transaction {:?} skipped: {}",
+                            batch_index,
+                            tx_hash,
+                            e
+                        );
+                        continue;
+                    }
+                };
+                batches.push(CommittedBatch {
+                    batch_index,
+                    batch_hash: H256(log.topics[2].0),
+                    l1_block: log.block_number.map_or(to, |n| n.as_u64()),
+                    chunks,
+                });
+            }
+            Ok(batches)
+        })
+    }
+}
+
+/// Fetches the traces of the committed chunks with the
+/// `scroll_getBlockTraceByNumberOrHash` of the l2geth node at `L1_WATCH_L2_RPC_URL`.
+#[derive(Debug)]
+struct RpcTraceSource {
+    url: String,
+    client: reqwest::Client,
+    runtime: tokio::runtime::Handle,
+}
+
+impl RpcTraceSource {
+    fn from_env() -> Option<Self> {
+        let url = std::env::var("L1_WATCH_L2_RPC_URL").ok()?;
+        Some(Self {
+            url,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()
+                .expect("failed to build trace client"),
+            runtime: tokio::runtime::Handle::current(),
+        })
+    }
+}
+
+impl TraceSource for RpcTraceSource {
+    fn block_trace(&self, number: u64) -> anyhow::Result<BlockTrace> {
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "scroll_getBlockTraceByNumberOrHash",
+            "params": [format!("{number:#x}")],
+        });
+        let response: serde_json::Value = self.runtime.block_on(async {
+            self.client
+                .post(&self.url)
+                .json(&request)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+        })?;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("trace of block {} failed: {}", number, error));
+        }
+        if response["result"].is_null() {
+            return Err(anyhow!("no trace of block {}", number));
+        }
+        Ok(serde_json::from_value(response["result"].clone())?)
+    }
+}
+
+/// Fetches the proofs missing from the storage traces with the `eth_getProof` of
+/// the l2geth node at `TRIE_PROOF_RPC_URL`, which serves zktrie proofs.
+#[derive(Debug)]
+struct RpcTrieProofSource {
+    url: String,
+    client: reqwest::Client,
+    // the traces are prepared on a blocking thread of the runtime
+    runtime: tokio::runtime::Handle,
+}
+
+impl RpcTrieProofSource {
+    fn from_env() -> Option<Self> {
+        let url = std::env::var("TRIE_PROOF_RPC_URL").ok()?;
+        log::info!("service: fetching missing trie proofs from {}", url);
+        Some(Self {
+            url,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()
+                .expect("failed to build trie proof client"),
+            runtime: tokio::runtime::Handle::current(),
+        })
+    }
+}
+
+impl TrieProofSource for RpcTrieProofSource {
+    fn get_proof(
+        &self,
+        address: zkevm::trie_repair::Address,
+        keys: &[zkevm::trie_repair::Word],
+        block_number: u64,
+    ) -> anyhow::Result<AccountTrieProof> {
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                let mut bytes = [0u8; 32];
+                key.to_big_endian(&mut bytes);
+                format!("0x{}", hex::encode(bytes))
+            })
+            .collect();
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_getProof",
+            "params": [address, keys, format!("{block_number:#x}")],
+        });
+        let response: serde_json::Value = self.runtime.block_on(async {
+            self.client
+                .post(&self.url)
+                .json(&request)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+        })?;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("eth_getProof failed: {}", error));
+        }
+        let result = &response["result"];
+        let nodes = |value: &serde_json::Value| -> anyhow::Result<Vec<Vec<u8>>> {
+            value
+                .as_array()
+                .ok_or_else(|| anyhow!("expected a list of nodes, got {}", value))?
+                .iter()
+                .map(|node| {
+                    let node = node.as_str().unwrap_or_default();
+                    Ok(hex::decode(node.trim_start_matches("0x"))?)
+                })
+                .collect()
+        };
+        let mut proof = AccountTrieProof {
+            account_proof: nodes(&result["accountProof"])?,
+            storage_proofs: vec![],
+        };
+        for storage in result["storageProof"].as_array().into_iter().flatten() {
+            let key = storage["key"].as_str().unwrap_or_default();
+            let key = zkevm::trie_repair::Word::from_str_radix(key.trim_start_matches("0x"), 16)?;
+            proof.storage_proofs.push((key, nodes(&storage["proof"])?));
+        }
+        Ok(proof)
+    }
+}
+
+/// The API of a coordinator, for a worker. With `COORDINATOR_API_KEY` set, it is
+/// sent as `X-Api-Key`.
+struct HttpCoordinator {
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+    // the worker runs on a blocking thread of the runtime
+    runtime: tokio::runtime::Handle,
+}
+
+impl HttpCoordinator {
+    fn new(url: String) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            api_key: std::env::var("COORDINATOR_API_KEY").ok(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(300))
+                .build()
+                .expect("failed to build coordinator client"),
+            runtime: tokio::runtime::Handle::current(),
+        }
+    }
+
+    fn post(&self, path: &str, worker: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .post(format!("{}{}", self.url, path))
+            .query(&[("worker", worker)]);
+        match &self.api_key {
+            Some(key) => request.header("x-api-key", key),
+            None => request,
+        }
+    }
+}
+
+impl Coordinator for HttpCoordinator {
+    fn lease(&self, worker: &str) -> anyhow::Result<Option<WitnessLease>> {
+        self.runtime.block_on(async {
+            let response = self
+                .post("/v1/worker/lease", worker)
+                .send()
+                .await?
+                .error_for_status()?;
+            if response.status() == reqwest::StatusCode::NO_CONTENT {
+                return Ok(None);
+            }
+            let id = response
+                .headers()
+                .get("x-job-id")
+                .and_then(|id| id.to_str().ok()?.parse().ok())
+                .ok_or_else(|| anyhow!("lease without x-job-id"))?;
+            let witness = response.bytes().await?.to_vec();
+            Ok(Some(WitnessLease { id, witness }))
+        })
+    }
+
+    fn complete(
+        &self,
+        id: JobId,
+        worker: &str,
+        result: Result<AggCircuitProof, String>,
+    ) -> anyhow::Result<()> {
+        self.runtime.block_on(async {
+            let request = match &result {
+                Ok(proof) => self
+                    .post(&format!("/v1/worker/jobs/{id}/proof"), worker)
+                    .json(proof),
+                Err(error) => self
+                    .post(&format!("/v1/worker/jobs/{id}/failure"), worker)
+                    .json(&WorkerFailure {
+                        error: error.clone(),
+                    }),
+            };
+            request.send().await?.error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+fn dispatch_error_response(e: DispatchError) -> Response<Body> {
+    match e {
+        DispatchError::NotDispatched { .. } => error_response(StatusCode::NOT_FOUND, e),
+        DispatchError::NotLeaseHolder { .. } => error_response(StatusCode::FORBIDDEN, e),
+        DispatchError::NotCoordinator | DispatchError::NoProver => {
+            error_response(StatusCode::CONFLICT, e)
+        }
+    }
+}
+
+fn header<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn auth_error_response(e: AuthError) -> Response<Body> {
+    match &e {
+        AuthError::Unauthorized(_) => error_response(StatusCode::UNAUTHORIZED, e),
+        AuthError::Forbidden { .. } => error_response(StatusCode::FORBIDDEN, e),
+        AuthError::RateLimited {
+            retry_after_secs, ..
+        } => {
+            let mut response = error_response(StatusCode::TOO_MANY_REQUESTS, &e);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(*retry_after_secs));
+            response
+        }
+    }
+}
+
+/// A request body above `--max-body-mb`.
+#[derive(Debug)]
+struct BodyTooLarge {
+    limit: u64,
+}
+
+impl std::fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request body above {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+/// The body of the request, refused once above `limit` bytes, by its
+/// `content-length` before reading any of it if set.
+async fn read_body(req: Request<Body>, limit: u64) -> anyhow::Result<Vec<u8>> {
+    let content_length = header(&req, CONTENT_LENGTH.as_str()).and_then(|len| len.parse().ok());
+    if content_length.map_or(false, |len: u64| len > limit) {
+        return Err(BodyTooLarge { limit }.into());
+    }
+    let mut body = req.into_body();
+    let mut buf = Vec::with_capacity(content_length.unwrap_or_default() as usize);
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if (buf.len() + chunk.len()) as u64 > limit {
+            return Err(BodyTooLarge { limit }.into());
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf)
+}
+
+async fn read_json<T: serde::de::DeserializeOwned>(
+    req: Request<Body>,
+    limit: u64,
+) -> anyhow::Result<T> {
+    let body = read_body(req, limit).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Traces of any schema version, see `types::migrate`.
+async fn read_traces(req: Request<Body>, limit: u64) -> anyhow::Result<Vec<BlockTrace>> {
+    let body = read_body(req, limit).await?;
+    Ok(traces_from_slice(&body)?)
+}
+
+/// 413 for a body too large, 400 for any other bad request.
+fn body_error_response(e: anyhow::Error) -> Response<Body> {
+    if e.is::<BodyTooLarge>() {
+        error_response(StatusCode::PAYLOAD_TOO_LARGE, e)
+    } else {
+        error_response(StatusCode::BAD_REQUEST, e)
+    }
+}
+
+fn json_response<T: serde::Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap()))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, e: impl std::fmt::Display) -> Response<Body> {
+    json_response(status, &serde_json::json!({ "error": e.to_string() }))
+}
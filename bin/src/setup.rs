@@ -1,7 +1,7 @@
 use clap::Parser;
 use zkevm::{
     circuit::DEGREE,
-    utils::{load_or_create_params, load_or_create_seed},
+    utils::{compress_params, encrypt_seed, load_or_create_params, load_or_create_seed, SeedKey},
 };
 
 #[derive(Parser, Debug)]
@@ -13,6 +13,13 @@ struct Args {
     /// generate seed and write into file
     #[clap(short, long = "seed")]
     seed_path: Option<String>,
+    /// Rewrite the params with compressed points, half the size.
+    #[clap(long = "compress", requires = "params-path")]
+    compress: bool,
+    /// Encrypt a plaintext seed in place, with `SEED_PASSPHRASE` or `SEED_KEY_FILE`.
+    /// The plaintext bytes are left on the disk, see `encrypt_seed`.
+    #[clap(long = "encrypt-seed", requires = "seed-path")]
+    encrypt_seed: bool,
 }
 
 fn main() {
@@ -22,8 +29,19 @@ fn main() {
     let args = Args::parse();
     if let Some(path) = args.params_path {
         load_or_create_params(&path, *DEGREE).expect("failed to load or create params");
+        if args.compress && compress_params(&path, *DEGREE).expect("failed to compress params") {
+            log::info!("params of degree {} compressed", *DEGREE);
+        }
     }
     if let Some(path) = args.seed_path {
         load_or_create_seed(&path).expect("failed to load or create seed");
+        if args.encrypt_seed {
+            let key = SeedKey::from_env()
+                .expect("failed to read the seed key")
+                .expect("SEED_PASSPHRASE or SEED_KEY_FILE is needed to encrypt the seed");
+            if encrypt_seed(&path, &key).expect("failed to encrypt seed") {
+                log::info!("seed {} encrypted", path);
+            }
+        }
     }
 }
@@ -0,0 +1,45 @@
+//! Profile the host and write the prover settings tuned to it, loaded by the
+//! provers of the host by default, see `zkevm::tune`.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::path::PathBuf;
+use zkevm::tune::{profile_host, recommend, TuneReport, TUNE_FILE};
+use zkevm::utils::estimate_proving_memory;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Dir the disk is profiled in, the one of the params and pks.
+    #[clap(long = "dir", default_value = ".")]
+    dir: PathBuf,
+    /// File the report is written into, `TUNE_FILE` if set, else `./tune.json`.
+    #[clap(long = "output")]
+    output: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    let args = Args::parse();
+    let host = profile_host(&args.dir)
+        .with_context(|| format!("failed to profile the host in {}", args.dir.display()))?;
+    // the memory of a job of an empty batch, the floor of the circuits
+    let settings = recommend(&host, estimate_proving_memory(&[]));
+    let report = TuneReport::new(host, settings);
+    let output = args
+        .output
+        .or_else(|| {
+            Some(TUNE_FILE.as_str())
+                .filter(|f| !f.is_empty())
+                .map(PathBuf::from)
+        })
+        .unwrap_or_else(|| PathBuf::from("./tune.json"));
+    report
+        .write(&output)
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    println!("{}", serde_json::to_string_pretty(&report.settings)?);
+    log::info!("tune report written into {}", output.display());
+    Ok(())
+}
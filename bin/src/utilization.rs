@@ -0,0 +1,44 @@
+use clap::Parser;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use zkevm::corpus::{analyze_dir, write_csv};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Dir of block traces, searched recursively for `.json` files.
+    #[clap(long = "traces")]
+    traces_dir: PathBuf,
+    /// Write the CSV into the file instead of stdout.
+    #[clap(long = "output")]
+    output: Option<PathBuf>,
+    /// Number of traces processed in parallel, the number of cpus by default.
+    /// Witness generation of a large block takes GBs of memory.
+    #[clap(long = "jobs")]
+    jobs: Option<usize>,
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    let args = Args::parse();
+    if let Some(jobs) = args.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .expect("failed to set up the thread pool");
+    }
+    let blocks = analyze_dir(&args.traces_dir).expect("failed to read traces dir");
+    let failed = blocks.iter().filter(|b| b.error.is_some()).count();
+    match args.output {
+        Some(path) => write_csv(
+            BufWriter::new(File::create(path).expect("failed to create output")),
+            &blocks,
+        ),
+        None => write_csv(std::io::stdout().lock(), &blocks),
+    }
+    .expect("failed to write csv");
+    log::info!("analyzed {} blocks, {} failed", blocks.len(), failed);
+}
@@ -1,9 +1,14 @@
 use clap::Parser;
 use log::info;
+use rayon::prelude::*;
+use serde_derive::Serialize;
 use std::fs::File;
 use std::io::Read;
-use zkevm::prover::{AggCircuitProof, TargetCircuitProof};
-use zkevm::verifier::Verifier;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use zkevm::prover::{AggCircuitProof, AggConfig, TargetCircuitProof};
+use zkevm::verifier::{Verifier, VkRegistry};
 use zkevm::{
     circuit::{SuperCircuit, AGG_DEGREE, DEGREE},
     utils::load_or_create_params,
@@ -24,6 +29,36 @@ struct Args {
     /// the path of agg circuit proof to verify.
     #[clap(long = "agg")]
     agg_proof: Option<String>,
+    /// Dir of agg proofs to verify: proof dirs holding a `full_proof.data`, or
+    /// proof json files.
+    #[clap(long = "dir")]
+    proof_dir: Option<PathBuf>,
+    /// Proofs of `--dir` verified concurrently, the number of cores if 0.
+    #[clap(long = "jobs", default_value = "0")]
+    jobs: usize,
+    /// Write the results of `--dir` as json into the file.
+    #[clap(long = "report")]
+    report_path: Option<PathBuf>,
+    /// Dir of the archived vks, `VK_REGISTRY` by default: agg proofs of other
+    /// circuit versions are verified with the vk of their version.
+    #[clap(long = "vk-registry")]
+    vk_registry: Option<PathBuf>,
+    /// Archive `--vk` into the registry as the vk of the current circuit version,
+    /// with the agg params if `--archive-params`.
+    #[clap(long = "archive", requires = "vk-registry")]
+    archive: bool,
+    #[clap(long = "archive-params", requires = "archive")]
+    archive_params: bool,
+}
+
+/// Result of a proof of `--dir`.
+#[derive(Serialize)]
+struct ProofReport {
+    path: PathBuf,
+    verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    millis: u128,
 }
 
 fn main() {
@@ -37,7 +72,22 @@ fn main() {
         .expect("failed to load or create params");
     let agg_vk = read_from_file(&args.vk_path.unwrap());
 
-    let mut v = Verifier::from_params(params, agg_params, Some(agg_vk));
+    let mut v =
+        Verifier::from_params(params, agg_params, Some(agg_vk)).expect("failed to init verifier");
+    if let Some(dir) = &args.vk_registry {
+        let registry = VkRegistry::open(dir).expect("failed to open vk registry");
+        v.set_vk_registry(Some(Arc::new(registry)));
+    }
+    if args.archive {
+        let agg_config = AggConfig::from_env().ok();
+        let entry = v
+            .archive_agg_vk(v.vk_registry().unwrap(), agg_config, args.archive_params)
+            .expect("failed to archive vk");
+        info!(
+            "vk {} archived as circuit version {}",
+            entry.vk_digest, entry.circuit_version
+        );
+    }
     if let Some(path) = args.super_proof {
         let proof_vec = read_from_file(&path);
         let proof = serde_json::from_slice::<TargetCircuitProof>(proof_vec.as_slice()).unwrap();
@@ -49,9 +99,96 @@ fn main() {
     if let Some(path) = args.agg_proof {
         let proof_vec = read_from_file(&path);
         let proof = serde_json::from_slice::<AggCircuitProof>(proof_vec.as_slice()).unwrap();
-        let verified = v.verify_agg_circuit_proof(proof).is_ok();
+        let verified = verify_agg(&v, proof).is_ok();
         info!("verify agg proof: {}", verified)
     }
+    if let Some(dir) = args.proof_dir {
+        let reports = verify_dir(&v, &dir, args.jobs);
+        let failed = reports.iter().filter(|r| !r.verified).count();
+        let total_millis: u128 = reports.iter().map(|r| r.millis).sum();
+        for report in &reports {
+            match &report.error {
+                Some(e) => info!(
+                    "{}: failed in {}ms, {}",
+                    report.path.display(),
+                    report.millis,
+                    e
+                ),
+                None => info!("{}: verified in {}ms", report.path.display(), report.millis),
+            }
+        }
+        info!(
+            "{} proofs of {}: {} verified, {} failed, {}ms of verification",
+            reports.len(),
+            dir.display(),
+            reports.len() - failed,
+            failed,
+            total_millis
+        );
+        if let Some(path) = args.report_path {
+            let f = File::create(&path).expect("failed to create report");
+            serde_json::to_writer_pretty(f, &reports).expect("failed to write report");
+        }
+        if failed != 0 {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Verify the agg proof with the vk of its circuit version if there is a registry.
+fn verify_agg(v: &Verifier, proof: AggCircuitProof) -> zkevm::error::Result<bool> {
+    if v.vk_registry().is_some() && !proof.circuit_version.is_unknown() {
+        v.verify_with_version(&proof, &proof.circuit_version)
+    } else {
+        v.verify_agg_circuit_proof(proof)
+    }
+}
+
+/// Verify the proofs of the dir on `jobs` threads, in the order of their paths.
+fn verify_dir(v: &Verifier, dir: &Path, jobs: usize) -> Vec<ProofReport> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .expect("failed to read proof dir")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| {
+            if path.is_dir() {
+                let proof = path.join("full_proof.data");
+                proof.exists().then_some(proof)
+            } else {
+                (path.extension().map_or(false, |ext| ext == "json")).then_some(path)
+            }
+        })
+        .collect();
+    paths.sort();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .expect("failed to build verification pool");
+    pool.install(|| {
+        paths
+            .into_par_iter()
+            .map(|path| {
+                let start = Instant::now();
+                let result = std::fs::read(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|buf| {
+                        serde_json::from_slice::<AggCircuitProof>(&buf).map_err(|e| e.to_string())
+                    })
+                    .and_then(|proof| verify_agg(v, proof).map_err(|e| e.to_string()));
+                let error = match result {
+                    Ok(true) => None,
+                    Ok(false) => Some("invalid proof".to_string()),
+                    Err(e) => Some(e),
+                };
+                ProofReport {
+                    path,
+                    verified: error.is_none(),
+                    error,
+                    millis: start.elapsed().as_millis(),
+                }
+            })
+            .collect()
+    })
 }
 
 fn read_from_file(path: &str) -> Vec<u8> {
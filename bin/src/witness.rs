@@ -0,0 +1,102 @@
+//! Generate the witness of the super circuit apart from proving it, and prove it
+//! later, e.g. on another host.
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+use zkevm::circuit::{SuperCircuit, AGG_DEGREE, DEGREE};
+use zkevm::prover::{derive_rng, Prover, WitnessArtifact};
+use zkevm::utils::{discover_block_traces, load_or_create_params, load_or_create_seed};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the capacity check and witness generation over the traces, and write
+    /// the witness artifact. Needs no params.
+    Generate {
+        /// Block trace file, dir (searched recursively) or quoted glob pattern of
+        /// the traces of a batch, in block order.
+        #[clap(long = "trace")]
+        trace_path: String,
+        /// Witness artifact written, in the binary witness format.
+        #[clap(long = "output")]
+        output: PathBuf,
+    },
+    /// Prove the super circuit over a witness artifact.
+    Prove {
+        #[clap(short, long = "params")]
+        params_path: String,
+        #[clap(long = "seed")]
+        seed_path: String,
+        #[clap(long = "witness")]
+        witness: PathBuf,
+        /// Super circuit proof written, as JSON.
+        #[clap(long = "output")]
+        output: PathBuf,
+        /// Also aggregate the proof and write the agg proof into the dir.
+        #[clap(long = "agg")]
+        agg_dir: Option<PathBuf>,
+    },
+}
+
+fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    match Args::parse().command {
+        Command::Generate { trace_path, output } => {
+            let traces: Vec<_> = discover_block_traces(&trace_path)?
+                .into_iter()
+                .map(|(_, trace)| trace)
+                .collect();
+
+            let now = Instant::now();
+            let artifact = WitnessArtifact::generate::<SuperCircuit>(&traces)?;
+            let digest = artifact.write(&output)?;
+            log::info!(
+                "witness of {} of the {} blocks written to {:?}, sha256 {}, elapsed: {:?}",
+                artifact.block_traces.len(),
+                artifact.total_num_of_blocks,
+                output,
+                digest,
+                now.elapsed()
+            );
+        }
+        Command::Prove {
+            params_path,
+            seed_path,
+            witness,
+            output,
+            agg_dir,
+        } => {
+            let artifact = WitnessArtifact::read(&witness)?;
+            let params = load_or_create_params(&params_path, *DEGREE)?;
+            let agg_params = load_or_create_params(&params_path, *AGG_DEGREE)?;
+            let seed = load_or_create_seed(&seed_path)?;
+            let mut prover = Prover::from_params_and_seed(params, agg_params, seed);
+            let mut rng = derive_rng(&mut prover.rng);
+
+            let now = Instant::now();
+            let proof = prover.prove_from_witness::<SuperCircuit>(&artifact, &mut rng)?;
+            log::info!("super circuit proved, elapsed: {:?}", now.elapsed());
+            fs::write(&output, serde_json::to_vec(&proof)?)?;
+
+            if let Some(mut agg_dir) = agg_dir {
+                let now = Instant::now();
+                let agg_proof = prover.create_agg_circuit_proof_impl(&[proof], &mut rng)?;
+                log::info!("agg circuit proved, elapsed: {:?}", now.elapsed());
+                fs::create_dir_all(&agg_dir)?;
+                agg_proof.write_to_dir(&mut agg_dir);
+            }
+        }
+    }
+    Ok(())
+}
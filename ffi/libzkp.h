@@ -0,0 +1,15 @@
+// C interface of libzkp, see ffi/src for the implementation.
+// All strings are NUL-terminated; JSON payloads use the serde format of the
+// zkevm crate. Strings returned by the library must be released with
+// `free_c_chars`.
+
+void init_prover(const char* params_path, const char* seed_path);
+const char* create_agg_proof(const char* trace);
+const char* create_agg_proof_multi(const char* traces);
+// Returns NULL on failure.
+const char* prove_block_traces(const char* traces);
+
+void init_verifier(const char* params_path, const char* agg_vk_path);
+char verify_agg_proof(const char* proof);
+
+void free_c_chars(char* ptr);
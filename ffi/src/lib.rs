@@ -1,8 +1,24 @@
 #![feature(once_cell)]
 
+use std::ffi::CString;
+use std::os::raw::c_char;
+
 pub mod prove;
+#[cfg(feature = "pyo3")]
+pub mod python;
 pub mod verify;
 
+/// Release a string returned by this library.
+///
+/// # Safety
+#[no_mangle]
+pub unsafe extern "C" fn free_c_chars(ptr: *mut c_char) {
+    if ptr.is_null() {
+        return;
+    }
+    drop(CString::from_raw(ptr));
+}
+
 pub(crate) mod utils {
     use std::ffi::{CStr, CString};
     use std::os::raw::c_char;
@@ -2,7 +2,7 @@ use crate::utils::{c_char_to_str, c_char_to_vec, vec_to_c_char};
 use libc::c_char;
 use rand::rngs::OsRng;
 use std::cell::OnceCell;
-use types::eth::BlockTrace;
+use types::migrate::{trace_from_slice, traces_from_slice};
 use zkevm::prover::Prover;
 
 static mut PROVER: OnceCell<Prover> = OnceCell::new();
@@ -22,7 +22,7 @@ pub unsafe extern "C" fn init_prover(params_path: *const c_char, seed_path: *con
 #[no_mangle]
 pub unsafe extern "C" fn create_agg_proof(trace_char: *const c_char) -> *const c_char {
     let trace_vec = c_char_to_vec(trace_char);
-    let trace = serde_json::from_slice::<BlockTrace>(&trace_vec).unwrap();
+    let trace = trace_from_slice(&trace_vec).unwrap();
     let proof = PROVER
         .get_mut()
         .unwrap()
@@ -36,7 +36,7 @@ pub unsafe extern "C" fn create_agg_proof(trace_char: *const c_char) -> *const c
 #[no_mangle]
 pub unsafe extern "C" fn create_agg_proof_multi(trace_char: *const c_char) -> *const c_char {
     let trace_vec = c_char_to_vec(trace_char);
-    let traces = serde_json::from_slice::<Vec<BlockTrace>>(&trace_vec).unwrap();
+    let traces = traces_from_slice(&trace_vec).unwrap();
     let proof = PROVER
         .get_mut()
         .unwrap()
@@ -45,3 +45,33 @@ pub unsafe extern "C" fn create_agg_proof_multi(trace_char: *const c_char) -> *c
     let proof_bytes = serde_json::to_vec(&proof).unwrap();
     vec_to_c_char(proof_bytes)
 }
+
+/// Prove a JSON array of block traces, returns the agg proof as JSON.
+/// Returns a null pointer if the traces cannot be parsed or proved.
+/// The returned string must be released with `free_c_chars`.
+///
+/// # Safety
+#[no_mangle]
+pub unsafe extern "C" fn prove_block_traces(traces_char: *const c_char) -> *const c_char {
+    let traces_vec = c_char_to_vec(traces_char);
+    let traces = match traces_from_slice(&traces_vec) {
+        Ok(traces) => traces,
+        Err(e) => {
+            log::error!("failed to parse block traces: {:?}", e);
+            return std::ptr::null();
+        }
+    };
+    let proof = match PROVER
+        .get_mut()
+        .unwrap()
+        .create_agg_circuit_proof_batch(traces.as_slice(), &mut OsRng)
+    {
+        Ok(proof) => proof,
+        Err(e) => {
+            log::error!("failed to prove block traces: {:?}", e);
+            return std::ptr::null();
+        }
+    };
+    let proof_bytes = serde_json::to_vec(&proof).unwrap();
+    vec_to_c_char(proof_bytes)
+}
@@ -0,0 +1,127 @@
+//! Python bindings of the prover and verifier, enabled by the `pyo3` feature.
+//!
+//! Block traces and proofs are passed around as JSON strings, in the same
+//! format as the C interface.
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use rand::rngs::OsRng;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use types::eth::BlockTrace;
+use types::migrate::trace_from_slice;
+use zkevm::circuit::{calculate_row_usage_of_trace, check_batch_capacity, SUB_CIRCUIT_NAMES};
+use zkevm::prover::{AggCircuitProof, Prover};
+use zkevm::utils::get_block_trace_from_file;
+use zkevm::verifier::Verifier;
+
+fn to_py_err<E: std::fmt::Debug>(e: E) -> PyErr {
+    PyRuntimeError::new_err(format!("{e:?}"))
+}
+
+fn parse_traces(traces: &[String]) -> PyResult<Vec<BlockTrace>> {
+    traces
+        .iter()
+        .map(|t| trace_from_slice(t.as_bytes()).map_err(to_py_err))
+        .collect()
+}
+
+#[pyclass(name = "Prover", unsendable)]
+struct PyProver {
+    inner: Prover,
+}
+
+#[pymethods]
+impl PyProver {
+    #[new]
+    fn new(params_path: &str, seed_path: &str) -> Self {
+        Self {
+            inner: Prover::from_fpath(params_path, seed_path),
+        }
+    }
+
+    /// Prove a list of block traces (JSON strings), returns the agg proof as JSON.
+    fn prove_block_traces(&mut self, traces: Vec<String>) -> PyResult<String> {
+        let traces = parse_traces(&traces)?;
+        let proof = self
+            .inner
+            .create_agg_circuit_proof_batch(&traces, &mut OsRng)
+            .map_err(to_py_err)?;
+        serde_json::to_string(&proof).map_err(to_py_err)
+    }
+
+    /// Mock prove a list of block traces (JSON strings) with the super circuit.
+    #[staticmethod]
+    fn mock_prove(traces: Vec<String>) -> PyResult<()> {
+        let traces = parse_traces(&traces)?;
+        Prover::mock_prove_target_circuit_batch::<zkevm::circuit::SuperCircuit>(&traces)
+            .map_err(to_py_err)
+    }
+}
+
+#[pyclass(name = "Verifier", unsendable)]
+struct PyVerifier {
+    inner: Verifier,
+}
+
+#[pymethods]
+impl PyVerifier {
+    #[new]
+    fn new(params_path: &str, agg_vk_path: &str) -> PyResult<Self> {
+        let mut agg_vk = vec![];
+        File::open(agg_vk_path)
+            .and_then(|mut f| f.read_to_end(&mut agg_vk))
+            .map_err(to_py_err)?;
+        Ok(Self {
+            inner: Verifier::from_fpath(params_path, Some(agg_vk)).map_err(to_py_err)?,
+        })
+    }
+
+    /// Verify an agg proof given as JSON.
+    fn verify_agg_proof(&self, proof: &str) -> PyResult<bool> {
+        let proof = serde_json::from_str::<AggCircuitProof>(proof).map_err(to_py_err)?;
+        self.inner
+            .verify_agg_circuit_proof(proof)
+            .map_err(to_py_err)
+    }
+}
+
+/// Load a block trace from file, returns it as JSON.
+#[pyfunction]
+fn load_trace(path: &str) -> PyResult<String> {
+    let trace = get_block_trace_from_file(path);
+    serde_json::to_string(&trace).map_err(to_py_err)
+}
+
+/// Rows needed by each sub circuit to prove the block trace.
+#[pyfunction]
+fn row_usage(trace: &str) -> PyResult<HashMap<String, usize>> {
+    let trace = trace_from_slice(trace.as_bytes()).map_err(to_py_err)?;
+    let rows = calculate_row_usage_of_trace(&trace).map_err(to_py_err)?;
+    Ok(SUB_CIRCUIT_NAMES
+        .iter()
+        .map(|name| name.to_string())
+        .zip(rows)
+        .collect())
+}
+
+/// Number of leading block traces that fit into one batch.
+#[pyfunction]
+fn check_capacity(traces: Vec<String>) -> PyResult<usize> {
+    let mut traces = parse_traces(&traces)?;
+    check_batch_capacity(&mut traces).map_err(to_py_err)?;
+    Ok(traces.len())
+}
+
+#[pymodule]
+fn zkp(_py: Python, m: &PyModule) -> PyResult<()> {
+    env_logger::try_init().ok();
+
+    m.add_class::<PyProver>()?;
+    m.add_class::<PyVerifier>()?;
+    m.add_function(wrap_pyfunction!(load_trace, m)?)?;
+    m.add_function(wrap_pyfunction!(row_usage, m)?)?;
+    m.add_function(wrap_pyfunction!(check_capacity, m)?)?;
+    Ok(())
+}
@@ -3,14 +3,20 @@ set -e
 
 # git_commit_id=`git rev-parse --short HEAD`
 
+# shared libs are .dylib on macOS, they are shipped as .so
+if [ "$(uname)" = "Darwin" ]; then DYLIB=dylib; else DYLIB=so; fi
+
 cargo build --release
-find target/release | grep libzktrie.so | xargs -i cp {} ./
+find target/release -name "libzktrie.${DYLIB}" | head -n 1 | xargs -I {} cp {} ./libzktrie.so
 cp target/release/libffi.a ./libzkp.a
+cp target/release/libffi.${DYLIB} ./libzkp.so
+cp ffi/libzkp.h ./libzkp.h
 
 shasum -a 256 libzkp.a > zkp.sha256
+shasum -a 256 libzkp.so >> zkp.sha256
 shasum -a 256 libzktrie.so > zktrie.sha256
 
-zip -r libs.zip libzktrie.so libzkp.a zkp.sha256 zktrie.sha256
+zip -r libs.zip libzktrie.so libzkp.a libzkp.so libzkp.h zkp.sha256 zktrie.sha256
 shasum -a 256 libs.zip > zip.sha256
 
-rm libzktrie.so libzkp.a zkp.sha256 zktrie.sha256
\ No newline at end of file
+rm libzktrie.so libzkp.a libzkp.so libzkp.h zkp.sha256 zktrie.sha256
\ No newline at end of file
@@ -23,6 +23,9 @@ pub struct BlockTrace {
     pub execution_results: Vec<ExecutionResult>,
     #[serde(rename = "storageTrace")]
     pub storage_trace: StorageTrace,
+    /// Root of the withdraw trie after the block, missing in older traces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub withdraw_trie_root: Option<H256>,
     //    #[serde(rename = "mptwitness", default)]
     //    pub mpt_witness: Vec<SMTTrace>,
 }
@@ -224,7 +227,8 @@ pub struct AccountProofWrapper {
     pub address: Option<Address>,
     pub nonce: Option<u64>,
     pub balance: Option<U256>,
-    #[serde(rename = "codeHash")]
+    /// Poseidon code hash, `codeHash` in `V1` traces, see `migrate`.
+    #[serde(rename = "poseidonCodeHash")]
     pub code_hash: Option<H256>,
     pub proof: Option<Vec<Bytes>>,
     pub storage: Option<StorageProofWrapper>,
@@ -0,0 +1,219 @@
+//! Parsing of block traces with a report of what doesn't match the schema.
+//!
+//! Sequencer upgrades tend to add fields, or to change the encoding of numbers,
+//! before the prover is updated. In the lenient mode unknown fields are ignored
+//! and numbers and bools encoded as strings are coerced, each reported as a
+//! warning. The strict mode, e.g. for CI, fails on any warning.
+
+use crate::eth::BlockTrace;
+use crate::migrate::{migrate_trace, MigrationError};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+impl FromStr for ParseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lenient" => Ok(Self::Lenient),
+            "strict" => Ok(Self::Strict),
+            _ => Err(format!("unknown trace parse mode {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceWarning {
+    /// A field not in the schema, by its path, e.g. `executionResults.0.foo`.
+    UnknownField(String),
+    /// A string turned into the number or bool expected at the path.
+    Coerced { path: String, from: String },
+}
+
+impl fmt::Display for TraceWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownField(path) => write!(f, "unknown field {path}"),
+            Self::Coerced { path, from } => write!(f, "coerced {path} from {from:?}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Migration(MigrationError),
+    /// The trace doesn't match the schema in the strict mode.
+    Strict(Vec<TraceWarning>),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Migration(e) => write!(f, "{e}"),
+            Self::Strict(warnings) => {
+                write!(f, "{} schema mismatches", warnings.len())?;
+                for warning in warnings {
+                    write!(f, "; {warning}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<MigrationError> for ParseError {
+    fn from(e: MigrationError) -> Self {
+        Self::Migration(e)
+    }
+}
+
+impl From<serde_json::Error> for ParseError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Migration(e.into())
+    }
+}
+
+/// Parse a trace of any version, see `migrate`, with the schema mismatches found.
+pub fn parse_trace(
+    buf: &[u8],
+    mode: ParseMode,
+) -> Result<(BlockTrace, Vec<TraceWarning>), ParseError> {
+    let (mut trace, _) = migrate_trace(serde_json::from_slice(buf)?)?;
+    let mut warnings = vec![];
+    coerce_trace(&mut trace, &mut warnings);
+    let trace: BlockTrace = serde_ignored::deserialize(trace, |path| {
+        warnings.push(TraceWarning::UnknownField(path.to_string()))
+    })?;
+    if mode == ParseMode::Strict && !warnings.is_empty() {
+        return Err(ParseError::Strict(warnings));
+    }
+    Ok((trace, warnings))
+}
+
+#[derive(Clone, Copy)]
+enum Expected {
+    Number,
+    Bool,
+}
+
+const TRACE_FIELDS: &[(&str, Expected)] = &[("chainID", Expected::Number)];
+const TX_FIELDS: &[(&str, Expected)] = &[
+    ("type", Expected::Number),
+    ("nonce", Expected::Number),
+    ("gas", Expected::Number),
+    ("isCreate", Expected::Bool),
+];
+const RESULT_FIELDS: &[(&str, Expected)] = &[
+    ("l1Fee", Expected::Number),
+    ("gas", Expected::Number),
+    ("failed", Expected::Bool),
+];
+const STEP_FIELDS: &[(&str, Expected)] = &[
+    ("pc", Expected::Number),
+    ("gas", Expected::Number),
+    ("gasCost", Expected::Number),
+    ("refund", Expected::Number),
+    ("depth", Expected::Number),
+];
+const ACCOUNT_FIELDS: &[(&str, Expected)] = &[("nonce", Expected::Number)];
+
+fn coerce_trace(trace: &mut Value, warnings: &mut Vec<TraceWarning>) {
+    let trace = match trace.as_object_mut() {
+        Some(trace) => trace,
+        None => return,
+    };
+    coerce_fields(trace, "", TRACE_FIELDS, warnings);
+    coerce_account(trace.get_mut("coinbase"), "coinbase", warnings);
+    for (i, tx) in array_mut(trace.get_mut("transactions")) {
+        coerce_fields(tx, &format!("transactions.{i}"), TX_FIELDS, warnings);
+    }
+    for (i, result) in array_mut(trace.get_mut("executionResults")) {
+        let path = format!("executionResults.{i}");
+        coerce_fields(result, &path, RESULT_FIELDS, warnings);
+        for key in ["from", "to", "accountCreated"] {
+            coerce_account(result.get_mut(key), &format!("{path}.{key}"), warnings);
+        }
+        for (j, account) in array_mut(result.get_mut("accountAfter")) {
+            coerce_fields(
+                account,
+                &format!("{path}.accountAfter.{j}"),
+                ACCOUNT_FIELDS,
+                warnings,
+            );
+        }
+        for (j, step) in array_mut(result.get_mut("structLogs")) {
+            coerce_fields(
+                step,
+                &format!("{path}.structLogs.{j}"),
+                STEP_FIELDS,
+                warnings,
+            );
+        }
+    }
+}
+
+fn coerce_account(account: Option<&mut Value>, path: &str, warnings: &mut Vec<TraceWarning>) {
+    if let Some(account) = account.and_then(Value::as_object_mut) {
+        coerce_fields(account, path, ACCOUNT_FIELDS, warnings);
+    }
+}
+
+fn coerce_fields(
+    object: &mut Map<String, Value>,
+    path: &str,
+    fields: &[(&str, Expected)],
+    warnings: &mut Vec<TraceWarning>,
+) {
+    for (key, expected) in fields {
+        let value = match object.get_mut(*key) {
+            Some(value) => value,
+            None => continue,
+        };
+        let s = match value {
+            Value::String(s) => s.clone(),
+            _ => continue,
+        };
+        let coerced = match expected {
+            Expected::Number => parse_number(&s).map(Value::from),
+            Expected::Bool => s.parse::<bool>().ok().map(Value::from),
+        };
+        // left as is if not coercible, to fail in the deserializer
+        if let Some(coerced) = coerced {
+            *value = coerced;
+            warnings.push(TraceWarning::Coerced {
+                path: if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{path}.{key}")
+                },
+                from: s,
+            });
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn array_mut(value: Option<&mut Value>) -> impl Iterator<Item = (usize, &mut Map<String, Value>)> {
+    value
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter_map(|(i, value)| value.as_object_mut().map(|object| (i, object)))
+}
@@ -1,4 +1,6 @@
 pub mod eth;
+pub mod lenient;
+pub mod migrate;
 
 pub mod base64 {
     use base64::{decode, encode};
@@ -19,3 +21,23 @@ pub mod base64 {
         decode(s.as_bytes()).map_err(serde::de::Error::custom)
     }
 }
+
+pub mod hex {
+    use hex::{decode, encode};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(data: &[u8], s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        String::serialize(&format!("0x{}", encode(data)), s)
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        decode(s.trim_start_matches("0x")).map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,155 @@
+//! Upgrades of block traces emitted by older l2geth releases to the schema of
+//! `BlockTrace`.
+//!
+//! - `V1`: traces without a `version`. Accounts and execution results carry the
+//!   poseidon code hash as `codeHash`, and an `mptwitness` is attached.
+//! - `V2`: traces with a `version`, the code hashes are `poseidonCodeHash` and
+//!   `keccakCodeHash`. The current schema.
+//!
+//! Migrations work on the JSON value, one version at a time, so that a trace of
+//! any version is upgraded by running the migrations from its version on.
+
+use crate::eth::BlockTrace;
+use serde_json::{Map, Value};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceVersion {
+    V1,
+    V2,
+}
+
+impl TraceVersion {
+    pub const CURRENT: TraceVersion = TraceVersion::V2;
+
+    /// Guess the version of a bare trace from its fields.
+    pub fn detect(trace: &Value) -> Self {
+        let has_legacy_code_hash =
+            |account: Option<&Value>| account.map_or(false, |a| a.get("codeHash").is_some());
+        let legacy = trace.get("version").is_none()
+            && (has_legacy_code_hash(trace.get("coinbase"))
+                || execution_results(trace).any(|result| {
+                    result.get("codeHash").is_some()
+                        || has_legacy_code_hash(result.get("from"))
+                        || has_legacy_code_hash(result.get("to"))
+                }));
+        if legacy {
+            Self::V1
+        } else {
+            Self::V2
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    NotATrace(String),
+    Serde(serde_json::Error),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotATrace(reason) => write!(f, "not a block trace: {reason}"),
+            Self::Serde(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<serde_json::Error> for MigrationError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Serde(e)
+    }
+}
+
+/// Upgrade a trace, bare or in a JSON-RPC response, to the current schema.
+/// Returns the bare trace and the version it was found in.
+pub fn migrate_trace(trace: Value) -> Result<(Value, TraceVersion), MigrationError> {
+    let mut trace = match trace {
+        Value::Object(mut response) if response.contains_key("result") => {
+            response.remove("result").unwrap()
+        }
+        trace => trace,
+    };
+    if !trace.is_object() {
+        return Err(MigrationError::NotATrace(
+            "expect a JSON object".to_string(),
+        ));
+    }
+
+    let version = TraceVersion::detect(&trace);
+    if version < TraceVersion::V2 {
+        migrate_v1_to_v2(&mut trace);
+    }
+    Ok((trace, version))
+}
+
+/// Deserialize a trace of any version.
+pub fn trace_from_slice(buf: &[u8]) -> Result<BlockTrace, MigrationError> {
+    let (trace, _) = migrate_trace(serde_json::from_slice(buf)?)?;
+    Ok(serde_json::from_value(trace)?)
+}
+
+/// Deserialize a JSON array of traces of any versions.
+pub fn traces_from_slice(buf: &[u8]) -> Result<Vec<BlockTrace>, MigrationError> {
+    match serde_json::from_slice(buf)? {
+        Value::Array(traces) => traces
+            .into_iter()
+            .map(|trace| Ok(serde_json::from_value(migrate_trace(trace)?.0)?))
+            .collect(),
+        _ => Err(MigrationError::NotATrace("expect a JSON array".to_string())),
+    }
+}
+
+fn migrate_v1_to_v2(trace: &mut Value) {
+    let trace = trace.as_object_mut().unwrap();
+    trace.remove("mptwitness");
+    if let Some(coinbase) = trace.get_mut("coinbase") {
+        rename_code_hash(coinbase);
+    }
+    if let Some(Value::Array(results)) = trace.get_mut("executionResults") {
+        for result in results.iter_mut().filter_map(Value::as_object_mut) {
+            rename(result, "codeHash", "poseidonCodeHash");
+            for key in ["from", "to", "accountCreated"] {
+                if let Some(account) = result.get_mut(key) {
+                    rename_code_hash(account);
+                }
+            }
+            if let Some(Value::Array(accounts)) = result.get_mut("accountAfter") {
+                accounts.iter_mut().for_each(rename_code_hash);
+            }
+            if let Some(Value::Array(steps)) = result.get_mut("structLogs") {
+                for step in steps {
+                    if let Some(Value::Array(proofs)) = step
+                        .get_mut("extraData")
+                        .and_then(|data| data.get_mut("proofList"))
+                    {
+                        proofs.iter_mut().for_each(rename_code_hash);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn rename_code_hash(account: &mut Value) {
+    if let Some(account) = account.as_object_mut() {
+        rename(account, "codeHash", "poseidonCodeHash");
+    }
+}
+
+fn rename(object: &mut Map<String, Value>, from: &str, to: &str) {
+    if let Some(value) = object.remove(from) {
+        object.entry(to).or_insert(value);
+    }
+}
+
+fn execution_results(trace: &Value) -> impl Iterator<Item = &Value> {
+    trace
+        .get("executionResults")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
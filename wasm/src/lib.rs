@@ -0,0 +1,67 @@
+//! WASM bindings for recomputing instances and verifying agg proofs client side.
+//!
+//! The decoding, the instance and the verification are the ones of zkevm, built
+//! with its `verify` feature alone, so that it can be compiled with
+//! `wasm-pack build wasm --target web`. Byte arguments are the raw artifacts
+//! written by the prover (see `AggCircuitProof::write_to_dir`), and results are
+//! returned as JSON strings of `0x`-prefixed big-endian field elements.
+
+use halo2_proofs::halo2curves::bn256::{Bn256, Fr};
+use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+use halo2_proofs::SerdeFormat;
+use wasm_bindgen::prelude::*;
+use zkevm::aggregation::{read_vk, verify_native};
+use zkevm::chunk::ChunkInfo;
+use zkevm::instance::{decode_column, AggInstance};
+
+fn to_js_err<E: std::fmt::Display>(e: E) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+fn fr_to_hex(f: &Fr) -> String {
+    let mut bytes = f.to_bytes();
+    bytes.reverse();
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// Decode the serialized instance of an agg proof, or its calldata encoding, into
+/// a flat JSON array.
+#[wasm_bindgen]
+pub fn decode_instances(instance: &[u8]) -> Result<String, JsValue> {
+    let column = decode_column(instance).map_err(to_js_err)?;
+    Ok(serde_json::to_string(&column.iter().map(fr_to_hex).collect::<Vec<_>>()).unwrap())
+}
+
+/// Compute the instance of an agg proof of the chunks, a JSON array of
+/// `ChunkInfo`: the accumulator of `instance`, the instance of the proof checked,
+/// then the public input hash of each chunk and their chain id. Serialized as
+/// `instance`, to be verified with `verify_agg_proof`, so that a proof verified
+/// against it is a proof of the chunks.
+#[wasm_bindgen]
+pub fn agg_instance_from_chunks(instance: &[u8], chunks: &str) -> Result<Vec<u8>, JsValue> {
+    let column = decode_column(instance).map_err(to_js_err)?;
+    let accumulator = AggInstance::from_column(&column)
+        .map_err(to_js_err)?
+        .accumulator;
+    let chunks: Vec<ChunkInfo> = serde_json::from_str(chunks).map_err(to_js_err)?;
+    let instance = AggInstance::of_chunks(accumulator, &chunks).map_err(to_js_err)?;
+    Ok(instance.encode())
+}
+
+/// Natively verify an agg proof for the chain `chain_id`, the last instance.
+///
+/// `params` only needs to hold the verifier part of the KZG setup, so a params
+/// file truncated to a small degree is enough.
+#[wasm_bindgen]
+pub fn verify_agg_proof(
+    params: &[u8],
+    vk: &[u8],
+    proof: &[u8],
+    instance: &[u8],
+    chain_id: u64,
+) -> Result<bool, JsValue> {
+    let params = ParamsKZG::<Bn256>::read_custom(&mut &params[..], SerdeFormat::RawBytes)
+        .map_err(to_js_err)?;
+    let vk = read_vk(vk).map_err(to_js_err)?;
+    verify_native(&params, &vk, instance, proof, chain_id).map_err(to_js_err)
+}
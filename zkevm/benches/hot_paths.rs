@@ -0,0 +1,178 @@
+//! Benches of the hot paths of proving.
+//!
+//! `cargo bench -p zkevm --bench hot_paths`. MSM and FFT run at `DEGREE`, the
+//! keccak witness on the keccak inputs of the USDC bridge deposit trace, the
+//! aggregation setup only with `BENCH_AGG=true`, as it needs the params of
+//! `AGG_DEGREE` in `BENCH_PARAMS_DIR` (default `./test_params`).
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use halo2_proofs::arithmetic::{best_fft, best_multiexp};
+use halo2_proofs::circuit::Value;
+use halo2_proofs::halo2curves::bn256::{Fr, G1Affine, G1};
+use halo2_proofs::halo2curves::group::ff::{Field, PrimeField};
+use halo2_proofs::halo2curves::group::{prime::PrimeCurveAffine, Curve};
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use snark_verifier_sdk::gen_pk;
+use snark_verifier_sdk::halo2::aggregation::AggregationCircuit;
+use std::time::Duration;
+use types::eth::BlockTrace;
+use zkevm::circuit::{
+    block_traces_to_witness_block, calculate_row_usage_of_witness_block, SuperCircuit,
+    TargetCircuit, AGG_DEGREE, DEGREE,
+};
+use zkevm::io::{serialize_fr_tensor, serialize_instance};
+use zkevm::keccak::multi_keccak_parallel;
+use zkevm::prover::Prover;
+use zkevm::utils::{load_or_create_params, read_env_var};
+use zkevm_circuits::evm_circuit::EvmCircuit;
+use zkevm_circuits::keccak_circuit::keccak_packed_multi::multi_keccak;
+use zkevm_circuits::state_circuit::StateCircuit;
+use zkevm_circuits::util::{Challenges, SubCircuit};
+
+const TRACE_PATH: &str = "./tests/traces/erc20/multiple.json";
+/// A hash-heavy trace, of a bridge deposit of USDC.
+const KECCAK_TRACE_PATH: &str = "./tests/traces/bridge/depositUSDC.json";
+
+fn rng() -> XorShiftRng {
+    XorShiftRng::from_seed([0x5a; 16])
+}
+
+fn trace_deserialization(c: &mut Criterion) {
+    let buf = std::fs::read(TRACE_PATH).unwrap();
+    c.bench_function("trace deserialization", |b| {
+        b.iter(|| serde_json::from_slice::<BlockTrace>(&buf).unwrap())
+    });
+}
+
+fn witness_generation(c: &mut Criterion) {
+    let block_traces = vec![zkevm::utils::get_block_trace_from_file(TRACE_PATH)];
+    let witness_block = block_traces_to_witness_block(&block_traces).unwrap();
+
+    let mut group = c.benchmark_group("witness generation");
+    group.bench_function("witness block", |b| {
+        b.iter(|| block_traces_to_witness_block(&block_traces).unwrap())
+    });
+    group.bench_function("row usage", |b| {
+        b.iter(|| calculate_row_usage_of_witness_block(&witness_block).unwrap())
+    });
+    group.bench_function("evm", |b| {
+        b.iter(|| EvmCircuit::<Fr>::new_from_block(&witness_block))
+    });
+    group.bench_function("state", |b| {
+        b.iter(|| StateCircuit::<Fr>::new_from_block(&witness_block))
+    });
+    group.bench_function("super", |b| {
+        b.iter(|| SuperCircuit::from_witness_block(&witness_block).unwrap())
+    });
+    group.finish();
+}
+
+fn keccak_witness(c: &mut Criterion) {
+    let block_traces = vec![zkevm::utils::get_block_trace_from_file(KECCAK_TRACE_PATH)];
+    let inputs = block_traces_to_witness_block(&block_traces)
+        .unwrap()
+        .keccak_inputs;
+    let challenges = Challenges::mock(
+        Value::known(Fr::from(0x100)),
+        Value::known(Fr::from(0x100)),
+        Value::known(Fr::from(0x100)),
+    );
+
+    let mut group = c.benchmark_group(format!("keccak witness of {} inputs", inputs.len()));
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| multi_keccak(&inputs, challenges, None).unwrap())
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| multi_keccak_parallel(&inputs, challenges, None).unwrap())
+    });
+    group.finish();
+}
+
+fn msm_and_fft(c: &mut Criterion) {
+    let k = *DEGREE as u32;
+    let n = 1usize << k;
+    let mut rng = rng();
+    let scalars: Vec<Fr> = (0..n).map(|_| Fr::random(&mut rng)).collect();
+    // consecutive multiples of the generator, cheaper to set up than random points
+    let bases: Vec<G1Affine> = {
+        let g = G1Affine::generator();
+        let mut acc = G1::from(g);
+        let projective: Vec<G1> = (0..n)
+            .map(|_| {
+                acc = acc + g;
+                acc
+            })
+            .collect();
+        let mut affine = vec![G1Affine::identity(); n];
+        G1::batch_normalize(&projective, &mut affine);
+        affine
+    };
+    let omega = (k..Fr::S).fold(Fr::root_of_unity(), |omega, _| omega.square());
+
+    let mut group = c.benchmark_group(format!("k = {k}"));
+    group.sample_size(10);
+    group.bench_function("msm", |b| b.iter(|| best_multiexp(&scalars, &bases)));
+    group.bench_function("fft", |b| {
+        b.iter_batched(
+            || scalars.clone(),
+            |mut a| best_fft(&mut a, omega, k),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn instance_serialization(c: &mut Criterion) {
+    let mut rng = rng();
+    // the agg circuit instance: 12 accumulator limbs plus the inner public inputs
+    let instance: Vec<Vec<Fr>> = vec![(0..14).map(|_| Fr::random(&mut rng)).collect()];
+    let mut group = c.benchmark_group("instance serialization");
+    group.bench_function("inner", |b| b.iter(|| serialize_instance(&instance)));
+    group.bench_function("agg", |b| {
+        b.iter(|| {
+            let tensor = serialize_fr_tensor(&[instance.clone()]);
+            serde_json::to_vec(&tensor).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn aggregation_setup(c: &mut Criterion) {
+    if !read_env_var("BENCH_AGG", false) {
+        return;
+    }
+    let params_dir = read_env_var("BENCH_PARAMS_DIR", "./test_params".to_string());
+    let params = load_or_create_params(&params_dir, *DEGREE).unwrap();
+    let agg_params = load_or_create_params(&params_dir, *AGG_DEGREE).unwrap();
+    let mut prover = Prover::from_params_and_seed(params, agg_params, [0x5a; 16]);
+    let trace = zkevm::utils::get_block_trace_from_file("./tests/traces/empty.json");
+    let snark = prover
+        .create_target_circuit_proof::<SuperCircuit>(&trace, &mut rng())
+        .unwrap()
+        .snark;
+
+    let mut group = c.benchmark_group("aggregation setup");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(600));
+    group.bench_function("circuit", |b| {
+        b.iter(|| AggregationCircuit::new(&prover.agg_params, [snark.clone()], rng()))
+    });
+    let circuit = AggregationCircuit::new(&prover.agg_params, [snark.clone()], rng());
+    group.bench_function("pk", |b| {
+        b.iter(|| gen_pk(&prover.agg_params, &circuit, None))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    trace_deserialization,
+    witness_generation,
+    keccak_witness,
+    msm_and_fft,
+    instance_serialization,
+    aggregation_setup
+);
+criterion_main!(benches);
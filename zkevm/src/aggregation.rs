@@ -0,0 +1,182 @@
+//! The aggregation circuit verified by the EVM, with the chain id as its last
+//! public input.
+//!
+//! The chain id is only copied into the instance: the circuit doesn't constrain
+//! it, so a prover may put any chain id there and the proof doesn't bind it. It
+//! is the public input hashes of the chunks of the inner snarks, whose preimage
+//! has the chain id, which tie a proof to a chain. Constraining the chain id
+//! instance against them would take a keccak of the preimages in this circuit,
+//! so the verifier recomputes the hashes instead, as the rollup contract does:
+//! `Verifier::verify_agg_proof_of_chunks` verifies a proof of chunks of its own
+//! chain, `Verifier::check_instances_against_blocks` checks it against the blocks,
+//! and `AggInstance::of_chunks` gives the instance of the chunks.
+//!
+//! The circuit and the native verification of its proofs only need the `verify`
+//! feature, so that the wasm bindings verify agg proofs with the same code as
+//! `Verifier`.
+
+use crate::error::{KeygenError, Result, VerificationError};
+use crate::instance::{chain_id_of, decode_column, InstanceLayout};
+use crate::io::load_instances;
+use halo2_proofs::circuit::{Layouter, SimpleFloorPlanner, Value};
+use halo2_proofs::halo2curves::bn256::{Bn256, Fr, G1Affine};
+use halo2_proofs::plonk::{
+    verify_proof, Advice, Circuit, Column, ConstraintSystem, Error, Selector, VerifyingKey,
+};
+use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+use halo2_proofs::poly::kzg::multiopen::VerifierSHPLONK;
+use halo2_proofs::poly::kzg::strategy::AccumulatorStrategy;
+use halo2_proofs::poly::VerificationStrategy;
+use halo2_proofs::transcript::TranscriptReadBuffer;
+use halo2_proofs::SerdeFormat;
+use snark_verifier::system::halo2::transcript::evm::EvmTranscript;
+use snark_verifier_sdk::halo2::aggregation::{AggregationCircuit, AggregationConfig};
+use snark_verifier_sdk::CircuitExt;
+
+#[derive(Clone)]
+pub struct ChainBoundAggregationCircuit {
+    pub inner: AggregationCircuit,
+    pub chain_id: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ChainBoundAggregationConfig {
+    inner: AggregationConfig,
+    /// Copied into the instance, unconstrained otherwise.
+    chain_id: Column<Advice>,
+}
+
+impl ChainBoundAggregationCircuit {
+    pub fn new(inner: AggregationCircuit, chain_id: u64) -> Self {
+        Self { inner, chain_id }
+    }
+
+    /// Row of the chain id in the instance column, see `InstanceLayout::chain_id`.
+    fn chain_id_row(&self) -> usize {
+        self.inner.num_instance()[0]
+    }
+}
+
+impl Circuit<Fr> for ChainBoundAggregationCircuit {
+    type Config = ChainBoundAggregationConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            inner: self.inner.without_witnesses(),
+            chain_id: self.chain_id,
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let inner = AggregationCircuit::configure(meta);
+        let chain_id = meta.advice_column();
+        meta.enable_equality(chain_id);
+        ChainBoundAggregationConfig { inner, chain_id }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        self.inner
+            .synthesize(config.inner.clone(), layouter.namespace(|| "aggregation"))?;
+        let chain_id = layouter.assign_region(
+            || "chain id",
+            |mut region| {
+                region.assign_advice(
+                    || "chain id",
+                    config.chain_id,
+                    0,
+                    || Value::known(Fr::from(self.chain_id)),
+                )
+            },
+        )?;
+        layouter.constrain_instance(chain_id.cell(), config.inner.instance, self.chain_id_row())
+    }
+}
+
+impl CircuitExt<Fr> for ChainBoundAggregationCircuit {
+    fn num_instance(&self) -> Vec<usize> {
+        vec![self.chain_id_row() + 1]
+    }
+
+    fn instances(&self) -> Vec<Vec<Fr>> {
+        let mut instances = self.inner.instances();
+        instances[0].push(Fr::from(self.chain_id));
+        instances
+    }
+
+    fn accumulator_indices() -> Option<Vec<(usize, usize)>> {
+        AggregationCircuit::accumulator_indices()
+    }
+
+    fn selectors(config: &Self::Config) -> Vec<Selector> {
+        AggregationCircuit::selectors(&config.inner)
+    }
+}
+
+/// Read a vk of the aggregation circuit, in the format of `serialize_vk`.
+pub fn read_vk(raw_vk: &[u8]) -> Result<VerifyingKey<G1Affine>, KeygenError> {
+    VerifyingKey::<G1Affine>::read::<_, ChainBoundAggregationCircuit>(
+        &mut &raw_vk[..],
+        SerdeFormat::Processed,
+    )
+    .map_err(|e| KeygenError::InvalidVk {
+        circuit: "aggregation".to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// Verify an agg proof natively, `instance` being serialized as the instance of an
+/// `AggCircuitProof`, and check the chain id it claims is `chain_id`, which the
+/// proof doesn't bind, see the module doc. `params` only needs the verifier part
+/// of the setup, i.e. params of any degree of the same ceremony.
+pub fn verify_native(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    instance: &[u8],
+    proof: &[u8],
+    chain_id: u64,
+) -> Result<bool> {
+    // the instance is the one of the proof, only the chain id is the verifier's
+    let column = decode_column(instance)?;
+    let actual = InstanceLayout::of_column_len(column.len())
+        .and_then(|layout| chain_id_of(&column[layout.chain_id()]))
+        .map_err(|reason| VerificationError::Invalid {
+            what: "aggregation instance",
+            reason,
+        })?;
+    if actual != chain_id {
+        return Err(VerificationError::InstanceMismatch {
+            field: "chain id".to_string(),
+            expected: chain_id.to_string(),
+            actual: actual.to_string(),
+        }
+        .into());
+    }
+
+    // deserialize instances
+    let instances: Vec<Vec<Vec<Fr>>> = load_instances(instance);
+    let instances1: Vec<Vec<&[Fr]>> = instances
+        .iter()
+        .map(|x| x.iter().map(|y| &y[..]).collect())
+        .collect();
+    let instances2: Vec<&[&[Fr]]> = instances1.iter().map(|x| &x[..]).collect();
+
+    let mut transcript = TranscriptReadBuffer::<_, G1Affine, _>::init(proof);
+    Ok(VerificationStrategy::<_, VerifierSHPLONK<Bn256>>::finalize(
+        verify_proof::<_, VerifierSHPLONK<Bn256>, _, EvmTranscript<_, _, _, _>, _>(
+            params,
+            vk,
+            AccumulatorStrategy::new(params),
+            &instances2,
+            &mut transcript,
+        )
+        .map_err(|e| VerificationError::Invalid {
+            what: "aggregation proof",
+            reason: format!("{e:?}"),
+        })?,
+    ))
+}
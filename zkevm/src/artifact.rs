@@ -0,0 +1,243 @@
+//! On-disk store of the artifacts a prover host produces.
+//!
+//! Artifacts are laid out by kind under a root dir, one entry (file or dir) per
+//! artifact:
+//! - `{root}/debug`: debug dumps of the prover, i.e. its `debug_dir`.
+//! - `{root}/snarks`: cached snarks of the target circuits.
+//! - `{root}/proofs`: completed agg proof bundles.
+//!
+//! - `{root}/objects`: the content of the entries added to the content store,
+//!   keyed by digest, see `content`.
+//!
+//! Each kind has a retention policy, `gc` removes the entries beyond it, and
+//! releases their content. Proof bundles can also be published to a
+//! content-addressed store, see `publish`.
+
+pub mod content;
+pub mod publish;
+
+use crate::utils::read_env_var;
+use content::{ContentStore, DIR_DIGEST_FILE};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    Debug,
+    Snark,
+    Proof,
+}
+
+impl ArtifactKind {
+    pub const ALL: [ArtifactKind; 3] = [Self::Debug, Self::Snark, Self::Proof];
+
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Snark => "snarks",
+            Self::Proof => "proofs",
+        }
+    }
+}
+
+/// Retention of one kind of artifacts. Entries older than `max_age` are removed,
+/// then the oldest ones until the kind takes at most `max_bytes`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Read from `{KIND}_RETENTION_HOURS` and `{KIND}_RETENTION_GB`, 0 or unset
+    /// means no limit.
+    pub fn from_env(kind: ArtifactKind) -> Self {
+        let (hours_var, gb_var) = match kind {
+            ArtifactKind::Debug => ("DEBUG_RETENTION_HOURS", "DEBUG_RETENTION_GB"),
+            ArtifactKind::Snark => ("SNARK_RETENTION_HOURS", "SNARK_RETENTION_GB"),
+            ArtifactKind::Proof => ("PROOF_RETENTION_HOURS", "PROOF_RETENTION_GB"),
+        };
+        let hours: u64 = read_env_var(hours_var, 0);
+        let gb: u64 = read_env_var(gb_var, 0);
+        Self {
+            max_age: (hours != 0).then(|| Duration::from_secs(hours * 3600)),
+            max_bytes: (gb != 0).then_some(gb << 30),
+        }
+    }
+}
+
+/// Space reclaimed by a gc run for one kind of artifacts.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GcMetric {
+    pub kind: ArtifactKind,
+    pub removed_entries: usize,
+    pub reclaimed_bytes: u64,
+    pub remaining_entries: usize,
+    pub remaining_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GcReport {
+    pub metrics: Vec<GcMetric>,
+}
+
+impl GcReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.metrics.iter().map(|m| m.reclaimed_bytes).sum()
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    modified: SystemTime,
+    bytes: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArtifactStore {
+    root: PathBuf,
+    content: Arc<ContentStore>,
+}
+
+impl ArtifactStore {
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        for kind in ArtifactKind::ALL {
+            fs::create_dir_all(root.join(kind.dir_name()))?;
+        }
+        let content = Arc::new(ContentStore::open(root.join("objects"))?);
+        Ok(Self { root, content })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn content(&self) -> &ContentStore {
+        &self.content
+    }
+
+    pub fn dir(&self, kind: ArtifactKind) -> PathBuf {
+        self.root.join(kind.dir_name())
+    }
+
+    /// Path of the artifact `name` of the kind, e.g. the dir of a proof bundle.
+    pub fn path(&self, kind: ArtifactKind, name: &str) -> PathBuf {
+        self.dir(kind).join(name)
+    }
+
+    /// Apply the retention policies read from the env to all kinds.
+    pub fn gc_from_env(&self) -> io::Result<GcReport> {
+        self.gc(RetentionPolicy::from_env)
+    }
+
+    pub fn gc(&self, policy: impl Fn(ArtifactKind) -> RetentionPolicy) -> io::Result<GcReport> {
+        let mut report = GcReport::default();
+        for kind in ArtifactKind::ALL {
+            let metric = self.gc_kind(kind, &policy(kind), SystemTime::now())?;
+            log::info!(
+                "artifact gc: {}: removed {} entries, reclaimed {} bytes, {} bytes remaining",
+                kind.dir_name(),
+                metric.removed_entries,
+                metric.reclaimed_bytes,
+                metric.remaining_bytes
+            );
+            report.metrics.push(metric);
+        }
+        Ok(report)
+    }
+
+    fn gc_kind(
+        &self,
+        kind: ArtifactKind,
+        policy: &RetentionPolicy,
+        now: SystemTime,
+    ) -> io::Result<GcMetric> {
+        let mut entries = self.entries(kind)?;
+        // oldest first
+        entries.sort_by(|a, b| a.modified.cmp(&b.modified).then(a.path.cmp(&b.path)));
+
+        let mut metric = GcMetric {
+            kind,
+            removed_entries: 0,
+            reclaimed_bytes: 0,
+            remaining_entries: 0,
+            remaining_bytes: 0,
+        };
+        let mut total: u64 = entries.iter().map(|e| e.bytes).sum();
+        for entry in entries {
+            let expired = policy.max_age.map_or(false, |max_age| {
+                now.duration_since(entry.modified).unwrap_or_default() > max_age
+            });
+            let over_size = policy
+                .max_bytes
+                .map_or(false, |max_bytes| total > max_bytes);
+            if expired || over_size {
+                self.release_content(&entry.path)?;
+                remove_entry(&entry.path)?;
+                total -= entry.bytes;
+                metric.removed_entries += 1;
+                metric.reclaimed_bytes += entry.bytes;
+            } else {
+                metric.remaining_entries += 1;
+                metric.remaining_bytes += entry.bytes;
+            }
+        }
+        Ok(metric)
+    }
+
+    /// Drop the references of an entry added to the content store.
+    fn release_content(&self, path: &Path) -> io::Result<()> {
+        match fs::read_to_string(path.join(DIR_DIGEST_FILE)) {
+            Ok(digest) => self.content.release_dir(digest.trim()),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn entries(&self, kind: ArtifactKind) -> io::Result<Vec<Entry>> {
+        let dir = self.dir(kind);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        fs::read_dir(dir)?
+            .map(|entry| {
+                let path = entry?.path();
+                let (modified, bytes) = modified_and_size(&path)?;
+                Ok(Entry {
+                    path,
+                    modified,
+                    bytes,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Latest modification time and total size of a file or a dir tree.
+fn modified_and_size(path: &Path) -> io::Result<(SystemTime, u64)> {
+    let meta = fs::symlink_metadata(path)?;
+    let mut modified = meta.modified()?;
+    let mut bytes = meta.len();
+    if meta.is_dir() {
+        bytes = 0;
+        for entry in fs::read_dir(path)? {
+            let (m, b) = modified_and_size(&entry?.path())?;
+            modified = modified.max(m);
+            bytes += b;
+        }
+    }
+    Ok((modified, bytes))
+}
+
+fn remove_entry(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
@@ -0,0 +1,221 @@
+//! Content-addressed store of the artifacts, with reference counting.
+//!
+//! Objects are kept in `{root}/objects/<sha256>`, once however many times they
+//! are added, e.g. the vk shared by every proof bundle or the proof of traces
+//! proved again. Each `put` takes a reference, each `release` drops one, and an
+//! object is removed with its last reference. The counts are kept in
+//! `{root}/objects/refs.json`, read and written back under an `flock` of
+//! `refs.json.lock`, so that stores of several processes on the same root, e.g.
+//! `gc` next to the service, don't lose each other's counts. The lock is only
+//! taken on Linux, elsewhere one process at a time must use the root.
+//!
+//! A dir, e.g. a proof bundle, is stored as its files plus a manifest mapping
+//! the file names to their digests, the digest of the manifest being the one of
+//! the dir. The files of the dir are replaced by hard links to the objects, so
+//! that the bundle layout stays as is while its content is stored once.
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Digest of the dir stored, in the dir.
+pub const DIR_DIGEST_FILE: &str = "content.sha256";
+
+/// Files of a dir to their digests.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DirManifest {
+    pub files: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct ContentStore {
+    dir: PathBuf,
+    /// Keeps the threads of the process out of each other's updates.
+    lock: Mutex<()>,
+}
+
+impl ContentStore {
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        load_refs(&dir)?;
+        Ok(Self {
+            dir,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Path of the object, if stored.
+    pub fn path(&self, digest: &str) -> Option<PathBuf> {
+        let path = self.object_path(digest)?;
+        path.exists().then_some(path)
+    }
+
+    pub fn get(&self, digest: &str) -> io::Result<Option<Vec<u8>>> {
+        self.path(digest).map(fs::read).transpose()
+    }
+
+    /// References taken on the object.
+    pub fn refs(&self, digest: &str) -> u64 {
+        // written by renames, so read whole without the lock
+        load_refs(&self.dir)
+            .ok()
+            .and_then(|refs| refs.get(digest).copied())
+            .unwrap_or(0)
+    }
+
+    /// Store the data, or take another reference if already stored. Returns its
+    /// digest, in hex.
+    pub fn put(&self, data: &[u8]) -> io::Result<String> {
+        let digest = hex::encode(Sha256::digest(data));
+        let path = self.dir.join(&digest);
+        self.update(|refs| {
+            if !path.exists() {
+                let tmp_path = self.dir.join(format!("{digest}.tmp"));
+                fs::write(&tmp_path, data)?;
+                fs::rename(&tmp_path, &path)?;
+            }
+            *refs.entry(digest.clone()).or_default() += 1;
+            Ok(())
+        })?;
+        Ok(digest)
+    }
+
+    /// Drop a reference on the object, returns whether it was the last one and
+    /// the object removed.
+    pub fn release(&self, digest: &str) -> io::Result<bool> {
+        self.update(|refs| {
+            let count = match refs.get_mut(digest) {
+                Some(count) => count,
+                None => return Ok(false),
+            };
+            *count -= 1;
+            let removed = *count == 0;
+            if removed {
+                refs.remove(digest);
+                if let Some(path) = self.object_path(digest) {
+                    fs::remove_file(path).or_else(ignore_not_found)?;
+                }
+            }
+            Ok(removed)
+        })
+    }
+
+    /// Store the files of the dir and its manifest, replacing the files by links
+    /// to the objects. Returns the digest of the manifest, which is also written
+    /// into the dir as `DIR_DIGEST_FILE`. Subdirs are left out.
+    pub fn put_dir(&self, dir: &Path) -> io::Result<String> {
+        let mut manifest = DirManifest::default();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if !path.is_file() || name == DIR_DIGEST_FILE {
+                continue;
+            }
+            let digest = self.put(&fs::read(&path)?)?;
+            link_or_keep(&self.dir.join(&digest), &path)?;
+            manifest.files.insert(name, digest);
+        }
+        let digest = self.put(&serde_json::to_vec(&manifest)?)?;
+        fs::write(dir.join(DIR_DIGEST_FILE), &digest)?;
+        Ok(digest)
+    }
+
+    /// Manifest of a dir stored.
+    pub fn get_dir(&self, digest: &str) -> io::Result<Option<DirManifest>> {
+        match self.get(digest)? {
+            Some(buf) => Ok(Some(serde_json::from_slice(&buf)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Drop the references taken by `put_dir` on the dir and its files.
+    pub fn release_dir(&self, digest: &str) -> io::Result<()> {
+        if let Some(manifest) = self.get_dir(digest)? {
+            for file_digest in manifest.files.values() {
+                self.release(file_digest)?;
+            }
+        }
+        self.release(digest)?;
+        Ok(())
+    }
+
+    /// None for anything but a sha256 in hex, so that a digest from a request
+    /// can't point outside of the store.
+    fn object_path(&self, digest: &str) -> Option<PathBuf> {
+        let valid = digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit());
+        valid.then(|| self.dir.join(digest.to_lowercase()))
+    }
+
+    /// Update the counts as just read from `refs.json`, and write them back,
+    /// with the store locked.
+    fn update<T>(
+        &self,
+        f: impl FnOnce(&mut BTreeMap<String, u64>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let _guard = self.lock.lock().unwrap();
+        let _lock = FileLock::exclusive(&self.dir.join("refs.json.lock"))?;
+        let mut refs = load_refs(&self.dir)?;
+        let result = f(&mut refs)?;
+        self.save(&refs)?;
+        Ok(result)
+    }
+
+    fn save(&self, refs: &BTreeMap<String, u64>) -> io::Result<()> {
+        let tmp_path = self.dir.join("refs.json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(refs)?)?;
+        fs::rename(&tmp_path, self.dir.join("refs.json"))
+    }
+}
+
+fn load_refs(dir: &Path) -> io::Result<BTreeMap<String, u64>> {
+    match fs::read(dir.join("refs.json")) {
+        Ok(buf) => Ok(serde_json::from_slice(&buf)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Exclusive lock of the file, released as it is dropped.
+struct FileLock {
+    _file: fs::File,
+}
+
+impl FileLock {
+    fn exclusive(path: &Path) -> io::Result<Self> {
+        let file = fs::OpenOptions::new().create(true).write(true).open(path)?;
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+            // safe: the fd stays open as long as the lock
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+/// Replace the file by a hard link to the object, the file is kept if the
+/// store is on another filesystem.
+fn link_or_keep(object: &Path, path: &Path) -> io::Result<()> {
+    let tmp_path = path.with_extension("link.tmp");
+    match fs::hard_link(object, &tmp_path) {
+        Ok(()) => fs::rename(&tmp_path, path),
+        Err(e) => {
+            log::debug!("content store: keeping {}: {}", path.display(), e);
+            Ok(())
+        }
+    }
+}
+
+fn ignore_not_found(e: io::Error) -> io::Result<()> {
+    match e.kind() {
+        io::ErrorKind::NotFound => Ok(()),
+        _ => Err(e),
+    }
+}
@@ -0,0 +1,92 @@
+//! Publication of proof bundles to a content-addressed store, e.g. IPFS.
+//!
+//! The store returns a content id (CID) of the published bundle, which pins its
+//! content: anyone fetching the bundle by CID gets the bytes the prover wrote.
+
+use crate::utils::read_env_var;
+use std::fmt::Debug;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+/// Pushes artifacts to a content-addressed store.
+pub trait Publisher: Send + Sync + Debug {
+    /// Publish the file or dir, returns its content id.
+    fn publish(&self, path: &Path) -> io::Result<String>;
+}
+
+/// Publishes by running a command with the path of the artifact as last argument.
+/// The command prints the content id as the last line of its output.
+#[derive(Clone, Debug)]
+pub struct CommandPublisher {
+    pub cmd: String,
+    pub args: Vec<String>,
+}
+
+impl CommandPublisher {
+    /// `ipfs add` of the kubo CLI, pinning the artifact into the local node or the
+    /// node at `api`, a multiaddr.
+    pub fn ipfs(api: Option<String>) -> Self {
+        let mut args = vec![];
+        if let Some(api) = api {
+            args.push(format!("--api={api}"));
+        }
+        args.extend(["add", "-r", "-Q", "--pin", "--cid-version=1"].map(String::from));
+        Self {
+            cmd: "ipfs".to_string(),
+            args,
+        }
+    }
+}
+
+impl Publisher for CommandPublisher {
+    fn publish(&self, path: &Path) -> io::Result<String> {
+        let output = Command::new(&self.cmd)
+            .args(&self.args)
+            .arg(path)
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "{} exited with {}: {}",
+                    self.cmd,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .last()
+        {
+            Some(cid) if !cid.contains(char::is_whitespace) => Ok(cid.to_string()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} printed no content id: {:?}", self.cmd, stdout),
+            )),
+        }
+    }
+}
+
+/// The publisher configured by `ARTIFACT_PUBLISH`, if any: `ipfs` for `ipfs add`
+/// (with `IPFS_API` as the node to pin to), or a command run with the path.
+pub fn publisher_from_env() -> Option<Arc<dyn Publisher>> {
+    match read_env_var("ARTIFACT_PUBLISH", "".to_string()).as_str() {
+        "" => None,
+        "ipfs" => {
+            let api = read_env_var("IPFS_API", "".to_string());
+            Some(Arc::new(CommandPublisher::ipfs(
+                (!api.is_empty()).then_some(api),
+            )))
+        }
+        cmd => Some(Arc::new(CommandPublisher {
+            cmd: cmd.to_string(),
+            args: vec![],
+        })),
+    }
+}
@@ -0,0 +1,203 @@
+//! Optional TEE attestation of agg proofs, for defense in depth.
+//!
+//! The prover asks its TEE for a quote whose report data commits to
+//! `sha256(trace_hash || instance_hash || vk_digest)` and stores it with the proof.
+//! The verifier recomputes the report data, finds it in the quote and leaves the
+//! signature chain of the quote, i.e. the DCAP or AMD collateral, to a `QuoteVerifier`.
+//!
+//! `TEE_ATTESTATION` picks the TEE of new provers: `sgx` reads quotes from the
+//! Gramine `/dev/attestation` interface, `sev-snp` needs `TEE_ATTESTATION_CMD`.
+//! With `TEE_ATTESTATION_CMD` set, the command is run with the report data hex
+//! as argument and prints the raw quote.
+
+use crate::error::{ProvingError, VerificationError};
+use crate::utils::read_env_var;
+use eth_types::H256;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Debug;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use types::base64;
+use types::eth::BlockTrace;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TeeKind {
+    Sgx,
+    SevSnp,
+}
+
+impl TeeKind {
+    /// Offset of the 64 bytes of report data in a quote: in the report body of
+    /// an SGX DCAP quote, after its 48 bytes header, or in an SEV-SNP report.
+    fn report_data_offset(&self) -> usize {
+        match self {
+            Self::Sgx => 48 + 320,
+            Self::SevSnp => 0x50,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Attestation {
+    pub kind: TeeKind,
+    #[serde(with = "base64")]
+    pub quote: Vec<u8>,
+    pub trace_hash: H256,
+    pub instance_hash: H256,
+    pub vk_digest: String,
+}
+
+impl Attestation {
+    /// The report data the quote of the attestation commits to.
+    pub fn report_data(&self) -> [u8; 64] {
+        report_data(&self.trace_hash, &self.instance_hash, &self.vk_digest)
+    }
+
+    /// The report data found in the quote.
+    pub fn quoted_report_data(&self) -> Option<&[u8]> {
+        let offset = self.kind.report_data_offset();
+        self.quote.get(offset..offset + 64)
+    }
+}
+
+/// `sha256(trace_hash || instance_hash || vk_digest)`, zero padded.
+pub fn report_data(trace_hash: &H256, instance_hash: &H256, vk_digest: &str) -> [u8; 64] {
+    let mut hasher = Sha256::new();
+    hasher.update(trace_hash);
+    hasher.update(instance_hash);
+    hasher.update(vk_digest.as_bytes());
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(&hasher.finalize());
+    data
+}
+
+pub fn trace_hash(block_traces: &[BlockTrace]) -> H256 {
+    trace_hash_into(block_traces, &mut Vec::new())
+}
+
+/// `trace_hash`, serializing the traces into `json`.
+pub fn trace_hash_into(block_traces: &[BlockTrace], json: &mut Vec<u8>) -> H256 {
+    json.clear();
+    serde_json::to_writer(&mut *json, block_traces).expect("block traces are serializable");
+    H256(Sha256::digest(&json).into())
+}
+
+pub fn instance_hash(instance: &[u8]) -> H256 {
+    H256(Sha256::digest(instance).into())
+}
+
+/// Obtains quotes from the TEE the prover runs in.
+pub trait Attester: Send + Sync + Debug {
+    fn kind(&self) -> TeeKind;
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, ProvingError>;
+}
+
+/// SGX quotes from the `/dev/attestation` interface of Gramine.
+#[derive(Debug)]
+pub struct GramineSgxAttester;
+
+impl Attester for GramineSgxAttester {
+    fn kind(&self) -> TeeKind {
+        TeeKind::Sgx
+    }
+
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, ProvingError> {
+        let dev = Path::new("/dev/attestation");
+        std::fs::write(dev.join("user_report_data"), report_data)
+            .and_then(|_| std::fs::read(dev.join("quote")))
+            .map_err(|e| ProvingError::Attestation(format!("sgx quote: {e}")))
+    }
+}
+
+/// Quotes printed by a command run with the report data hex as argument.
+#[derive(Debug)]
+pub struct CommandAttester {
+    pub kind: TeeKind,
+    pub cmd: String,
+}
+
+impl Attester for CommandAttester {
+    fn kind(&self) -> TeeKind {
+        self.kind
+    }
+
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, ProvingError> {
+        let output = Command::new(&self.cmd)
+            .arg(hex::encode(report_data))
+            .output()
+            .map_err(|e| ProvingError::Attestation(format!("{}: {e}", self.cmd)))?;
+        if !output.status.success() {
+            return Err(ProvingError::Attestation(format!(
+                "{} exited with {}: {}",
+                self.cmd,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// The attester configured by `TEE_ATTESTATION` and `TEE_ATTESTATION_CMD`, if any.
+pub fn attester_from_env() -> Result<Option<Arc<dyn Attester>>, ProvingError> {
+    let kind = match read_env_var("TEE_ATTESTATION", "".to_string()).as_str() {
+        "" => return Ok(None),
+        "sgx" => TeeKind::Sgx,
+        "sev-snp" => TeeKind::SevSnp,
+        other => {
+            return Err(ProvingError::Attestation(format!(
+                "unknown TEE_ATTESTATION {other}"
+            )))
+        }
+    };
+    let cmd = read_env_var("TEE_ATTESTATION_CMD", "".to_string());
+    Ok(Some(match (kind, cmd.is_empty()) {
+        (_, false) => Arc::new(CommandAttester { kind, cmd }),
+        (TeeKind::Sgx, true) => Arc::new(GramineSgxAttester),
+        (TeeKind::SevSnp, true) => {
+            return Err(ProvingError::Attestation(
+                "sev-snp attestation needs TEE_ATTESTATION_CMD".to_string(),
+            ))
+        }
+    }))
+}
+
+/// Checks the signature chain of a quote, and that it comes from an expected enclave.
+pub trait QuoteVerifier {
+    fn verify(&self, kind: TeeKind, quote: &[u8]) -> Result<(), VerificationError>;
+}
+
+/// Quotes checked by a command run with the tee kind and the path of the quote,
+/// e.g. a wrapper of the DCAP quote verification library; exit status 0 accepts.
+#[derive(Debug)]
+pub struct CommandQuoteVerifier {
+    pub cmd: String,
+}
+
+impl QuoteVerifier for CommandQuoteVerifier {
+    fn verify(&self, kind: TeeKind, quote: &[u8]) -> Result<(), VerificationError> {
+        let path = std::env::temp_dir().join(format!(
+            "quote_{}_{}",
+            std::process::id(),
+            hex::encode(&Sha256::digest(quote)[..8])
+        ));
+        std::fs::write(&path, quote).map_err(|e| VerificationError::Attestation(e.to_string()))?;
+        let kind = serde_json::to_value(kind).unwrap();
+        let status = Command::new(&self.cmd)
+            .arg(kind.as_str().unwrap())
+            .arg(&path)
+            .status();
+        let _ = std::fs::remove_file(&path);
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(VerificationError::Attestation(format!(
+                "quote rejected by {}: {status}",
+                self.cmd
+            ))),
+            Err(e) => Err(VerificationError::Attestation(format!("{}: {e}", self.cmd))),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Append-only audit log of the proving and verification operations, for the
+//! review of what a prover fleet actually produced.
+//!
+//! With `AUDIT_LOG=<file>`, every proof generated and every proof verified appends
+//! a JSON record to the file, one per line: the operation, the circuit, the hash of
+//! its input (the `trace_hash` of the block traces, or the `instance_hash` of the
+//! proofs aggregated or verified), the digests of the vk and of the proof, the
+//! outcome, the duration and the operator, `AUDIT_OPERATOR` or the user running
+//! the prover.
+//!
+//! Records are appended with a single write each, synced to disk, so that records
+//! of concurrent provers sharing the file don't interleave and an operation isn't
+//! lost on a crash. The log is only ever appended to, which the filesystem can
+//! enforce, e.g. with `chattr +a`, as only appends are needed.
+
+use crate::error::Result;
+use crate::utils::read_env_var;
+use crate::version::CircuitVersion;
+use eth_types::H256;
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File the operations are appended to, empty not to audit them.
+pub static AUDIT_LOG: Lazy<String> = Lazy::new(|| read_env_var("AUDIT_LOG", "".to_string()));
+
+/// Operator recorded, the user running the prover by default.
+pub static AUDIT_OPERATOR: Lazy<String> = Lazy::new(|| {
+    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    read_env_var("AUDIT_OPERATOR", user)
+});
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    ProveTarget,
+    ProveAgg,
+    VerifyTarget,
+    VerifyAgg,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    /// The proof doesn't verify.
+    Failure,
+    /// The operation failed, see `AuditRecord::error`.
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditRecord {
+    /// Unix time in milliseconds the operation ended at.
+    pub time_ms: u64,
+    pub operator: String,
+    pub host: String,
+    pub pid: u32,
+    pub operation: AuditOperation,
+    pub circuit: String,
+    pub circuit_version: CircuitVersion,
+    /// sha256 of the block traces, or of the instance of the proofs, in hex.
+    pub input_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vk_digest: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof_digest: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proved_blocks: Option<usize>,
+    pub outcome: AuditOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+impl AuditRecord {
+    pub fn new(
+        operation: AuditOperation,
+        circuit: &str,
+        circuit_version: &CircuitVersion,
+        input_hash: &H256,
+    ) -> Self {
+        Self {
+            time_ms: 0,
+            operator: AUDIT_OPERATOR.clone(),
+            host: crate::provenance::hostname(),
+            pid: std::process::id(),
+            operation,
+            circuit: circuit.to_string(),
+            circuit_version: circuit_version.clone(),
+            input_hash: hex::encode(input_hash.as_bytes()),
+            vk_digest: None,
+            proof_digest: None,
+            proved_blocks: None,
+            outcome: AuditOutcome::Success,
+            error: None,
+            duration_ms: 0,
+        }
+    }
+
+    /// The vk, and the proof with the blocks it proves, of the operation.
+    pub fn with_proof(mut self, vk: &[u8], proof: &[u8], proved_blocks: usize) -> Self {
+        self.vk_digest = Some(sha256_hex(vk));
+        self.proof_digest = Some(sha256_hex(proof));
+        self.proved_blocks = Some(proved_blocks);
+        self
+    }
+
+    /// End a proving operation with its result, and its vk, proof and proved blocks.
+    pub fn proved<T>(
+        self,
+        elapsed: Duration,
+        result: &Result<T>,
+        proof: impl FnOnce(&T) -> (&[u8], &[u8], usize),
+    ) -> Self {
+        match result {
+            Ok(t) => {
+                let (vk, proof, proved_blocks) = proof(t);
+                self.with_proof(vk, proof, proved_blocks).ended(
+                    elapsed,
+                    AuditOutcome::Success,
+                    None,
+                )
+            }
+            Err(e) => self.ended(elapsed, AuditOutcome::Error, Some(e.to_string())),
+        }
+    }
+
+    /// End the operation, failed with `error` if any.
+    pub fn ended(
+        mut self,
+        elapsed: Duration,
+        outcome: AuditOutcome,
+        error: Option<String>,
+    ) -> Self {
+        self.time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_millis() as u64);
+        self.duration_ms = elapsed.as_millis() as u64;
+        self.outcome = outcome;
+        self.error = error;
+        self
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AuditLog({})", self.path.display())
+    }
+}
+
+impl AuditLog {
+    /// Open the log for appending, created if missing.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+
+    /// Append the record, logging the failures: a failed append doesn't fail the
+    /// operation it records.
+    pub fn record(&self, record: &AuditRecord) {
+        if let Err(e) = self.append(record) {
+            log::error!(
+                "failed to append {:?} of {} to the audit log {}: {}",
+                record.operation,
+                record.circuit,
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// The records of a log, e.g. for review.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<AuditRecord>> {
+        BufReader::new(File::open(path)?)
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+}
+
+/// The audit log at `AUDIT_LOG`, if any.
+pub fn audit_log_from_env() -> Option<Arc<AuditLog>> {
+    if AUDIT_LOG.is_empty() {
+        return None;
+    }
+    match AuditLog::open(AUDIT_LOG.as_str()) {
+        Ok(log) => Some(Arc::new(log)),
+        Err(e) => {
+            log::error!("failed to open the audit log {}: {}", *AUDIT_LOG, e);
+            None
+        }
+    }
+}
+
+fn sha256_hex(buf: &[u8]) -> String {
+    hex::encode(Sha256::digest(buf))
+}
@@ -0,0 +1,243 @@
+//! Commitments of a chunk of blocks, as consumed by the batching logic.
+//!
+//! The data hash of a chunk is
+//! `keccak(block_context_0 || .. || block_context_k || tx_hash_0 || .. )`, where a
+//! block context is `number (8) || timestamp (8) || base_fee (32) || gas_limit (8)
+//! || num_txs (2) || num_l1_msgs (2)` bytes, big endian.
+//!
+//! The public input of the super circuit is the public input hash of its chunk,
+//! as two field elements: the high and low 128 bits.
+//!
+//! In the calldata of `commitBatch` of the rollup contract, a chunk is encoded as
+//! `num_blocks (1) || block_context_0 || .. || block_context_k || l2_txs`.
+
+use crate::error::TraceError;
+use eth_types::H256;
+use ethers_core::types::U256;
+use ethers_core::utils::keccak256;
+use serde_derive::{Deserialize, Serialize};
+use types::eth::BlockTrace;
+
+/// Tx type of the L1 messages.
+const L1_MESSAGE_TX_TYPE: u8 = 0x7e;
+
+/// Bytes of a block context.
+pub const BLOCK_CONTEXT_LEN: usize = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChunkInfo {
+    pub chain_id: u64,
+    pub prev_state_root: H256,
+    pub post_state_root: H256,
+    /// Withdraw trie root after the last block, zero for traces without it.
+    pub withdraw_root: H256,
+    pub data_hash: H256,
+}
+
+/// What the commitments of a chunk are computed from, i.e. a block trace on the
+/// prover side, or a header plus tx hashes fetched from a node on the verifier side.
+pub trait BlockHeaderLike {
+    fn number(&self) -> Option<u64>;
+    fn timestamp(&self) -> u64;
+    fn base_fee(&self) -> U256;
+    fn gas_limit(&self) -> u64;
+    fn tx_hashes(&self) -> Vec<H256>;
+    fn num_l1_msgs(&self) -> usize;
+    /// State root before the block.
+    fn parent_state_root(&self) -> H256;
+    /// State root after the block.
+    fn state_root(&self) -> H256;
+    fn withdraw_root(&self) -> Option<H256>;
+}
+
+impl BlockHeaderLike for BlockTrace {
+    fn number(&self) -> Option<u64> {
+        self.header.number.map(|n| n.as_u64())
+    }
+    fn timestamp(&self) -> u64 {
+        self.header.timestamp.as_u64()
+    }
+    fn base_fee(&self) -> U256 {
+        self.header.base_fee_per_gas.unwrap_or_default()
+    }
+    fn gas_limit(&self) -> u64 {
+        self.header.gas_limit.as_u64()
+    }
+    fn tx_hashes(&self) -> Vec<H256> {
+        self.transactions.iter().map(|tx| tx.tx_hash).collect()
+    }
+    fn num_l1_msgs(&self) -> usize {
+        self.transactions
+            .iter()
+            .filter(|tx| tx.type_ == L1_MESSAGE_TX_TYPE)
+            .count()
+    }
+    fn parent_state_root(&self) -> H256 {
+        self.storage_trace.root_before
+    }
+    fn state_root(&self) -> H256 {
+        self.storage_trace.root_after
+    }
+    fn withdraw_root(&self) -> Option<H256> {
+        self.withdraw_trie_root
+    }
+}
+
+/// A block as claimed by a third party, e.g. the rollup contract or an L2 node.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub timestamp: u64,
+    #[serde(default)]
+    pub base_fee: U256,
+    pub gas_limit: u64,
+    pub tx_hashes: Vec<H256>,
+    #[serde(default)]
+    pub num_l1_msgs: usize,
+    pub parent_state_root: H256,
+    pub state_root: H256,
+    #[serde(default)]
+    pub withdraw_root: Option<H256>,
+}
+
+impl BlockHeaderLike for BlockHeader {
+    fn number(&self) -> Option<u64> {
+        Some(self.number)
+    }
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+    fn base_fee(&self) -> U256 {
+        self.base_fee
+    }
+    fn gas_limit(&self) -> u64 {
+        self.gas_limit
+    }
+    fn tx_hashes(&self) -> Vec<H256> {
+        self.tx_hashes.clone()
+    }
+    fn num_l1_msgs(&self) -> usize {
+        self.num_l1_msgs
+    }
+    fn parent_state_root(&self) -> H256 {
+        self.parent_state_root
+    }
+    fn state_root(&self) -> H256 {
+        self.state_root
+    }
+    fn withdraw_root(&self) -> Option<H256> {
+        self.withdraw_root
+    }
+}
+
+impl From<&BlockTrace> for BlockHeader {
+    fn from(trace: &BlockTrace) -> Self {
+        Self {
+            number: trace.number().unwrap_or_default(),
+            timestamp: trace.timestamp(),
+            base_fee: trace.base_fee(),
+            gas_limit: trace.gas_limit(),
+            tx_hashes: trace.tx_hashes(),
+            num_l1_msgs: trace.num_l1_msgs(),
+            parent_state_root: trace.parent_state_root(),
+            state_root: trace.state_root(),
+            withdraw_root: trace.withdraw_root(),
+        }
+    }
+}
+
+impl ChunkInfo {
+    pub fn from_block_traces(block_traces: &[BlockTrace]) -> Result<Self, TraceError> {
+        let chain_id = block_traces.first().map_or(0, |t| t.chain_id.as_u64());
+        if let Some(trace) = block_traces
+            .iter()
+            .find(|t| t.chain_id.as_u64() != chain_id)
+        {
+            return Err(TraceError::Invalid(format!(
+                "chain id {} of block {:?} differs from chain id {} of the chunk",
+                trace.chain_id, trace.header.number, chain_id
+            )));
+        }
+        Self::from_blocks(chain_id, block_traces)
+    }
+
+    pub fn from_blocks<B: BlockHeaderLike>(
+        chain_id: u64,
+        blocks: &[B],
+    ) -> Result<Self, TraceError> {
+        let (first, last) = match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(TraceError::Invalid("empty chunk".to_string())),
+        };
+
+        let mut data = vec![];
+        for block in blocks {
+            let number = block
+                .number()
+                .ok_or_else(|| TraceError::Invalid("block without number".to_string()))?;
+            data.extend_from_slice(&number.to_be_bytes());
+            data.extend_from_slice(&block.timestamp().to_be_bytes());
+            let mut base_fee = [0u8; 32];
+            block.base_fee().to_big_endian(&mut base_fee);
+            data.extend_from_slice(&base_fee);
+            data.extend_from_slice(&block.gas_limit().to_be_bytes());
+            data.extend_from_slice(&(block.tx_hashes().len() as u16).to_be_bytes());
+            data.extend_from_slice(&(block.num_l1_msgs() as u16).to_be_bytes());
+        }
+        for block in blocks {
+            for tx_hash in block.tx_hashes() {
+                data.extend_from_slice(tx_hash.as_bytes());
+            }
+        }
+
+        Ok(Self {
+            chain_id,
+            prev_state_root: first.parent_state_root(),
+            post_state_root: last.state_root(),
+            withdraw_root: last.withdraw_root().unwrap_or_default(),
+            data_hash: H256(keccak256(&data)),
+        })
+    }
+
+    /// `keccak(chain_id (8) || prev_state_root || post_state_root || withdraw_root || data_hash)`
+    pub fn public_input_hash(&self) -> H256 {
+        let mut preimage = self.chain_id.to_be_bytes().to_vec();
+        for hash in [
+            self.prev_state_root,
+            self.post_state_root,
+            self.withdraw_root,
+            self.data_hash,
+        ] {
+            preimage.extend_from_slice(hash.as_bytes());
+        }
+        H256(keccak256(&preimage))
+    }
+}
+
+/// Numbers of the blocks of a chunk encoded as in the calldata of `commitBatch`,
+/// checked to be consecutive.
+pub fn chunk_block_numbers(chunk: &[u8]) -> Result<Vec<u64>, TraceError> {
+    let invalid = |reason: String| Err(TraceError::Invalid(format!("encoded chunk {reason}")));
+    let num_blocks = match chunk.first() {
+        Some(0) | None => return invalid("without blocks".to_string()),
+        Some(n) => *n as usize,
+    };
+    let contexts = match chunk.get(1..1 + num_blocks * BLOCK_CONTEXT_LEN) {
+        Some(contexts) => contexts,
+        None => {
+            return invalid(format!(
+                "of {} bytes, too short for {} blocks",
+                chunk.len(),
+                num_blocks
+            ))
+        }
+    };
+    let numbers: Vec<u64> = contexts
+        .chunks(BLOCK_CONTEXT_LEN)
+        .map(|context| u64::from_be_bytes(context[..8].try_into().unwrap()))
+        .collect();
+    if let Some(pair) = numbers.windows(2).find(|pair| pair[1] != pair[0] + 1) {
+        return invalid(format!("with block {} after block {}", pair[1], pair[0]));
+    }
+    Ok(numbers)
+}
@@ -1,18 +1,28 @@
 use halo2_proofs::halo2curves::bn256::Fr;
 use once_cell::sync::Lazy;
 use snark_verifier_sdk::CircuitExt;
+use std::collections::BTreeMap;
 use types::eth::BlockTrace;
 use zkevm_circuits::witness;
 
 mod builder;
+mod partial_trie;
 mod super_circuit;
+mod witness_check;
 pub use super_circuit::SuperCircuit;
 
+pub use crate::aggregation::ChainBoundAggregationCircuit;
+pub use witness_check::{
+    check_witness, check_witness_block, WitnessDivergence, WITNESS_CHECK, WITNESS_CHECK_STRICT,
+};
+
+use crate::error::Result;
 use crate::utils::read_env_var;
 
 pub use self::builder::{
-    block_traces_to_witness_block, calculate_row_usage_of_trace,
-    calculate_row_usage_of_witness_block, check_batch_capacity, SUB_CIRCUIT_NAMES,
+    block_traces_to_witness_block, calculate_row_usage_of_batch, calculate_row_usage_of_trace,
+    calculate_row_usage_of_witness_block, check_batch_capacity, circuit_capacity, is_empty_block,
+    split_block_trace, split_block_trace_with_capacity, SUB_CIRCUIT_NAMES,
 };
 
 ////// params for degree = 19 ////////////
@@ -26,18 +36,86 @@ const MAX_EXP_STEPS: usize = 10_000;
 */
 
 ////// params for degree = 20 ////////////
-pub static DEGREE: Lazy<usize> = Lazy::new(|| read_env_var("DEGREE", 20));
-const MAX_TXS: usize = 32;
-const MAX_INNER_BLOCKS: usize = 100;
-const MAX_CALLDATA: usize = 400_000;
-const MAX_RWS: usize = 1_000_000;
-const MAX_KECCAK_ROWS: usize = 524_000;
-const MAX_EXP_STEPS: usize = 10_000;
+#[cfg(not(feature = "test-mode"))]
+mod capacity {
+    use super::*;
+
+    pub static DEGREE: Lazy<usize> = Lazy::new(|| read_env_var("DEGREE", 20));
+    pub static AGG_DEGREE: Lazy<usize> = Lazy::new(|| read_env_var("AGG_DEGREE", 26));
+    pub(super) const MAX_TXS: usize = 32;
+    pub(super) const MAX_INNER_BLOCKS: usize = 100;
+    pub(super) const MAX_CALLDATA: usize = 400_000;
+    pub(super) const MAX_RWS: usize = 1_000_000;
+    pub(super) const MAX_KECCAK_ROWS: usize = 524_000;
+    pub(super) const MAX_EXP_STEPS: usize = 10_000;
+}
+
+////// params of the `test-mode` feature, degree = 18 ////////////
+// small enough to prove, aggregate and verify a few blocks on a laptop
+#[cfg(feature = "test-mode")]
+mod capacity {
+    use super::*;
+
+    pub static DEGREE: Lazy<usize> = Lazy::new(|| read_env_var("DEGREE", 18));
+    pub static AGG_DEGREE: Lazy<usize> = Lazy::new(|| read_env_var("AGG_DEGREE", 22));
+    pub(super) const MAX_TXS: usize = 8;
+    pub(super) const MAX_INNER_BLOCKS: usize = 10;
+    pub(super) const MAX_CALLDATA: usize = 40_000;
+    pub(super) const MAX_RWS: usize = 200_000;
+    pub(super) const MAX_KECCAK_ROWS: usize = 200_000;
+    pub(super) const MAX_EXP_STEPS: usize = 1_000;
+}
+
+pub use capacity::{AGG_DEGREE, DEGREE};
+use capacity::{MAX_CALLDATA, MAX_EXP_STEPS, MAX_INNER_BLOCKS, MAX_KECCAK_ROWS, MAX_RWS, MAX_TXS};
 
 pub static CHAIN_ID: Lazy<u64> = Lazy::new(|| read_env_var("CHAIN_ID", 0x82751));
-pub static AGG_DEGREE: Lazy<usize> = Lazy::new(|| read_env_var("AGG_DEGREE", 26));
 pub static AUTO_TRUNCATE: Lazy<bool> = Lazy::new(|| read_env_var("AUTO_TRUNCATE", true));
 
+/// Degrees of the circuits proved below `DEGREE`, e.g. `state=18,poseidon=19`,
+/// see `TargetCircuit::degree`. An invalid value panics, forced when a `Prover`
+/// is built.
+pub static CIRCUIT_DEGREES: Lazy<BTreeMap<String, u32>> = Lazy::new(|| {
+    let degrees = read_env_var("CIRCUIT_DEGREES", String::new());
+    parse_circuit_degrees(&degrees).unwrap_or_else(|e| panic!("invalid CIRCUIT_DEGREES: {e}"))
+});
+
+/// Degree of the circuit of that name, see `TargetCircuit::degree`.
+pub fn circuit_degree(name: &str) -> u32 {
+    CIRCUIT_DEGREES.get(name).copied().unwrap_or(*DEGREE as u32)
+}
+
+/// Parse `name=degree` pairs separated by commas, none above `DEGREE`. The super
+/// circuit can't be lowered: its capacities, `MAX_RWS`, `MAX_TXS` and the others,
+/// are sized for `DEGREE`.
+pub fn parse_circuit_degrees(degrees: &str) -> Result<BTreeMap<String, u32>, String> {
+    let mut parsed = BTreeMap::new();
+    for pair in degrees
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (name, degree) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=degree, got {pair}"))?;
+        let degree: u32 = degree
+            .trim()
+            .parse()
+            .map_err(|e| format!("degree of {name}: {e}"))?;
+        if degree == 0 || degree as usize > *DEGREE {
+            return Err(format!("degree {degree} of {name} not in 1..={}", *DEGREE));
+        }
+        if name.trim() == SuperCircuit::name() && degree as usize != *DEGREE {
+            return Err(format!(
+                "degree {degree} of {name}: its capacities are sized for DEGREE {}",
+                *DEGREE
+            ));
+        }
+        parsed.insert(name.trim().to_string(), degree);
+    }
+    Ok(parsed)
+}
+
 /// A target circuit trait is a wrapper of inner circuit, with convenient APIs for building
 /// circuits from traces.
 pub trait TargetCircuit {
@@ -59,7 +137,7 @@ pub trait TargetCircuit {
     }
 
     /// Build the inner circuit and the instances from a traces
-    fn from_block_trace(block_trace: &BlockTrace) -> anyhow::Result<(Self::Inner, Vec<Vec<Fr>>)>
+    fn from_block_trace(block_trace: &BlockTrace) -> Result<(Self::Inner, Vec<Vec<Fr>>)>
     where
         Self: Sized,
     {
@@ -67,7 +145,7 @@ pub trait TargetCircuit {
     }
 
     /// Build the inner circuit and the instances from a list of traces
-    fn from_block_traces(block_traces: &[BlockTrace]) -> anyhow::Result<(Self::Inner, Vec<Vec<Fr>>)>
+    fn from_block_traces(block_traces: &[BlockTrace]) -> Result<(Self::Inner, Vec<Vec<Fr>>)>
     where
         Self: Sized,
     {
@@ -78,11 +156,11 @@ pub trait TargetCircuit {
     /// Build the inner circuit and the instances from the witness block
     fn from_witness_block(
         witness_block: &witness::Block<Fr>,
-    ) -> anyhow::Result<(Self::Inner, Vec<Vec<Fr>>)>
+    ) -> Result<(Self::Inner, Vec<Vec<Fr>>)>
     where
         Self: Sized;
 
-    fn estimate_rows(block_traces: &[BlockTrace]) -> anyhow::Result<usize> {
+    fn estimate_rows(block_traces: &[BlockTrace]) -> Result<usize> {
         let witness_block = block_traces_to_witness_block(block_traces)?;
         Ok(Self::estimate_rows_from_witness_block(&witness_block))
     }
@@ -94,4 +172,12 @@ pub trait TargetCircuit {
     fn public_input_len() -> usize {
         0
     }
+
+    /// Degree the circuit is proved at, `DEGREE` unless lowered by
+    /// `CIRCUIT_DEGREES`, e.g. a small state circuit next to a large super circuit.
+    /// Snarks of different degrees are aggregated together, their params are the
+    /// inner params of the prover downsized.
+    fn degree() -> u32 {
+        circuit_degree(&Self::name())
+    }
 }
@@ -1,19 +1,23 @@
+use super::partial_trie::PartialTrie;
 use super::{MAX_CALLDATA, MAX_EXP_STEPS, MAX_RWS, MAX_TXS};
 use crate::circuit::{
     TargetCircuit, AUTO_TRUNCATE, CHAIN_ID, DEGREE, MAX_INNER_BLOCKS, MAX_KECCAK_ROWS,
 };
-use anyhow::bail;
+use crate::error::{CapacityError, Result, TraceError};
 use bus_mapping::circuit_input_builder::{self, BlockHead, CircuitInputBuilder, CircuitsParams};
 use bus_mapping::state_db::{Account, CodeDB, StateDB};
 use eth_types::evm_types::OpcodeId;
-use eth_types::ToAddress;
+use eth_types::{Hash, ToAddress, Word};
 use ethers_core::types::{Bytes, U256};
 use halo2_proofs::halo2curves::bn256::Fr;
 use is_even::IsEven;
 use itertools::Itertools;
 use mpt_zktrie::state::ZktrieState;
+use once_cell::sync::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::time::Instant;
-use types::eth::{BlockTrace, EthBlock, ExecStep};
+use types::eth::{BlockTrace, EthBlock, ExecStep, StorageTrace};
 use zkevm_circuits::evm_circuit::witness::block_apply_mpt_state;
 use zkevm_circuits::evm_circuit::witness::{block_convert, Block};
 use zkevm_circuits::util::SubCircuit;
@@ -21,15 +25,27 @@ use zkevm_circuits::util::SubCircuit;
 pub const SUB_CIRCUIT_NAMES: [&str; 11] = [
     "evm", "state", "bytecode", "copy", "keccak", "tx", "rlp", "exp", "pi", "poseidon", "mpt",
 ];
+const BYTECODE_CIRCUIT_IDX: usize = 2;
+
+/// Row usage of a block without txs, the same for all of them: it touches no
+/// state and no code. Computed from the first one seen, for the capacity checks
+/// only: the witness of a batch of empty blocks is still built and padded in
+/// full when it is proved, there's no fast path for proving them.
+static EMPTY_BLOCK_ROW_USAGE: OnceCell<Vec<usize>> = OnceCell::new();
+
+/// Whether the block has no txs, i.e. its row usage is `EMPTY_BLOCK_ROW_USAGE`.
+/// Blocks with a single transfer aren't, their mpt rows depend on the depth of
+/// the accounts in the trie.
+pub fn is_empty_block(block_trace: &BlockTrace) -> bool {
+    block_trace.transactions.is_empty() && block_trace.execution_results.is_empty()
+}
 
 // TODO: optimize it later
-pub fn calculate_row_usage_of_trace(block_trace: &BlockTrace) -> Result<Vec<usize>, anyhow::Error> {
+pub fn calculate_row_usage_of_trace(block_trace: &BlockTrace) -> Result<Vec<usize>> {
     let witness_block = block_traces_to_witness_block(std::slice::from_ref(block_trace))?;
     calculate_row_usage_of_witness_block(&witness_block)
 }
-pub fn calculate_row_usage_of_witness_block(
-    witness_block: &Block<Fr>,
-) -> Result<Vec<usize>, anyhow::Error> {
+pub fn calculate_row_usage_of_witness_block(witness_block: &Block<Fr>) -> Result<Vec<usize>> {
     let rows =
         <crate::circuit::SuperCircuit as TargetCircuit>::Inner::min_num_rows_block_subcircuits(
             witness_block,
@@ -50,10 +66,57 @@ pub fn calculate_row_usage_of_witness_block(
     Ok(rows)
 }
 
+/// Row usage of the blocks as one batch, i.e. the sum of the row usage of each
+/// block, less the bytecodes counted for an earlier block: the bytecode table of
+/// the batch witness holds every bytecode once.
+pub fn calculate_row_usage_of_batch(block_traces: &[BlockTrace]) -> Result<Vec<usize>> {
+    let mut seen_codes = HashSet::new();
+    let mut acc = vec![0; SUB_CIRCUIT_NAMES.len()];
+    for block in block_traces {
+        let usage = batch_row_usage_of_trace(block, &mut seen_codes)?;
+        acc.iter_mut()
+            .zip(usage)
+            .for_each(|(acc, usage)| *acc += usage);
+    }
+    Ok(acc)
+}
+
+/// Row usage of a block in a batch after the blocks whose code hashes are seen.
+fn batch_row_usage_of_trace(
+    block_trace: &BlockTrace,
+    seen_codes: &mut HashSet<Word>,
+) -> Result<Vec<usize>> {
+    // quiet periods are mostly empty blocks, don't build a witness for each to
+    // count its rows
+    if is_empty_block(block_trace) {
+        return EMPTY_BLOCK_ROW_USAGE
+            .get_or_try_init(|| calculate_row_usage_of_trace(block_trace))
+            .cloned();
+    }
+    let witness_block = block_traces_to_witness_block(std::slice::from_ref(block_trace))?;
+    let mut rows = calculate_row_usage_of_witness_block(&witness_block)?;
+    // a bytecode takes a row per byte, plus its header row
+    let dup_rows: usize = witness_block
+        .bytecodes
+        .iter()
+        .filter(|(hash, _)| !seen_codes.insert(**hash))
+        .map(|(_, bytecode)| bytecode.bytes.len() + 1)
+        .sum();
+    if dup_rows > 0 {
+        log::debug!(
+            "block {:?}: {} bytecode rows already in the batch",
+            block_trace.header.number,
+            dup_rows
+        );
+        rows[BYTECODE_CIRCUIT_IDX] = rows[BYTECODE_CIRCUIT_IDX].saturating_sub(dup_rows);
+    }
+    Ok(rows)
+}
+
 // FIXME: we need better API name for this.
 // This function also mutates the block trace.
 /// ...
-pub fn check_batch_capacity(block_traces: &mut Vec<BlockTrace>) -> Result<(), anyhow::Error> {
+pub fn check_batch_capacity(block_traces: &mut Vec<BlockTrace>) -> Result<()> {
     let block_traces_len = block_traces.len();
     let total_tx_count = block_traces
         .iter()
@@ -71,7 +134,11 @@ pub fn check_batch_capacity(block_traces: &mut Vec<BlockTrace>) -> Result<(), an
     );
 
     if block_traces_len > MAX_INNER_BLOCKS {
-        bail!("too many blocks");
+        return Err(CapacityError::TooManyBlocks {
+            num_blocks: block_traces_len,
+            max_blocks: MAX_INNER_BLOCKS,
+        }
+        .into());
     }
 
     if !*AUTO_TRUNCATE {
@@ -82,8 +149,9 @@ pub fn check_batch_capacity(block_traces: &mut Vec<BlockTrace>) -> Result<(), an
     let t = Instant::now();
     let mut acc = Vec::new();
     let mut truncate_idx = block_traces.len();
+    let mut seen_codes = HashSet::new();
     for (idx, block) in block_traces.iter().enumerate() {
-        let usage = calculate_row_usage_of_trace(block)?;
+        let usage = batch_row_usage_of_trace(block, &mut seen_codes)?;
         if acc.is_empty() {
             acc = usage;
         } else {
@@ -103,7 +171,7 @@ pub fn check_batch_capacity(block_traces: &mut Vec<BlockTrace>) -> Result<(), an
             rows,
             rows_and_names
         );
-        if *rows >= (1 << *DEGREE) - 256 {
+        if *rows >= circuit_capacity() {
             log::warn!("truncate blocks [{}..{})", idx, block_traces_len);
             truncate_idx = idx;
             break;
@@ -117,14 +185,153 @@ pub fn check_batch_capacity(block_traces: &mut Vec<BlockTrace>) -> Result<(), an
         .sum::<usize>();
     if total_tx_count != 0 && total_tx_count2 == 0 {
         // the circuit cannot even prove the first non-empty block...
-        bail!("circuit capacity not enough");
+        return Err(CapacityError::FirstBlockTooLarge.into());
     }
     Ok(())
 }
 
-pub fn block_traces_to_witness_block(
-    block_traces: &[BlockTrace],
-) -> Result<Block<Fr>, anyhow::Error> {
+/// Max number of rows a single super circuit instance can hold.
+pub fn circuit_capacity() -> usize {
+    (1 << *DEGREE) - 256
+}
+
+/// Partition the transactions of one block into consecutive sub-blocks, each of
+/// which fits into a single super circuit instance.
+///
+/// Every partition keeps the header of the original block. Its storage trace
+/// starts at the state left by the partitions before it, see `PartialTrie`, so
+/// that the state-root public inputs of the resulting circuits link up from the
+/// block's `root_before` to its `root_after`.
+pub fn split_block_trace(block_trace: &BlockTrace) -> Result<Vec<BlockTrace>> {
+    split_block_trace_with_capacity(block_trace, circuit_capacity())
+}
+
+/// `split_block_trace` into partitions of less than `capacity` rows.
+pub fn split_block_trace_with_capacity(
+    block_trace: &BlockTrace,
+    capacity: usize,
+) -> Result<Vec<BlockTrace>> {
+    let tx_num = block_trace.transactions.len();
+    if tx_num == 0 {
+        return Ok(vec![block_trace.clone()]);
+    }
+
+    let t = Instant::now();
+    let mut partitions = Vec::new();
+    let mut trie = None;
+    let mut storage_trace = block_trace.storage_trace.clone();
+    let mut start = 0;
+    while start < tx_num {
+        // binary search the longest run of txs starting at `start` that fits
+        let (mut lo, mut hi) = (start, tx_num);
+        let mut fit = None;
+        while lo < hi {
+            let mid = (lo + hi + 1) / 2;
+            let partition = block_trace_partition(block_trace, start..mid, &storage_trace);
+            let (witness_block, sdb) = witness_block_and_state(std::slice::from_ref(&partition))?;
+            let rows = calculate_row_usage_of_witness_block(&witness_block)?;
+            if *itertools::max(&rows).unwrap() < capacity {
+                lo = mid;
+                fit = Some((post_state_root(&witness_block), sdb));
+            } else {
+                hi = mid - 1;
+            }
+        }
+        let (root_after, sdb) = match fit {
+            Some(fit) if lo > start => fit,
+            _ => {
+                return Err(CapacityError::TxTooLarge {
+                    tx_index: start,
+                    block_number: block_trace.header.number.map(|n| n.as_u64()),
+                }
+                .into())
+            }
+        };
+
+        let mut partition = block_trace_partition(block_trace, start..lo, &storage_trace);
+        partition.storage_trace.root_after = root_after;
+        log::debug!(
+            "partition {} of block {:?}: txs [{}..{}), root {:?} -> {:?}",
+            partitions.len(),
+            block_trace.header.number,
+            start,
+            lo,
+            partition.storage_trace.root_before,
+            partition.storage_trace.root_after
+        );
+        partitions.push(partition);
+        start = lo;
+        if start == tx_num {
+            break;
+        }
+
+        // the next partition is proved against the state this one leaves
+        if trie.is_none() {
+            trie = Some(PartialTrie::new(&block_trace.storage_trace)?);
+        }
+        let partial = trie.as_mut().unwrap();
+        partial.apply(&sdb)?;
+        if partial.root() != root_after {
+            return Err(TraceError::Witness(format!(
+                "state root {:?} of the trie after txs [..{}) of block {:?}, the witness has {:?}",
+                partial.root(),
+                start,
+                block_trace.header.number,
+                root_after
+            ))
+            .into());
+        }
+        storage_trace = partial.storage_trace()?;
+    }
+    let root_after = partitions.last().unwrap().storage_trace.root_after;
+    if root_after != block_trace.storage_trace.root_after {
+        return Err(TraceError::Witness(format!(
+            "partitions of block {:?} end at state root {:?}, not {:?}",
+            block_trace.header.number, root_after, block_trace.storage_trace.root_after
+        ))
+        .into());
+    }
+    log::info!(
+        "split block {:?} with {} txs into {} partitions, takes {:?}",
+        block_trace.header.number,
+        tx_num,
+        partitions.len(),
+        t.elapsed()
+    );
+    Ok(partitions)
+}
+
+fn block_trace_partition(
+    block_trace: &BlockTrace,
+    tx_range: Range<usize>,
+    storage_trace: &StorageTrace,
+) -> BlockTrace {
+    BlockTrace {
+        chain_id: block_trace.chain_id,
+        coinbase: block_trace.coinbase.clone(),
+        header: block_trace.header.clone(),
+        transactions: block_trace.transactions[tx_range.clone()].to_vec(),
+        execution_results: block_trace.execution_results[tx_range].to_vec(),
+        storage_trace: storage_trace.clone(),
+        withdraw_trie_root: block_trace.withdraw_trie_root,
+    }
+}
+
+fn post_state_root(witness_block: &Block<Fr>) -> eth_types::Hash {
+    let mut root = [0u8; 32];
+    witness_block
+        .mpt_updates
+        .new_root()
+        .to_big_endian(&mut root);
+    eth_types::Hash::from(root)
+}
+
+pub fn block_traces_to_witness_block(block_traces: &[BlockTrace]) -> Result<Block<Fr>> {
+    Ok(witness_block_and_state(block_traces)?.0)
+}
+
+/// The witness block, and the state after the blocks.
+fn witness_block_and_state(block_traces: &[BlockTrace]) -> Result<(Block<Fr>, StateDB)> {
     let old_root = if block_traces.is_empty() {
         eth_types::Hash::zero()
     } else {
@@ -157,7 +364,8 @@ pub fn block_traces_to_witness_block(
                 .iter()
                 .map(Bytes::as_ref)
         }),
-    )?;
+    )
+    .map_err(witness_error)?;
 
     let chain_ids = block_traces
         .iter()
@@ -202,19 +410,22 @@ pub fn block_traces_to_witness_block(
             geth_trace.push(result.into());
         }
         // TODO: Get the history_hashes.
-        let mut header = BlockHead::new(chain_id, Vec::new(), &eth_block)?;
+        let mut header = BlockHead::new(chain_id, Vec::new(), &eth_block).map_err(witness_error)?;
         // override zeroed minder field with additional "coinbase" field in blocktrace
         if let Some(address) = block_trace.coinbase.address {
             header.coinbase = address;
         }
 
         builder.block.headers.insert(header.number.as_u64(), header);
-        builder.handle_block_inner(&eth_block, geth_trace.as_slice(), false, is_last)?;
+        builder
+            .handle_block_inner(&eth_block, geth_trace.as_slice(), false, is_last)
+            .map_err(witness_error)?;
 
         let per_block_metric = false;
         if per_block_metric {
             let t = Instant::now();
-            let block = block_convert::<Fr>(&builder.block, &builder.code_db)?;
+            let block =
+                block_convert::<Fr>(&builder.block, &builder.code_db).map_err(witness_error)?;
             log::debug!("block convert time {:?}", t.elapsed());
             let rows =
                 <crate::circuit::SuperCircuit as TargetCircuit>::Inner::min_num_rows_block(&block);
@@ -235,19 +446,24 @@ pub fn block_traces_to_witness_block(
     }
 
     builder.set_value_ops_call_context_rwc_eor();
-    builder.set_end_block()?;
+    builder.set_end_block().map_err(witness_error)?;
 
-    let mut witness_block = block_convert(&builder.block, &builder.code_db)?;
+    let mut witness_block =
+        block_convert(&builder.block, &builder.code_db).map_err(witness_error)?;
     log::debug!(
         "witness_block.circuits_params {:?}",
         witness_block.circuits_params
     );
 
     block_apply_mpt_state(&mut witness_block, zktrie_state);
-    Ok(witness_block)
+    Ok((witness_block, builder.sdb))
 }
 
-pub fn decode_bytecode(bytecode: &str) -> Result<Vec<u8>, anyhow::Error> {
+fn witness_error(e: impl std::fmt::Debug) -> TraceError {
+    TraceError::Witness(format!("{e:?}"))
+}
+
+pub fn decode_bytecode(bytecode: &str) -> Result<Vec<u8>, TraceError> {
     let mut stripped = if let Some(stripped) = bytecode.strip_prefix("0x") {
         stripped.to_string()
     } else {
@@ -259,7 +475,7 @@ pub fn decode_bytecode(bytecode: &str) -> Result<Vec<u8>, anyhow::Error> {
         stripped = format!("0{stripped}");
     }
 
-    hex::decode(stripped).map_err(|e| e.into())
+    hex::decode(stripped).map_err(|e| TraceError::Invalid(format!("bytecode: {e}")))
 }
 /*
 #[derive(Debug, Clone)]
@@ -372,14 +588,20 @@ fn get_account_created_codehash(step: &ExecStep) -> Result<eth_types::H256, anyh
     }
 }
 */
-fn trace_code(cdb: &mut CodeDB, step: &ExecStep, sdb: &StateDB, code: Bytes, stack_pos: usize) {
+fn trace_code(
+    codes: &mut BatchCodes,
+    step: &ExecStep,
+    sdb: &StateDB,
+    code: Bytes,
+    stack_pos: usize,
+) {
     let stack = step
         .stack
         .as_ref()
         .expect("should have stack in call context");
     let addr = stack[stack.len() - stack_pos - 1].to_address(); //stack N-stack_pos
 
-    let hash = cdb.insert(code.to_vec());
+    let hash = codes.insert(code);
 
     // sanity check
     let (existed, data) = sdb.get_account(&addr);
@@ -390,16 +612,48 @@ fn trace_code(cdb: &mut CodeDB, step: &ExecStep, sdb: &StateDB, code: Bytes, sta
         );
     };
 }
-pub fn build_codedb(sdb: &StateDB, blocks: &[BlockTrace]) -> Result<CodeDB, anyhow::Error> {
-    let mut cdb = CodeDB::new();
 
-    for block in blocks.iter().rev() {
-        // notice empty codehash always kept as keccak256(nil)
-        cdb.insert(Vec::new());
+/// The code db of a batch. The same contracts are called from many blocks, a
+/// bytecode is decoded and hashed (poseidon) once per batch.
+struct BatchCodes<'a> {
+    cdb: CodeDB,
+    hashes: HashMap<Bytes, Hash>,
+    decoded: HashMap<&'a str, Hash>,
+}
+
+impl<'a> BatchCodes<'a> {
+    fn insert(&mut self, code: Bytes) -> Hash {
+        if let Some(hash) = self.hashes.get(&code) {
+            return *hash;
+        }
+        let hash = self.cdb.insert(code.to_vec());
+        self.hashes.insert(code, hash);
+        hash
+    }
+
+    fn insert_hex(&mut self, bytecode: &'a str) -> Result<Hash, TraceError> {
+        if let Some(hash) = self.decoded.get(bytecode) {
+            return Ok(*hash);
+        }
+        let hash = self.insert(decode_bytecode(bytecode)?.into());
+        self.decoded.insert(bytecode, hash);
+        Ok(hash)
+    }
+}
+
+pub fn build_codedb(sdb: &StateDB, blocks: &[BlockTrace]) -> Result<CodeDB, TraceError> {
+    let mut codes = BatchCodes {
+        cdb: CodeDB::new(),
+        hashes: HashMap::new(),
+        decoded: HashMap::new(),
+    };
+    // notice empty codehash always kept as keccak256(nil)
+    codes.insert(Bytes::default());
 
+    for block in blocks.iter().rev() {
         for (er_idx, execution_result) in block.execution_results.iter().enumerate() {
             if let Some(bytecode) = &execution_result.byte_code {
-                let _hash = cdb.insert(decode_bytecode(bytecode)?.to_vec());
+                let _hash = codes.insert_hex(bytecode)?;
 
                 if execution_result.account_created.is_none() {
                     //assert_eq!(Some(hash), execution_result.code_hash);
@@ -418,22 +672,20 @@ pub fn build_codedb(sdb: &StateDB, blocks: &[BlockTrace]) -> Result<CodeDB, anyh
                             } else {
                                 1
                             };
-                            let callee_code = data.get_code_at(code_idx);
-                            if callee_code.is_none() {
-                                log::error!("cannot get code of call: {:?}", step);
-                            }
-                            trace_code(&mut cdb, step, sdb, callee_code.unwrap(), 1);
+                            let callee_code = data.get_code_at(code_idx).ok_or_else(|| {
+                                TraceError::Invalid(format!("cannot get code of call: {step:?}"))
+                            })?;
+                            trace_code(&mut codes, step, sdb, callee_code, 1);
                         }
                         OpcodeId::CREATE | OpcodeId::CREATE2 => {
                             // notice we do not need to insert code for CREATE,
                             // bustmapping do this job
                         }
                         OpcodeId::EXTCODESIZE | OpcodeId::EXTCODECOPY => {
-                            let code = data.get_code_at(0);
-                            if code.is_none() {
-                                log::error!("cannot get code of ext: {:?}", step);
-                            }
-                            trace_code(&mut cdb, step, sdb, code.unwrap(), 0);
+                            let code = data.get_code_at(0).ok_or_else(|| {
+                                TraceError::Invalid(format!("cannot get code of ext: {step:?}"))
+                            })?;
+                            trace_code(&mut codes, step, sdb, code, 0);
                         }
 
                         _ => {}
@@ -443,7 +695,12 @@ pub fn build_codedb(sdb: &StateDB, blocks: &[BlockTrace]) -> Result<CodeDB, anyh
         }
     }
 
-    Ok(cdb)
+    log::debug!(
+        "code db of {} blocks: {} distinct bytecodes",
+        blocks.len(),
+        codes.hashes.len()
+    );
+    Ok(codes.cdb)
 }
 
 /*
@@ -0,0 +1,181 @@
+//! The state trie of a block, as far as the proofs of its storage trace go, for
+//! the storage traces of its partitions, see `split_block_trace`.
+//!
+//! The trie starts at the state before the block. Once a partition is witnessed,
+//! the values it leaves in the accounts and slots of the block are written into
+//! the trie, so that the next partition gets proofs against its own pre-state:
+//! the nonces, balances and slots written by the partitions before it, and a
+//! `root_before` matching them.
+
+use crate::error::TraceError;
+use bus_mapping::state_db::StateDB;
+use eth_types::{Address, Hash, Word};
+use ethers_core::types::Bytes;
+use std::collections::HashMap;
+use types::eth::StorageTrace;
+use zktrie::{ZkMemoryDb, ZkTrie};
+
+/// Fields of an account leaf: the code size and the nonce packed into the first,
+/// then the balance, the storage root, the keccak code hash and the poseidon code
+/// hash.
+type AccountFields = [[u8; 32]; 5];
+
+pub(crate) struct PartialTrie {
+    db: ZkMemoryDb,
+    root: [u8; 32],
+    /// The accounts and slots the block touches, the keys of its proofs.
+    accounts: Vec<Address>,
+    slots: HashMap<Address, Vec<Word>>,
+    deletion_proofs: Vec<Bytes>,
+}
+
+impl PartialTrie {
+    /// The trie of the nodes of the proofs of the block. Needs the hash scheme of
+    /// zktrie set up, i.e. a `ZktrieState` built before.
+    pub(crate) fn new(storage_trace: &StorageTrace) -> Result<Self, TraceError> {
+        let mut db = ZkMemoryDb::new();
+        let account_proofs = storage_trace
+            .proofs
+            .iter()
+            .flat_map(|proofs| proofs.values());
+        let storage_proofs = storage_trace
+            .storage_proofs
+            .values()
+            .flat_map(|proofs| proofs.values());
+        for node in account_proofs
+            .chain(storage_proofs)
+            .flatten()
+            .chain(&storage_trace.deletion_proofs)
+        {
+            // entries that aren't nodes, e.g. the magic bytes ending a proof, are
+            // skipped; a missing node fails to prove below
+            let _ = db.add_node_bytes(node.as_ref());
+        }
+        let mut accounts: Vec<_> = storage_trace
+            .proofs
+            .iter()
+            .flat_map(|proofs| proofs.keys().copied())
+            .collect();
+        accounts.sort();
+        let slots = storage_trace
+            .storage_proofs
+            .iter()
+            .map(|(address, proofs)| {
+                let mut slots: Vec<_> = proofs.keys().copied().collect();
+                slots.sort();
+                (*address, slots)
+            })
+            .collect();
+        let root = storage_trace.root_before.0;
+        // the root of the proofs is in the trie
+        new_trie(&mut db, &root)?;
+        Ok(Self {
+            db,
+            root,
+            accounts,
+            slots,
+            deletion_proofs: storage_trace.deletion_proofs.clone(),
+        })
+    }
+
+    pub(crate) fn root(&self) -> Hash {
+        Hash::from(self.root)
+    }
+
+    /// The storage trace of a partition starting at the current state, its
+    /// `root_after` left to the caller.
+    pub(crate) fn storage_trace(&mut self) -> Result<StorageTrace, TraceError> {
+        let trie = new_trie(&mut self.db, &self.root)?;
+        let mut proofs = HashMap::new();
+        let mut storage_proofs: HashMap<_, HashMap<_, _>> = HashMap::new();
+        for address in &self.accounts {
+            let key = address.as_bytes();
+            proofs.insert(*address, prove(&trie, key)?);
+            let slots = match self.slots.get(address) {
+                Some(slots) => slots,
+                None => continue,
+            };
+            let storage_root = trie.get_account(key).map_or([0; 32], |fields| fields[2]);
+            let storage = new_trie(&mut self.db, &storage_root)?;
+            for slot in slots {
+                storage_proofs
+                    .entry(*address)
+                    .or_default()
+                    .insert(*slot, prove(&storage, &word_bytes(slot))?);
+            }
+        }
+        Ok(StorageTrace {
+            root_before: self.root(),
+            root_after: self.root(),
+            proofs: Some(proofs),
+            storage_proofs,
+            deletion_proofs: self.deletion_proofs.clone(),
+        })
+    }
+
+    /// Write the accounts and slots of the block as left by a partition, `sdb`
+    /// being the state after it.
+    pub(crate) fn apply(&mut self, sdb: &StateDB) -> Result<(), TraceError> {
+        let mut trie = new_trie(&mut self.db, &self.root)?;
+        for address in &self.accounts {
+            let key = address.as_bytes();
+            let old = trie.get_account(key);
+            let (found, account) = sdb.get_account(address);
+            // an account the block reads without creating it stays out of the trie
+            if !found || (old.is_none() && account.is_empty()) {
+                continue;
+            }
+            let storage_root = old.map_or([0; 32], |fields| fields[2]);
+            let mut storage = new_trie(&mut self.db, &storage_root)?;
+            for slot in self.slots.get(address).into_iter().flatten() {
+                let (_, value) = sdb.get_storage(address, slot);
+                let slot = word_bytes(slot);
+                if value.is_zero() {
+                    storage.delete(&slot);
+                } else {
+                    storage
+                        .update_store(&slot, &word_bytes(value))
+                        .map_err(|e| trie_error("storage update", address, e))?;
+                }
+            }
+
+            let mut fields: AccountFields = [[0; 32]; 5];
+            fields[0][16..24].copy_from_slice(&account.code_size.as_u64().to_be_bytes());
+            fields[0][24..32].copy_from_slice(&account.nonce.as_u64().to_be_bytes());
+            account.balance.to_big_endian(&mut fields[1]);
+            fields[2] = storage.root();
+            fields[3] = account.keccak_code_hash.0;
+            fields[4] = account.code_hash.0;
+            trie.update_account(key, &fields)
+                .map_err(|e| trie_error("account update", address, e))?;
+        }
+        self.root = trie.root();
+        Ok(())
+    }
+}
+
+fn new_trie(db: &mut ZkMemoryDb, root: &[u8; 32]) -> Result<ZkTrie, TraceError> {
+    db.new_trie(root).ok_or_else(|| {
+        TraceError::Witness(format!(
+            "trie root {:?} not in the proofs of the block",
+            Hash::from(*root)
+        ))
+    })
+}
+
+fn prove(trie: &ZkTrie, key: &[u8]) -> Result<Vec<Bytes>, TraceError> {
+    let nodes = trie
+        .prove(key)
+        .map_err(|e| TraceError::Witness(format!("failed to prove {}: {e:?}", hex::encode(key))))?;
+    Ok(nodes.into_iter().map(Bytes::from).collect())
+}
+
+fn word_bytes(word: &Word) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    word.to_big_endian(&mut bytes);
+    bytes
+}
+
+fn trie_error(what: &str, address: &Address, e: impl std::fmt::Debug) -> TraceError {
+    TraceError::Witness(format!("{what} of {address:?}: {e:?}"))
+}
@@ -1,10 +1,9 @@
-
-use super::{TargetCircuit, DEGREE};
+use super::TargetCircuit;
 
 use anyhow::bail;
 use halo2_proofs::halo2curves::bn256::Fr;
-use zkevm_circuits::witness;
 use zkevm_circuits::util::SubCircuit;
+use zkevm_circuits::witness;
 
 pub struct StateCircuit {}
 impl TargetCircuit for StateCircuit {
@@ -28,7 +27,7 @@ impl TargetCircuit for StateCircuit {
         let inner = StateCircuitImpl::<Fr>::new(
             witness_block.rws.clone(),
             // TODO: put it into CircuitParams?
-            (1 << *DEGREE) - 64,
+            (1 << Self::degree()) - 64,
         );
         let instance = vec![];
         Ok((inner, instance))
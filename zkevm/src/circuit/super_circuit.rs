@@ -1,7 +1,7 @@
-use super::{TargetCircuit, DEGREE};
+use super::TargetCircuit;
 
 use super::{MAX_CALLDATA, MAX_INNER_BLOCKS, MAX_TXS};
-use anyhow::bail;
+use crate::error::{CapacityError, ProvingError, Result};
 use halo2_proofs::halo2curves::bn256::Fr;
 use zkevm_circuits::util::SubCircuit;
 use zkevm_circuits::{super_circuit::SuperCircuit as SuperCircuitTpl, witness};
@@ -17,19 +17,21 @@ impl TargetCircuit for SuperCircuit {
         "super".to_string()
     }
 
-    fn from_witness_block(
-        witness_block: &witness::Block<Fr>,
-    ) -> anyhow::Result<(Self::Inner, Vec<Vec<Fr>>)>
+    fn from_witness_block(witness_block: &witness::Block<Fr>) -> Result<(Self::Inner, Vec<Vec<Fr>>)>
     where
         Self: Sized,
     {
-        let (k, inner, instance) = Self::Inner::build_from_witness_block(witness_block.clone())?;
-        if k as usize > *DEGREE {
-            bail!(
-                "circuit not enough: DEGREE = {}, less than k needed: {}",
-                *DEGREE,
-                k
-            );
+        let (k, inner, instance) = Self::Inner::build_from_witness_block(witness_block.clone())
+            .map_err(|e| ProvingError::Circuit {
+                circuit: Self::name(),
+                reason: format!("{e:?}"),
+            })?;
+        if k > Self::degree() {
+            return Err(CapacityError::DegreeTooLow {
+                degree: Self::degree() as usize,
+                needed: k,
+            }
+            .into());
         }
         Ok((inner, instance))
     }
@@ -0,0 +1,277 @@
+//! Cross-check of a witness block against the traces it is built from, so that a
+//! bug of the witness builder is reported as the quantity it got wrong rather
+//! than as an unexplained constraint failure.
+//!
+//! From the rws of the witness are derived, per block:
+//! - the gas used by each tx, from the cumulative gas of the receipts, and whether
+//!   it failed, compared with the `gas` and `failed` of its execution result;
+//! - the logs bloom of the block, from the address and topics of the logs, compared
+//!   with the `logsBloom` of the header;
+//! - the storage slots read or written, compared with the slots of the `SLOAD` and
+//!   `SSTORE` proofs of the execution results.
+//!
+//! The return data of a tx, the `return_value` of its execution result, isn't
+//! compared: the witness only holds the return data of the calls read back by
+//! their caller, through the copy events of `RETURNDATACOPY`, not the one of the
+//! root call, which no rw nor copy event of the witness carries.
+
+use crate::error::TraceError;
+use crate::utils::read_env_var;
+use bus_mapping::operation::Target;
+use eth_types::evm_types::OpcodeId;
+use eth_types::{Address, Word};
+use ethers_core::types::Bloom;
+use ethers_core::utils::keccak256;
+use halo2_proofs::halo2curves::bn256::Fr;
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use types::eth::BlockTrace;
+use zkevm_circuits::table::{TxLogFieldTag, TxReceiptFieldTag};
+use zkevm_circuits::witness::{Block, Rw};
+
+/// Cross-check the witness of every batch before proving it.
+pub static WITNESS_CHECK: Lazy<bool> = Lazy::new(|| read_env_var("WITNESS_CHECK", true));
+/// Fail the batches whose witness diverges from the traces, otherwise only warn.
+pub static WITNESS_CHECK_STRICT: Lazy<bool> =
+    Lazy::new(|| read_env_var("WITNESS_CHECK_STRICT", false));
+
+/// A quantity derived from the witness which differs from the traces.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WitnessDivergence {
+    pub block: u64,
+    /// Index of the tx in the block, if about a tx.
+    pub tx: Option<usize>,
+    pub field: String,
+    pub trace: String,
+    pub witness: String,
+}
+
+impl fmt::Display for WitnessDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block {}", self.block)?;
+        if let Some(tx) = self.tx {
+            write!(f, " tx {tx}")?;
+        }
+        write!(
+            f,
+            " {}: trace {}, witness {}",
+            self.field, self.trace, self.witness
+        )
+    }
+}
+
+/// Cross-check the witness if `WITNESS_CHECK`, failing on a divergence if
+/// `WITNESS_CHECK_STRICT`.
+pub fn check_witness(
+    block_traces: &[BlockTrace],
+    witness_block: &Block<Fr>,
+) -> Result<(), TraceError> {
+    if !*WITNESS_CHECK {
+        return Ok(());
+    }
+    let divergences = check_witness_block(block_traces, witness_block);
+    for divergence in &divergences {
+        log::warn!("witness diverges from the traces, {}", divergence);
+    }
+    match divergences.first() {
+        Some(first) if *WITNESS_CHECK_STRICT => Err(TraceError::Witness(format!(
+            "{} quantities diverge from the traces, first {}",
+            divergences.len(),
+            first
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The quantities of the witness which differ from the traces, in block order.
+pub fn check_witness_block(
+    block_traces: &[BlockTrace],
+    witness_block: &Block<Fr>,
+) -> Vec<WitnessDivergence> {
+    let witness = WitnessQuantities::of(witness_block);
+    let mut divergences = vec![];
+    for block_trace in block_traces {
+        let number = block_trace.header.number.map_or(0, |n| n.as_u64());
+        let mut diverge = |tx, field: &str, trace: String, witness: String| {
+            divergences.push(WitnessDivergence {
+                block: number,
+                tx,
+                field: field.to_string(),
+                trace,
+                witness,
+            })
+        };
+        let empty = BlockQuantities::default();
+        let block = witness.blocks.get(&number).unwrap_or(&empty);
+
+        let results = &block_trace.execution_results;
+        if results.len() != block.txs.len() {
+            diverge(
+                None,
+                "number of txs",
+                results.len().to_string(),
+                block.txs.len().to_string(),
+            );
+        }
+        for (i, (result, tx)) in results.iter().zip(&block.txs).enumerate() {
+            if result.gas != tx.gas_used {
+                diverge(
+                    Some(i),
+                    "gas used",
+                    result.gas.to_string(),
+                    tx.gas_used.to_string(),
+                );
+            }
+            if result.failed != tx.failed {
+                diverge(
+                    Some(i),
+                    "failed",
+                    result.failed.to_string(),
+                    tx.failed.to_string(),
+                );
+            }
+        }
+
+        if let Some(logs_bloom) = block_trace.header.logs_bloom {
+            if logs_bloom != block.logs_bloom {
+                diverge(
+                    None,
+                    "logs bloom",
+                    format!("{logs_bloom:?}"),
+                    format!("{:?}", block.logs_bloom),
+                );
+            }
+        }
+
+        let traced = traced_storage(block_trace);
+        for (address, key) in traced.symmetric_difference(&block.storage) {
+            let touched = |set: &BTreeSet<_>| {
+                let touched = set.contains(&(*address, *key));
+                if touched { "touched" } else { "untouched" }.to_string()
+            };
+            diverge(
+                None,
+                &format!("storage slot {address:?} {key:#x}"),
+                touched(&traced),
+                touched(&block.storage),
+            );
+        }
+    }
+    divergences
+}
+
+#[derive(Default)]
+struct TxQuantities {
+    gas_used: u64,
+    failed: bool,
+}
+
+#[derive(Default)]
+struct BlockQuantities {
+    txs: Vec<TxQuantities>,
+    logs_bloom: Bloom,
+    storage: BTreeSet<(Address, Word)>,
+}
+
+struct WitnessQuantities {
+    blocks: BTreeMap<u64, BlockQuantities>,
+}
+
+impl WitnessQuantities {
+    fn of(witness_block: &Block<Fr>) -> Self {
+        let rws = |target| witness_block.rws.0.get(&target).into_iter().flatten();
+
+        let mut receipts: HashMap<usize, (u64, u64)> = HashMap::new();
+        for rw in rws(Target::TxReceipt) {
+            if let Rw::TxReceipt {
+                tx_id,
+                field_tag,
+                value,
+                ..
+            } = rw
+            {
+                let receipt = receipts.entry(*tx_id).or_default();
+                match field_tag {
+                    TxReceiptFieldTag::PostStateOrStatus => receipt.0 = *value,
+                    TxReceiptFieldTag::CumulativeGasUsed => receipt.1 = *value,
+                    _ => {}
+                }
+            }
+        }
+
+        let mut tx_blocks = HashMap::new();
+        let mut blocks: BTreeMap<u64, BlockQuantities> = BTreeMap::new();
+        for tx in &witness_block.txs {
+            tx_blocks.insert(tx.id, tx.block_number);
+            let block = blocks.entry(tx.block_number).or_default();
+            let (status, cumulative_gas) = receipts.get(&tx.id).copied().unwrap_or_default();
+            // the cumulative gas of the receipts starts over in every block
+            let previous: u64 = block.txs.iter().map(|tx| tx.gas_used).sum();
+            block.txs.push(TxQuantities {
+                gas_used: cumulative_gas.saturating_sub(previous),
+                failed: status == 0,
+            });
+        }
+
+        for rw in rws(Target::TxLog) {
+            if let Rw::TxLog {
+                tx_id,
+                field_tag,
+                value,
+                ..
+            } = rw
+            {
+                let block = tx_blocks.get(tx_id).and_then(|n| blocks.get_mut(n));
+                let mut word = [0u8; 32];
+                value.to_big_endian(&mut word);
+                match (block, field_tag) {
+                    (Some(block), TxLogFieldTag::Address) => {
+                        accrue(&mut block.logs_bloom, &word[12..])
+                    }
+                    (Some(block), TxLogFieldTag::Topic) => accrue(&mut block.logs_bloom, &word),
+                    _ => {}
+                }
+            }
+        }
+
+        for rw in rws(Target::Storage) {
+            if let Rw::AccountStorage {
+                tx_id,
+                account_address,
+                storage_key,
+                ..
+            } = rw
+            {
+                if let Some(block) = tx_blocks.get(tx_id).and_then(|n| blocks.get_mut(n)) {
+                    block.storage.insert((*account_address, *storage_key));
+                }
+            }
+        }
+        Self { blocks }
+    }
+}
+
+/// The slots of the `SLOAD` and `SSTORE` proofs of the execution results.
+fn traced_storage(block_trace: &BlockTrace) -> BTreeSet<(Address, Word)> {
+    block_trace
+        .execution_results
+        .iter()
+        .flat_map(|result| &result.exec_steps)
+        .filter(|step| matches!(step.op, OpcodeId::SLOAD | OpcodeId::SSTORE))
+        .filter_map(|step| step.extra_data.as_ref()?.proof_list.as_ref())
+        .flatten()
+        .filter_map(|wrapper| Some((wrapper.address?, wrapper.storage.as_ref()?.key?)))
+        .collect()
+}
+
+/// Add the bytes to the bloom, as the 3 bits of the M3:2048 filter of the yellow
+/// paper.
+fn accrue(bloom: &mut Bloom, input: &[u8]) {
+    let hash = keccak256(input);
+    for i in 0..3 {
+        let bit = ((usize::from(hash[2 * i]) << 8) | usize::from(hash[2 * i + 1])) & 2047;
+        bloom.0[255 - bit / 8] |= 1 << (bit % 8);
+    }
+}
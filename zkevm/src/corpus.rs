@@ -0,0 +1,126 @@
+//! Circuit utilization over a corpus of block traces.
+//!
+//! Only witness generation and row accounting are run, block by block, so that
+//! chunk sizes and degrees can be picked from the utilization of historical blocks.
+
+use crate::circuit::{calculate_row_usage_of_trace, circuit_capacity, SUB_CIRCUIT_NAMES};
+use crate::utils::read_block_trace_from_file;
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+
+pub use crate::utils::trace_files;
+
+/// Row usage of a single block, or why it couldn't be computed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BlockUtilization {
+    pub path: String,
+    pub block_number: Option<u64>,
+    pub num_txs: usize,
+    pub calldata_len: usize,
+    /// Rows of each sub circuit, in the order of `SUB_CIRCUIT_NAMES`.
+    pub rows: Vec<usize>,
+    pub witness_ms: u128,
+    pub error: Option<String>,
+}
+
+impl BlockUtilization {
+    pub fn of_file(path: &Path) -> Self {
+        let mut utilization = Self {
+            path: path.display().to_string(),
+            ..Default::default()
+        };
+        let trace = match read_block_trace_from_file(path) {
+            Ok(trace) => trace,
+            Err(e) => {
+                utilization.error = Some(e.to_string());
+                return utilization;
+            }
+        };
+        utilization.block_number = trace.header.number.map(|n| n.as_u64());
+        utilization.num_txs = trace.transactions.len();
+        utilization.calldata_len = trace.transactions.iter().map(|tx| tx.data.len()).sum();
+
+        let start = Instant::now();
+        match calculate_row_usage_of_trace(&trace) {
+            Ok(rows) => utilization.rows = rows,
+            Err(e) => utilization.error = Some(e.to_string()),
+        }
+        utilization.witness_ms = start.elapsed().as_millis();
+        utilization
+    }
+
+    /// The sub circuit with the most rows, and its rows.
+    pub fn bottleneck(&self) -> Option<(&'static str, usize)> {
+        SUB_CIRCUIT_NAMES
+            .iter()
+            .zip(&self.rows)
+            .max_by_key(|(_, rows)| **rows)
+            .map(|(name, rows)| (*name, *rows))
+    }
+}
+
+/// Utilization of every trace under `dir`, computed in parallel, in path order.
+pub fn analyze_dir(dir: &Path) -> std::io::Result<Vec<BlockUtilization>> {
+    let files = trace_files(dir)?;
+    log::info!("analyzing {} traces under {}", files.len(), dir.display());
+    Ok(files
+        .par_iter()
+        .map(|path| BlockUtilization::of_file(path))
+        .collect())
+}
+
+/// One CSV row per block: the rows of each sub circuit, then the bottleneck and
+/// its share of the capacity of a super circuit at `DEGREE`.
+pub fn write_csv(mut writer: impl Write, blocks: &[BlockUtilization]) -> std::io::Result<()> {
+    let capacity = circuit_capacity();
+    let mut header = vec!["path", "block", "num_txs", "calldata_len"];
+    header.extend(SUB_CIRCUIT_NAMES);
+    header.extend([
+        "max_rows",
+        "bottleneck",
+        "utilization",
+        "witness_ms",
+        "error",
+    ]);
+    writeln!(writer, "{}", header.join(","))?;
+
+    for block in blocks {
+        let mut fields = vec![
+            csv_field(&block.path),
+            block
+                .block_number
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+            block.num_txs.to_string(),
+            block.calldata_len.to_string(),
+        ];
+        if block.rows.len() == SUB_CIRCUIT_NAMES.len() {
+            fields.extend(block.rows.iter().map(|rows| rows.to_string()));
+        } else {
+            fields.extend(SUB_CIRCUIT_NAMES.iter().map(|_| String::new()));
+        }
+        match block.bottleneck() {
+            Some((name, rows)) => fields.extend([
+                rows.to_string(),
+                name.to_string(),
+                format!("{:.4}", rows as f64 / capacity as f64),
+            ]),
+            None => fields.extend([String::new(), String::new(), String::new()]),
+        }
+        fields.push(block.witness_ms.to_string());
+        fields.push(csv_field(block.error.as_deref().unwrap_or_default()));
+        writeln!(writer, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
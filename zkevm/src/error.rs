@@ -0,0 +1,206 @@
+//! Errors of the zkevm library.
+//!
+//! `ZkEvmError` wraps one error type per failure category, so that embedders can
+//! match on the category, e.g. retry a `Capacity` error with fewer blocks while
+//! paging on a `Keygen` error. Errors of the underlying crates are kept as the
+//! `Debug` output, as not all of them implement `std::error::Error`.
+
+use crate::instance::FieldDiff;
+use std::io;
+use thiserror::Error;
+
+pub type Result<T, E = ZkEvmError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum ZkEvmError {
+    #[error(transparent)]
+    Trace(#[from] TraceError),
+    #[error(transparent)]
+    Capacity(#[from] CapacityError),
+    #[error(transparent)]
+    Params(#[from] ParamsError),
+    #[error(transparent)]
+    Keygen(#[from] KeygenError),
+    #[error(transparent)]
+    Proving(#[from] ProvingError),
+    #[error(transparent)]
+    Verification(#[from] VerificationError),
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// The block traces can't be read or turned into a witness block.
+#[derive(Debug, Error)]
+pub enum TraceError {
+    #[error("failed to read block trace {path}: {source}")]
+    Read {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to parse block trace {path}: {reason}")]
+    Parse { path: String, reason: String },
+    #[error("invalid block trace: {0}")]
+    Invalid(String),
+    #[error("failed to build witness block: {0}")]
+    Witness(String),
+    #[error("{target} at {location} is not supported")]
+    Unsupported { target: String, location: String },
+    #[error("first block of the batch skipped, {target} at {location}")]
+    Skipped { target: String, location: String },
+}
+
+/// The block traces don't fit into the circuits.
+#[derive(Debug, Error)]
+pub enum CapacityError {
+    #[error("too many blocks: {num_blocks}, at most {max_blocks}")]
+    TooManyBlocks {
+        num_blocks: usize,
+        max_blocks: usize,
+    },
+    #[error("circuit capacity not enough for the first non-empty block")]
+    FirstBlockTooLarge,
+    #[error("circuit capacity not enough for tx {tx_index} of block {block_number:?}")]
+    TxTooLarge {
+        tx_index: usize,
+        block_number: Option<u64>,
+    },
+    #[error("only {proved} of the {total} blocks of the chunk fit into the circuit")]
+    ChunkTruncated { proved: usize, total: usize },
+    #[error("too many chunks: {num_chunks}, at most {max_chunks} per batch")]
+    TooManyChunks {
+        num_chunks: usize,
+        max_chunks: usize,
+    },
+    #[error("circuit not enough: DEGREE = {degree}, less than k needed: {needed}")]
+    DegreeTooLow { degree: usize, needed: u32 },
+}
+
+/// The params or the seed can't be loaded or created.
+#[derive(Debug, Error)]
+pub enum ParamsError {
+    #[error("params {path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{0} should be a folder")]
+    NotADir(String),
+    #[error(
+        "invalid params file len {actual} for degree {degree}, expected {expected}. \
+         check DEGREE or remove the invalid params file"
+    )]
+    InvalidLength {
+        degree: usize,
+        actual: u64,
+        expected: u64,
+    },
+    #[error(
+        "params {path} are of degree {actual}, expected at least {expected}. \
+         check DEGREE/AGG_DEGREE or the params file"
+    )]
+    DegreeMismatch {
+        path: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error(
+        "params {path} of {len} bytes are neither raw nor compressed params of degree \
+         {file_degree}, they may be of another curve or format"
+    )]
+    UnknownFormat {
+        path: String,
+        file_degree: usize,
+        len: u64,
+    },
+    #[error("params {path} has sha256 {actual}, expected {expected}")]
+    DigestMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("no params of degree {degree}, the inner params are of degree {params_degree}")]
+    DegreeTooHigh { degree: u32, params_degree: u32 },
+    #[error("invalid aggregation circuit config: {0}")]
+    InvalidAggConfig(String),
+    #[error("seed {path}: {source}")]
+    Seed {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("seed {path} can't be encrypted or decrypted: {reason}")]
+    SeedKey { path: String, reason: String },
+}
+
+/// The keys of a circuit can't be generated, or aren't the expected ones.
+#[derive(Debug, Error)]
+pub enum KeygenError {
+    #[error("failed to generate {circuit} {key}: {reason}")]
+    Generate {
+        circuit: String,
+        key: &'static str,
+        reason: String,
+    },
+    #[error("failed to read {circuit} vk: {reason}")]
+    InvalidVk { circuit: String, reason: String },
+    #[error("vk digest mismatch: expected {expected}, actual {actual}")]
+    VkDigestMismatch { expected: String, actual: String },
+    #[error("pk file {path}: {reason}")]
+    InvalidPk { path: String, reason: String },
+}
+
+/// The prover failed on a valid witness.
+#[derive(Debug, Error)]
+pub enum ProvingError {
+    #[error("failed to build {circuit} circuit: {reason}")]
+    Circuit { circuit: String, reason: String },
+    #[error("mock prover of {circuit} failed: {reason}")]
+    MockProver { circuit: String, reason: String },
+    #[error("proving timed out after {elapsed:?}, before {phase}")]
+    Timeout {
+        phase: String,
+        elapsed: std::time::Duration,
+    },
+    #[error("proving cancelled before {phase}")]
+    Cancelled { phase: String },
+    #[error("no unfinished aggregation in {dir}")]
+    NothingToResume { dir: String },
+    #[error("unfinished aggregation in {dir} is of other traces")]
+    ResumeTracesMismatch { dir: String },
+    #[error("witness artifact {reason}")]
+    InvalidWitness { reason: String },
+    #[error("tee attestation failed: {0}")]
+    Attestation(String),
+}
+
+/// A proof can't be checked, or doesn't verify.
+#[derive(Debug, Error)]
+pub enum VerificationError {
+    #[error("aggregation verification key is not found")]
+    MissingVk,
+    #[error("no archived vk of circuit version {0}")]
+    UnknownVersion(String),
+    #[error("invalid {what}: {reason}")]
+    Invalid { what: &'static str, reason: String },
+    #[error("{circuit} proof verification failed")]
+    Failed { circuit: String },
+    #[error("invalid tee attestation: {0}")]
+    Attestation(String),
+    #[error("{field} mismatch: expected {expected}, got {actual}")]
+    InstanceMismatch {
+        field: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("instance doesn't match the traces: {}", diff_names(.0))]
+    TracesMismatch(Vec<FieldDiff>),
+}
+
+fn diff_names(diffs: &[FieldDiff]) -> String {
+    let names: Vec<_> = diffs.iter().map(|diff| diff.name.as_str()).collect();
+    names.join(", ")
+}
@@ -0,0 +1,366 @@
+//! Layout of the instance column of the aggregation circuit.
+//!
+//! The column holds the limbs of the KZG accumulator, followed by the instances
+//! of the inner snarks, then the chain id. The instance of a super circuit snark
+//! is the public input hash of its chunk, as the high and low 128 bits.
+//!
+//! `InstanceLayout` gives the row of each field, `AggInstance` encodes and
+//! decodes the fields, so that consumers don't index the column themselves. The
+//! state roots, data hash and block count of a chunk are not in the column, they
+//! are committed to by its public input hash, see `ChunkInfo`.
+
+use crate::chunk::ChunkInfo;
+use crate::error::VerificationError;
+use crate::io::{deserialize_fr_tensor, serialize_fr_tensor};
+use eth_types::H256;
+use halo2curves::bn256::Fr;
+use halo2curves::group::ff::PrimeField;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+
+/// Limbs of the two G1 points of the accumulator.
+pub const ACCUMULATOR_LIMBS: usize = 12;
+/// Limbs of a coordinate of an accumulator point.
+const LIMBS_PER_COORDINATE: usize = 3;
+/// Field elements of the instance of an inner snark.
+pub const INNER_INSTANCE_LEN: usize = 2;
+
+/// Rows of the fields in the instance column of an aggregation of `num_snarks`
+/// inner snarks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstanceLayout {
+    pub num_snarks: usize,
+}
+
+/// A field of the instance column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstanceField {
+    /// Limb of a coordinate of a point of the accumulator, the points being
+    /// `lhs` then `rhs` and the coordinates `x` then `y`.
+    AccumulatorLimb {
+        point: usize,
+        coordinate: usize,
+        limb: usize,
+    },
+    /// High (0) or low (1) 128 bits of the public input hash of an inner snark.
+    PiHashHalf {
+        snark: usize,
+        half: usize,
+    },
+    ChainId,
+}
+
+impl fmt::Display for InstanceField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AccumulatorLimb {
+                point,
+                coordinate,
+                limb,
+            } => write!(
+                f,
+                "accumulator.{}.{}[{limb}]",
+                ["lhs", "rhs"][*point],
+                ["x", "y"][*coordinate]
+            ),
+            Self::PiHashHalf { snark, half } => {
+                write!(f, "snark[{snark}].pi_hash.{}", ["hi", "lo"][*half])
+            }
+            Self::ChainId => write!(f, "chain_id"),
+        }
+    }
+}
+
+impl InstanceLayout {
+    pub fn new(num_snarks: usize) -> Self {
+        Self { num_snarks }
+    }
+
+    /// The layout of a column of `len` field elements.
+    pub fn of_column_len(len: usize) -> Result<Self, String> {
+        if len < ACCUMULATOR_LIMBS + 1 || (len - ACCUMULATOR_LIMBS - 1) % INNER_INSTANCE_LEN != 0 {
+            return Err(format!(
+                "{len} field elements don't fit {ACCUMULATOR_LIMBS} accumulator limbs, pairs of hash halves and a chain id"
+            ));
+        }
+        Ok(Self::new(
+            (len - ACCUMULATOR_LIMBS - 1) / INNER_INSTANCE_LEN,
+        ))
+    }
+
+    /// Field elements of the column.
+    pub fn column_len(&self) -> usize {
+        ACCUMULATOR_LIMBS + self.num_snarks * INNER_INSTANCE_LEN + 1
+    }
+
+    pub fn accumulator(&self) -> Range<usize> {
+        0..ACCUMULATOR_LIMBS
+    }
+
+    /// Rows of the instance of the `i`th inner snark.
+    pub fn snark(&self, i: usize) -> Range<usize> {
+        let start = ACCUMULATOR_LIMBS + i * INNER_INSTANCE_LEN;
+        start..start + INNER_INSTANCE_LEN
+    }
+
+    pub fn chain_id(&self) -> usize {
+        self.column_len() - 1
+    }
+
+    /// The field at a row, None past the column.
+    pub fn field(&self, row: usize) -> Option<InstanceField> {
+        if row < ACCUMULATOR_LIMBS {
+            return Some(InstanceField::AccumulatorLimb {
+                point: row / (2 * LIMBS_PER_COORDINATE),
+                coordinate: row / LIMBS_PER_COORDINATE % 2,
+                limb: row % LIMBS_PER_COORDINATE,
+            });
+        }
+        match row.cmp(&self.chain_id()) {
+            std::cmp::Ordering::Less => Some(InstanceField::PiHashHalf {
+                snark: (row - ACCUMULATOR_LIMBS) / INNER_INSTANCE_LEN,
+                half: (row - ACCUMULATOR_LIMBS) % INNER_INSTANCE_LEN,
+            }),
+            std::cmp::Ordering::Equal => Some(InstanceField::ChainId),
+            std::cmp::Ordering::Greater => None,
+        }
+    }
+}
+
+/// The public inputs of an agg proof, decoded from its instance column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggInstance {
+    pub accumulator: Vec<Fr>,
+    /// Public input hash of each inner snark, in order.
+    pub pi_hashes: Vec<H256>,
+    pub chain_id: u64,
+}
+
+impl AggInstance {
+    /// Decode the serialized instance of an `AggCircuitProof`.
+    pub fn decode(buf: &[u8]) -> Result<Self, VerificationError> {
+        let column = decode_column(buf)?;
+        Self::from_column(&column).map_err(invalid)
+    }
+
+    pub fn from_column(column: &[Fr]) -> Result<Self, String> {
+        let layout = InstanceLayout::of_column_len(column.len())?;
+        let pi_hashes = (0..layout.num_snarks)
+            .map(|i| {
+                let halves = &column[layout.snark(i)];
+                hash_from_halves(halves[0], halves[1])
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            accumulator: column[layout.accumulator()].to_vec(),
+            pi_hashes,
+            chain_id: chain_id_of(&column[layout.chain_id()])?,
+        })
+    }
+
+    /// The instance of an aggregation of the chunks, one snark each, with the
+    /// accumulator of the proof checked: the public input hash of each chunk and
+    /// their chain id.
+    pub fn of_chunks(accumulator: Vec<Fr>, chunks: &[ChunkInfo]) -> Result<Self, String> {
+        if accumulator.len() != ACCUMULATOR_LIMBS {
+            return Err(format!(
+                "{} accumulator limbs, expected {ACCUMULATOR_LIMBS}",
+                accumulator.len()
+            ));
+        }
+        let chain_id = chunks.first().ok_or("no chunks")?.chain_id;
+        if let Some(chunk) = chunks.iter().find(|chunk| chunk.chain_id != chain_id) {
+            return Err(format!(
+                "chunks of chain ids {chain_id} and {}",
+                chunk.chain_id
+            ));
+        }
+        Ok(Self {
+            accumulator,
+            pi_hashes: chunks.iter().map(ChunkInfo::public_input_hash).collect(),
+            chain_id,
+        })
+    }
+
+    pub fn layout(&self) -> InstanceLayout {
+        InstanceLayout::new(self.pi_hashes.len())
+    }
+
+    /// The instance column, the inverse of `from_column`.
+    pub fn to_column(&self) -> Vec<Fr> {
+        let mut column = Vec::with_capacity(self.layout().column_len());
+        column.extend_from_slice(&self.accumulator);
+        for hash in &self.pi_hashes {
+            column.extend(hash_to_halves(*hash));
+        }
+        column.push(Fr::from(self.chain_id));
+        column
+    }
+
+    /// Serialize as the instance of an `AggCircuitProof`, the inverse of `decode`.
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(&serialize_fr_tensor(&[vec![self.to_column()]])).unwrap()
+    }
+}
+
+/// The instance of an inner snark committing to `hash`.
+pub fn hash_to_halves(hash: H256) -> [Fr; 2] {
+    let hi = u128::from_be_bytes(hash[..16].try_into().unwrap());
+    let lo = u128::from_be_bytes(hash[16..].try_into().unwrap());
+    [Fr::from_u128(hi), Fr::from_u128(lo)]
+}
+
+/// The chain id of the `InstanceLayout::chain_id` row of an instance column.
+pub fn chain_id_of(fr: &Fr) -> Result<u64, String> {
+    let repr = fr.to_repr();
+    if repr.as_ref()[8..].iter().any(|b| *b != 0) {
+        return Err(format!("chain id {fr:?} has more than 64 bits"));
+    }
+    Ok(u64::from_le_bytes(repr.as_ref()[..8].try_into().unwrap()))
+}
+
+fn hash_from_halves(hi: Fr, lo: Fr) -> Result<H256, String> {
+    let mut hash = H256::zero();
+    for (half, bytes) in [hi, lo].into_iter().zip(hash.0.chunks_mut(16)) {
+        // little endian repr
+        let repr = half.to_repr();
+        if repr.as_ref()[16..].iter().any(|b| *b != 0) {
+            return Err(format!("{half:?} has more than 128 bits"));
+        }
+        let mut half_bytes = repr.as_ref()[..16].to_vec();
+        half_bytes.reverse();
+        bytes.copy_from_slice(&half_bytes);
+    }
+    Ok(hash)
+}
+
+fn invalid(reason: String) -> VerificationError {
+    VerificationError::Invalid {
+        what: "aggregation instance",
+        reason,
+    }
+}
+
+/// The instance column from either the serialized instance of an `AggCircuitProof`,
+/// or the EVM calldata encoding: 32-byte big endian words.
+pub fn decode_column(buf: &[u8]) -> Result<Vec<Fr>, VerificationError> {
+    let raw: Vec<Vec<Vec<Vec<u8>>>> = match serde_json::from_slice(buf) {
+        Ok(raw) => raw,
+        Err(e) if buf.is_empty() || buf.len() % 32 != 0 => return Err(invalid(e.to_string())),
+        Err(_) => {
+            return buf
+                .chunks(32)
+                .map(|word| {
+                    let mut repr = [0u8; 32];
+                    repr.copy_from_slice(word);
+                    repr.reverse();
+                    Option::from(Fr::from_repr(repr)).ok_or_else(|| {
+                        invalid(format!("0x{} is not a field element", hex::encode(word)))
+                    })
+                })
+                .collect()
+        }
+    };
+    // deserialize_fr_tensor panics on malformed field elements
+    for fr in raw.iter().flatten().flatten() {
+        let repr: [u8; 32] = fr
+            .clone()
+            .try_into()
+            .map_err(|_| invalid("field element of other than 32 bytes".to_string()))?;
+        if bool::from(Fr::from_repr(repr).is_none()) {
+            return Err(invalid(format!(
+                "0x{} is not a field element",
+                hex::encode(fr)
+            )));
+        }
+    }
+    match &deserialize_fr_tensor(raw)[..] {
+        [columns] if columns.len() == 1 => Ok(columns[0].clone()),
+        _ => Err(invalid("expected a single instance column".to_string())),
+    }
+}
+
+/// Name and hex value of each field of an instance column. The fields of a
+/// column which doesn't fit the layout are named by their index.
+pub fn named_fields(column: &[Fr]) -> Vec<(String, String)> {
+    let hex_fr = |fr: &Fr| {
+        let mut bytes = fr.to_repr().as_ref().to_vec();
+        bytes.reverse();
+        format!("0x{}", hex::encode(bytes))
+    };
+    let instance = match AggInstance::from_column(column) {
+        Ok(instance) => instance,
+        Err(_) => {
+            return column
+                .iter()
+                .enumerate()
+                .map(|(i, fr)| (format!("fr[{i}]"), hex_fr(fr)))
+                .collect()
+        }
+    };
+
+    let layout = instance.layout();
+    let mut fields = vec![];
+    for (row, limb) in instance.accumulator.iter().enumerate() {
+        let name = layout.field(row).unwrap().to_string();
+        fields.push((name, hex_fr(limb)));
+    }
+    for (i, hash) in instance.pi_hashes.iter().enumerate() {
+        fields.push((format!("snark[{i}].pi_hash"), format!("{hash:?}")));
+    }
+    fields.push((
+        InstanceField::ChainId.to_string(),
+        instance.chain_id.to_string(),
+    ));
+    fields
+}
+
+/// A field which differs between two instances, `None` on the side missing it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub name: String,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side =
+            |value: &Option<String>| value.clone().unwrap_or_else(|| "<missing>".to_string());
+        write!(
+            f,
+            "{}:\n  - {}\n  + {}",
+            self.name,
+            side(&self.left),
+            side(&self.right)
+        )
+    }
+}
+
+/// Decode two instance blobs and list the fields which differ, in column order.
+pub fn diff_instances(left: &[u8], right: &[u8]) -> Result<Vec<FieldDiff>, VerificationError> {
+    let left = named_fields(&decode_column(left)?);
+    let right = named_fields(&decode_column(right)?);
+    let mut diffs = vec![];
+    for (name, value) in &left {
+        let other = right.iter().find(|(n, _)| n == name).map(|(_, v)| v);
+        if other != Some(value) {
+            diffs.push(FieldDiff {
+                name: name.clone(),
+                left: Some(value.clone()),
+                right: other.cloned(),
+            });
+        }
+    }
+    for (name, value) in &right {
+        if !left.iter().any(|(n, _)| n == name) {
+            diffs.push(FieldDiff {
+                name: name.clone(),
+                left: None,
+                right: Some(value.clone()),
+            });
+        }
+    }
+    Ok(diffs)
+}
@@ -3,14 +3,16 @@ use std::{
     path::PathBuf,
 };
 
+#[cfg(feature = "prover")]
 use halo2_proofs::{
-    halo2curves::bn256::{Bn256, Fq, Fr, G1Affine},
+    halo2curves::bn256::Bn256,
     plonk::VerifyingKey,
     poly::{commitment::Params, kzg::commitment::ParamsKZG},
     SerdeFormat,
 };
+use halo2curves::bn256::{Fq, Fr, G1Affine};
+use halo2curves::group::ff::PrimeField;
 use num_bigint::BigUint;
-use snark_verifier::util::arithmetic::PrimeField;
 
 pub fn serialize_fr(f: &Fr) -> Vec<u8> {
     f.to_bytes().to_vec()
@@ -113,6 +115,7 @@ pub fn load_verify_circuit_proof(folder: &mut PathBuf) -> Vec<u8> {
     read_file(folder, "verify_circuit_proof.data")
 }
 
+#[cfg(feature = "prover")]
 pub fn write_verify_circuit_params(folder: &mut PathBuf, verify_circuit_params: &ParamsKZG<Bn256>) {
     folder.push("verify_circuit.params");
     let mut fd = std::fs::File::create(folder.as_path()).unwrap();
@@ -121,6 +124,7 @@ pub fn write_verify_circuit_params(folder: &mut PathBuf, verify_circuit_params:
     verify_circuit_params.write(&mut fd).unwrap();
 }
 
+#[cfg(feature = "prover")]
 pub fn serialize_vk(vk: &VerifyingKey<G1Affine>) -> Vec<u8> {
     let mut result = Vec::<u8>::new();
     vk.write(&mut result, SerdeFormat::Processed).unwrap();
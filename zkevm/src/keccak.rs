@@ -0,0 +1,54 @@
+//! Keccak witness rows computed on the rayon pool.
+//!
+//! `multi_keccak` of the keccak circuit hashes the inputs of a block one after
+//! another, which dominates witness generation of hash-heavy blocks. The rows of
+//! an input don't depend on the other inputs, so `multi_keccak_parallel` hashes
+//! them in parallel and concatenates the rows in input order, giving the rows of
+//! `multi_keccak`.
+//!
+//! Proving doesn't use it: the keccak circuit of zkevm-circuits calls its own
+//! `multi_keccak` when assigned, and takes no other, so witness generation is as
+//! sequential as before. It backs the `hot_paths` bench, measuring what a keccak
+//! circuit patched to call it would gain.
+
+use eth_types::Field;
+use halo2_proofs::circuit::Value;
+use halo2_proofs::plonk::Error;
+use rayon::prelude::*;
+use zkevm_circuits::keccak_circuit::keccak_packed_multi::{keccak, multi_keccak, KeccakRow};
+use zkevm_circuits::util::Challenges;
+
+/// The rows of keccak of every input, padded with hashes of no data up to
+/// `capacity` hashes if set, as `multi_keccak`.
+pub fn multi_keccak_parallel<F: Field>(
+    inputs: &[Vec<u8>],
+    challenges: Challenges<Value<F>>,
+    capacity: Option<usize>,
+) -> Result<Vec<KeccakRow<F>>, Error> {
+    // the dummy rows at the start
+    let mut rows = multi_keccak(&[], challenges, None)?;
+    let num_dummy_rows = rows.len();
+    let hashed: Vec<Vec<KeccakRow<F>>> = inputs
+        .par_iter()
+        .map(|input| {
+            let mut rows = vec![];
+            keccak(&mut rows, input, challenges);
+            rows
+        })
+        .collect();
+    rows.reserve(hashed.iter().map(Vec::len).sum());
+    rows.extend(hashed.into_iter().flatten());
+
+    if let Some(capacity) = capacity {
+        let mut padding = vec![];
+        keccak(&mut padding, &[], challenges);
+        let num_rows = num_dummy_rows + capacity * padding.len();
+        if rows.len() > num_rows {
+            return Err(Error::BoundsFailure);
+        }
+        while rows.len() < num_rows {
+            rows.extend(padding.iter().cloned());
+        }
+    }
+    Ok(rows)
+}
@@ -1,33 +1,53 @@
+//! Without the default `prover` feature only the types are built: the block
+//! traces, the proofs and their instance, the errors and the io helpers, without
+//! the circuits nor the aggregation. The `verify` feature adds the aggregation
+//! circuit and the native verification of its proofs, see `aggregation`.
+
+#[cfg(feature = "verify")]
+pub mod aggregation;
+pub mod artifact;
+pub mod attestation;
+pub mod audit;
+pub mod chunk;
+#[cfg(feature = "prover")]
 pub mod circuit;
+#[cfg(feature = "prover")]
+pub mod corpus;
+pub mod error;
+pub mod instance;
 // pub mod inner;
 pub mod io;
+#[cfg(feature = "prover")]
+pub mod keccak;
+pub mod proof;
+pub mod provenance;
+#[cfg(feature = "prover")]
 pub mod prover;
+#[cfg(feature = "prover")]
+pub mod service;
+pub mod skip;
+pub mod trie_repair;
+#[cfg(feature = "prover")]
+pub mod tune;
 pub mod utils;
+#[cfg(feature = "prover")]
 pub mod verifier;
+pub mod version;
 
 // Terminology used throughout this library.
 //
 // - Inner Circuit / Target Circuit / Super Circuit: they all mean the same thing.
 // The first circuit. It takes inputs from block traces, and produces proofs pi_1 that are NOT verified on chain.
 //
-// - Target Circuit proof: proof for the Inner circuit.
+// - Target Circuit proof: proof for the Inner circuit, with a Poseidon transcript so that
+// it is cheap to verify inside the aggregation circuit.
 //
 // - Aggregation Circuit.
 // The second circuit. It takes pi_1 from previous section, and produces proofs pi_2 that are verified on chain.
 //
-// - AggCircuitProof: proof for the aggregation circuit.
+// - AggCircuitProof: proof for the aggregation circuit, with a Keccak transcript so that
+// it is verified by the EVM.
 //
 // - Prover: the prover that is responsible for the whole process.
 // I.e., aggregation prover that takes in a list of traces, produces
 // a proof that can be verified on chain
-
-pub mod proof {
-    use crate::prover::AggCircuitProof;
-    use serde_derive::{Deserialize, Serialize};
-
-    #[derive(Serialize, Deserialize, Debug)]
-    pub struct ZkProof {
-        pub id: u64,
-        pub agg_proof: AggCircuitProof,
-    }
-}
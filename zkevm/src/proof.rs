@@ -0,0 +1,125 @@
+//! The proofs handed out of the prover, available without the `prover` feature
+//! to the crates only reading them.
+
+mod canonical;
+
+pub use canonical::{canonical_digest, to_canonical_json};
+
+use crate::attestation::Attestation;
+use crate::io::{
+    write_verify_circuit_instance, write_verify_circuit_proof, write_verify_circuit_vk,
+};
+use crate::provenance::ProvenanceManifest;
+use crate::version::CircuitVersion;
+use serde_derive::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use types::{base64, hex};
+
+#[cfg(feature = "prover")]
+use crate::instance::decode_column;
+#[cfg(feature = "prover")]
+use snark_verifier::loader::evm::encode_calldata;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ZkProof {
+    pub id: u64,
+    pub agg_proof: AggCircuitProof,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct AggCircuitProof {
+    #[serde(with = "base64")]
+    pub proof: Vec<u8>,
+    #[serde(with = "base64")]
+    pub instance: Vec<u8>,
+    #[serde(with = "base64")]
+    pub vk: Vec<u8>,
+    pub total_proved_block_count: usize,
+    #[serde(default)]
+    pub circuit_version: CircuitVersion,
+    /// TEE quote over the traces, instance and vk, see `Prover::set_attester`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<Attestation>,
+    /// How the proof was made, see `provenance`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<ProvenanceManifest>,
+}
+
+impl AggCircuitProof {
+    pub fn write_to_dir(&self, out_dir: &mut PathBuf) {
+        write_verify_circuit_instance(out_dir, &self.instance);
+        write_verify_circuit_proof(out_dir, &self.proof);
+        write_verify_circuit_vk(out_dir, &self.vk);
+
+        out_dir.push("full_proof.data");
+        let mut fd = std::fs::File::create(out_dir.as_path()).unwrap();
+        out_dir.pop();
+        serde_json::to_writer_pretty(&mut fd, &self).unwrap();
+        self.write_manifest_to_dir(out_dir);
+    }
+
+    /// Write the proof in the coordinator JSON schema into `out_dir`.
+    pub fn write_coordinator_json_to_dir(&self, out_dir: &mut PathBuf) {
+        out_dir.push("coordinator_proof.json");
+        let mut fd = std::fs::File::create(out_dir.as_path()).unwrap();
+        out_dir.pop();
+        serde_json::to_writer_pretty(&mut fd, &CoordinatorProof::from(self)).unwrap();
+        self.write_manifest_to_dir(out_dir);
+    }
+
+    fn write_manifest_to_dir(&self, out_dir: &Path) {
+        if let Some(manifest) = &self.manifest {
+            manifest.write_to_dir(out_dir).unwrap();
+        }
+    }
+
+    /// Calldata of the verifier contract: the instance column as 32-byte big
+    /// endian words, then the proof. The column starts with the limbs of the
+    /// accumulator, which the contract reads back into the two G1 points it
+    /// checks the pairing of, so the limbs are passed as they are, see
+    /// `InstanceLayout`.
+    #[cfg(feature = "prover")]
+    pub fn encode_calldata(&self) -> crate::error::Result<Vec<u8>> {
+        let column = decode_column(&self.instance)?;
+        Ok(encode_calldata(&[column], &self.proof))
+    }
+
+    /// Serialize the proof into the JSON schema consumed by the coordinator/relayer.
+    pub fn to_coordinator_json(&self) -> crate::error::Result<String> {
+        Ok(serde_json::to_string(&CoordinatorProof::from(self))?)
+    }
+
+    /// The canonical JSON of the proof, the same bytes on every machine.
+    pub fn to_canonical_json(&self) -> crate::error::Result<String> {
+        to_canonical_json(self)
+    }
+
+    /// sha256 of the canonical JSON of the proof, in hex.
+    pub fn canonical_digest(&self) -> crate::error::Result<String> {
+        canonical_digest(self)
+    }
+}
+
+/// The aggregation proof in the schema expected by the coordinator and relayer:
+/// snake_case field names and `0x`-prefixed hex encoded bytes.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct CoordinatorProof {
+    #[serde(with = "hex")]
+    pub proof: Vec<u8>,
+    #[serde(with = "hex")]
+    pub instance: Vec<u8>,
+    #[serde(with = "hex")]
+    pub vk: Vec<u8>,
+    pub block_count: usize,
+}
+
+impl From<&AggCircuitProof> for CoordinatorProof {
+    fn from(p: &AggCircuitProof) -> Self {
+        Self {
+            proof: p.proof.clone(),
+            instance: p.instance.clone(),
+            vk: p.vk.clone(),
+            block_count: p.total_proved_block_count,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Canonical JSON of the proofs, so that the same proof gives the same bytes on
+//! every machine, and in every language following the same rules, e.g. to
+//! content-hash proofs or compare them byte for byte.
+//!
+//! The rules are those of JSON canonicalization (RFC 8785) for the values the
+//! proofs hold:
+//! - no whitespace;
+//! - object keys sorted by their UTF-16 code units;
+//! - integers in decimal without exponent nor leading zeros, written in full even
+//!   above 2^53; floats are refused, as no proof field holds one;
+//! - strings escaped with `\"`, `\\`, `\b`, `\f`, `\n`, `\r` and `\t`, the other
+//!   control characters as `\u00xx` in lower case hex, everything else in UTF-8.
+//!
+//! Bytes keep the encoding of their field, e.g. base64 for the proof, instance
+//! and vk, so the canonical JSON is read back as any other JSON.
+
+use crate::error::Result;
+use serde::ser::Error;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// The canonical JSON of the value.
+pub fn to_canonical_json<T: Serialize>(value: &T) -> Result<String> {
+    let mut out = String::new();
+    write_value(&mut out, &serde_json::to_value(value)?)?;
+    Ok(out)
+}
+
+/// sha256 of the canonical JSON of the value, in hex.
+pub fn canonical_digest<T: Serialize>(value: &T) -> Result<String> {
+    Ok(hex::encode(Sha256::digest(to_canonical_json(value)?)))
+}
+
+fn write_value(out: &mut String, value: &Value) -> Result<()> {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => out.push_str(&u.to_string()),
+            (_, Some(i)) => out.push_str(&i.to_string()),
+            _ => {
+                return Err(
+                    serde_json::Error::custom(format!("float {n} in canonical json")).into(),
+                )
+            }
+        },
+        Value::String(s) => write_str(out, s),
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, value)?;
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_str(out, key);
+                out.push(':');
+                write_value(out, value)?;
+            }
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
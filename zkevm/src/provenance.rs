@@ -0,0 +1,140 @@
+//! Provenance manifest of agg proofs, so that a proof failing on chain long after
+//! it was made can be traced back to exactly what made it.
+//!
+//! The manifest records the hash of the traces, the digests of the params and of
+//! the proving keys, the circuit config, the build and the host of the prover,
+//! and when the proving started and ended. It is stored with the agg proofs of
+//! block traces and written next to them as `manifest.json`.
+//!
+//! Digesting the params and keys streams gigabytes through sha256, so a prover
+//! digests them once, the params when it is built and a pk when it is generated
+//! or loaded, the digest of a pk file being in its header, and stamps every
+//! manifest with the same digests; `PROVENANCE_MANIFEST=false` leaves the manifest
+//! out.
+
+use crate::utils::read_env_var;
+use crate::version::CircuitVersion;
+use eth_types::H256;
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// Store a provenance manifest with every agg proof of block traces.
+pub static PROVENANCE_MANIFEST: Lazy<bool> =
+    Lazy::new(|| read_env_var("PROVENANCE_MANIFEST", true));
+
+/// File of the manifest next to the proof.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProvenanceManifest {
+    /// sha256 of the JSON of the block traces, see `attestation::trace_hash`.
+    pub trace_hash: H256,
+    /// sha256 of the params of the inner circuits and of the agg circuit, as
+    /// serialized by `Params::write`.
+    pub params_digest: String,
+    pub agg_params_digest: String,
+    /// sha256 of the proving keys in the raw halo2 format by circuit name, `agg`
+    /// for the aggregation circuit, see `PkHeader::pk_digest`.
+    pub pk_digests: BTreeMap<String, String>,
+    /// Digest of the vk of the proof, see `utils::vk_digest`.
+    pub vk_digest: String,
+    pub circuit: CircuitConfig,
+    pub build: BuildInfo,
+    pub hostname: String,
+    /// RFC 3339 times the proving started and ended at.
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+/// The config the keys of the proof depend on.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CircuitConfig {
+    pub degree: u32,
+    pub agg_degree: u32,
+    pub circuit_version: CircuitVersion,
+    /// Degrees of the circuits proved below `degree`, by circuit name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub circuit_degrees: BTreeMap<String, u32>,
+    /// The `AggConfig` of the prover, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agg_config: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub crate_version: String,
+    /// `git describe` of the tree the prover was built from.
+    pub git_commit: String,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: git_commit().to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "prover")]
+fn git_commit() -> &'static str {
+    git_version::git_version!(
+        args = ["--always", "--dirty", "--abbrev=40"],
+        fallback = "unknown"
+    )
+}
+
+/// Builds without the prover don't prove, their commit isn't recorded.
+#[cfg(not(feature = "prover"))]
+fn git_commit() -> &'static str {
+    "unknown"
+}
+
+impl ProvenanceManifest {
+    /// Write the manifest as `manifest.json` into `dir`.
+    pub fn write_to_dir(&self, dir: &Path) -> io::Result<()> {
+        let f = std::fs::File::create(dir.join(MANIFEST_FILE))?;
+        serde_json::to_writer_pretty(f, self)?;
+        Ok(())
+    }
+
+    pub fn read_from_dir(dir: &Path) -> io::Result<Self> {
+        let f = std::fs::File::open(dir.join(MANIFEST_FILE))?;
+        Ok(serde_json::from_reader(f)?)
+    }
+}
+
+/// sha256 of the bytes written into it, in hex, without keeping them in memory.
+#[derive(Default)]
+pub struct DigestWriter(Sha256);
+
+impl DigestWriter {
+    pub fn finish(self) -> String {
+        hex::encode(self.0.finalize())
+    }
+}
+
+impl io::Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Name of the host, from the kernel or `HOSTNAME`, `unknown` if neither.
+pub fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
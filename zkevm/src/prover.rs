@@ -1,29 +1,117 @@
-use crate::io::{
-    write_verify_circuit_instance, write_verify_circuit_proof, write_verify_circuit_vk,
-};
+use crate::attestation::Attester;
+use crate::audit::AuditLog;
+use crate::error::ProvingError;
+use crate::skip::{SkipList, SkipReport};
+use crate::trie_repair::TrieProofSource;
 use crate::utils::read_env_var;
+use crate::version::CircuitVersion;
 use halo2_proofs::halo2curves::bn256::{Bn256, G1Affine};
 use halo2_proofs::plonk::ProvingKey;
 use halo2_proofs::poly::kzg::commitment::ParamsKZG;
 use once_cell::sync::Lazy;
-use rand_xorshift::XorShiftRng;
 use serde_derive::{Deserialize, Serialize};
 use snark_verifier_sdk::Snark;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use types::base64;
 
+mod agg_config;
 mod evm;
 mod inner_circuit;
+mod memory;
 mod mock;
 mod outer_circuit;
+mod pipeline;
+mod pk_store;
+mod resume;
+mod rng;
 mod util;
+mod warm_up;
+mod witness;
+
+pub use crate::proof::{AggCircuitProof, CoordinatorProof};
+pub use agg_config::{AggConfig, AggStrategy};
+pub use memory::{
+    available_memory, AllocStats, BufferPool, LeakCheck, MemoryGate, MemoryPermit, WitnessMemory,
+    ALLOC_LEAK_CHECK_MB, WITNESS_RETAINED_MB,
+};
+pub(crate) use outer_circuit::inner_instance_hash;
+pub use pipeline::{
+    BatchProof, BlockRange, BundleProof, ChunkProof, PipelineOutput, MAX_CHUNKS_PER_BATCH,
+};
+pub use pk_store::{
+    write_pk_file, MappedPk, PkHeader, PkSection, PkStore, PK_DIR, PK_FORMAT_VERSION, PK_MAGIC,
+};
+pub use resume::{AggResumeState, AGG_RESUME_DIR};
+pub use rng::{derive_rng, prover_rng, rng_from_seed, ProverRng, RngSeed};
+pub use warm_up::{WarmUpReport, WarmUpStep};
+pub use witness::{WitnessArtifact, WITNESS_FORMAT_VERSION, WITNESS_MAGIC};
 
 #[cfg(target_os = "linux")]
 extern crate procfs;
 
 pub static OPT_MEM: Lazy<bool> = Lazy::new(|| read_env_var("OPT_MEM", false));
 pub static MOCK_PROVE: Lazy<bool> = Lazy::new(|| read_env_var("MOCK_PROVE", false));
+/// Expected sha256 digest (hex) of the aggregation vk. Empty means not pinned.
+pub static AGG_VK_DIGEST: Lazy<String> =
+    Lazy::new(|| read_env_var("AGG_VK_DIGEST", "".to_string()));
+/// Refuse to prove or verify on an agg vk digest mismatch, otherwise only warn.
+pub static AGG_VK_DIGEST_STRICT: Lazy<bool> =
+    Lazy::new(|| read_env_var("AGG_VK_DIGEST_STRICT", true));
+
+/// Wall-clock budget of a proving job, with cooperative cancellation.
+///
+/// The prover checks it between the phases of proving, i.e. witness generation,
+/// keygen and proving of each circuit; a phase in flight runs to completion.
+#[derive(Clone, Debug)]
+pub struct Deadline {
+    start: Instant,
+    timeout: Option<Duration>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            timeout: Some(timeout),
+            cancelled: Default::default(),
+        }
+    }
+
+    /// Never times out, but can be cancelled.
+    pub fn never() -> Self {
+        Self {
+            start: Instant::now(),
+            timeout: None,
+            cancelled: Default::default(),
+        }
+    }
+
+    /// Cancel the job, from any thread holding a clone of the deadline.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn check(&self, next_phase: &str) -> Result<(), ProvingError> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(ProvingError::Cancelled {
+                phase: next_phase.to_string(),
+            });
+        }
+        let elapsed = self.start.elapsed();
+        match self.timeout {
+            Some(timeout) if elapsed >= timeout => Err(ProvingError::Timeout {
+                phase: next_phase.to_string(),
+                elapsed,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct TargetCircuitProof {
@@ -33,29 +121,22 @@ pub struct TargetCircuitProof {
     pub vk: Vec<u8>,
     pub num_of_proved_blocks: usize,
     pub total_num_of_blocks: usize,
+    #[serde(default)]
+    pub circuit_version: CircuitVersion,
+    /// Why blocks were left out, if any were by the skip list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_report: Option<SkipReport>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Default)]
-pub struct AggCircuitProof {
-    #[serde(with = "base64")]
-    pub proof: Vec<u8>,
-    #[serde(with = "base64")]
-    pub instance: Vec<u8>,
-    #[serde(with = "base64")]
-    pub vk: Vec<u8>,
-    pub total_proved_block_count: usize,
-}
-
-impl AggCircuitProof {
-    pub fn write_to_dir(&self, out_dir: &mut PathBuf) {
-        write_verify_circuit_instance(out_dir, &self.instance);
-        write_verify_circuit_proof(out_dir, &self.proof);
-        write_verify_circuit_vk(out_dir, &self.vk);
+impl TargetCircuitProof {
+    /// The canonical JSON of the proof, see `proof::to_canonical_json`.
+    pub fn to_canonical_json(&self) -> crate::error::Result<String> {
+        crate::proof::to_canonical_json(self)
+    }
 
-        out_dir.push("full_proof.data");
-        let mut fd = std::fs::File::create(out_dir.as_path()).unwrap();
-        out_dir.pop();
-        serde_json::to_writer_pretty(&mut fd, &self).unwrap()
+    /// sha256 of the canonical JSON of the proof, in hex.
+    pub fn canonical_digest(&self) -> crate::error::Result<String> {
+        crate::proof::canonical_digest(self)
     }
 }
 
@@ -65,10 +146,50 @@ impl AggCircuitProof {
 pub struct Prover {
     pub params: ParamsKZG<Bn256>,
     pub agg_params: ParamsKZG<Bn256>,
-    pub rng: XorShiftRng,
+    /// The inner params downsized to the degrees of the circuits below them, see
+    /// `TargetCircuit::degree`.
+    pub degree_params: BTreeMap<u32, ParamsKZG<Bn256>>,
+    /// Randomness of the proofs, see `prover_rng`.
+    pub rng: ProverRng,
     /// We may have a list of public keys for different inner circuits.
     /// Those keys are stored as a hash map, and keyed by a `name` String.
     pub target_circuit_pks: HashMap<String, ProvingKey<G1Affine>>,
     pub agg_pk: Option<ProvingKey<G1Affine>>,
+    /// The snarks `agg_pk` aggregates, see `snark_layout`.
+    pub agg_pk_layout: Option<String>,
+    /// Digests of `params` and `agg_params` for the provenance manifests, taken
+    /// once when the prover is built, if `PROVENANCE_MANIFEST`.
+    pub params_digests: Option<[String; 2]>,
+    /// Digests of the pks for the provenance manifests by circuit name, `agg` for
+    /// the aggregation circuit, taken once as they are loaded or generated.
+    pub pk_digests: BTreeMap<String, String>,
+    /// Keys of the batch and bundle circuits, keyed by level and number of snarks.
+    pub level_pks: HashMap<String, ProvingKey<G1Affine>>,
+    /// Chunks of every batch, `MAX_CHUNKS_PER_BATCH` by default.
+    pub max_chunks_per_batch: Option<usize>,
+    /// Snark the batches are padded with, see `Prover::set_max_chunks_per_batch`.
+    pub dummy_chunk_snark: Option<Snark>,
     pub debug_dir: String,
+    /// Circuit version of the keys, stamped into every proof.
+    pub circuit_version: CircuitVersion,
+    /// Deadline of the current job, see `Prover::set_deadline`.
+    pub deadline: Option<Deadline>,
+    /// Aggregation circuit config, see `Prover::set_agg_config`.
+    pub agg_config: Option<AggConfig>,
+    /// Where aggregations persist their inputs, see `Prover::set_resume_dir`.
+    pub resume_dir: Option<PathBuf>,
+    /// Attests the agg proofs of block traces, see `Prover::set_attester`.
+    pub attester: Option<Arc<dyn Attester>>,
+    /// Opcodes and precompiles that skip a block or fail the batch, `SKIP_LIST` by default.
+    pub skip_list: SkipList,
+    /// Freed witness memory kept for the next job, `WITNESS_RETAINED_MB` by default.
+    pub witness_memory: WitnessMemory,
+    /// Buffers reused across jobs, capped as `witness_memory`.
+    pub buffer_pool: BufferPool,
+    /// Where the proofs generated are recorded, `AUDIT_LOG` by default.
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// Fetches the proofs missing from the storage traces, see `Prover::set_trie_proof_source`.
+    pub trie_proof_source: Option<Arc<dyn TrieProofSource>>,
+    /// Where the pks are loaded from and written to, `PK_DIR` by default.
+    pub pk_store: Option<Arc<PkStore>>,
 }
@@ -0,0 +1,175 @@
+//! Configuration of the aggregation circuit.
+//!
+//! The SDK reads it from the JSON file at `VERIFY_CONFIG`, both when building
+//! the circuit and in `configure`. A config set on the Prover is validated and
+//! written to a file that `VERIFY_CONFIG` is pointed to.
+
+use super::Prover;
+use crate::error::ParamsError;
+use crate::utils::read_env_var;
+use halo2_proofs::poly::commitment::Params;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+/// Bits of the base field of bn254, the limbs of a non-native element must cover them.
+const BASE_FIELD_BITS: usize = 254;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggStrategy {
+    Simple,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AggConfig {
+    pub strategy: AggStrategy,
+    pub degree: u32,
+    /// Advice columns per phase.
+    pub num_advice: Vec<usize>,
+    /// Advice columns copied into the lookup per phase.
+    pub num_lookup_advice: Vec<usize>,
+    pub num_fixed: usize,
+    /// The range check lookup table has `2^lookup_bits` rows.
+    pub lookup_bits: usize,
+    pub limb_bits: usize,
+    pub num_limbs: usize,
+}
+
+impl AggConfig {
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let f = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    /// The config the SDK would use, from the file at `VERIFY_CONFIG`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_file(&read_env_var(
+            "VERIFY_CONFIG",
+            "./configs/verify_circuit.config".to_string(),
+        ))
+    }
+
+    /// Config of the agg circuit at the default `AGG_DEGREE` of the `test-mode`
+    /// feature, which `configs/verify_circuit.config` is too large for.
+    pub fn test_mode() -> Self {
+        Self {
+            strategy: AggStrategy::Simple,
+            degree: 22,
+            num_advice: vec![8],
+            num_lookup_advice: vec![1],
+            num_fixed: 1,
+            lookup_bits: 20,
+            limb_bits: 88,
+            num_limbs: 3,
+        }
+    }
+
+    /// Config of a new prover with agg params of degree `agg_degree`: none, so
+    /// that the one at `VERIFY_CONFIG` is used, but in `test-mode` when it isn't
+    /// set.
+    pub(crate) fn default_for(agg_degree: u32) -> Option<Self> {
+        if !cfg!(feature = "test-mode") || std::env::var_os("VERIFY_CONFIG").is_some() {
+            return None;
+        }
+        Some(Self::test_mode()).filter(|config| config.degree == agg_degree)
+    }
+
+    /// Check the config fits the agg params of degree `agg_degree`.
+    pub fn validate(&self, agg_degree: u32) -> Result<(), ParamsError> {
+        let invalid = |reason: String| Err(ParamsError::InvalidAggConfig(reason));
+        if self.degree != agg_degree {
+            return invalid(format!(
+                "degree {} differs from the agg params degree {}",
+                self.degree, agg_degree
+            ));
+        }
+        if self.lookup_bits == 0 || self.lookup_bits >= self.degree as usize {
+            return invalid(format!(
+                "lookup_bits {} must be in 1..{}",
+                self.lookup_bits, self.degree
+            ));
+        }
+        if self.num_advice.is_empty() || self.num_advice.contains(&0) {
+            return invalid("num_advice must be non zero in every phase".to_string());
+        }
+        if self.num_lookup_advice.len() != self.num_advice.len() {
+            return invalid(format!(
+                "num_lookup_advice has {} phases, num_advice {}",
+                self.num_lookup_advice.len(),
+                self.num_advice.len()
+            ));
+        }
+        if self.num_fixed == 0 {
+            return invalid("num_fixed must be non zero".to_string());
+        }
+        if self.limb_bits == 0 || self.limb_bits * self.num_limbs < BASE_FIELD_BITS {
+            return invalid(format!(
+                "{} limbs of {} bits don't cover {} bits",
+                self.num_limbs, self.limb_bits, BASE_FIELD_BITS
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write the config where `VERIFY_CONFIG` points. Notice that the env var is
+    /// process wide, i.e. all provers of the process share the last config applied.
+    fn apply(&self) -> std::io::Result<()> {
+        let json = serde_json::to_vec(self)?;
+        let digest = hex::encode(&Sha256::digest(&json)[..8]);
+        let path: PathBuf = std::env::temp_dir().join(format!("agg_config_{digest}.json"));
+        if !path.exists() {
+            let tmp_path = path.with_extension("json.tmp");
+            std::fs::write(&tmp_path, &json)?;
+            std::fs::rename(&tmp_path, &path)?;
+        }
+        std::env::set_var("VERIFY_CONFIG", &path);
+        Ok(())
+    }
+
+    /// Run `f` with the config applied, then point `VERIFY_CONFIG` back where it
+    /// was, e.g. to read a vk of another config.
+    pub(crate) fn with_applied<T>(
+        config: Option<&Self>,
+        f: impl FnOnce() -> T,
+    ) -> std::io::Result<T> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(f()),
+        };
+        let previous = std::env::var_os("VERIFY_CONFIG");
+        config.apply()?;
+        let result = f();
+        match previous {
+            Some(previous) => std::env::set_var("VERIFY_CONFIG", previous),
+            None => std::env::remove_var("VERIFY_CONFIG"),
+        }
+        Ok(result)
+    }
+}
+
+impl Prover {
+    /// Set the aggregation circuit config, `None` to use the one at `VERIFY_CONFIG`.
+    /// The agg pks are dropped, as they depend on the config.
+    pub fn set_agg_config(&mut self, config: Option<AggConfig>) -> Result<(), ParamsError> {
+        if let Some(config) = &config {
+            config.validate(self.agg_params.k())?;
+        }
+        self.agg_config = config;
+        self.agg_pk = None;
+        self.agg_pk_layout = None;
+        self.pk_digests.remove("agg");
+        self.level_pks.clear();
+        Ok(())
+    }
+
+    /// Make the SDK see the config of this prover, before building an agg circuit.
+    pub(crate) fn apply_agg_config(&self) -> Result<(), ParamsError> {
+        match &self.agg_config {
+            Some(config) => config.apply().map_err(|source| ParamsError::Io {
+                path: "VERIFY_CONFIG".to_string(),
+                source,
+            }),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,17 +1,23 @@
 //! Inner circuit related APIs
 
-use crate::circuit::{block_traces_to_witness_block, check_batch_capacity, TargetCircuit, DEGREE};
+use crate::audit::{AuditOperation, AuditRecord};
+use crate::circuit::{
+    block_traces_to_witness_block, check_batch_capacity, check_witness, TargetCircuit,
+};
 use crate::io::{serialize_instance, serialize_vk};
 use crate::prover::MOCK_PROVE;
-use crate::utils::metric_of_witness_block;
+use crate::skip::SkipReport;
+use crate::trie_repair::repair_storage_trace;
+use crate::utils::{metric_of_witness_block, params_of_degree};
 
-use anyhow::{bail, Error};
+use crate::error::{ProvingError, Result};
 use halo2_proofs::dev::MockProver;
 use halo2_proofs::halo2curves::bn256::Fr;
 use halo2_proofs::SerdeFormat;
 use log::info;
 use rand::Rng;
 use snark_verifier_sdk::halo2::gen_snark_shplonk;
+use std::time::Instant;
 use types::eth::BlockTrace;
 
 use super::{Prover, TargetCircuitProof};
@@ -23,7 +29,7 @@ impl Prover {
         &mut self,
         block_traces: &[BlockTrace],
         rng: &mut (impl Rng + Send),
-    ) -> anyhow::Result<TargetCircuitProof> {
+    ) -> Result<TargetCircuitProof> {
         self.create_target_circuit_proof_batch::<C>(block_traces, rng)
     }
 
@@ -33,7 +39,7 @@ impl Prover {
         &mut self,
         block_trace: &BlockTrace,
         rng: &mut (impl Rng + Send),
-    ) -> anyhow::Result<TargetCircuitProof, Error> {
+    ) -> Result<TargetCircuitProof> {
         self.create_target_circuit_proof_batch::<C>(&[block_trace.clone()], rng)
     }
 
@@ -42,24 +48,59 @@ impl Prover {
         &mut self,
         block_traces: &[BlockTrace],
         rng: &mut (impl Rng + Send),
-    ) -> anyhow::Result<TargetCircuitProof, Error> {
+    ) -> Result<TargetCircuitProof> {
+        let start = Instant::now();
+        let result = self.prove_target_circuit_batch::<C>(block_traces, rng);
+        self.audit_target_proof::<C>(block_traces, start, &result);
+        result
+    }
+
+    /// Record the proof of the traces into the audit log, if any.
+    pub(crate) fn audit_target_proof<C: TargetCircuit>(
+        &self,
+        block_traces: &[BlockTrace],
+        start: Instant,
+        result: &Result<TargetCircuitProof>,
+    ) {
+        if let Some(log) = &self.audit_log {
+            let record = AuditRecord::new(
+                AuditOperation::ProveTarget,
+                &C::name(),
+                &self.circuit_version,
+                &self.trace_hash(block_traces),
+            );
+            log.record(&record.proved(start.elapsed(), result, |proof| {
+                (&proof.vk, &proof.snark.proof, proof.num_of_proved_blocks)
+            }));
+        }
+    }
+
+    fn prove_target_circuit_batch<C: TargetCircuit>(
+        &mut self,
+        block_traces: &[BlockTrace],
+        rng: &mut (impl Rng + Send),
+    ) -> Result<TargetCircuitProof> {
         let total_num_of_blocks = block_traces.len();
 
         //
         // Process the traces and prepare the witnesses and inputs to the inner circuits
         //
-        let ((circuit, instance), num_of_proved_blocks) = {
+        let ((circuit, instance), num_of_proved_blocks, skip_report) = {
             let mut block_traces = block_traces.to_vec();
-            check_batch_capacity(&mut block_traces)?;
+            let skip_report = self.prepare_block_traces(&mut block_traces)?;
+            self.check_deadline("witness generation")?;
             let witness_block = block_traces_to_witness_block(&block_traces)?;
+            check_witness(&block_traces, &witness_block)?;
             log::info!(
                 "proving batch of len {}, batch metric {:?}",
                 total_num_of_blocks,
                 metric_of_witness_block(&witness_block)
             );
+            self.check_deadline(&format!("{} circuit building", C::name()))?;
             (
                 C::from_witness_block(&witness_block)?,
                 witness_block.context.ctxs.len(),
+                skip_report,
             )
         };
 
@@ -73,13 +114,33 @@ impl Prover {
             block_traces[block_traces.len() - 1].header.hash.unwrap(),
             block_traces.len()
         );
-        self.create_target_circuit_proof_from_circuit::<C>(
+        let mut proof = self.create_target_circuit_proof_from_circuit::<C>(
             circuit,
             instance,
             rng,
             total_num_of_blocks,
             num_of_proved_blocks,
-        )
+        )?;
+        proof.skip_report = skip_report;
+        self.witness_memory.release();
+        Ok(proof)
+    }
+
+    /// Repair the storage traces, then apply the skip list and the capacity check
+    /// to the traces, leaving the blocks to prove.
+    pub(crate) fn prepare_block_traces(
+        &self,
+        block_traces: &mut Vec<BlockTrace>,
+    ) -> Result<Option<SkipReport>> {
+        if let Some(source) = &self.trie_proof_source {
+            for block_trace in block_traces.iter_mut() {
+                repair_storage_trace(block_trace, source.as_ref())?;
+            }
+        }
+        let skip_report = self.skip_list.apply(block_traces)?;
+        self.check_deadline("capacity check")?;
+        check_batch_capacity(block_traces)?;
+        Ok(Some(skip_report).filter(|report| !report.is_empty()))
     }
 
     ///
@@ -92,27 +153,43 @@ impl Prover {
         rng: &mut (impl Rng + Send),
         total_num_of_blocks: usize,
         num_of_proved_blocks: usize,
-    ) -> anyhow::Result<TargetCircuitProof, Error> {
+    ) -> Result<TargetCircuitProof> {
         if *MOCK_PROVE {
             log::info!("mock prove {} start", C::name());
-            let prover = MockProver::<Fr>::run(*DEGREE as u32, &circuit, instance.clone())?;
+            let prover =
+                MockProver::<Fr>::run(C::degree(), &circuit, instance.clone()).map_err(|e| {
+                    ProvingError::MockProver {
+                        circuit: C::name(),
+                        reason: format!("{e:?}"),
+                    }
+                })?;
             if let Err(errs) = prover.verify_par() {
                 log::error!("err num: {}", errs.len());
                 for err in &errs {
                     log::error!("{}", err);
                 }
-                bail!("{:#?}", errs);
+                return Err(ProvingError::MockProver {
+                    circuit: C::name(),
+                    reason: format!("{errs:#?}"),
+                }
+                .into());
             }
             log::info!("mock prove {} done", C::name());
         }
 
         if !self.target_circuit_pks.contains_key(&C::name()) {
-            self.init_pk::<C>(&C::dummy_inner_circuit());
+            self.check_deadline(&format!("{} keygen", C::name()))?;
+            self.init_pk::<C>(&C::dummy_inner_circuit())?;
         }
+        self.check_deadline(&format!("{} proving", C::name()))?;
+        let params = params_of_degree(&self.params, &mut self.degree_params, C::degree())?;
         let pk = &self.target_circuit_pks[&C::name()];
 
-        // Generate the SNARK proof for the inner circuit
-        let snark_proof = gen_snark_shplonk(&self.params, pk, circuit, rng, None::<String>);
+        // Generate the SNARK proof for the inner circuit. The SDK runs it on a Poseidon
+        // transcript, which the aggregation circuit verifies natively; only the agg
+        // proof uses the Keccak (EVM) transcript. Its protocol is compiled with the
+        // params of the degree of the circuit, which the aggregation reads it at.
+        let snark_proof = gen_snark_shplonk(params, pk, circuit, rng, None::<String>);
 
         let instance_bytes = serialize_instance(&instance);
         let name = C::name();
@@ -128,17 +205,19 @@ impl Prover {
             vk: serialize_vk(pk.get_vk()),
             total_num_of_blocks,
             num_of_proved_blocks,
+            circuit_version: self.circuit_version.clone(),
+            skip_report: None,
         };
         if !self.debug_dir.is_empty() {
             // write vk
-            let mut fd = std::fs::File::create(format!("{}/{}.vk", self.debug_dir, &name)).unwrap();
-            pk.get_vk().write(&mut fd, SerdeFormat::Processed).unwrap();
+            let mut fd = std::fs::File::create(format!("{}/{}.vk", self.debug_dir, &name))?;
+            pk.get_vk().write(&mut fd, SerdeFormat::Processed)?;
             drop(fd);
 
             // write proof
             let output_file = format!("{}/{}_proof.json", self.debug_dir, name);
-            let mut fd = std::fs::File::create(output_file).unwrap();
-            serde_json::to_writer_pretty(&mut fd, &target_proof).unwrap();
+            let mut fd = std::fs::File::create(output_file)?;
+            serde_json::to_writer_pretty(&mut fd, &target_proof)?;
         }
         Ok(target_proof)
     }
@@ -0,0 +1,294 @@
+//! Memory of the witnesses, kept by the allocator across the jobs of a prover.
+//!
+//! The witness of a batch is made of huge vectors, allocated during witness
+//! generation and freed once the circuit is proved. The allocator keeps the freed
+//! memory as a pool for the next job, which reuses it without page faulting it in
+//! again. Fragmentation makes the pool grow over jobs though, so once a job is
+//! done the pool is trimmed down to `WITNESS_RETAINED_MB`, the rest being returned
+//! to the OS. Trimming needs glibc, elsewhere the pool is left as is.
+//!
+//! The buffers the prover allocates itself, the JSON of the block traces hashed
+//! for the audit log, the attestation and the manifest, and the traces of the
+//! witnesses decoded by the workers, are reused across jobs from its
+//! `BufferPool`, capped by the same `WITNESS_RETAINED_MB`. The columns of the
+//! circuits are allocated by halo2 and zkevm-circuits, and left to the trim.
+//!
+//! With the `jemalloc` feature, and jemalloc as the global allocator, the
+//! statistics of the allocator can be read after each job, see `AllocStats`, and
+//! the memory still allocated between jobs checked for leaks, see `LeakCheck`.
+//!
+//! Jobs proved in parallel go through a `MemoryGate`: a job starts once its
+//! estimated memory, see `estimate_proving_memory`, fits into what the jobs in
+//! flight leave of the budget, so that the host isn't OOM-killed halfway through.
+
+use crate::tune::TUNED_SETTINGS;
+use crate::utils::read_env_var;
+use once_cell::sync::Lazy;
+use serde_derive::Serialize;
+use std::sync::{Condvar, Mutex};
+
+/// Freed memory kept for the next job in MB, unlimited if negative. The tuned
+/// one by default, see `tune`.
+pub static WITNESS_RETAINED_MB: Lazy<i64> = Lazy::new(|| {
+    let tuned = TUNED_SETTINGS.as_ref().map(|s| s.witness_retained_mb);
+    read_env_var("WITNESS_RETAINED_MB", tuned.unwrap_or(-1))
+});
+
+/// Growth in MB of the memory allocated between jobs reported as a leak, 0 to
+/// disable the check.
+pub static ALLOC_LEAK_CHECK_MB: Lazy<u64> = Lazy::new(|| read_env_var("ALLOC_LEAK_CHECK_MB", 0));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WitnessMemory {
+    /// Freed memory kept once a job is done, `None` to keep all of it.
+    pub retained_bytes: Option<usize>,
+}
+
+impl Default for WitnessMemory {
+    fn default() -> Self {
+        Self {
+            retained_bytes: usize::try_from(*WITNESS_RETAINED_MB)
+                .ok()
+                .map(|mb| mb << 20),
+        }
+    }
+}
+
+impl WitnessMemory {
+    /// Trim the pool, once the witness and the circuit of a job are dropped.
+    pub fn release(&self) {
+        let retained_bytes = match self.retained_bytes {
+            Some(retained_bytes) => retained_bytes,
+            None => return,
+        };
+        let before = used_memory();
+        if trim(retained_bytes) {
+            log::info!(
+                "witness memory trimmed to {}MB, {}MB returned",
+                retained_bytes >> 20,
+                before.saturating_sub(used_memory()) >> 20
+            );
+        }
+    }
+}
+
+/// Byte buffers reused across the jobs of a prover. The buffers given back are
+/// kept up to `retained_bytes` of capacity, the largest ones first, `None` to
+/// keep all of them.
+#[derive(Debug, Default)]
+pub struct BufferPool {
+    retained_bytes: Option<usize>,
+    /// Sorted by capacity, the largest last.
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    pub fn new(retained_bytes: Option<usize>) -> Self {
+        Self {
+            retained_bytes,
+            buffers: Default::default(),
+        }
+    }
+
+    /// An empty buffer, the largest one kept or a new one.
+    pub fn take(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    /// Keep the buffer for the next `take`, dropping the smallest ones kept over
+    /// the cap.
+    pub fn give(&self, mut buf: Vec<u8>) {
+        buf.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        let at = buffers.partition_point(|kept| kept.capacity() <= buf.capacity());
+        buffers.insert(at, buf);
+        if let Some(retained_bytes) = self.retained_bytes {
+            while retained(&buffers) > retained_bytes {
+                buffers.remove(0);
+            }
+        }
+    }
+
+    /// Capacity of the buffers kept.
+    pub fn retained_bytes(&self) -> usize {
+        retained(&self.buffers.lock().unwrap())
+    }
+}
+
+fn retained(buffers: &[Vec<u8>]) -> usize {
+    buffers.iter().map(Vec::capacity).sum()
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn trim(pad: usize) -> bool {
+    // safe: only releases free memory of the malloc arenas
+    unsafe { libc::malloc_trim(pad) == 1 }
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn trim(_pad: usize) -> bool {
+    false
+}
+
+/// Admits the jobs whose estimated memory fits into the budget, the others
+/// waiting for the memory of the jobs in flight. A job over the whole budget runs
+/// alone, a budget of 0 admits all jobs.
+#[derive(Debug)]
+pub struct MemoryGate {
+    budget: u64,
+    /// Memory reserved, and jobs in flight.
+    in_use: Mutex<(u64, usize)>,
+    released: Condvar,
+}
+
+impl MemoryGate {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            in_use: Mutex::new((0, 0)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// A gate over the memory available on the host, see `available_memory`, with
+    /// no limit if unknown.
+    pub fn from_available_memory() -> Self {
+        Self::new(available_memory())
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Wait for `bytes` to fit, then reserve them until the permit is dropped.
+    pub fn acquire(&self, bytes: u64) -> MemoryPermit<'_> {
+        let mut in_use = self.in_use.lock().unwrap();
+        while self.budget != 0 && in_use.1 > 0 && in_use.0 + bytes > self.budget {
+            in_use = self.released.wait(in_use).unwrap();
+        }
+        in_use.0 += bytes;
+        in_use.1 += 1;
+        MemoryPermit { gate: self, bytes }
+    }
+
+    /// Memory reserved by the jobs in flight.
+    pub fn in_use(&self) -> u64 {
+        self.in_use.lock().unwrap().0
+    }
+}
+
+/// The memory of a job in flight, released on drop.
+#[derive(Debug)]
+pub struct MemoryPermit<'a> {
+    gate: &'a MemoryGate,
+    bytes: u64,
+}
+
+impl Drop for MemoryPermit<'_> {
+    fn drop(&mut self) {
+        let mut in_use = self.gate.in_use.lock().unwrap();
+        in_use.0 -= self.bytes;
+        in_use.1 -= 1;
+        drop(in_use);
+        self.gate.released.notify_all();
+    }
+}
+
+/// `MemAvailable` of the host in bytes, 0 if unknown.
+#[cfg(target_os = "linux")]
+pub fn available_memory() -> u64 {
+    procfs::Meminfo::new().map_or(0, |m| m.mem_available.unwrap_or(m.mem_free))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn available_memory() -> u64 {
+    0
+}
+
+#[cfg(target_os = "linux")]
+fn used_memory() -> usize {
+    procfs::Meminfo::new().map_or(0, |m| (m.mem_total - m.mem_free) as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn used_memory() -> usize {
+    0
+}
+
+/// Statistics of jemalloc, in bytes.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    /// Allocated by the application.
+    pub allocated: u64,
+    /// In active pages, `allocated` plus the fragmentation.
+    pub active: u64,
+    /// In pages resident in physical memory.
+    pub resident: u64,
+    /// In chunks mapped by the allocator.
+    pub mapped: u64,
+    /// Unmapped but kept as virtual memory for reuse.
+    pub retained: u64,
+}
+
+impl AllocStats {
+    /// None without the `jemalloc` feature.
+    #[cfg(feature = "jemalloc")]
+    pub fn read() -> Option<Self> {
+        use tikv_jemalloc_ctl::{epoch, stats};
+
+        // the stats are cached until the epoch is advanced
+        epoch::advance().ok()?;
+        Some(Self {
+            allocated: stats::allocated::read().ok()? as u64,
+            active: stats::active::read().ok()? as u64,
+            resident: stats::resident::read().ok()? as u64,
+            mapped: stats::mapped::read().ok()? as u64,
+            retained: stats::retained::read().ok()? as u64,
+        })
+    }
+
+    /// None without the `jemalloc` feature.
+    #[cfg(not(feature = "jemalloc"))]
+    pub fn read() -> Option<Self> {
+        None
+    }
+
+    /// Share of the active memory not allocated by the application.
+    pub fn fragmentation(&self) -> f64 {
+        if self.active == 0 {
+            return 0.0;
+        }
+        self.active.saturating_sub(self.allocated) as f64 / self.active as f64
+    }
+}
+
+/// Compares the memory allocated once a job is done with the one after the
+/// first job, when the keys and caches of the prover are in place. A prover
+/// going back to that baseline between jobs doesn't leak.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LeakCheck {
+    pub threshold_bytes: u64,
+    baseline: Option<u64>,
+}
+
+impl LeakCheck {
+    pub fn new(threshold_bytes: u64) -> Self {
+        Self {
+            threshold_bytes,
+            baseline: None,
+        }
+    }
+
+    /// From `ALLOC_LEAK_CHECK_MB`, None if disabled.
+    pub fn from_env() -> Option<Self> {
+        (*ALLOC_LEAK_CHECK_MB != 0).then(|| Self::new(*ALLOC_LEAK_CHECK_MB << 20))
+    }
+
+    /// Record the stats after a job, returns the growth over the baseline if
+    /// beyond the threshold.
+    pub fn check(&mut self, stats: &AllocStats) -> Option<u64> {
+        let baseline = *self.baseline.get_or_insert(stats.allocated);
+        let growth = stats.allocated.saturating_sub(baseline);
+        (growth > self.threshold_bytes).then_some(growth)
+    }
+}
@@ -1,21 +1,20 @@
 use super::Prover;
-use crate::circuit::{block_traces_to_witness_block, check_batch_capacity, TargetCircuit, DEGREE};
+use crate::circuit::{block_traces_to_witness_block, check_batch_capacity, TargetCircuit};
+use crate::error::{ProvingError, Result};
+use crate::skip::SKIP_LIST;
 use crate::utils::metric_of_witness_block;
-use anyhow::bail;
 use halo2_proofs::dev::MockProver;
 use halo2_proofs::halo2curves::bn256::Fr;
 use types::eth::BlockTrace;
 
 impl Prover {
-    pub fn mock_prove_target_circuit<C: TargetCircuit>(
-        block_trace: &BlockTrace,
-    ) -> anyhow::Result<()> {
+    pub fn mock_prove_target_circuit<C: TargetCircuit>(block_trace: &BlockTrace) -> Result<()> {
         Self::mock_prove_target_circuit_batch::<C>(&[block_trace.clone()])
     }
 
     pub fn mock_prove_target_circuit_batch<C: TargetCircuit>(
         block_traces: &[BlockTrace],
-    ) -> anyhow::Result<()> {
+    ) -> Result<()> {
         log::info!(
             "start mock prove {}, rows needed {:?}",
             C::name(),
@@ -23,6 +22,7 @@ impl Prover {
         );
         let original_block_len = block_traces.len();
         let mut block_traces = block_traces.to_vec();
+        SKIP_LIST.apply(&mut block_traces)?;
         check_batch_capacity(&mut block_traces)?;
         let witness_block = block_traces_to_witness_block(&block_traces)?;
         log::info!(
@@ -31,13 +31,22 @@ impl Prover {
             metric_of_witness_block(&witness_block)
         );
         let (circuit, instance) = C::from_witness_block(&witness_block)?;
-        let prover = MockProver::<Fr>::run(*DEGREE as u32, &circuit, instance)?;
+        let prover = MockProver::<Fr>::run(C::degree(), &circuit, instance).map_err(|e| {
+            ProvingError::MockProver {
+                circuit: C::name(),
+                reason: format!("{e:?}"),
+            }
+        })?;
         if let Err(errs) = prover.verify_par() {
             log::error!("err num: {}", errs.len());
             for err in &errs {
                 log::error!("{}", err);
             }
-            bail!("{:#?}", errs);
+            return Err(ProvingError::MockProver {
+                circuit: C::name(),
+                reason: format!("{errs:#?}"),
+            }
+            .into());
         }
         log::info!(
             "mock prove {} done. block proved {}/{}, batch metric: {:?}",
@@ -1,14 +1,23 @@
 //! This module implements outer circuit related APIs for Prover.
 
-use super::{AggCircuitProof, Prover};
-use crate::circuit::{SuperCircuit, TargetCircuit};
+use super::{derive_rng, AggCircuitProof, Prover};
+use crate::attestation::instance_hash;
+use crate::audit::{AuditOperation, AuditRecord};
+use crate::circuit::{
+    circuit_degree, split_block_trace, ChainBoundAggregationCircuit, SuperCircuit, TargetCircuit,
+    CHAIN_ID,
+};
+use crate::error::{ProvingError, Result, VerificationError};
 use crate::io::{serialize_fr_tensor, serialize_vk};
 use crate::prover::TargetCircuitProof;
-use rand::{Rng, SeedableRng};
-use rand_xorshift::XorShiftRng;
+use crate::verifier::{evm_verify_agg_proof, verify_agg_proof};
+use chrono::Utc;
+use eth_types::H256;
+use rand::Rng;
 use snark_verifier_sdk::evm::gen_evm_proof_shplonk;
 use snark_verifier_sdk::halo2::aggregation::AggregationCircuit;
 use snark_verifier_sdk::CircuitExt;
+use std::time::Instant;
 use types::eth::BlockTrace;
 
 impl Prover {
@@ -16,7 +25,7 @@ impl Prover {
     /// Used for Debugging only.
     pub fn load_aggregation_circuit_instance<C: TargetCircuit>(
         &self,
-    ) -> anyhow::Result<TargetCircuitProof> {
+    ) -> Result<TargetCircuitProof> {
         assert!(!self.debug_dir.is_empty());
         log::debug!("load aggregation circuit instance: {}", C::name());
         let file_name = format!("{}/{}_proof.json", self.debug_dir, C::name());
@@ -31,20 +40,94 @@ impl Prover {
         &mut self,
         block_trace: &BlockTrace,
         rng: &mut (impl Rng + Send),
-    ) -> anyhow::Result<AggCircuitProof> {
+    ) -> Result<AggCircuitProof> {
         self.create_agg_circuit_proof_batch(&[block_trace.clone()], rng)
     }
 
     /// Input a list of block traces, generate a proof for the aggregation circuit.
     /// This proof is verifiable by the evm.
+    ///
+    /// With a resume dir set, the inner proofs are persisted before aggregating
+    /// them, see `Prover::resume_agg_from_dir`.
     pub fn create_agg_circuit_proof_batch(
         &mut self,
         block_traces: &[BlockTrace],
         rng: &mut (impl Rng + Send),
-    ) -> anyhow::Result<AggCircuitProof> {
-        let circuit_results: Vec<TargetCircuitProof> =
-            vec![self.prove_inner_circuit::<SuperCircuit>(block_traces, rng)?];
-        self.create_agg_circuit_proof_impl(circuit_results.as_ref(), rng)
+    ) -> Result<AggCircuitProof> {
+        let started_at = Utc::now();
+        let mut agg_proof = if let Some(dir) = self.resume_dir.clone() {
+            self.create_agg_circuit_proof_batch_resumable(&dir, block_traces, rng)?
+        } else {
+            let circuit_results: Vec<TargetCircuitProof> =
+                vec![self.prove_inner_circuit::<SuperCircuit>(block_traces, rng)?];
+            self.create_agg_circuit_proof_impl(circuit_results.as_ref(), rng)?
+        };
+        self.seal_agg_proof(block_traces, started_at, &mut agg_proof)?;
+        Ok(agg_proof)
+    }
+
+    /// Generate the agg proof of the block traces and verify it natively, and in
+    /// revm with `evm_verify`, before returning it: a proof which doesn't verify
+    /// is an error, never returned. The randomness is drawn from the prover rng.
+    pub fn prove_and_verify_agg(
+        &mut self,
+        block_traces: &[BlockTrace],
+        evm_verify: bool,
+    ) -> Result<AggCircuitProof> {
+        let mut rng = derive_rng(&mut self.rng);
+        let agg_proof = self.create_agg_circuit_proof_batch(block_traces, &mut rng)?;
+
+        self.check_deadline("aggregation verification")?;
+        let vk = self
+            .agg_pk
+            .as_ref()
+            .ok_or(VerificationError::MissingVk)?
+            .get_vk();
+        if !verify_agg_proof(&self.agg_params, vk, &agg_proof)? {
+            return Err(VerificationError::Failed {
+                circuit: "aggregation".to_string(),
+            }
+            .into());
+        }
+        if evm_verify {
+            self.check_deadline("aggregation evm verification")?;
+            evm_verify_agg_proof(&self.agg_params, vk, &agg_proof)?;
+        }
+        log::info!(
+            "agg proof of {} blocks verified{}",
+            agg_proof.total_proved_block_count,
+            if evm_verify { ", also in revm" } else { "" }
+        );
+        Ok(agg_proof)
+    }
+
+    /// Input a block trace that may exceed the capacity of a single super circuit.
+    /// The transactions of the block are split into several super circuit proofs with
+    /// chained state roots, which are then aggregated into one proof for the block.
+    ///
+    /// Notice that the aggregation proving key depends on the number of inner snarks,
+    /// i.e. on the number of partitions of the block.
+    pub fn create_agg_circuit_proof_split(
+        &mut self,
+        block_trace: &BlockTrace,
+        rng: &mut (impl Rng + Send),
+    ) -> Result<AggCircuitProof> {
+        let started_at = Utc::now();
+        let partitions = split_block_trace(block_trace)?;
+        let mut circuit_results = Vec::with_capacity(partitions.len());
+        for partition in partitions.iter() {
+            self.check_deadline("proving the next partition")?;
+            circuit_results.push(self.create_target_circuit_proof::<SuperCircuit>(partition, rng)?);
+        }
+        let mut agg_proof = self.create_agg_circuit_proof_impl(circuit_results.as_ref(), rng)?;
+        // all partitions belong to the same block
+        agg_proof.total_proved_block_count = 1;
+        self.seal_agg_proof(
+            std::slice::from_ref(block_trace),
+            started_at,
+            &mut agg_proof,
+        )?;
+        Ok(agg_proof)
     }
 
     /// Input an instance of the aggregation circuit, output its proof.
@@ -55,28 +138,57 @@ impl Prover {
         &mut self,
         inner_circuit_results: &[TargetCircuitProof],
         rng: &mut (impl Rng + Send),
-    ) -> anyhow::Result<AggCircuitProof> {
-        let mut seed1 = [0u8; 16];
-        rng.fill_bytes(&mut seed1);
-        let mut seed2 = [0u8; 16];
-        rng.fill_bytes(&mut seed2);
-        let rng1 = XorShiftRng::from_seed(seed1);
-        let mut rng2 = XorShiftRng::from_seed(seed2);
+    ) -> Result<AggCircuitProof> {
+        let start = Instant::now();
+        let result = self.prove_agg_circuit(inner_circuit_results, rng);
+        if let Some(log) = &self.audit_log {
+            let record = AuditRecord::new(
+                AuditOperation::ProveAgg,
+                "aggregation",
+                &self.circuit_version,
+                &inner_instance_hash(inner_circuit_results),
+            );
+            log.record(&record.proved(start.elapsed(), &result, |proof| {
+                (&proof.vk, &proof.proof, proof.total_proved_block_count)
+            }));
+        }
+        result
+    }
+
+    fn prove_agg_circuit(
+        &mut self,
+        inner_circuit_results: &[TargetCircuitProof],
+        rng: &mut (impl Rng + Send),
+    ) -> Result<AggCircuitProof> {
+        let rng1 = derive_rng(rng);
+        let mut rng2 = derive_rng(rng);
 
         // build the aggregation circuit inputs from the inner circuit outputs
-        let agg_circuit = AggregationCircuit::new(
-            &self.agg_params,
-            inner_circuit_results.iter().map(|p| p.snark.clone()),
-            rng1,
+        check_snark_degrees(inner_circuit_results)?;
+        self.check_deadline("aggregation circuit building")?;
+        self.apply_agg_config()?;
+        let agg_circuit = ChainBoundAggregationCircuit::new(
+            AggregationCircuit::new(
+                &self.agg_params,
+                inner_circuit_results.iter().map(|p| p.snark.clone()),
+                rng1,
+            ),
+            *CHAIN_ID,
         );
-        let pk = match self.agg_pk.clone() {
-            Some(pk) => pk,
-            None => panic!("aggregation proving key is not found"),
-        };
+        // another number of snarks, e.g. of a split block, is another circuit
+        let layout = snark_layout(inner_circuit_results);
+        if self.agg_pk.is_none() || self.agg_pk_layout.as_deref() != Some(layout.as_str()) {
+            self.check_deadline("aggregation keygen")?;
+            self.init_agg_pk(&agg_circuit, &layout)?;
+        }
+        self.check_deadline("aggregation proving")?;
+        let pk = self.agg_pk.as_ref().unwrap();
+        // the vk digest is checked by init_agg_pk
+        let vk_bytes = serialize_vk(pk.get_vk());
 
         let agg_proof = gen_evm_proof_shplonk(
             &self.agg_params,
-            &pk,
+            pk,
             agg_circuit.clone(),
             agg_circuit.instances(),
             &mut rng2,
@@ -94,8 +206,6 @@ impl Prover {
         // serialize instances
         let instances_for_serde = serialize_fr_tensor(&[agg_circuit.instances()]);
         let instance_bytes = serde_json::to_vec(&instances_for_serde)?;
-        // serialize vk
-        let vk_bytes = serialize_vk(pk.get_vk());
 
         log::info!(
             "create agg proof done, block proved {}/{}",
@@ -107,6 +217,50 @@ impl Prover {
             instance: instance_bytes,
             vk: vk_bytes,
             total_proved_block_count,
+            circuit_version: self.circuit_version.clone(),
+            attestation: None,
+            manifest: None,
         })
     }
 }
+
+/// Hash of the instances of the inner proofs, the input of an aggregation.
+pub(crate) fn inner_instance_hash(inner_proofs: &[TargetCircuitProof]) -> H256 {
+    let instances: Vec<_> = inner_proofs
+        .iter()
+        .map(|proof| proof.snark.instances.clone())
+        .collect();
+    let json =
+        serde_json::to_vec(&serialize_fr_tensor(&instances)).expect("instances are serializable");
+    instance_hash(&json)
+}
+
+/// The snarks of an aggregation by circuit and degree, e.g. `super20,super20`.
+/// The agg circuit, so its pk, depends on the number of snarks it verifies and
+/// on their domains.
+pub(crate) fn snark_layout(inner_circuit_results: &[TargetCircuitProof]) -> String {
+    inner_circuit_results
+        .iter()
+        .map(|proof| format!("{}{}", proof.name, proof.snark.protocol.domain.k))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Check every snark is of the degree its circuit is proved at. The aggregation
+/// circuit reads a snark at the domain of its protocol, so snarks of different
+/// degrees are aggregated together, but the agg pk is kept for the degrees of
+/// the snarks it was generated from.
+fn check_snark_degrees(inner_circuit_results: &[TargetCircuitProof]) -> Result<()> {
+    for proof in inner_circuit_results {
+        let degree = proof.snark.protocol.domain.k as u32;
+        let expected = circuit_degree(&proof.name);
+        if degree != expected {
+            return Err(ProvingError::Circuit {
+                circuit: proof.name.clone(),
+                reason: format!("snark of degree {degree}, the circuit is proved at {expected}"),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,362 @@
+//! Three-level aggregation: block traces -> chunk proofs -> batch proofs -> bundle proof.
+//!
+//! - A chunk proof is a super circuit snark of a few consecutive blocks.
+//! - A batch proof is an aggregation circuit snark of consecutive chunk proofs,
+//!   with a Poseidon transcript so that it is aggregated again.
+//! - A bundle proof aggregates consecutive batch proofs into a proof verified by
+//!   the EVM, the chain id last in its instance. Bundle a single batch to verify
+//!   it on chain.
+//!
+//! `prove_pipeline` persists every intermediate proof into a dir and picks up
+//! the ones already there; each level can also be proved on its own from
+//! loaded intermediates.
+//!
+//! The batch circuit, and so its vk, depends on the number of chunks. With
+//! `MAX_CHUNKS_PER_BATCH`, batches of fewer chunks are padded with dummy chunks,
+//! snarks of the super circuit over no blocks, so that every batch has the same vk.
+
+use super::{derive_rng, AggCircuitProof, Prover, ProverRng, TargetCircuitProof};
+use crate::chunk::ChunkInfo;
+use crate::circuit::{ChainBoundAggregationCircuit, SuperCircuit, CHAIN_ID};
+use crate::error::{CapacityError, Result, TraceError};
+use crate::io::{serialize_fr_tensor, serialize_vk};
+use crate::utils::read_env_var;
+use crate::version::CircuitVersion;
+use halo2_proofs::halo2curves::bn256::Fr;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use snark_verifier_sdk::evm::gen_evm_proof_shplonk;
+use snark_verifier_sdk::halo2::aggregation::AggregationCircuit;
+use snark_verifier_sdk::halo2::gen_snark_shplonk;
+use snark_verifier_sdk::{gen_pk, CircuitExt, Snark};
+use std::fs::{self, File};
+use std::path::Path;
+use types::base64;
+use types::eth::BlockTrace;
+
+/// Chunks of every batch, padded with dummy chunks, 0 not to pad.
+pub static MAX_CHUNKS_PER_BATCH: Lazy<usize> =
+    Lazy::new(|| read_env_var("MAX_CHUNKS_PER_BATCH", 0));
+
+/// Numbers of the first and the last block, inclusive.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl BlockRange {
+    fn of_traces(block_traces: &[BlockTrace]) -> Result<Self, TraceError> {
+        let number = |trace: Option<&BlockTrace>| {
+            trace
+                .and_then(|t| t.header.number)
+                .map(|n| n.as_u64())
+                .ok_or_else(|| TraceError::Invalid("chunk without block number".to_string()))
+        };
+        Ok(Self {
+            first: number(block_traces.first())?,
+            last: number(block_traces.last())?,
+        })
+    }
+
+    /// The range spanning consecutive ranges.
+    fn concat(ranges: impl IntoIterator<Item = BlockRange>) -> Result<Self, TraceError> {
+        let mut ranges = ranges.into_iter();
+        let mut range = ranges
+            .next()
+            .ok_or_else(|| TraceError::Invalid("nothing to aggregate".to_string()))?;
+        for next in ranges {
+            if next.first != range.last + 1 {
+                return Err(TraceError::Invalid(format!(
+                    "blocks {}..={} don't follow blocks {}..={}",
+                    next.first, next.last, range.first, range.last
+                )));
+            }
+            range.last = next.last;
+        }
+        Ok(range)
+    }
+
+    fn file_name(&self, level: &str) -> String {
+        format!("{}_{}_{}.json", level, self.first, self.last)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChunkProof {
+    pub block_range: BlockRange,
+    /// State roots, withdraw root and data hash of the chunk.
+    pub info: ChunkInfo,
+    pub proof: TargetCircuitProof,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchProof {
+    pub block_range: BlockRange,
+    pub chunks: Vec<ChunkInfo>,
+    /// Dummy chunks aggregated after `chunks`, up to the max chunks per batch.
+    #[serde(default)]
+    pub num_dummy_chunks: usize,
+    pub snark: Snark,
+    #[serde(with = "base64")]
+    pub vk: Vec<u8>,
+    #[serde(default)]
+    pub circuit_version: CircuitVersion,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BundleProof {
+    pub block_range: BlockRange,
+    pub num_batches: usize,
+    pub proof: AggCircuitProof,
+}
+
+/// Proofs of the top levels of a pipeline run.
+#[derive(Debug)]
+pub struct PipelineOutput {
+    pub batches: Vec<BatchProof>,
+    pub bundle: Option<BundleProof>,
+}
+
+impl Prover {
+    /// Prove a chunk of consecutive blocks. All of them must fit into the super circuit.
+    pub fn prove_chunk(&mut self, block_traces: &[BlockTrace]) -> Result<ChunkProof> {
+        let block_range = BlockRange::of_traces(block_traces)?;
+        let info = ChunkInfo::from_block_traces(block_traces)?;
+        let mut rng = derive_rng(&mut self.rng);
+        let proof = self.prove_inner_circuit::<SuperCircuit>(block_traces, &mut rng)?;
+        if proof.num_of_proved_blocks != proof.total_num_of_blocks {
+            return Err(CapacityError::ChunkTruncated {
+                proved: proof.num_of_proved_blocks,
+                total: proof.total_num_of_blocks,
+            }
+            .into());
+        }
+        Ok(ChunkProof {
+            block_range,
+            info,
+            proof,
+        })
+    }
+
+    /// Aggregate consecutive chunk proofs into a batch proof.
+    pub fn prove_batch(&mut self, chunks: &[ChunkProof]) -> Result<BatchProof> {
+        let block_range = BlockRange::concat(chunks.iter().map(|c| c.block_range))?;
+        for (prev, next) in chunks.iter().zip(chunks.iter().skip(1)) {
+            if prev.info.post_state_root != next.info.prev_state_root {
+                return Err(TraceError::Invalid(format!(
+                    "chunk of blocks {}..={} doesn't start from the post state root of the previous one",
+                    next.block_range.first, next.block_range.last
+                ))
+                .into());
+            }
+        }
+        let mut snarks: Vec<Snark> = chunks.iter().map(|c| c.proof.snark.clone()).collect();
+        let num_dummy_chunks = self.pad_chunk_snarks(&mut snarks)?;
+        let num_snarks = snarks.len();
+        let (circuit, mut rng) =
+            self.level_circuit("batch", snarks.into_iter(), |circuit| circuit)?;
+
+        self.check_deadline("batch proving")?;
+        let pk = &self.level_pks[&level_key("batch", num_snarks)];
+        let vk = serialize_vk(pk.get_vk());
+        let snark = gen_snark_shplonk(&self.agg_params, pk, circuit, &mut rng, None::<String>);
+        log::info!(
+            "batch proof of blocks {}..={} done",
+            block_range.first,
+            block_range.last
+        );
+        Ok(BatchProof {
+            block_range,
+            chunks: chunks.iter().map(|c| c.info.clone()).collect(),
+            num_dummy_chunks,
+            snark,
+            vk,
+            circuit_version: self.circuit_version.clone(),
+        })
+    }
+
+    /// Aggregate consecutive batch proofs into a proof verified by the EVM.
+    pub fn prove_bundle(&mut self, batches: &[BatchProof]) -> Result<BundleProof> {
+        let block_range = BlockRange::concat(batches.iter().map(|b| b.block_range))?;
+        let snarks = batches.iter().map(|b| b.snark.clone());
+        let (circuit, mut rng) = self.level_circuit("bundle", snarks, |circuit| {
+            ChainBoundAggregationCircuit::new(circuit, *CHAIN_ID)
+        })?;
+
+        self.check_deadline("bundle proving")?;
+        let pk = &self.level_pks[&level_key("bundle", batches.len())];
+        let vk = serialize_vk(pk.get_vk());
+        let instances = circuit.instances();
+        let proof =
+            gen_evm_proof_shplonk(&self.agg_params, pk, circuit, instances.clone(), &mut rng);
+        let instance = serde_json::to_vec(&serialize_fr_tensor(&[instances]))?;
+        log::info!(
+            "bundle proof of blocks {}..={} done",
+            block_range.first,
+            block_range.last
+        );
+        Ok(BundleProof {
+            block_range,
+            num_batches: batches.len(),
+            proof: AggCircuitProof {
+                proof,
+                instance,
+                vk,
+                total_proved_block_count: (block_range.last - block_range.first + 1) as usize,
+                circuit_version: self.circuit_version.clone(),
+                attestation: None,
+                manifest: None,
+            },
+        })
+    }
+
+    /// Prove `batches` of chunks of block traces, then bundle the batches if `bundle`.
+    /// Every proof is written into `dir`, and loaded from it instead of proved
+    /// if already there, so that an interrupted run resumes where it stopped.
+    pub fn prove_pipeline(
+        &mut self,
+        batches: &[Vec<Vec<BlockTrace>>],
+        bundle: bool,
+        dir: &Path,
+    ) -> Result<PipelineOutput> {
+        fs::create_dir_all(dir)?;
+        let mut batch_proofs = Vec::with_capacity(batches.len());
+        for chunks in batches {
+            let ranges = chunks
+                .iter()
+                .map(|c| BlockRange::of_traces(c))
+                .collect::<Result<Vec<_>, _>>()?;
+            let batch_file = BlockRange::concat(ranges.iter().copied())?.file_name("batch");
+            if let Some(batch) = load_json(dir, &batch_file)? {
+                batch_proofs.push(batch);
+                continue;
+            }
+
+            let mut chunk_proofs = Vec::with_capacity(chunks.len());
+            for (chunk, range) in chunks.iter().zip(ranges) {
+                let chunk_file = range.file_name("chunk");
+                let chunk_proof = match load_json(dir, &chunk_file)? {
+                    Some(chunk_proof) => chunk_proof,
+                    None => {
+                        let chunk_proof = self.prove_chunk(chunk)?;
+                        dump_json(dir, &chunk_file, &chunk_proof)?;
+                        chunk_proof
+                    }
+                };
+                chunk_proofs.push(chunk_proof);
+            }
+            let batch = self.prove_batch(&chunk_proofs)?;
+            dump_json(dir, &batch_file, &batch)?;
+            batch_proofs.push(batch);
+        }
+
+        let bundle = if bundle {
+            let bundle_file =
+                BlockRange::concat(batch_proofs.iter().map(|b| b.block_range))?.file_name("bundle");
+            match load_json(dir, &bundle_file)? {
+                Some(bundle) => Some(bundle),
+                None => {
+                    let bundle = self.prove_bundle(&batch_proofs)?;
+                    dump_json(dir, &bundle_file, &bundle)?;
+                    Some(bundle)
+                }
+            }
+        } else {
+            None
+        };
+        Ok(PipelineOutput {
+            batches: batch_proofs,
+            bundle,
+        })
+    }
+
+    /// Chunks of every batch, padded with dummy chunks, `None` not to pad.
+    /// `MAX_CHUNKS_PER_BATCH` by default.
+    pub fn set_max_chunks_per_batch(&mut self, max_chunks: Option<usize>) {
+        self.max_chunks_per_batch = max_chunks;
+    }
+
+    /// Pad the snarks of the chunks of a batch with dummy snarks up to the max
+    /// chunks per batch, returns the number of dummy snarks.
+    fn pad_chunk_snarks(&mut self, snarks: &mut Vec<Snark>) -> Result<usize> {
+        let max_chunks = match self.max_chunks_per_batch {
+            Some(max_chunks) => max_chunks,
+            None => return Ok(0),
+        };
+        if snarks.len() > max_chunks {
+            return Err(CapacityError::TooManyChunks {
+                num_chunks: snarks.len(),
+                max_chunks,
+            }
+            .into());
+        }
+        let num_dummy_chunks = max_chunks - snarks.len();
+        if num_dummy_chunks != 0 {
+            let dummy = self.dummy_chunk_snark()?;
+            snarks.resize(max_chunks, dummy);
+        }
+        Ok(num_dummy_chunks)
+    }
+
+    /// Snark of the super circuit over no blocks, proved once per prover.
+    fn dummy_chunk_snark(&mut self) -> Result<Snark> {
+        if let Some(snark) = &self.dummy_chunk_snark {
+            return Ok(snark.clone());
+        }
+        self.check_deadline("dummy chunk proving")?;
+        let mut rng = derive_rng(&mut self.rng);
+        let snark = self
+            .create_target_circuit_proof_batch::<SuperCircuit>(&[], &mut rng)?
+            .snark;
+        self.dummy_chunk_snark = Some(snark.clone());
+        Ok(snark)
+    }
+
+    /// Build the aggregation circuit of a level and make sure its pk is there.
+    /// The pk depends on the level and the number of snarks.
+    fn level_circuit<C: CircuitExt<Fr>>(
+        &mut self,
+        level: &str,
+        snarks: impl ExactSizeIterator<Item = Snark>,
+        wrap: impl FnOnce(AggregationCircuit) -> C,
+    ) -> Result<(C, ProverRng)> {
+        let key = level_key(level, snarks.len());
+        self.check_deadline(&format!("{level} circuit building"))?;
+        self.apply_agg_config()?;
+        let circuit = wrap(AggregationCircuit::new(
+            &self.agg_params,
+            snarks,
+            derive_rng(&mut self.rng),
+        ));
+        if !self.level_pks.contains_key(&key) {
+            self.check_deadline(&format!("{level} keygen"))?;
+            Self::tick(&format!("before init pk of {key}"));
+            let pk = gen_pk(&self.agg_params, &circuit, None);
+            self.level_pks.insert(key.clone(), pk);
+            Self::tick(&format!("after init pk of {key}"));
+        }
+        Ok((circuit, derive_rng(&mut self.rng)))
+    }
+}
+
+fn level_key(level: &str, num_snarks: usize) -> String {
+    format!("{level}_{num_snarks}")
+}
+
+pub(super) fn dump_json<T: serde::Serialize>(dir: &Path, name: &str, value: &T) -> Result<()> {
+    let tmp_path = dir.join(format!("{name}.tmp"));
+    serde_json::to_writer(File::create(&tmp_path)?, value)?;
+    fs::rename(&tmp_path, dir.join(name))?;
+    Ok(())
+}
+
+pub(super) fn load_json<T: DeserializeOwned>(dir: &Path, name: &str) -> Result<Option<T>> {
+    let path = dir.join(name);
+    if !path.exists() {
+        return Ok(None);
+    }
+    log::info!("resume from {:?}", path);
+    Ok(Some(serde_json::from_reader(File::open(path)?)?))
+}
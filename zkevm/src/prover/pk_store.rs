@@ -0,0 +1,420 @@
+//! Proving keys on disk, memory-mapped to load.
+//!
+//! The pks of the super and the aggregation circuits are tens of GB serialized,
+//! and minutes of keygen. With `PK_DIR` set, a pk generated is written to
+//! `<PK_DIR>/<circuit version>/<circuit>.pk`, and the provers started next load
+//! it from there instead.
+//!
+//! A pk file is a header page, the magic, the length of the header and the header
+//! as JSON, then sections aligned to pages:
+//! - `vk`, the vk as serialized into the proofs, to check the pk decoded against;
+//! - `fixed_commitments` and `permutation_commitments`, raw points read in place,
+//!   e.g. to compare the keys of two files without decoding either;
+//! - `pk`, the pk in the raw halo2 format, field elements as they are in memory,
+//!   so that decoding it is a copy out of the mapping.
+//!
+//! The file is mapped, so it is read at the speed of the page cache, which keeps
+//! a single copy of the file for all the processes of the host. The pk proved
+//! with isn't shared though: halo2 owns the vectors of a pk, so every prover
+//! copies it out of the mapping, and holds the full pk in memory of its own. With
+//! `PARAMS_SHARED` on Linux 6.4+, the copies are merged, see `utils::share_params`.
+
+use crate::error::{KeygenError, Result};
+use crate::io::serialize_vk;
+use crate::provenance::DigestWriter;
+use crate::utils::{read_env_var, vk_digest};
+use crate::version::CircuitVersion;
+use halo2_proofs::halo2curves::bn256::{Fr, G1Affine};
+use halo2_proofs::halo2curves::serde::SerdeObject;
+use halo2_proofs::plonk::{Circuit, ProvingKey, VerifyingKey};
+use halo2_proofs::SerdeFormat;
+use memmap2::Mmap;
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Dir of the pks of new provers, none if empty.
+pub static PK_DIR: Lazy<String> = Lazy::new(|| read_env_var("PK_DIR", String::new()));
+
+pub const PK_MAGIC: [u8; 8] = *b"zkevmpk\0";
+pub const PK_FORMAT_VERSION: u32 = 1;
+/// Alignment of the sections, and length of the header.
+const PAGE: u64 = 4096;
+/// Bytes of a raw point.
+const POINT_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PkHeader {
+    pub format_version: u32,
+    pub circuit: String,
+    pub degree: u32,
+    pub circuit_version: CircuitVersion,
+    /// What else the pk depends on, e.g. the agg config, empty if nothing.
+    pub config: String,
+    /// Digest of the `vk` section, see `utils::vk_digest`.
+    pub vk_digest: String,
+    /// sha256 of the `pk` section, the digest of the pk in provenance manifests,
+    /// empty in the files written before it was recorded.
+    #[serde(default)]
+    pub pk_digest: String,
+    pub sections: Vec<PkSection>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PkSection {
+    pub name: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// A pk file mapped into memory.
+pub struct MappedPk {
+    path: PathBuf,
+    header: PkHeader,
+    map: Mmap,
+}
+
+impl MappedPk {
+    pub fn open(path: &Path) -> Result<Self> {
+        let invalid = |reason: String| KeygenError::InvalidPk {
+            path: path.display().to_string(),
+            reason,
+        };
+        let file = File::open(path)?;
+        // safe as long as the file isn't written while mapped: pk files are
+        // replaced by a rename, never written in place
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < PAGE as usize || map[..8] != PK_MAGIC {
+            return Err(invalid("not a pk file".to_string()).into());
+        }
+        let header_len = u32::from_le_bytes(map[8..12].try_into().unwrap()) as usize;
+        let header: PkHeader = map
+            .get(12..12 + header_len)
+            .ok_or_else(|| invalid(format!("header of {header_len} bytes")))
+            .and_then(|header| {
+                serde_json::from_slice(header).map_err(|e| invalid(format!("header: {e}")))
+            })?;
+        if header.format_version != PK_FORMAT_VERSION {
+            return Err(invalid(format!("format version {}", header.format_version)).into());
+        }
+        for section in &header.sections {
+            let in_bounds = section
+                .offset
+                .checked_add(section.len)
+                .map_or(false, |end| end <= map.len() as u64);
+            if section.offset % PAGE != 0 || !in_bounds {
+                return Err(invalid(format!(
+                    "section {} at {}..+{} of a file of {} bytes",
+                    section.name,
+                    section.offset,
+                    section.len,
+                    map.len()
+                ))
+                .into());
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            header,
+            map,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn header(&self) -> &PkHeader {
+        &self.header
+    }
+
+    /// The bytes of the section, in place.
+    pub fn section(&self, name: &str) -> Result<&[u8]> {
+        let section = self
+            .header
+            .sections
+            .iter()
+            .find(|section| section.name == name)
+            .ok_or_else(|| KeygenError::InvalidPk {
+                path: self.path.display().to_string(),
+                reason: format!("no section {name}"),
+            })?;
+        Ok(&self.map[section.offset as usize..(section.offset + section.len) as usize])
+    }
+
+    /// The commitments of the fixed columns, read from the mapping without
+    /// decoding the pk.
+    pub fn fixed_commitments(&self) -> Result<Vec<G1Affine>> {
+        self.points("fixed_commitments")
+    }
+
+    /// The commitments of the permutation, read from the mapping without
+    /// decoding the pk.
+    pub fn permutation_commitments(&self) -> Result<Vec<G1Affine>> {
+        self.points("permutation_commitments")
+    }
+
+    fn points(&self, name: &str) -> Result<Vec<G1Affine>> {
+        Ok(self
+            .section(name)?
+            .chunks_exact(POINT_LEN)
+            .map(G1Affine::from_raw_bytes_unchecked)
+            .collect())
+    }
+
+    pub fn vk<C: Circuit<Fr>>(&self) -> Result<VerifyingKey<G1Affine>> {
+        let mut vk = self.section("vk")?;
+        VerifyingKey::<G1Affine>::read::<_, C>(&mut vk, SerdeFormat::Processed).map_err(|e| {
+            KeygenError::InvalidVk {
+                circuit: self.header.circuit.clone(),
+                reason: e.to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// sha256 of the `pk` section, from the header if recorded there.
+    pub fn pk_digest(&self) -> Result<String> {
+        if !self.header.pk_digest.is_empty() {
+            return Ok(self.header.pk_digest.clone());
+        }
+        let mut writer = DigestWriter::default();
+        writer.write_all(self.section("pk")?)?;
+        Ok(writer.finish())
+    }
+
+    /// Decode the pk, checked against the vk of the file. The pk is a copy, in
+    /// vectors owned by halo2, not a view of the mapping: it takes its full size in
+    /// memory, on top of the pages of the file in the page cache.
+    pub fn proving_key<C: Circuit<Fr>>(&self) -> Result<ProvingKey<G1Affine>> {
+        let invalid = |reason: String| KeygenError::InvalidPk {
+            path: self.path.display().to_string(),
+            reason,
+        };
+        let mut buf = self.section("pk")?;
+        #[cfg(unix)]
+        self.map.advise(memmap2::Advice::Sequential).ok();
+        let pk = ProvingKey::<G1Affine>::read::<_, C>(&mut buf, SerdeFormat::RawBytesUnchecked)
+            .map_err(|e| invalid(e.to_string()))?;
+        let actual = vk_digest(&serialize_vk(pk.get_vk()));
+        if actual != self.header.vk_digest {
+            return Err(invalid(format!(
+                "vk {} of the pk differs from the one of the file {}",
+                actual, self.header.vk_digest
+            ))
+            .into());
+        }
+        Ok(pk)
+    }
+}
+
+/// Write the pk file, through a temp file so that a crash doesn't leave a
+/// truncated pk behind. Returns the digest of the pk, see `PkHeader::pk_digest`.
+pub fn write_pk_file(
+    path: &Path,
+    circuit: &str,
+    circuit_version: &CircuitVersion,
+    config: &str,
+    pk: &ProvingKey<G1Affine>,
+) -> Result<String> {
+    let vk = pk.get_vk();
+    let vk_bytes = serialize_vk(vk);
+    let raw_points = |points: &[G1Affine]| -> Vec<u8> {
+        points
+            .iter()
+            .flat_map(|point| point.to_raw_bytes())
+            .collect()
+    };
+    let mut header = PkHeader {
+        format_version: PK_FORMAT_VERSION,
+        circuit: circuit.to_string(),
+        degree: vk.get_domain().k(),
+        circuit_version: circuit_version.clone(),
+        config: config.to_string(),
+        vk_digest: vk_digest(&vk_bytes),
+        pk_digest: String::new(),
+        sections: vec![],
+    };
+    let mut pk_digest = String::new();
+
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    writer.seek(SeekFrom::Start(PAGE))?;
+    let mut section = |writer: &mut BufWriter<File>,
+                       name: &str,
+                       write: &mut dyn FnMut(&mut BufWriter<File>) -> io::Result<()>|
+     -> io::Result<()> {
+        let offset = writer.stream_position()?;
+        let padding = (PAGE - offset % PAGE) % PAGE;
+        writer.write_all(&vec![0u8; padding as usize])?;
+        let offset = offset + padding;
+        write(writer)?;
+        header.sections.push(PkSection {
+            name: name.to_string(),
+            offset,
+            len: writer.stream_position()? - offset,
+        });
+        Ok(())
+    };
+    section(&mut writer, "vk", &mut |w| w.write_all(&vk_bytes))?;
+    section(&mut writer, "fixed_commitments", &mut |w| {
+        w.write_all(&raw_points(vk.fixed_commitments()))
+    })?;
+    section(&mut writer, "permutation_commitments", &mut |w| {
+        w.write_all(&raw_points(vk.permutation().commitments()))
+    })?;
+    section(&mut writer, "pk", &mut |w| {
+        let mut tee = DigestTee {
+            writer: w,
+            digest: DigestWriter::default(),
+        };
+        pk.write(&mut tee, SerdeFormat::RawBytesUnchecked)?;
+        pk_digest = tee.digest.finish();
+        Ok(())
+    })?;
+    header.pk_digest = pk_digest.clone();
+
+    let header_json = serde_json::to_vec(&header)?;
+    if 12 + header_json.len() as u64 > PAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("pk header of {} bytes", header_json.len()),
+        )
+        .into());
+    }
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&PK_MAGIC)?;
+    writer.write_all(&(header_json.len() as u32).to_le_bytes())?;
+    writer.write_all(&header_json)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(pk_digest)
+}
+
+/// Writes through, digesting what is written.
+struct DigestTee<'a, W> {
+    writer: &'a mut W,
+    digest: DigestWriter,
+}
+
+impl<W: Write> Write for DigestTee<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.digest.write_all(&buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// The pks of a dir, by circuit version and circuit.
+#[derive(Debug)]
+pub struct PkStore {
+    root: PathBuf,
+}
+
+impl PkStore {
+    pub fn open(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    /// The store at `PK_DIR`, if set.
+    pub fn from_env() -> Option<Self> {
+        let dir = PK_DIR.as_str();
+        if dir.is_empty() {
+            return None;
+        }
+        Self::open(dir)
+            .map_err(|e| log::error!("failed to open the pk dir {}: {}", dir, e))
+            .ok()
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn path(&self, circuit_version: &CircuitVersion, circuit: &str) -> PathBuf {
+        let name = |name: &str| name.replace(|c| c == '/' || c == '\\', "_");
+        self.root
+            .join(name(&circuit_version.0))
+            .join(format!("{}.pk", name(circuit)))
+    }
+
+    /// The pk of the circuit at `degree` with its digest, see `PkHeader::pk_digest`,
+    /// none if not stored for the version and config, or at another degree, e.g.
+    /// after `CIRCUIT_DEGREES` changed.
+    pub fn load<C: Circuit<Fr>>(
+        &self,
+        circuit: &str,
+        degree: u32,
+        circuit_version: &CircuitVersion,
+        config: &str,
+    ) -> Result<Option<(ProvingKey<G1Affine>, String)>> {
+        let path = self.path(circuit_version, circuit);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let start = Instant::now();
+        let mapped = MappedPk::open(&path)?;
+        let header = mapped.header();
+        if header.circuit != circuit
+            || header.circuit_version != *circuit_version
+            || header.config != config
+        {
+            log::warn!(
+                "pk {} is of another circuit or config, generating it again",
+                path.display()
+            );
+            return Ok(None);
+        }
+        if header.degree != degree {
+            log::warn!(
+                "pk {} is of degree {}, not {}, generating it again",
+                path.display(),
+                header.degree,
+                degree
+            );
+            return Ok(None);
+        }
+        let pk = mapped.proving_key::<C>()?;
+        let pk_digest = mapped.pk_digest()?;
+        log::info!(
+            "pk of {} loaded from {} in {:?}",
+            circuit,
+            path.display(),
+            start.elapsed()
+        );
+        Ok(Some((pk, pk_digest)))
+    }
+
+    /// Write the pk, returns its path and digest.
+    pub fn save(
+        &self,
+        circuit: &str,
+        circuit_version: &CircuitVersion,
+        config: &str,
+        pk: &ProvingKey<G1Affine>,
+    ) -> Result<(PathBuf, String)> {
+        let path = self.path(circuit_version, circuit);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let start = Instant::now();
+        let pk_digest = write_pk_file(&path, circuit, circuit_version, config, pk)?;
+        log::info!(
+            "pk of {} written to {} in {:?}",
+            circuit,
+            path.display(),
+            start.elapsed()
+        );
+        Ok((path, pk_digest))
+    }
+}
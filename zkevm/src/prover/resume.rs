@@ -0,0 +1,147 @@
+//! Resumable aggregation.
+//!
+//! With a resume dir set, `create_agg_circuit_proof_batch` persists the inner
+//! proofs and the seeds of the aggregation before proving it, so that a crashed
+//! aggregation, e.g. killed by the OOM killer, is finished by `resume_agg_from_dir`
+//! without proving the inner circuits again.
+//!
+//! The state holds the hash of the traces it was proved from, and is only resumed
+//! for these traces. Its inner proofs are verified before they are aggregated, so
+//! that a corrupt or tampered state fails rather than being aggregated.
+
+use super::pipeline::{dump_json, load_json};
+use super::{rng_from_seed, AggCircuitProof, Prover, RngSeed, TargetCircuitProof};
+use crate::attestation::trace_hash;
+use crate::circuit::{SuperCircuit, TargetCircuit};
+use crate::error::{ProvingError, Result, VerificationError};
+use crate::utils::{params_of_degree, read_env_var};
+use chrono::Utc;
+use eth_types::H256;
+use halo2_proofs::poly::commitment::ParamsProver;
+use once_cell::sync::Lazy;
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use snark_verifier_sdk::halo2::verify_snark_shplonk;
+use std::path::{Path, PathBuf};
+use types::eth::BlockTrace;
+
+/// Default resume dir of new provers, empty for none.
+pub static AGG_RESUME_DIR: Lazy<String> =
+    Lazy::new(|| read_env_var("AGG_RESUME_DIR", "".to_string()));
+
+const AGG_RESUME_FILE: &str = "agg_resume.json";
+
+/// Inputs of `create_agg_circuit_proof_impl` of an unfinished aggregation.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AggResumeState {
+    /// Hash of the traces the inner proofs are of, see `attestation::trace_hash`.
+    pub trace_hash: H256,
+    /// Seed the inner proofs were proved with.
+    pub inner_seed: RngSeed,
+    /// Seed of the rng of the aggregation.
+    pub agg_seed: RngSeed,
+    pub inner_proofs: Vec<TargetCircuitProof>,
+}
+
+impl AggResumeState {
+    /// Whether the state is of the aggregation of `block_traces`.
+    pub fn is_of(&self, block_traces: &[BlockTrace]) -> bool {
+        self.trace_hash == trace_hash(block_traces)
+    }
+}
+
+impl Prover {
+    /// Persist the inputs of the aggregations into `dir`, `None` not to persist them.
+    pub fn set_resume_dir(&mut self, dir: Option<PathBuf>) {
+        self.resume_dir = dir;
+    }
+
+    /// The state of an unfinished aggregation in `dir`, if any.
+    pub fn load_agg_resume_state(dir: &Path) -> Result<Option<AggResumeState>> {
+        load_json(dir, AGG_RESUME_FILE)
+    }
+
+    /// Finish the aggregation of `block_traces` persisted into `dir` by a crashed
+    /// `create_agg_circuit_proof_batch`. Fails if the state is of other traces, or
+    /// if one of its inner proofs doesn't verify.
+    pub fn resume_agg_from_dir(
+        &mut self,
+        dir: &Path,
+        block_traces: &[BlockTrace],
+    ) -> Result<AggCircuitProof> {
+        let started_at = Utc::now();
+        let state =
+            Self::load_agg_resume_state(dir)?.ok_or_else(|| ProvingError::NothingToResume {
+                dir: dir.display().to_string(),
+            })?;
+        if !state.is_of(block_traces) {
+            return Err(ProvingError::ResumeTracesMismatch {
+                dir: dir.display().to_string(),
+            }
+            .into());
+        }
+        self.verify_inner_proofs(&state.inner_proofs)?;
+        let mut agg_proof = self.finish_agg(dir, state)?;
+        self.seal_agg_proof(block_traces, started_at, &mut agg_proof)?;
+        Ok(agg_proof)
+    }
+
+    /// Verify the super circuit snarks of a state with the vk of the prover.
+    fn verify_inner_proofs(&mut self, inner_proofs: &[TargetCircuitProof]) -> Result<()> {
+        let name = SuperCircuit::name();
+        if !self.target_circuit_pks.contains_key(&name) {
+            self.init_pk::<SuperCircuit>(&SuperCircuit::dummy_inner_circuit())?;
+        }
+        let params = params_of_degree(
+            &self.params,
+            &mut self.degree_params,
+            SuperCircuit::degree(),
+        )?;
+        let vk = self.target_circuit_pks[&name].get_vk();
+        for proof in inner_proofs {
+            let verified = proof.name == name
+                && verify_snark_shplonk::<<SuperCircuit as TargetCircuit>::Inner>(
+                    params.verifier_params(),
+                    proof.snark.clone(),
+                    vk,
+                );
+            if !verified {
+                return Err(VerificationError::Failed {
+                    circuit: proof.name.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn create_agg_circuit_proof_batch_resumable(
+        &mut self,
+        dir: &Path,
+        block_traces: &[BlockTrace],
+        rng: &mut (impl Rng + Send),
+    ) -> Result<AggCircuitProof> {
+        let inner_seed = rng.gen();
+        let agg_seed = rng.gen();
+        let mut inner_rng = rng_from_seed(inner_seed);
+        let inner_proofs =
+            vec![self.prove_inner_circuit::<SuperCircuit>(block_traces, &mut inner_rng)?];
+
+        std::fs::create_dir_all(dir)?;
+        let state = AggResumeState {
+            trace_hash: self.trace_hash(block_traces),
+            inner_seed,
+            agg_seed,
+            inner_proofs,
+        };
+        dump_json(dir, AGG_RESUME_FILE, &state)?;
+        self.finish_agg(dir, state)
+    }
+
+    fn finish_agg(&mut self, dir: &Path, state: AggResumeState) -> Result<AggCircuitProof> {
+        let mut rng = rng_from_seed(state.agg_seed);
+        let proof = self.create_agg_circuit_proof_impl(&state.inner_proofs, &mut rng)?;
+        std::fs::remove_file(dir.join(AGG_RESUME_FILE))?;
+        Ok(proof)
+    }
+}
@@ -0,0 +1,51 @@
+//! The randomness of the prover, blinding the proofs.
+//!
+//! The rng is ChaCha20, keyed from the OS entropy; the seed file, if any, is
+//! mixed into the key, but doesn't make the proofs reproducible. Built with the
+//! `deterministic-tests` feature, it is XorShift seeded from the seed alone, so
+//! that tests get the same proofs on every run; never for production proofs.
+
+use rand::Rng;
+use rand::SeedableRng;
+
+#[cfg(not(feature = "deterministic-tests"))]
+pub type ProverRng = rand_chacha::ChaCha20Rng;
+#[cfg(feature = "deterministic-tests")]
+pub type ProverRng = rand_xorshift::XorShiftRng;
+
+/// Seed of a `ProverRng`, e.g. persisted to resume an aggregation.
+pub type RngSeed = [u8; 32];
+
+/// The rng of a prover, with the seed of its seed file if any.
+#[cfg(not(feature = "deterministic-tests"))]
+pub fn prover_rng(seed: Option<[u8; 16]>) -> ProverRng {
+    use sha2::{Digest, Sha256};
+
+    let mut key: RngSeed = rand::rngs::OsRng.gen();
+    if let Some(seed) = seed {
+        key = Sha256::new()
+            .chain_update(key)
+            .chain_update(seed)
+            .finalize()
+            .into();
+    }
+    ProverRng::from_seed(key)
+}
+
+/// The rng of a prover, with the seed of its seed file if any.
+#[cfg(feature = "deterministic-tests")]
+pub fn prover_rng(seed: Option<[u8; 16]>) -> ProverRng {
+    ProverRng::from_seed(seed.unwrap_or_default())
+}
+
+/// The rng of the seed.
+pub fn rng_from_seed(seed: RngSeed) -> ProverRng {
+    #[cfg(feature = "deterministic-tests")]
+    let seed: [u8; 16] = seed[..16].try_into().unwrap();
+    ProverRng::from_seed(seed)
+}
+
+/// An rng of its own for a proof, drawn from `rng`.
+pub fn derive_rng(rng: &mut (impl Rng + ?Sized)) -> ProverRng {
+    rng_from_seed(rng.gen())
+}
@@ -1,26 +1,239 @@
 //! Initialization and utility APIs for Prover.
 //!
-use super::Prover;
-use crate::circuit::{TargetCircuit, AGG_DEGREE, DEGREE};
-use crate::utils::load_or_create_params;
-use crate::utils::load_seed;
-use halo2_proofs::halo2curves::bn256::Bn256;
-use halo2_proofs::plonk::keygen_pk2;
-use halo2_proofs::poly::commitment::ParamsProver;
+use super::{
+    prover_rng, AggCircuitProof, AggConfig, BufferPool, Deadline, PkStore, Prover, ProverRng,
+    WitnessMemory, AGG_RESUME_DIR, AGG_VK_DIGEST, AGG_VK_DIGEST_STRICT, MAX_CHUNKS_PER_BATCH,
+};
+use crate::attestation::{
+    attester_from_env, instance_hash, report_data, trace_hash_into, Attestation, Attester,
+};
+use crate::audit::{audit_log_from_env, AuditLog};
+use crate::circuit::{
+    ChainBoundAggregationCircuit, TargetCircuit, AGG_DEGREE, CIRCUIT_DEGREES, DEGREE,
+};
+use crate::error::{KeygenError, ProvingError, Result};
+use crate::io::serialize_vk;
+use crate::provenance::{
+    hostname, BuildInfo, CircuitConfig, DigestWriter, ProvenanceManifest, PROVENANCE_MANIFEST,
+};
+use crate::skip::{SkipList, SKIP_LIST};
+use crate::trie_repair::TrieProofSource;
+#[cfg(feature = "test-mode")]
+use crate::utils::dev_params;
+use crate::utils::{check_vk_digest, load_seed, vk_digest};
+use crate::utils::{load_or_create_params, load_params_any_format, params_of_degree};
+use crate::version::CircuitVersion;
+use chrono::{DateTime, Utc};
+use eth_types::H256;
+use halo2_proofs::halo2curves::bn256::{Bn256, Fr, G1Affine};
+use halo2_proofs::plonk::{keygen_pk2, Circuit, ProvingKey};
+use halo2_proofs::poly::commitment::{Params, ParamsProver};
 use halo2_proofs::poly::kzg::commitment::{ParamsKZG, ParamsVerifierKZG};
-use rand::SeedableRng;
-use rand_xorshift::XorShiftRng;
+use halo2_proofs::SerdeFormat;
+use once_cell::sync::Lazy;
+use snark_verifier_sdk::gen_pk;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use types::eth::BlockTrace;
 
 impl Prover {
     /// Build a new Prover from parameters.
-    pub fn new(params: ParamsKZG<Bn256>, agg_params: ParamsKZG<Bn256>, rng: XorShiftRng) -> Self {
+    pub fn new(params: ParamsKZG<Bn256>, agg_params: ParamsKZG<Bn256>, rng: ProverRng) -> Self {
+        // an invalid CIRCUIT_DEGREES fails here, not at the first keygen
+        Lazy::force(&CIRCUIT_DEGREES);
+        let agg_config = AggConfig::default_for(agg_params.k());
+        let params_digests = if *PROVENANCE_MANIFEST {
+            match (params_digest(&params), params_digest(&agg_params)) {
+                (Ok(params_digest), Ok(agg_params_digest)) => {
+                    Some([params_digest, agg_params_digest])
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    log::error!("failed to digest the params, digested per proof: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
         Self {
             params,
             agg_params,
+            degree_params: Default::default(),
             rng,
             target_circuit_pks: Default::default(),
             agg_pk: None,
+            agg_pk_layout: None,
+            params_digests,
+            pk_digests: Default::default(),
+            level_pks: Default::default(),
+            max_chunks_per_batch: Some(*MAX_CHUNKS_PER_BATCH).filter(|max| *max != 0),
+            dummy_chunk_snark: None,
             debug_dir: Default::default(),
+            circuit_version: CircuitVersion::current(),
+            deadline: None,
+            agg_config,
+            resume_dir: Some(AGG_RESUME_DIR.as_str())
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+            attester: attester_from_env().unwrap_or_else(|e| {
+                log::error!("{}, agg proofs are not attested", e);
+                None
+            }),
+            skip_list: SKIP_LIST.clone(),
+            witness_memory: WitnessMemory::default(),
+            buffer_pool: BufferPool::new(WitnessMemory::default().retained_bytes),
+            audit_log: audit_log_from_env(),
+            trie_proof_source: None,
+            pk_store: PkStore::from_env().map(Arc::new),
+        }
+    }
+
+    /// Load the pks from `store` and write the ones generated into it, `None` to
+    /// generate them every time.
+    pub fn set_pk_store(&mut self, store: Option<Arc<PkStore>>) {
+        self.pk_store = store;
+    }
+
+    /// Fetch the proofs missing from the storage traces from `source`, `None` to
+    /// fail the blocks missing some.
+    pub fn set_trie_proof_source(&mut self, source: Option<Arc<dyn TrieProofSource>>) {
+        self.trie_proof_source = source;
+    }
+
+    /// Record the proofs generated into the log, `None` not to record them.
+    pub fn set_audit_log(&mut self, audit_log: Option<Arc<AuditLog>>) {
+        self.audit_log = audit_log;
+    }
+
+    /// Hash of the block traces, serialized into a buffer of the pool.
+    pub(crate) fn trace_hash(&self, block_traces: &[BlockTrace]) -> H256 {
+        let mut json = self.buffer_pool.take();
+        let hash = trace_hash_into(block_traces, &mut json);
+        self.buffer_pool.give(json);
+        hash
+    }
+
+    /// Cap the freed witness memory, and the buffers, kept for the next job,
+    /// `None` to keep all.
+    pub fn set_witness_retained_bytes(&mut self, retained_bytes: Option<usize>) {
+        self.witness_memory = WitnessMemory { retained_bytes };
+        self.buffer_pool = BufferPool::new(retained_bytes);
+    }
+
+    /// Set the opcodes and precompiles that skip a block or fail the batch.
+    pub fn set_skip_list(&mut self, skip_list: SkipList) {
+        self.skip_list = skip_list;
+    }
+
+    /// Attest the agg proofs of block traces with a TEE quote, `None` not to.
+    pub fn set_attester(&mut self, attester: Option<Arc<dyn Attester>>) {
+        self.attester = attester;
+    }
+
+    pub(crate) fn attest(
+        &self,
+        block_traces: &[BlockTrace],
+        agg_proof: &mut AggCircuitProof,
+    ) -> Result<(), ProvingError> {
+        let attester = match &self.attester {
+            Some(attester) => attester,
+            None => return Ok(()),
+        };
+        let trace_hash = self.trace_hash(block_traces);
+        let instance_hash = instance_hash(&agg_proof.instance);
+        let vk_digest = vk_digest(&agg_proof.vk);
+        let quote = attester.quote(&report_data(&trace_hash, &instance_hash, &vk_digest))?;
+        agg_proof.attestation = Some(Attestation {
+            kind: attester.kind(),
+            quote,
+            trace_hash,
+            instance_hash,
+            vk_digest,
+        });
+        Ok(())
+    }
+
+    /// Attest the agg proof of the block traces and stamp its manifest, proved
+    /// since `started_at`: the last step of every path proving block traces, the
+    /// service and its workers included.
+    pub(crate) fn seal_agg_proof(
+        &self,
+        block_traces: &[BlockTrace],
+        started_at: DateTime<Utc>,
+        agg_proof: &mut AggCircuitProof,
+    ) -> Result<()> {
+        self.attest(block_traces, agg_proof)?;
+        self.stamp_manifest(block_traces, started_at, agg_proof)
+    }
+
+    /// Store the provenance manifest of the agg proof of the block traces, proved
+    /// since `started_at`, if `PROVENANCE_MANIFEST`.
+    pub(crate) fn stamp_manifest(
+        &self,
+        block_traces: &[BlockTrace],
+        started_at: DateTime<Utc>,
+        agg_proof: &mut AggCircuitProof,
+    ) -> Result<()> {
+        if !*PROVENANCE_MANIFEST {
+            return Ok(());
+        }
+        // the digests are taken as the params and keys are loaded, only the ones
+        // missing, e.g. of pks set by hand, are taken here
+        let [params_digest, agg_params_digest] = match &self.params_digests {
+            Some(digests) => digests.clone(),
+            None => [
+                params_digest(&self.params)?,
+                params_digest(&self.agg_params)?,
+            ],
+        };
+        let mut pk_digests = BTreeMap::new();
+        let pks = self
+            .target_circuit_pks
+            .iter()
+            .map(|(name, pk)| (name.as_str(), pk))
+            .chain(self.agg_pk.iter().map(|pk| ("agg", pk)));
+        for (name, pk) in pks {
+            let digest = match self.pk_digests.get(name) {
+                Some(digest) => digest.clone(),
+                None => pk_digest(pk)?,
+            };
+            pk_digests.insert(name.to_string(), digest);
+        }
+        agg_proof.manifest = Some(ProvenanceManifest {
+            trace_hash: self.trace_hash(block_traces),
+            params_digest,
+            agg_params_digest,
+            pk_digests,
+            vk_digest: vk_digest(&agg_proof.vk),
+            circuit: CircuitConfig {
+                degree: self.params.k(),
+                agg_degree: self.agg_params.k(),
+                circuit_version: self.circuit_version.clone(),
+                circuit_degrees: CIRCUIT_DEGREES.clone(),
+                agg_config: self
+                    .agg_config
+                    .as_ref()
+                    .map(|config| serde_json::to_value(config).expect("config is serializable")),
+            },
+            build: BuildInfo::current(),
+            hostname: hostname(),
+            started_at: started_at.to_rfc3339(),
+            finished_at: Utc::now().to_rfc3339(),
+        });
+        Ok(())
+    }
+
+    /// Bound the wall-clock time of the next proofs, `None` to lift it.
+    /// Proving fails with `ProvingError::Timeout` at the first phase boundary past it.
+    pub fn set_deadline(&mut self, deadline: Option<Deadline>) {
+        self.deadline = deadline;
+    }
+
+    pub(crate) fn check_deadline(&self, next_phase: &str) -> Result<(), ProvingError> {
+        match &self.deadline {
+            Some(deadline) => deadline.check(next_phase),
+            None => Ok(()),
         }
     }
 
@@ -41,18 +254,125 @@ impl Prover {
     }
 
     /// Initiates the public key for a given inner circuit.
-    pub(crate) fn init_pk<C: TargetCircuit>(&mut self, circuit: &<C as TargetCircuit>::Inner) {
+    pub(crate) fn init_pk<C: TargetCircuit>(
+        &mut self,
+        circuit: &<C as TargetCircuit>::Inner,
+    ) -> Result<()> {
         Self::tick(&format!("before init pk of {}", C::name()));
-        let pk = keygen_pk2(&self.params, circuit)
-            .unwrap_or_else(|e| panic!("failed to generate {} pk: {:?}", C::name(), e));
+        let (pk, digest) = match self.stored_pk::<C::Inner>(&C::name(), C::degree(), "") {
+            Some((pk, digest)) => (pk, Some(digest)),
+            None => {
+                let params = params_of_degree(&self.params, &mut self.degree_params, C::degree())?;
+                let pk = keygen_pk2(params, circuit).map_err(|e| KeygenError::Generate {
+                    circuit: C::name(),
+                    key: "pk",
+                    reason: format!("{e:?}"),
+                })?;
+                let digest = self.store_pk(&C::name(), "", &pk);
+                (pk, digest)
+            }
+        };
+        self.record_pk_digest(&C::name(), &pk, digest);
         self.target_circuit_pks.insert(C::name(), pk);
         Self::tick(&format!("after init pk of {}", C::name()));
+        Ok(())
+    }
+
+    /// Initiates the public key for the aggregation circuit of the snarks of
+    /// `layout`, see `snark_layout`. Its vk is checked against `AGG_VK_DIGEST`,
+    /// whether the pk is loaded or generated, so that a prover of the wrong
+    /// circuit fails when warming up rather than after proving the inner snarks.
+    pub(crate) fn init_agg_pk(
+        &mut self,
+        circuit: &ChainBoundAggregationCircuit,
+        layout: &str,
+    ) -> Result<(), KeygenError> {
+        Self::tick("before init pk of aggregation");
+        // the agg circuit embeds the vks of the snarks it verifies
+        let inner_vks: BTreeMap<_, _> = self
+            .target_circuit_pks
+            .iter()
+            .map(|(name, pk)| (name.clone(), vk_digest(&serialize_vk(pk.get_vk()))))
+            .collect();
+        let config = serde_json::json!({
+            "agg_config": self.agg_config,
+            "inner_vks": inner_vks,
+            "snarks": layout,
+        })
+        .to_string();
+        let agg_degree = self.agg_params.k();
+        let (pk, digest) =
+            match self.stored_pk::<ChainBoundAggregationCircuit>("agg", agg_degree, &config) {
+                Some((pk, digest)) => (pk, Some(digest)),
+                None => {
+                    let pk = gen_pk(&self.agg_params, circuit, None);
+                    let digest = self.store_pk("agg", &config, &pk);
+                    (pk, digest)
+                }
+            };
+        check_vk_digest(
+            &serialize_vk(pk.get_vk()),
+            &AGG_VK_DIGEST,
+            *AGG_VK_DIGEST_STRICT,
+        )?;
+        self.record_pk_digest("agg", &pk, digest);
+        self.agg_pk = Some(pk);
+        self.agg_pk_layout = Some(layout.to_string());
+        Self::tick("after init pk of aggregation");
+        Ok(())
+    }
+
+    /// The pk of the store, if there. A pk failing to load is generated again.
+    fn stored_pk<C: Circuit<Fr>>(
+        &self,
+        circuit: &str,
+        degree: u32,
+        config: &str,
+    ) -> Option<(ProvingKey<G1Affine>, String)> {
+        let store = self.pk_store.as_ref()?;
+        store
+            .load::<C>(circuit, degree, &self.circuit_version, config)
+            .map_err(|e| log::error!("failed to load the pk of {}: {}", circuit, e))
+            .ok()
+            .flatten()
+    }
+
+    /// Write the pk into the store, if any, returns its digest once written.
+    fn store_pk(&self, circuit: &str, config: &str, pk: &ProvingKey<G1Affine>) -> Option<String> {
+        let store = self.pk_store.as_ref()?;
+        match store.save(circuit, &self.circuit_version, config, pk) {
+            Ok((_, digest)) => Some(digest),
+            Err(e) => {
+                log::error!("failed to store the pk of {}: {}", circuit, e);
+                None
+            }
+        }
+    }
+
+    /// Keep the digest of the pk of `circuit` for the manifests, taken from the pk
+    /// unless `digest` is known, e.g. from its pk file.
+    fn record_pk_digest(
+        &mut self,
+        circuit: &str,
+        pk: &ProvingKey<G1Affine>,
+        digest: Option<String>,
+    ) {
+        self.pk_digests.remove(circuit);
+        if !*PROVENANCE_MANIFEST {
+            return;
+        }
+        match digest.map_or_else(|| pk_digest(pk), Ok) {
+            Ok(digest) => {
+                self.pk_digests.insert(circuit.to_string(), digest);
+            }
+            Err(e) => log::error!("failed to digest the pk of {}: {}", circuit, e),
+        }
     }
 
     pub fn from_params_and_rng(
         params: ParamsKZG<Bn256>,
         agg_params: ParamsKZG<Bn256>,
-        rng: XorShiftRng,
+        rng: ProverRng,
     ) -> Self {
         {
             let target_params_verifier: &ParamsVerifierKZG<Bn256> = params.verifier_params();
@@ -73,15 +393,47 @@ impl Prover {
         agg_params: ParamsKZG<Bn256>,
         seed: [u8; 16],
     ) -> Self {
-        let rng = XorShiftRng::from_seed(seed);
-        Self::from_params_and_rng(params, agg_params, rng)
+        Self::from_params_and_rng(params, agg_params, prover_rng(Some(seed)))
     }
 
     pub fn from_fpath(params_fpath: &str, seed_fpath: &str) -> Self {
-        let params = load_or_create_params(params_fpath, *DEGREE).expect("failed to init params");
-        let agg_params =
-            load_or_create_params(params_fpath, *AGG_DEGREE).expect("failed to init params");
-        let seed = load_seed(seed_fpath).expect("failed to init rng");
-        Self::from_params_and_seed(params, agg_params, seed)
+        Self::try_from_fpath(params_fpath, seed_fpath).expect("failed to init prover")
+    }
+
+    /// A prover over the params generated in memory by `dev_params`.
+    #[cfg(feature = "test-mode")]
+    pub fn dev() -> Self {
+        Self::from_params_and_seed(dev_params(*DEGREE), dev_params(*AGG_DEGREE), [0u8; 16])
+    }
+
+    pub fn try_from_fpath(params_fpath: &str, seed_fpath: &str) -> Result<Self> {
+        let params = load_or_create_params(params_fpath, *DEGREE)?;
+        let agg_params = load_or_create_params(params_fpath, *AGG_DEGREE)?;
+        let seed = load_seed(seed_fpath)?;
+        Ok(Self::from_params_and_seed(params, agg_params, seed))
     }
+
+    /// Like `try_from_fpath`, but fails on missing params instead of creating
+    /// them, e.g. for paths given over an API.
+    pub fn try_load_from_fpath(params_fpath: &str, seed_fpath: &str) -> Result<Self> {
+        let params = load_params_any_format(params_fpath, *DEGREE)?;
+        let agg_params = load_params_any_format(params_fpath, *AGG_DEGREE)?;
+        let seed = load_seed(seed_fpath)?;
+        Ok(Self::from_params_and_seed(params, agg_params, seed))
+    }
+}
+
+/// sha256 of the params as serialized by `Params::write`.
+fn params_digest(params: &ParamsKZG<Bn256>) -> std::io::Result<String> {
+    let mut writer = DigestWriter::default();
+    params.write(&mut writer)?;
+    Ok(writer.finish())
+}
+
+/// sha256 of the pk in the raw halo2 format, the `pk` section of its pk file, see
+/// `PkHeader::pk_digest`.
+fn pk_digest(pk: &ProvingKey<G1Affine>) -> std::io::Result<String> {
+    let mut writer = DigestWriter::default();
+    pk.write(&mut writer, SerdeFormat::RawBytesUnchecked)?;
+    Ok(writer.finish())
 }
@@ -0,0 +1,99 @@
+//! Warm-up of a Prover, so that the first job doesn't pay for the setup.
+
+use super::outer_circuit::snark_layout;
+use super::{derive_rng, Prover};
+use crate::circuit::{ChainBoundAggregationCircuit, SuperCircuit, TargetCircuit, CHAIN_ID};
+use crate::error::Result;
+use serde_derive::{Deserialize, Serialize};
+use snark_verifier_sdk::halo2::aggregation::AggregationCircuit;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WarmUpStep {
+    pub name: String,
+    pub elapsed: Duration,
+}
+
+/// Timing of each step of a warm-up, in order. Steps with nothing to do,
+/// e.g. a pk already loaded, are not listed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WarmUpReport {
+    pub steps: Vec<WarmUpStep>,
+}
+
+impl WarmUpReport {
+    pub fn total(&self) -> Duration {
+        self.steps.iter().map(|s| s.elapsed).sum()
+    }
+
+    fn time<T>(&mut self, name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let start = Instant::now();
+        let result = f()?;
+        let elapsed = start.elapsed();
+        log::info!("warm up: {} took {:?}", name, elapsed);
+        self.steps.push(WarmUpStep {
+            name: name.to_string(),
+            elapsed,
+        });
+        Ok(result)
+    }
+}
+
+impl Prover {
+    /// Load the params and the seed, then warm up the new prover.
+    pub fn warm_up_from_fpath(
+        params_fpath: &str,
+        seed_fpath: &str,
+        dummy_proof: bool,
+    ) -> Result<(Self, WarmUpReport)> {
+        let mut report = WarmUpReport::default();
+        let mut prover =
+            report.time("params", || Self::try_from_fpath(params_fpath, seed_fpath))?;
+        report.steps.extend(prover.warm_up(dummy_proof)?.steps);
+        Ok((prover, report))
+    }
+
+    /// Generate the proving keys of the super circuit and of the aggregation
+    /// circuit, and with `dummy_proof` run an aggregation proof of an empty batch.
+    ///
+    /// The agg pk is generated from a snark of an empty batch, so one inner proof
+    /// is run unless the agg pk is already there.
+    pub fn warm_up(&mut self, dummy_proof: bool) -> Result<WarmUpReport> {
+        let mut report = WarmUpReport::default();
+        let mut rng = derive_rng(&mut self.rng);
+
+        if !self.target_circuit_pks.contains_key(&SuperCircuit::name()) {
+            report.time("inner pk", || {
+                Ok(self.init_pk::<SuperCircuit>(&SuperCircuit::dummy_inner_circuit())?)
+            })?;
+        }
+        if self.agg_pk.is_some() && !dummy_proof {
+            return Ok(report);
+        }
+
+        let inner_proof = report.time("inner dummy proof", || {
+            self.create_target_circuit_proof_batch::<SuperCircuit>(&[], &mut rng)
+        })?;
+        let layout = snark_layout(std::slice::from_ref(&inner_proof));
+        if self.agg_pk_layout.as_deref() != Some(layout.as_str()) {
+            report.time("agg pk", || {
+                self.apply_agg_config()?;
+                let circuit = ChainBoundAggregationCircuit::new(
+                    AggregationCircuit::new(
+                        &self.agg_params,
+                        [inner_proof.snark.clone()],
+                        derive_rng(&mut rng),
+                    ),
+                    *CHAIN_ID,
+                );
+                Ok(self.init_agg_pk(&circuit, &layout)?)
+            })?;
+        }
+        if dummy_proof {
+            report.time("agg dummy proof", || {
+                self.create_agg_circuit_proof_impl(&[inner_proof], &mut rng)
+            })?;
+        }
+        Ok(report)
+    }
+}
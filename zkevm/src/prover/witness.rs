@@ -0,0 +1,201 @@
+//! Witness generation apart from proving.
+//!
+//! `generate_witness` runs the cheap phase of an inner proof: the skip list, the
+//! capacity check and witness generation, and returns a `WitnessArtifact` which
+//! `prove_from_witness` proves later, e.g. after the prover crashed, or on a host
+//! with the memory for proving while the witnesses are generated elsewhere.
+//!
+//! Neither the witness block of zkevm-circuits nor the columns assigned by halo2
+//! can be serialized, so the artifact holds what witness generation ran on: the
+//! block traces left by the skip list and the capacity check, with the instance
+//! the witness gave. Proving builds the witness again from those traces, without
+//! the skip list nor the capacity check, and fails if it doesn't give the same
+//! instance.
+//!
+//! The artifact is thus a checked bundle of traces rather than a portable
+//! witness: the host proving it pays for witness generation again. Artifacts are
+//! written in a compact versioned binary encoding with digests, see `format`, so
+//! that they can be handed between hosts.
+
+mod format;
+
+pub use format::{WITNESS_FORMAT_VERSION, WITNESS_MAGIC};
+
+use super::{BufferPool, Prover, TargetCircuitProof};
+use crate::circuit::{
+    block_traces_to_witness_block, check_batch_capacity, check_witness, TargetCircuit,
+};
+use crate::error::{ProvingError, Result};
+use crate::io::{deserialize_fr_matrix, serialize_fr_matrix};
+use crate::skip::{SkipReport, SKIP_LIST};
+use crate::version::CircuitVersion;
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+use types::eth::BlockTrace;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WitnessArtifact {
+    /// Name of the target circuit.
+    pub circuit: String,
+    pub degree: usize,
+    pub circuit_version: CircuitVersion,
+    /// Traces the witness is generated from.
+    pub block_traces: Vec<BlockTrace>,
+    /// Number of blocks given, before the skip list and the capacity check.
+    pub total_num_of_blocks: usize,
+    /// Instance of the circuit, as serialized field elements.
+    pub instance: Vec<Vec<Vec<u8>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_report: Option<SkipReport>,
+}
+
+impl WitnessArtifact {
+    /// Generate the witness without a prover, e.g. on a host without the params,
+    /// with the skip list at `SKIP_LIST`.
+    pub fn generate<C: TargetCircuit>(block_traces: &[BlockTrace]) -> Result<Self> {
+        let mut traces = block_traces.to_vec();
+        let skip_report = SKIP_LIST.apply(&mut traces)?;
+        check_batch_capacity(&mut traces)?;
+        Self::from_traces::<C>(
+            traces,
+            block_traces.len(),
+            Some(skip_report).filter(|report| !report.is_empty()),
+            CircuitVersion::current(),
+        )
+    }
+
+    fn from_traces<C: TargetCircuit>(
+        block_traces: Vec<BlockTrace>,
+        total_num_of_blocks: usize,
+        skip_report: Option<SkipReport>,
+        circuit_version: CircuitVersion,
+    ) -> Result<Self> {
+        let witness_block = block_traces_to_witness_block(&block_traces)?;
+        check_witness(&block_traces, &witness_block)?;
+        let (_, instance) = C::from_witness_block(&witness_block)?;
+        Ok(Self {
+            circuit: C::name(),
+            degree: C::degree() as usize,
+            circuit_version,
+            block_traces,
+            total_num_of_blocks,
+            instance: serialize_fr_matrix(&instance),
+            skip_report,
+        })
+    }
+
+    /// The binary encoding of the artifact, see `format`.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        format::encode(self)
+    }
+
+    /// Decode the binary encoding, checking its digests.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        format::decode(buf, &BufferPool::new(Some(0)))
+    }
+
+    /// `decode`, the traces being decompressed into a buffer of `pool`.
+    pub fn decode_with(buf: &[u8], pool: &BufferPool) -> Result<Self> {
+        format::decode(buf, pool)
+    }
+
+    /// Write the binary encoding, returns its digest in hex.
+    pub fn write(&self, path: &Path) -> Result<String> {
+        let buf = self.encode()?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, &buf)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(format::digest(&buf).unwrap())
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::decode(&fs::read(path)?)
+    }
+}
+
+impl Prover {
+    /// Generate the witness of the target circuit for the traces, without proving it.
+    pub fn generate_witness<C: TargetCircuit>(
+        &mut self,
+        block_traces: &[BlockTrace],
+    ) -> Result<WitnessArtifact> {
+        let mut traces = block_traces.to_vec();
+        let skip_report = self.prepare_block_traces(&mut traces)?;
+        self.check_deadline("witness generation")?;
+        let artifact = WitnessArtifact::from_traces::<C>(
+            traces,
+            block_traces.len(),
+            skip_report,
+            self.circuit_version.clone(),
+        );
+        self.witness_memory.release();
+        artifact
+    }
+
+    /// Prove the target circuit over the witness of the artifact.
+    pub fn prove_from_witness<C: TargetCircuit>(
+        &mut self,
+        artifact: &WitnessArtifact,
+        rng: &mut (impl Rng + Send),
+    ) -> Result<TargetCircuitProof> {
+        let start = Instant::now();
+        let result = self.prove_witness::<C>(artifact, rng);
+        self.audit_target_proof::<C>(&artifact.block_traces, start, &result);
+        result
+    }
+
+    fn prove_witness<C: TargetCircuit>(
+        &mut self,
+        artifact: &WitnessArtifact,
+        rng: &mut (impl Rng + Send),
+    ) -> Result<TargetCircuitProof> {
+        let invalid = |reason: String| ProvingError::InvalidWitness { reason };
+        if artifact.circuit != C::name() {
+            return Err(invalid(format!("of {}, not {}", artifact.circuit, C::name())).into());
+        }
+        if artifact.degree != C::degree() as usize {
+            return Err(invalid(format!(
+                "of degree {}, not {}",
+                artifact.degree,
+                C::degree()
+            ))
+            .into());
+        }
+        if !artifact
+            .circuit_version
+            .is_compatible(&self.circuit_version)
+        {
+            return Err(invalid(format!(
+                "of circuit version {}, not {}",
+                artifact.circuit_version, self.circuit_version
+            ))
+            .into());
+        }
+
+        self.check_deadline("witness generation")?;
+        let ((circuit, instance), num_of_proved_blocks) = {
+            let witness_block = block_traces_to_witness_block(&artifact.block_traces)?;
+            self.check_deadline(&format!("{} circuit building", C::name()))?;
+            (
+                C::from_witness_block(&witness_block)?,
+                witness_block.context.ctxs.len(),
+            )
+        };
+        if instance != deserialize_fr_matrix(artifact.instance.clone()) {
+            return Err(invalid("instance differs from the one of its traces".to_string()).into());
+        }
+        let mut proof = self.create_target_circuit_proof_from_circuit::<C>(
+            circuit,
+            instance,
+            rng,
+            artifact.total_num_of_blocks,
+            num_of_proved_blocks,
+        )?;
+        proof.skip_report = artifact.skip_report.clone();
+        self.witness_memory.release();
+        Ok(proof)
+    }
+}
@@ -0,0 +1,189 @@
+//! Binary encoding of a `WitnessArtifact`, for handing the checked traces from
+//! the hosts running the skip list and the capacity check to the hosts proving
+//! them, which generate the witness again.
+//!
+//! Integers are little endian. The encoding is:
+//! - the magic `ZKWT` and the format version, a u32;
+//! - the header: the circuit name and the circuit version, each a u16 length and
+//!   UTF-8 bytes, then the degree and the number of blocks given, u32s;
+//! - the sections, a u32 count then per section a u8 tag, a u64 length, the
+//!   sha256 of the data and the data;
+//! - the sha256 of everything before it, the digest of the witness.
+//!
+//! The sections are the instance, as a u32 count of columns and per column a u32
+//! count of 32-byte field elements; the block traces, zstd compressed JSON; and
+//! the skip report as JSON if any. Sections of unknown tags are skipped, so that a
+//! section can be added without bumping the version.
+
+use super::WitnessArtifact;
+use crate::error::{ProvingError, Result};
+use crate::prover::BufferPool;
+use crate::version::CircuitVersion;
+use sha2::{Digest, Sha256};
+
+pub const WITNESS_MAGIC: &[u8; 4] = b"ZKWT";
+/// Version of the encoding, the one written and the only one read.
+pub const WITNESS_FORMAT_VERSION: u32 = 1;
+
+const INSTANCE: u8 = 1;
+const BLOCK_TRACES: u8 = 2;
+const SKIP_REPORT: u8 = 3;
+
+pub(super) fn encode(artifact: &WitnessArtifact) -> Result<Vec<u8>> {
+    let mut buf = WITNESS_MAGIC.to_vec();
+    buf.extend(WITNESS_FORMAT_VERSION.to_le_bytes());
+    put_str(&mut buf, &artifact.circuit)?;
+    put_str(&mut buf, &artifact.circuit_version.0)?;
+    buf.extend((artifact.degree as u32).to_le_bytes());
+    buf.extend((artifact.total_num_of_blocks as u32).to_le_bytes());
+
+    let mut sections = vec![(INSTANCE, encode_instance(&artifact.instance)?)];
+    let traces = zstd::encode_all(&serde_json::to_vec(&artifact.block_traces)?[..], 0)?;
+    sections.push((BLOCK_TRACES, traces));
+    if let Some(skip_report) = &artifact.skip_report {
+        sections.push((SKIP_REPORT, serde_json::to_vec(skip_report)?));
+    }
+    buf.extend((sections.len() as u32).to_le_bytes());
+    for (tag, data) in sections {
+        buf.push(tag);
+        buf.extend((data.len() as u64).to_le_bytes());
+        buf.extend(Sha256::digest(&data));
+        buf.extend(data);
+    }
+    let digest = Sha256::digest(&buf);
+    buf.extend(digest);
+    Ok(buf)
+}
+
+pub(super) fn decode(buf: &[u8], pool: &BufferPool) -> Result<WitnessArtifact> {
+    if buf.len() < 32 {
+        return Err(invalid("truncated"));
+    }
+    let (body, digest) = buf.split_at(buf.len() - 32);
+    let mut r = Reader(body);
+    if r.take(4)? != WITNESS_MAGIC {
+        return Err(invalid("without the ZKWT magic"));
+    }
+    let version = r.u32()?;
+    if version != WITNESS_FORMAT_VERSION {
+        return Err(invalid(&format!(
+            "of format version {version}, expected {WITNESS_FORMAT_VERSION}"
+        )));
+    }
+    // checked once the version is known, so that a newer format fails on it
+    if Sha256::digest(body).as_slice() != digest {
+        return Err(invalid("corrupted, digest mismatch"));
+    }
+
+    let circuit = r.str()?;
+    let circuit_version = CircuitVersion(r.str()?);
+    let degree = r.u32()? as usize;
+    let total_num_of_blocks = r.u32()? as usize;
+    let (mut instance, mut block_traces, mut skip_report) = (None, None, None);
+    for _ in 0..r.u32()? {
+        let tag = r.take(1)?[0];
+        let len = r.u64()? as usize;
+        let section_digest = r.take(32)?;
+        let data = r.take(len)?;
+        if Sha256::digest(data).as_slice() != section_digest {
+            return Err(invalid(&format!(
+                "section {tag} corrupted, digest mismatch"
+            )));
+        }
+        match tag {
+            INSTANCE => instance = Some(decode_instance(data)?),
+            BLOCK_TRACES => {
+                let mut json = pool.take();
+                let traces = zstd::stream::copy_decode(data, &mut json)
+                    .map(|()| serde_json::from_slice(&json));
+                pool.give(json);
+                block_traces = Some(traces??);
+            }
+            SKIP_REPORT => skip_report = Some(serde_json::from_slice(data)?),
+            _ => log::warn!("witness: skipping section of unknown tag {}", tag),
+        }
+    }
+    Ok(WitnessArtifact {
+        circuit,
+        degree,
+        circuit_version,
+        block_traces: block_traces.ok_or_else(|| invalid("without traces"))?,
+        total_num_of_blocks,
+        instance: instance.ok_or_else(|| invalid("without instance"))?,
+        skip_report,
+    })
+}
+
+/// sha256 of the encoding, in hex, which ends with it.
+pub(super) fn digest(buf: &[u8]) -> Option<String> {
+    (buf.len() >= 32).then(|| hex::encode(&buf[buf.len() - 32..]))
+}
+
+fn encode_instance(instance: &[Vec<Vec<u8>>]) -> Result<Vec<u8>> {
+    let mut buf = (instance.len() as u32).to_le_bytes().to_vec();
+    for column in instance {
+        buf.extend((column.len() as u32).to_le_bytes());
+        for fr in column {
+            if fr.len() != 32 {
+                return Err(invalid("with a field element of other than 32 bytes"));
+            }
+            buf.extend(fr);
+        }
+    }
+    Ok(buf)
+}
+
+fn decode_instance(data: &[u8]) -> Result<Vec<Vec<Vec<u8>>>> {
+    let mut r = Reader(data);
+    let mut instance = vec![];
+    for _ in 0..r.u32()? {
+        let len = r.u32()?;
+        instance.push(
+            (0..len)
+                .map(|_| r.take(32).map(<[u8]>::to_vec))
+                .collect::<Result<_>>()?,
+        );
+    }
+    Ok(instance)
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) -> Result<()> {
+    let len = u16::try_from(s.len()).map_err(|_| invalid("with a name too long"))?;
+    buf.extend(len.to_le_bytes());
+    buf.extend(s.as_bytes());
+    Ok(())
+}
+
+fn invalid(reason: &str) -> crate::error::ZkEvmError {
+    ProvingError::InvalidWitness {
+        reason: reason.to_string(),
+    }
+    .into()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid("truncated"));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = u16::from_le_bytes(self.take(2)?.try_into().unwrap());
+        String::from_utf8(self.take(len as usize)?.to_vec())
+            .map_err(|_| invalid("with a name not UTF-8"))
+    }
+}
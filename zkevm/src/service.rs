@@ -0,0 +1,960 @@
+//! A long running proving service.
+//!
+//! Proving jobs are put into a queue, which is served by worker threads. The prover
+//! can be reloaded at runtime, e.g. after a circuit upgrade: a fresh prover is
+//! built from the new params and keys, new jobs are picked up by it, while the jobs
+//! in flight drain on the old one. The queue is kept as is during a reload.
+//!
+//! Admission is bounded so that a burst of submissions can't take the host down:
+//! a job is rejected when the queue is full or when its estimated memory alone is
+//! above the budget, and a queued job only starts once the memory of the jobs in
+//! flight leaves room for it.
+//!
+//! The status of every job, with a timeline of its phases, is kept in a history
+//! file in the root of the artifact store, which survives restarts. With an
+//! exporter, the spans of every job are exported for distributed tracing, see
+//! `telemetry`. Subscribers are called with the status of a job on every change,
+//! e.g. to push it to clients instead of having them poll. With a relayer, the
+//! proof of every job done is submitted to L1, see `relayer`. With a watcher, the
+//! jobs of the batches committed to L1 are enqueued as they are, see `watcher`.
+//!
+//! A coordinator, see `ProverService::coordinator`, generates the witnesses of
+//! the jobs without a prover and leaves proving them to remote workers, see
+//! `dispatch`, and verifies their proofs.
+
+pub mod auth;
+pub mod dispatch;
+pub mod history;
+pub mod relayer;
+pub mod telemetry;
+pub mod watcher;
+
+use crate::artifact::publish::Publisher;
+use crate::artifact::{ArtifactKind, ArtifactStore};
+use crate::circuit::SuperCircuit;
+use crate::error::{ProvingError, VerificationError, ZkEvmError};
+use crate::prover::{
+    derive_rng, AggCircuitProof, AllocStats, Deadline, LeakCheck, Prover, WitnessArtifact,
+};
+use crate::utils::estimate_proving_memory;
+use crate::verifier::Verifier;
+use anyhow::anyhow;
+use dispatch::{Coordinator, Dispatch, DispatchError, WitnessLease, WORKER_LEASE_SECS};
+use history::JobHistory;
+use relayer::{L1Client, Relayer, RelayerConfig, Submission, SubmissionState};
+use serde_derive::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use telemetry::{job_spans, SpanExporter, TraceContext};
+use thiserror::Error;
+use types::eth::BlockTrace;
+use watcher::L1Watcher;
+
+pub type JobId = u64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Proving,
+    Done,
+    Failed,
+    /// Failed for running past the job timeout.
+    TimedOut,
+}
+
+impl JobState {
+    /// Whether the job is over, its status won't change anymore.
+    pub fn is_final(&self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::TimedOut)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPhase {
+    Submitted,
+    Started,
+    /// On a coordinator, the witness is waiting for a worker.
+    WitnessGenerated,
+    /// On a coordinator, a worker leased the witness.
+    Leased,
+    InnerCircuitProved,
+    AggCircuitProved,
+    Done,
+    Failed,
+    TimedOut,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobEvent {
+    pub phase: JobPhase,
+    /// Unix timestamp in milliseconds.
+    pub at: u64,
+}
+
+impl JobEvent {
+    pub fn now(phase: JobPhase) -> Self {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self { phase, at }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobStatus {
+    pub id: JobId,
+    pub state: JobState,
+    pub num_blocks: usize,
+    /// Numbers of the blocks of the job.
+    #[serde(default)]
+    pub block_numbers: Vec<u64>,
+    /// Estimated peak memory of proving the job, in bytes.
+    pub estimated_memory: u64,
+    /// Generation of the prover that picked up the job.
+    pub prover_generation: Option<u64>,
+    /// Directory of the proof, once the job is done.
+    pub output_dir: Option<String>,
+    /// Digest of the proof dir in the content store of the artifacts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_digest: Option<String>,
+    /// Content id of the proof dir, once published.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub timeline: Vec<JobEvent>,
+    /// Index of the worker that picked up the job.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker: Option<usize>,
+    /// On a coordinator, name of the worker that leased the witness last.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_worker: Option<String>,
+    /// Distributed trace of the job, in hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    /// Submission of the proof to L1, with the receipt once included.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submission: Option<Submission>,
+}
+
+/// Query of the job history, all the given conditions must hold.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct JobFilter {
+    pub state: Option<JobState>,
+    /// Jobs which prove the block.
+    pub block_number: Option<u64>,
+    /// Max number of jobs returned, latest first, 0 for no limit.
+    #[serde(default)]
+    pub limit: usize,
+}
+
+impl JobFilter {
+    pub fn matches(&self, status: &JobStatus) -> bool {
+        self.state.map_or(true, |state| status.state == state)
+            && self
+                .block_number
+                .map_or(true, |number| status.block_numbers.contains(&number))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ServiceConfig {
+    /// Proofs are written into the proof bundle `{job_id}` of the store.
+    pub artifacts: ArtifactStore,
+    /// Number of worker threads, i.e. the max number of jobs proved concurrently,
+    /// or of witnesses generated concurrently on a coordinator.
+    pub workers: usize,
+    /// Max number of jobs waiting in the queue, 0 for no limit.
+    pub max_queued_jobs: usize,
+    /// Max total estimated memory in bytes of the jobs in flight, 0 for no limit.
+    pub max_memory: u64,
+    /// Wall-clock budget of a job from when it starts proving, `None` for no limit.
+    pub job_timeout: Option<Duration>,
+    /// Publishes the proof of every job done, its failure doesn't fail the job.
+    pub publisher: Option<Arc<dyn Publisher>>,
+    /// Exports the spans of every job once it ends.
+    pub exporter: Option<Arc<dyn SpanExporter>>,
+    /// Submits the proof of every job done to L1, with the `RelayerConfig` from
+    /// the env.
+    pub relayer: Option<Arc<dyn L1Client>>,
+}
+
+/// Why a submission was turned down.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum AdmissionError {
+    #[error("queue is full ({max_queued_jobs} jobs waiting), retry later")]
+    QueueFull { max_queued_jobs: usize },
+    #[error(
+        "job needs an estimated {estimated_memory} bytes of memory, \
+         above the budget of {max_memory} bytes, split it into smaller jobs"
+    )]
+    MemoryExceeded {
+        estimated_memory: u64,
+        max_memory: u64,
+    },
+}
+
+/// Why a reload failed, the current prover kept.
+#[derive(Debug, Error)]
+pub enum ReloadError {
+    #[error(transparent)]
+    Dispatch(#[from] DispatchError),
+    #[error("failed to load the prover: {0}")]
+    Load(#[from] ZkEvmError),
+}
+
+/// Called with the status of a job on every change, until it returns false.
+pub type Subscriber = Box<dyn FnMut(&JobStatus) -> bool + Send>;
+
+struct Job {
+    id: JobId,
+    block_traces: Vec<BlockTrace>,
+    estimated_memory: u64,
+    trace: TraceContext,
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<Job>,
+    /// Total estimated memory of the jobs in flight.
+    memory_in_use: u64,
+}
+
+/// A prover together with the generation it was loaded in.
+struct ProverGeneration {
+    id: u64,
+    prover: Mutex<Prover>,
+}
+
+struct Shared {
+    config: ServiceConfig,
+    queue: Mutex<Queue>,
+    queue_cv: Condvar,
+    jobs: Mutex<HashMap<JobId, JobStatus>>,
+    history: JobHistory,
+    subscribers: Mutex<Vec<Subscriber>>,
+    leak_check: Mutex<Option<LeakCheck>>,
+    /// Witnesses waiting for the workers, on a coordinator.
+    dispatch: Option<Dispatch>,
+    /// Verifier of the proofs of the workers, on a coordinator.
+    verifier: Option<Verifier>,
+    /// Jobs done, to the relayer thread.
+    relay_tx: Mutex<Option<Sender<JobId>>>,
+    /// None on a coordinator.
+    prover: RwLock<Option<Arc<ProverGeneration>>>,
+    next_job_id: AtomicU64,
+    shutdown: AtomicBool,
+}
+
+pub struct ProverService {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+    relayer: Option<JoinHandle<()>>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl ProverService {
+    pub fn new(prover: Prover, config: ServiceConfig) -> Self {
+        Self::start(Some(prover), None, config)
+    }
+
+    /// A service which generates the witnesses of the jobs and leaves proving
+    /// them to the workers leasing them, see `dispatch`. Their proofs are
+    /// verified with `verifier`, which needs the agg vk, before ending the jobs.
+    pub fn coordinator(config: ServiceConfig, verifier: Verifier) -> Self {
+        Self::start(None, Some(verifier), config)
+    }
+
+    fn start(prover: Option<Prover>, verifier: Option<Verifier>, config: ServiceConfig) -> Self {
+        let (history, past_jobs) = JobHistory::open(config.artifacts.root().join("jobs.jsonl"))
+            .expect("failed to open job history");
+        let next_job_id = past_jobs.last().map_or(0, |status| status.id + 1);
+        let (relay_tx, relay_rx) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            queue: Default::default(),
+            queue_cv: Default::default(),
+            jobs: Mutex::new(past_jobs.into_iter().map(|s| (s.id, s)).collect()),
+            history,
+            subscribers: Default::default(),
+            leak_check: Mutex::new(LeakCheck::from_env()),
+            relay_tx: Mutex::new(config.relayer.is_some().then_some(relay_tx)),
+            dispatch: prover
+                .is_none()
+                .then(|| Dispatch::new(Duration::from_secs(*WORKER_LEASE_SECS))),
+            verifier,
+            prover: RwLock::new(prover.map(|prover| {
+                Arc::new(ProverGeneration {
+                    id: 0,
+                    prover: Mutex::new(prover),
+                })
+            })),
+            next_job_id: AtomicU64::new(next_job_id),
+            shutdown: AtomicBool::new(false),
+            config,
+        });
+        let workers = (0..shared.config.workers.max(1))
+            .map(|i| {
+                let shared = shared.clone();
+                std::thread::Builder::new()
+                    .name(format!("prover-worker-{i}"))
+                    .spawn(move || shared.worker_loop(i))
+                    .expect("failed to spawn prover worker")
+            })
+            .collect();
+        let relayer = shared.config.relayer.clone().map(|client| {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("relayer".to_string())
+                .spawn(move || {
+                    shared.relay_loop(Relayer::new(client, RelayerConfig::default()), relay_rx)
+                })
+                .expect("failed to spawn relayer")
+        });
+        Self {
+            shared,
+            workers,
+            relayer,
+            watcher: None,
+        }
+    }
+
+    /// Enqueue the jobs of the batches committed to L1 found by the watcher,
+    /// polled on a thread of its own until the service shuts down.
+    pub fn watch_l1(&mut self, mut watcher: L1Watcher) {
+        let shared = self.shared.clone();
+        let handle = std::thread::Builder::new()
+            .name("l1-watcher".to_string())
+            .spawn(move || {
+                let poll_interval = watcher.config().poll_interval;
+                while !shared.shutdown.load(Ordering::SeqCst) {
+                    if let Err(e) =
+                        watcher.poll(&mut |block_traces| shared.submit(block_traces, None))
+                    {
+                        log::error!("service: l1 watcher: {:#}", e);
+                    }
+                    let next_poll = Instant::now() + poll_interval;
+                    while Instant::now() < next_poll && !shared.shutdown.load(Ordering::SeqCst) {
+                        std::thread::sleep(Duration::from_millis(200));
+                    }
+                }
+            })
+            .expect("failed to spawn l1 watcher");
+        self.watcher = Some(handle);
+    }
+
+    /// Put a proving job for the block traces into the queue.
+    pub fn submit(&self, block_traces: Vec<BlockTrace>) -> Result<JobId, AdmissionError> {
+        self.submit_traced(block_traces, None)
+    }
+
+    /// Same as `submit`, the job span being a child of the span of the W3C
+    /// `traceparent` of the caller.
+    pub fn submit_traced(
+        &self,
+        block_traces: Vec<BlockTrace>,
+        traceparent: Option<&str>,
+    ) -> Result<JobId, AdmissionError> {
+        self.shared.submit(block_traces, traceparent)
+    }
+
+    pub fn status(&self, id: JobId) -> Option<JobStatus> {
+        self.shared.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn artifacts(&self) -> &ArtifactStore {
+        &self.shared.config.artifacts
+    }
+
+    /// Call the subscriber with the status of every job submitted or changed
+    /// from now on. It is called from the worker threads, so it must not block.
+    pub fn subscribe(&self, subscriber: Subscriber) {
+        self.shared.subscribers.lock().unwrap().push(subscriber);
+    }
+
+    /// Jobs of the history matching the filter, latest first.
+    pub fn jobs(&self, filter: &JobFilter) -> Vec<JobStatus> {
+        let jobs = self.shared.jobs.lock().unwrap();
+        let mut matched: Vec<JobStatus> = jobs
+            .values()
+            .filter(|status| filter.matches(status))
+            .cloned()
+            .collect();
+        matched.sort_by(|a, b| b.id.cmp(&a.id));
+        if filter.limit != 0 {
+            matched.truncate(filter.limit);
+        }
+        matched
+    }
+
+    /// Number of jobs waiting in the queue.
+    pub fn queue_len(&self) -> usize {
+        self.shared.queue.lock().unwrap().jobs.len()
+    }
+
+    /// Total estimated memory in bytes of the jobs in flight.
+    pub fn memory_in_use(&self) -> u64 {
+        self.shared.queue.lock().unwrap().memory_in_use
+    }
+
+    /// Generation of the prover that new jobs are picked up by, none on a
+    /// coordinator.
+    pub fn prover_generation(&self) -> Option<u64> {
+        self.shared.prover.read().unwrap().as_ref().map(|g| g.id)
+    }
+
+    /// Swap in a new prover, returns its generation.
+    /// Jobs in flight keep running on the old prover, which is dropped once they finish.
+    /// A coordinator has no prover to reload, its workers are reloaded instead.
+    pub fn reload(&self, prover: Prover) -> Result<u64, DispatchError> {
+        let mut current = self.shared.prover.write().unwrap();
+        let id = match current.as_ref() {
+            Some(current) => current.id + 1,
+            None => return Err(DispatchError::NoProver),
+        };
+        *current = Some(Arc::new(ProverGeneration {
+            id,
+            prover: Mutex::new(prover),
+        }));
+        log::info!("service: prover reloaded, generation {}", id);
+        Ok(id)
+    }
+
+    /// Build a new prover from the existing params and seed files and swap it in,
+    /// no params are created. Loading the params may take minutes, the queue is
+    /// being served meanwhile.
+    pub fn reload_from_fpath(
+        &self,
+        params_fpath: &str,
+        seed_fpath: &str,
+    ) -> Result<u64, ReloadError> {
+        if self.is_coordinator() {
+            return Err(DispatchError::NoProver.into());
+        }
+        log::info!("service: loading prover from {}", params_fpath);
+        let prover = Prover::try_load_from_fpath(params_fpath, seed_fpath)?;
+        Ok(self.reload(prover)?)
+    }
+
+    pub fn is_coordinator(&self) -> bool {
+        self.shared.dispatch.is_some()
+    }
+
+    /// Number of witnesses waiting for a worker, on a coordinator.
+    pub fn witnesses_waiting(&self) -> usize {
+        self.shared.dispatch.as_ref().map_or(0, Dispatch::waiting)
+    }
+
+    /// Lease the witness of a job to the worker, see `dispatch`.
+    pub fn lease_witness(&self, worker: &str) -> Result<Option<WitnessLease>, DispatchError> {
+        let dispatch = self
+            .shared
+            .dispatch
+            .as_ref()
+            .ok_or(DispatchError::NotCoordinator)?;
+        let lease = dispatch.lease(worker);
+        if let Some(lease) = &lease {
+            log::info!("service: witness of job {} leased by {}", lease.id, worker);
+            self.shared.update_status(lease.id, JobPhase::Leased, |s| {
+                s.remote_worker = Some(worker.to_string());
+            });
+        }
+        Ok(lease)
+    }
+
+    /// End a job leased with the proof of the worker holding the lease, or its
+    /// failure. A proof which fails to verify, or to match the traces of the job,
+    /// fails the job.
+    pub fn complete_witness(
+        &self,
+        id: JobId,
+        worker: &str,
+        result: Result<AggCircuitProof, String>,
+    ) -> Result<(), DispatchError> {
+        let dispatch = self
+            .shared
+            .dispatch
+            .as_ref()
+            .ok_or(DispatchError::NotCoordinator)?;
+        let (trace, block_traces) = dispatch.take(id, worker)?;
+        log::info!("service: result of job {} from {}", id, worker);
+        let result = result
+            .map_err(|e| anyhow!("worker {}: {}", worker, e))
+            .and_then(|proof| {
+                self.shared
+                    .verify_worker_proof(&proof, &block_traces)
+                    .map_err(|e| anyhow!("proof of worker {}: {:?}", worker, e))?;
+                self.shared.write_proof(id, &proof)
+            });
+        self.shared.end_job(id, result);
+        self.shared.export_spans(id, &trace);
+        Ok(())
+    }
+
+    /// Stop the watcher, the workers once the jobs in flight are done, then the
+    /// relayer. Jobs still in the queue are not proved, submissions not over are
+    /// resumed on the next start.
+    pub fn shutdown(self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.shared.queue_cv.notify_all();
+        if let Some(watcher) = self.watcher {
+            watcher.join().ok();
+        }
+        for worker in self.workers {
+            worker.join().ok();
+        }
+        self.shared.relay_tx.lock().unwrap().take();
+        if let Some(relayer) = self.relayer {
+            relayer.join().ok();
+        }
+    }
+}
+
+impl Shared {
+    fn submit(
+        &self,
+        block_traces: Vec<BlockTrace>,
+        traceparent: Option<&str>,
+    ) -> Result<JobId, AdmissionError> {
+        let config = &self.config;
+
+        let estimated_memory = estimate_proving_memory(&block_traces);
+        if config.max_memory != 0 && estimated_memory > config.max_memory {
+            return Err(AdmissionError::MemoryExceeded {
+                estimated_memory,
+                max_memory: config.max_memory,
+            });
+        }
+
+        let mut queue = self.queue.lock().unwrap();
+        if config.max_queued_jobs != 0 && queue.jobs.len() >= config.max_queued_jobs {
+            return Err(AdmissionError::QueueFull {
+                max_queued_jobs: config.max_queued_jobs,
+            });
+        }
+        let id = self.next_job_id.fetch_add(1, Ordering::SeqCst);
+        let trace = TraceContext::new(traceparent);
+        let status = JobStatus {
+            id,
+            state: JobState::Queued,
+            num_blocks: block_traces.len(),
+            block_numbers: block_traces
+                .iter()
+                .filter_map(|trace| trace.header.number.map(|n| n.as_u64()))
+                .collect(),
+            estimated_memory,
+            prover_generation: None,
+            output_dir: None,
+            content_digest: None,
+            cid: None,
+            error: None,
+            timeline: vec![JobEvent::now(JobPhase::Submitted)],
+            worker: None,
+            remote_worker: None,
+            trace_id: Some(trace.trace_id_hex()),
+            submission: None,
+        };
+        self.history.record(&status);
+        self.jobs.lock().unwrap().insert(id, status.clone());
+        self.notify(&status);
+        queue.jobs.push_back(Job {
+            id,
+            block_traces,
+            estimated_memory,
+            trace,
+        });
+        drop(queue);
+        self.queue_cv.notify_all();
+        log::info!(
+            "service: job {} queued, estimated memory {} bytes",
+            id,
+            estimated_memory
+        );
+        Ok(id)
+    }
+
+    /// Wait for the job at the front of the queue to fit into the memory budget,
+    /// and reserve its memory.
+    fn next_job(&self) -> Option<Job> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(job) = queue.jobs.front() {
+                let fits = self.config.max_memory == 0
+                    || queue.memory_in_use + job.estimated_memory <= self.config.max_memory;
+                if fits {
+                    let job = queue.jobs.pop_front().unwrap();
+                    queue.memory_in_use += job.estimated_memory;
+                    return Some(job);
+                }
+            }
+            queue = self.queue_cv.wait(queue).unwrap();
+        }
+    }
+
+    fn release_memory(&self, job: &Job) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.memory_in_use -= job.estimated_memory;
+        drop(queue);
+        self.queue_cv.notify_all();
+    }
+
+    /// Update the status of a job as it enters the phase, and persist it.
+    fn update_status(&self, id: JobId, phase: JobPhase, f: impl FnOnce(&mut JobStatus)) {
+        let status = match self.jobs.lock().unwrap().get_mut(&id) {
+            Some(status) => {
+                f(status);
+                status.timeline.push(JobEvent::now(phase));
+                self.history.record(status);
+                status.clone()
+            }
+            None => return,
+        };
+        self.notify(&status);
+    }
+
+    /// Call the subscribers, dropping those done.
+    fn notify(&self, status: &JobStatus) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain_mut(|subscriber| subscriber(status));
+    }
+
+    fn worker_loop(&self, worker: usize) {
+        while let Some(job) = self.next_job() {
+            match &self.dispatch {
+                Some(dispatch) => self.generate_witness(dispatch, worker, &job),
+                None => self.prove_job(worker, &job),
+            }
+            self.report_memory(job.id);
+        }
+    }
+
+    fn prove_job(&self, worker: usize, job: &Job) {
+        // hold the generation for the whole job, so that a reload lets it drain
+        let generation = self.prover.read().unwrap().clone().unwrap();
+        self.update_status(job.id, JobPhase::Started, |s| {
+            s.state = JobState::Proving;
+            s.prover_generation = Some(generation.id);
+            s.worker = Some(worker);
+        });
+        log::info!(
+            "service: proving job {} with prover generation {}",
+            job.id,
+            generation.id
+        );
+
+        let result = catch_unwind(AssertUnwindSafe(|| self.prove(&generation, job)))
+            .unwrap_or_else(|e| Err(anyhow!("prover panicked: {}", panic_message(&*e))));
+        drop(generation);
+        self.release_memory(job);
+        self.end_job(job.id, result);
+        self.export_spans(job.id, &job.trace);
+    }
+
+    /// On a coordinator, generate the witness of the job for the workers.
+    fn generate_witness(&self, dispatch: &Dispatch, worker: usize, job: &Job) {
+        self.update_status(job.id, JobPhase::Started, |s| {
+            s.state = JobState::Proving;
+            s.worker = Some(worker);
+        });
+        let result = catch_unwind(AssertUnwindSafe(|| -> anyhow::Result<Vec<u8>> {
+            Ok(WitnessArtifact::generate::<SuperCircuit>(&job.block_traces)?.encode()?)
+        }))
+        .unwrap_or_else(|e| {
+            Err(anyhow!(
+                "witness generation panicked: {}",
+                panic_message(&*e)
+            ))
+        });
+        self.release_memory(job);
+        match result {
+            Ok(witness) => {
+                log::info!(
+                    "service: witness of job {} generated, {} bytes",
+                    job.id,
+                    witness.len()
+                );
+                self.update_status(job.id, JobPhase::WitnessGenerated, |_| {});
+                dispatch.push(job.id, witness, job.block_traces.clone(), job.trace);
+            }
+            Err(e) => {
+                self.end_job(job.id, Err(e));
+                self.export_spans(job.id, &job.trace);
+            }
+        }
+    }
+
+    /// Verify the agg proof of a worker, and check it proves the traces of its job.
+    fn verify_worker_proof(
+        &self,
+        proof: &AggCircuitProof,
+        block_traces: &[BlockTrace],
+    ) -> crate::error::Result<()> {
+        let verifier = self
+            .verifier
+            .as_ref()
+            .expect("a coordinator has a verifier");
+        if !verifier.verify_agg_circuit_proof(proof.clone())? {
+            return Err(VerificationError::Failed {
+                circuit: "aggregation".to_string(),
+            }
+            .into());
+        }
+        verifier.check_proof_matches_traces(proof, block_traces)
+    }
+
+    /// End the job with its proof dir, or its failure.
+    fn end_job(&self, id: JobId, result: anyhow::Result<String>) {
+        match result {
+            Ok(output_dir) => {
+                log::info!("service: job {} done", id);
+                let content_digest = self.store_content(id, &output_dir);
+                let cid = self.publish(id, &output_dir);
+                self.update_status(id, JobPhase::Done, |s| {
+                    s.state = JobState::Done;
+                    s.output_dir = Some(output_dir);
+                    s.content_digest = content_digest;
+                    s.cid = cid;
+                });
+                if let Some(relay_tx) = self.relay_tx.lock().unwrap().as_ref() {
+                    relay_tx.send(id).ok();
+                }
+            }
+            Err(e) => {
+                log::error!("service: job {} failed: {:?}", id, e);
+                let timed_out = matches!(
+                    e.downcast_ref::<ZkEvmError>(),
+                    Some(ZkEvmError::Proving(ProvingError::Timeout { .. }))
+                );
+                let (phase, state) = if timed_out {
+                    (JobPhase::TimedOut, JobState::TimedOut)
+                } else {
+                    (JobPhase::Failed, JobState::Failed)
+                };
+                self.update_status(id, phase, |s| {
+                    s.state = state;
+                    s.error = Some(format!("{e:?}"));
+                });
+            }
+        }
+    }
+
+    /// Submit the proofs of the jobs done as they come, and check the submissions
+    /// in flight every poll interval, until the service shuts down.
+    fn relay_loop(&self, mut relayer: Relayer, jobs_done: mpsc::Receiver<JobId>) {
+        let resumed: Vec<JobStatus> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|s| matches!(&s.submission, Some(sub) if !sub.state.is_final()))
+            .cloned()
+            .collect();
+        for status in resumed {
+            match self.calldata_of(&status) {
+                Ok(calldata) => {
+                    log::info!("service: resuming the submission of job {}", status.id);
+                    relayer.resume(status.id, calldata, status.submission.unwrap());
+                }
+                Err(e) => log::error!(
+                    "service: can't resume the submission of job {}: {:#}",
+                    status.id,
+                    e
+                ),
+            }
+        }
+
+        let poll_interval = relayer.config().poll_interval;
+        let mut last_poll = Instant::now();
+        loop {
+            match jobs_done.recv_timeout(poll_interval.saturating_sub(last_poll.elapsed())) {
+                Ok(id) => {
+                    let status = match self.jobs.lock().unwrap().get(&id) {
+                        Some(status) => status.clone(),
+                        None => continue,
+                    };
+                    let submission = match self.calldata_of(&status) {
+                        Ok(calldata) => relayer.submit(id, calldata),
+                        Err(e) => Submission {
+                            state: SubmissionState::Failed,
+                            error: Some(format!("{e:#}")),
+                            ..Default::default()
+                        },
+                    };
+                    self.record_submission(id, submission);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    for (id, submission) in relayer.poll() {
+                        self.record_submission(id, submission);
+                    }
+                    last_poll = Instant::now();
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    /// Calldata of the proof of the job, from its proof dir.
+    fn calldata_of(&self, status: &JobStatus) -> anyhow::Result<Vec<u8>> {
+        let output_dir = status
+            .output_dir
+            .as_ref()
+            .ok_or_else(|| anyhow!("job {} has no proof", status.id))?;
+        let path = std::path::Path::new(output_dir).join("full_proof.data");
+        let proof: AggCircuitProof = serde_json::from_slice(&std::fs::read(path)?)?;
+        Ok(proof.encode_calldata()?)
+    }
+
+    /// Update the submission of a job, and persist it. It is not a phase of the
+    /// job, the timeline is left as is.
+    fn record_submission(&self, id: JobId, submission: Submission) {
+        let status = match self.jobs.lock().unwrap().get_mut(&id) {
+            Some(status) => {
+                status.submission = Some(submission);
+                self.history.record(status);
+                status.clone()
+            }
+            None => return,
+        };
+        self.notify(&status);
+    }
+
+    /// Log the allocator stats once a job is done, and check them for a leak.
+    fn report_memory(&self, id: JobId) {
+        let stats = match AllocStats::read() {
+            Some(stats) => stats,
+            None => return,
+        };
+        log::info!(
+            "service: after job {}: allocated {}MB, active {}MB ({:.1}% fragmentation), \
+             resident {}MB, mapped {}MB, retained {}MB",
+            id,
+            stats.allocated >> 20,
+            stats.active >> 20,
+            stats.fragmentation() * 100.0,
+            stats.resident >> 20,
+            stats.mapped >> 20,
+            stats.retained >> 20
+        );
+        if let Some(leak_check) = self.leak_check.lock().unwrap().as_mut() {
+            if let Some(growth) = leak_check.check(&stats) {
+                log::warn!(
+                    "service: {}MB more allocated after job {} than after the first job, \
+                     likely a leak",
+                    growth >> 20,
+                    id
+                );
+            }
+        }
+    }
+
+    fn export_spans(&self, id: JobId, trace: &TraceContext) {
+        let exporter = match &self.config.exporter {
+            Some(exporter) => exporter,
+            None => return,
+        };
+        let status = match self.jobs.lock().unwrap().get(&id) {
+            Some(status) => status.clone(),
+            None => return,
+        };
+        let request = job_spans(exporter.service_name(), &status, trace);
+        if let Err(e) = exporter.export(&request) {
+            log::warn!("service: failed to export spans of job {}: {:#}", id, e);
+        }
+    }
+
+    fn prove(&self, generation: &ProverGeneration, job: &Job) -> anyhow::Result<String> {
+        // a panic of a previous job must not take the prover down
+        let mut prover = generation.prover.lock().unwrap_or_else(|e| e.into_inner());
+        // the deadline is lifted again even if the job fails, the prover is reused
+        prover.set_deadline(self.config.job_timeout.map(Deadline::after));
+        let result = self.prove_phases(&mut prover, job);
+        prover.set_deadline(None);
+        self.write_proof(job.id, &result?)
+    }
+
+    /// Write the proof into the proof bundle of the job, returns its dir.
+    fn write_proof(&self, id: JobId, agg_proof: &AggCircuitProof) -> anyhow::Result<String> {
+        let mut out_dir = self
+            .config
+            .artifacts
+            .path(ArtifactKind::Proof, &id.to_string());
+        std::fs::create_dir_all(&out_dir)?;
+        agg_proof.write_to_dir(&mut out_dir);
+        Ok(out_dir.to_string_lossy().to_string())
+    }
+
+    /// Add the proof dir to the content store, its failure doesn't fail the job.
+    fn store_content(&self, id: JobId, output_dir: &str) -> Option<String> {
+        match self.config.artifacts.content().put_dir(output_dir.as_ref()) {
+            Ok(digest) => Some(digest),
+            Err(e) => {
+                log::error!("service: failed to store proof of job {}: {}", id, e);
+                None
+            }
+        }
+    }
+
+    fn publish(&self, id: JobId, output_dir: &str) -> Option<String> {
+        let publisher = self.config.publisher.as_ref()?;
+        match publisher.publish(output_dir.as_ref()) {
+            Ok(cid) => {
+                log::info!("service: proof of job {} published as {}", id, cid);
+                Some(cid)
+            }
+            Err(e) => {
+                log::error!("service: failed to publish proof of job {}: {}", id, e);
+                None
+            }
+        }
+    }
+
+    fn prove_phases(&self, prover: &mut Prover, job: &Job) -> anyhow::Result<AggCircuitProof> {
+        let started_at = chrono::Utc::now();
+        let mut rng = derive_rng(&mut prover.rng);
+        let inner_proof =
+            prover.prove_inner_circuit::<SuperCircuit>(&job.block_traces, &mut rng)?;
+        self.update_status(job.id, JobPhase::InnerCircuitProved, |_| {});
+        let mut agg_proof = prover.create_agg_circuit_proof_impl(&[inner_proof], &mut rng)?;
+        prover.seal_agg_proof(&job.block_traces, started_at, &mut agg_proof)?;
+        self.update_status(job.id, JobPhase::AggCircuitProved, |_| {});
+        Ok(agg_proof)
+    }
+}
+
+fn panic_message(e: &(dyn Any + Send)) -> String {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+impl Coordinator for ProverService {
+    fn lease(&self, worker: &str) -> anyhow::Result<Option<WitnessLease>> {
+        Ok(self.lease_witness(worker)?)
+    }
+
+    fn complete(
+        &self,
+        id: JobId,
+        worker: &str,
+        result: Result<AggCircuitProof, String>,
+    ) -> anyhow::Result<()> {
+        Ok(self.complete_witness(id, worker, result)?)
+    }
+}
@@ -0,0 +1,318 @@
+//! Authentication and per-key rate limits of the proving service API.
+//!
+//! A client presents either an API key in `X-Api-Key`, or a `Bearer` token in
+//! `Authorization` which is an API key or a HS256 JWT signed with the shared
+//! secret. Only the sha256 digests of the API keys are kept in the config.
+//!
+//! Every route needs a `Scope`: the scopes of a key are in the config, the ones
+//! of a JWT in its space separated `scope` claim, `submit` by default. Every
+//! identity, `key:<name>` or `jwt:<sub>` so that a JWT can't spend the requests
+//! of the key of the same name, gets a token bucket of requests, charged only by
+//! the routes rate limited, see `Access`.
+
+use hmac::{Hmac, Mac};
+use serde_derive::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_minute: u32,
+    /// Requests allowed at once after being idle.
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            per_minute: 60,
+            burst: 10,
+        }
+    }
+}
+
+/// What an identity may call.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Every route, e.g. `/v1/reload`.
+    Admin,
+    /// Submitting jobs and reading their status, events and artifacts.
+    Submit,
+    /// The lease and completion routes of the workers of a coordinator.
+    Worker,
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Self::Admin),
+            "submit" => Ok(Self::Submit),
+            "worker" => Ok(Self::Worker),
+            _ => Err(format!("unknown scope {s}")),
+        }
+    }
+}
+
+fn default_scopes() -> Vec<Scope> {
+    vec![Scope::Submit]
+}
+
+/// The scope a route needs, and whether it takes a request off the rate limit.
+/// Event streams and the polling of workers aren't rate limited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub scope: Scope,
+    pub rate_limited: bool,
+}
+
+impl Access {
+    pub fn limited(scope: Scope) -> Self {
+        Self {
+            scope,
+            rate_limited: true,
+        }
+    }
+
+    pub fn unlimited(scope: Scope) -> Self {
+        Self {
+            scope,
+            rate_limited: false,
+        }
+    }
+}
+
+/// An authenticated client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    /// `key:<name>` of an API key, `jwt:<sub>` of a JWT.
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Identity {
+    /// Whether the identity may call the routes of `scope`, always for admins.
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ApiKey {
+    pub name: String,
+    /// Hex sha256 digest of the key.
+    pub sha256: String,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>,
+    /// Secret of the HS256 JWTs, JWTs are refused if unset.
+    #[serde(default)]
+    pub jwt_secret: Option<String>,
+    /// Rate limit of JWT subjects and of the keys without their own.
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+}
+
+impl AuthConfig {
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let f = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum AuthError {
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("{name} lacks the {scope:?} scope")]
+    Forbidden { name: String, scope: Scope },
+    #[error("rate limit of {name} exceeded, retry after {retry_after_secs}s")]
+    RateLimited { name: String, retry_after_secs: u64 },
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    sub: String,
+    #[serde(default)]
+    exp: Option<u64>,
+    /// Space separated scopes.
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+pub struct Authenticator {
+    config: AuthConfig,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Authenticator {
+    pub fn new(config: AuthConfig) -> Self {
+        Self {
+            config,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Authenticate the request headers, check the identity has the scope of the
+    /// route and, if rate limited, take one request off its rate limit.
+    pub fn authorize(
+        &self,
+        authorization: Option<&str>,
+        api_key: Option<&str>,
+        access: Access,
+    ) -> Result<Identity, AuthError> {
+        let (identity, rate_limit) = self.authenticate(authorization, api_key)?;
+        if !identity.allows(access.scope) {
+            return Err(AuthError::Forbidden {
+                name: identity.name,
+                scope: access.scope,
+            });
+        }
+        if access.rate_limited {
+            self.take(&identity.name, rate_limit)?;
+        }
+        Ok(identity)
+    }
+
+    fn authenticate(
+        &self,
+        authorization: Option<&str>,
+        api_key: Option<&str>,
+    ) -> Result<(Identity, RateLimit), AuthError> {
+        if let Some(key) = api_key {
+            return self.check_api_key(key);
+        }
+        let token = authorization
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .ok_or_else(|| AuthError::Unauthorized("missing credentials".to_string()))?;
+        if token.matches('.').count() == 2 {
+            let identity = self.check_jwt(token)?;
+            Ok((identity, self.config.rate_limit))
+        } else {
+            self.check_api_key(token)
+        }
+    }
+
+    fn check_api_key(&self, key: &str) -> Result<(Identity, RateLimit), AuthError> {
+        let digest = hex::encode(Sha256::digest(key.as_bytes()));
+        self.config
+            .api_keys
+            .iter()
+            .find(|k| k.sha256.eq_ignore_ascii_case(&digest))
+            .map(|k| {
+                let identity = Identity {
+                    name: format!("key:{}", k.name),
+                    scopes: k.scopes.clone(),
+                };
+                (identity, k.rate_limit.unwrap_or(self.config.rate_limit))
+            })
+            .ok_or_else(|| AuthError::Unauthorized("unknown api key".to_string()))
+    }
+
+    fn check_jwt(&self, token: &str) -> Result<Identity, AuthError> {
+        let unauthorized = |reason: &str| AuthError::Unauthorized(format!("invalid jwt: {reason}"));
+        let secret = self
+            .config
+            .jwt_secret
+            .as_ref()
+            .ok_or_else(|| unauthorized("jwt not enabled"))?;
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(h), Some(p), Some(s)) => (h, p, s),
+            _ => return Err(unauthorized("malformed")),
+        };
+
+        let decode = |part: &str| {
+            base64::decode_config(part, base64::URL_SAFE_NO_PAD)
+                .map_err(|_| unauthorized("bad base64"))
+        };
+        let jwt_header: JwtHeader =
+            serde_json::from_slice(&decode(header)?).map_err(|_| unauthorized("bad header"))?;
+        if jwt_header.alg != "HS256" {
+            return Err(unauthorized("alg must be HS256"));
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .map_err(|_| unauthorized("bad secret"))?;
+        mac.update(header.as_bytes());
+        mac.update(b".");
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&decode(signature)?)
+            .map_err(|_| unauthorized("bad signature"))?;
+
+        let claims: JwtClaims =
+            serde_json::from_slice(&decode(payload)?).map_err(|_| unauthorized("bad claims"))?;
+        if let Some(exp) = claims.exp {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            if now >= exp {
+                return Err(unauthorized("expired"));
+            }
+        }
+        let scopes = match &claims.scope {
+            Some(scope) => scope
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|e: String| unauthorized(&e))?,
+            None => default_scopes(),
+        };
+        Ok(Identity {
+            name: format!("jwt:{}", claims.sub),
+            scopes,
+        })
+    }
+
+    fn take(&self, name: &str, rate_limit: RateLimit) -> Result<(), AuthError> {
+        let per_sec = rate_limit.per_minute as f64 / 60.0;
+        let burst = rate_limit.burst.max(1) as f64;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(name.to_string()).or_insert(Bucket {
+            tokens: burst,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_sec).min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens < 1.0 {
+            let retry_after_secs = if per_sec > 0.0 {
+                ((1.0 - bucket.tokens) / per_sec).ceil() as u64
+            } else {
+                u64::MAX
+            };
+            return Err(AuthError::RateLimited {
+                name: name.to_string(),
+                retry_after_secs,
+            });
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
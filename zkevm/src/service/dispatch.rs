@@ -0,0 +1,233 @@
+//! Split deployment: a coordinator generating the witnesses, and proving workers.
+//!
+//! A coordinator, see `ProverService::coordinator`, takes the jobs as a service
+//! does, but only runs the skip list, the capacity check and witness generation,
+//! which need no proving keys, then holds the witness artifact until a worker
+//! leases it. Workers, see `run_worker`, lease the artifacts over the API of the
+//! coordinator, generate the witnesses again from their traces, see
+//! `prover::witness`, prove them with their own prover and send the agg proofs
+//! back, the coordinator then
+//! verifies them against the traces of their jobs and ends the jobs as a service
+//! proving them would: the proof is stored, published and relayed. A proof which
+//! fails to verify fails its job. Fetching and checking the traces and proving
+//! are thus scaled apart, witness generation stays with proving.
+//!
+//! A lease expires after `WORKER_LEASE_SECS` without a result, e.g. when the
+//! worker died, and the witness is leased again. A proof coming after its lease
+//! expired is still taken until the expiry is noticed, on the next lease. The
+//! results of the workers not holding the lease of a job are refused; the API
+//! names the worker after its credentials when authenticated.
+
+use super::telemetry::TraceContext;
+use super::{panic_message, JobId};
+use crate::circuit::SuperCircuit;
+use crate::prover::{derive_rng, AggCircuitProof, Prover, WitnessArtifact};
+use crate::utils::read_env_var;
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use types::eth::BlockTrace;
+
+/// Time a worker has to prove a witness leased.
+pub static WORKER_LEASE_SECS: Lazy<u64> = Lazy::new(|| read_env_var("WORKER_LEASE_SECS", 4 * 3600));
+
+/// Interval of a worker polling an idle coordinator.
+pub static WORKER_POLL_SECS: Lazy<u64> = Lazy::new(|| read_env_var("WORKER_POLL_SECS", 10));
+
+/// The coordinator as seen by a worker, over its API or in process.
+pub trait Coordinator: Send + Sync {
+    /// Lease the witness of a job, none if none is waiting.
+    fn lease(&self, worker: &str) -> anyhow::Result<Option<WitnessLease>>;
+
+    /// Send the agg proof of a job leased, or why proving it failed.
+    fn complete(
+        &self,
+        id: JobId,
+        worker: &str,
+        result: Result<AggCircuitProof, String>,
+    ) -> anyhow::Result<()>;
+}
+
+#[derive(Clone, Debug)]
+pub struct WitnessLease {
+    pub id: JobId,
+    /// Binary encoding of the `WitnessArtifact`.
+    pub witness: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum DispatchError {
+    #[error("not a coordinator, jobs are proved by the service itself")]
+    NotCoordinator,
+    #[error("a coordinator has no prover, its workers prove the jobs")]
+    NoProver,
+    #[error("job {id} has no witness waiting for a proof")]
+    NotDispatched { id: JobId },
+    #[error("job {id} is not leased by {worker}")]
+    NotLeaseHolder { id: JobId, worker: String },
+}
+
+struct Witness {
+    encoded: Vec<u8>,
+    /// The traces of the job, to check the proof against.
+    block_traces: Vec<BlockTrace>,
+    trace: TraceContext,
+}
+
+#[derive(Default)]
+struct DispatchState {
+    /// Jobs waiting for a worker, in order.
+    waiting: VecDeque<JobId>,
+    witnesses: HashMap<JobId, Witness>,
+    /// Worker and expiry of every lease.
+    leases: HashMap<JobId, (String, Instant)>,
+}
+
+/// Witnesses of a coordinator, until their proofs come.
+pub(super) struct Dispatch {
+    lease_duration: Duration,
+    state: Mutex<DispatchState>,
+}
+
+impl Dispatch {
+    pub(super) fn new(lease_duration: Duration) -> Self {
+        Self {
+            lease_duration,
+            state: Default::default(),
+        }
+    }
+
+    pub(super) fn push(
+        &self,
+        id: JobId,
+        encoded: Vec<u8>,
+        block_traces: Vec<BlockTrace>,
+        trace: TraceContext,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.witnesses.insert(
+            id,
+            Witness {
+                encoded,
+                block_traces,
+                trace,
+            },
+        );
+        state.waiting.push_back(id);
+    }
+
+    /// Lease the first witness waiting, the ones of expired leases going first.
+    pub(super) fn lease(&self, worker: &str) -> Option<WitnessLease> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut expired: Vec<JobId> = state
+            .leases
+            .iter()
+            .filter(|(_, (_, expiry))| *expiry <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        expired.sort_unstable_by(|a, b| b.cmp(a));
+        for id in expired {
+            let (previous, _) = state.leases.remove(&id).unwrap();
+            log::warn!("dispatch: lease of job {} by {} expired", id, previous);
+            state.waiting.push_front(id);
+        }
+
+        let id = state.waiting.pop_front()?;
+        state
+            .leases
+            .insert(id, (worker.to_string(), now + self.lease_duration));
+        Some(WitnessLease {
+            id,
+            witness: state.witnesses[&id].encoded.clone(),
+        })
+    }
+
+    /// Drop the witness of a job once its result came from the worker holding
+    /// its lease, and return the traces of the job.
+    pub(super) fn take(
+        &self,
+        id: JobId,
+        worker: &str,
+    ) -> Result<(TraceContext, Vec<BlockTrace>), DispatchError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.witnesses.contains_key(&id) {
+            return Err(DispatchError::NotDispatched { id });
+        }
+        match state.leases.get(&id) {
+            Some((holder, _)) if holder == worker => {}
+            _ => {
+                return Err(DispatchError::NotLeaseHolder {
+                    id,
+                    worker: worker.to_string(),
+                })
+            }
+        }
+        state.leases.remove(&id);
+        let witness = state.witnesses.remove(&id).unwrap();
+        Ok((witness.trace, witness.block_traces))
+    }
+
+    /// Number of witnesses waiting for a worker.
+    pub(super) fn waiting(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+}
+
+/// Prove the witnesses leased from the coordinator as the worker `name`, polling
+/// every `poll_interval` when none is waiting, until `stop` is set.
+pub fn run_worker(
+    prover: &mut Prover,
+    coordinator: &dyn Coordinator,
+    name: &str,
+    poll_interval: Duration,
+    stop: &AtomicBool,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        let lease = match coordinator.lease(name) {
+            Ok(Some(lease)) => lease,
+            Ok(None) => {
+                std::thread::sleep(poll_interval);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("worker {}: failed to lease a witness: {:#}", name, e);
+                std::thread::sleep(poll_interval);
+                continue;
+            }
+        };
+        log::info!("worker {}: proving job {}", name, lease.id);
+        let result = catch_unwind(AssertUnwindSafe(|| prove_lease(prover, &lease)))
+            .unwrap_or_else(|e| Err(anyhow!("prover panicked: {}", panic_message(&*e))))
+            .map_err(|e| format!("{e:?}"));
+        if let Err(e) = &result {
+            log::error!("worker {}: job {} failed: {}", name, lease.id, e);
+        }
+        // the lease expires if the result is lost, and the job is proved again
+        if let Err(e) = coordinator.complete(lease.id, name, result) {
+            log::error!(
+                "worker {}: failed to send the result of job {}: {:#}",
+                name,
+                lease.id,
+                e
+            );
+        }
+    }
+}
+
+fn prove_lease(prover: &mut Prover, lease: &WitnessLease) -> anyhow::Result<AggCircuitProof> {
+    let started_at = chrono::Utc::now();
+    let artifact = WitnessArtifact::decode_with(&lease.witness, &prover.buffer_pool)?;
+    let mut rng = derive_rng(&mut prover.rng);
+    let inner_proof = prover.prove_from_witness::<SuperCircuit>(&artifact, &mut rng)?;
+    let mut agg_proof = prover.create_agg_circuit_proof_impl(&[inner_proof], &mut rng)?;
+    // the quote of the worker's TEE and its manifest, over the traces the witness
+    // was generated from
+    prover.seal_agg_proof(&artifact.block_traces, started_at, &mut agg_proof)?;
+    Ok(agg_proof)
+}
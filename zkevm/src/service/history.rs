@@ -0,0 +1,73 @@
+//! Persistent history of the jobs of the service.
+//!
+//! Every change of a job status is appended as a JSON line to the history file.
+//! On start the file is replayed, the last line of a job wins, and compacted.
+//! The block traces of the queue are not persisted, so the jobs that were not
+//! finished when the service stopped are marked failed.
+
+use super::{JobEvent, JobId, JobPhase, JobState, JobStatus};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub struct JobHistory {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl JobHistory {
+    /// Open the history file, returns the jobs found in it ordered by id.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<(Self, Vec<JobStatus>)> {
+        let path = path.as_ref().to_path_buf();
+        let mut jobs = BTreeMap::<JobId, JobStatus>::new();
+        if path.exists() {
+            for (i, line) in BufReader::new(File::open(&path)?).lines().enumerate() {
+                match serde_json::from_str::<JobStatus>(&line?) {
+                    Ok(status) => {
+                        jobs.insert(status.id, status);
+                    }
+                    // e.g. a line cut short by a crash
+                    Err(e) => log::warn!("job history: skip line {} of {:?}: {}", i + 1, path, e),
+                }
+            }
+        }
+        for status in jobs.values_mut() {
+            if matches!(status.state, JobState::Queued | JobState::Proving) {
+                status.state = JobState::Failed;
+                status.error = Some("interrupted by a restart of the service".to_string());
+                status.timeline.push(JobEvent::now(JobPhase::Failed));
+            }
+        }
+
+        let tmp_path = path.with_extension("jsonl.tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        for status in jobs.values() {
+            writeln!(tmp, "{}", serde_json::to_string(status)?)?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+
+        let file = OpenOptions::new().append(true).open(&path)?;
+        let history = Self {
+            path,
+            file: Mutex::new(file),
+        };
+        Ok((history, jobs.into_values().collect()))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append the status of a job. Failures are logged, they must not fail the job.
+    pub fn record(&self, status: &JobStatus) {
+        let result = serde_json::to_string(status)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(self.file.lock().unwrap(), "{line}"));
+        if let Err(e) = result {
+            log::error!("job history: failed to record job {}: {}", status.id, e);
+        }
+    }
+}
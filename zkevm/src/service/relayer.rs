@@ -0,0 +1,417 @@
+//! Submission of the proofs of the jobs done to L1.
+//!
+//! With a relayer, the proof of every job done is queued for submission: its
+//! calldata, see `AggCircuitProof::encode_calldata`, is sent to the verifier
+//! contract in a transaction at the next nonce of the relayer account. Nonces are
+//! tracked locally, so that several submissions can be pending at once, starting
+//! from the pending nonce of the account and past the submissions resumed from
+//! the history.
+//!
+//! A transaction not included after `bump_after` is replaced, at the same nonce,
+//! by one with its gas price bumped by `gas_bump_percent`, or the current gas
+//! price if higher, up to `max_gas_price`. Once the receipt of one of the
+//! transactions of a nonce is `confirmations` blocks deep the submission is over,
+//! confirmed or reverted. An included transaction reorged out is waited for, or
+//! bumped, again. The submission of a job is recorded in its status, and so in
+//! the job history.
+
+use super::JobId;
+use crate::utils::read_env_var;
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use eth_types::H256;
+
+/// Blocks on top of the one including a submission, it included, for it to be
+/// final.
+pub static RELAYER_CONFIRMATIONS: Lazy<u64> =
+    Lazy::new(|| read_env_var("RELAYER_CONFIRMATIONS", 6));
+/// Seconds a transaction may stay pending before it is replaced.
+pub static RELAYER_BUMP_AFTER_SECS: Lazy<u64> =
+    Lazy::new(|| read_env_var("RELAYER_BUMP_AFTER_SECS", 180));
+/// Gas price raise of a replacement, nodes take replacements of at least 10%.
+pub static RELAYER_GAS_BUMP_PERCENT: Lazy<u64> =
+    Lazy::new(|| read_env_var("RELAYER_GAS_BUMP_PERCENT", 12));
+/// Max gas price in gwei, 0 for no limit.
+pub static RELAYER_MAX_GAS_PRICE_GWEI: Lazy<u64> =
+    Lazy::new(|| read_env_var("RELAYER_MAX_GAS_PRICE_GWEI", 0));
+/// Seconds between the checks of the pending submissions.
+pub static RELAYER_POLL_SECS: Lazy<u64> = Lazy::new(|| read_env_var("RELAYER_POLL_SECS", 12));
+/// Sends failing in a row before a submission is given up.
+pub static RELAYER_MAX_FAILED_SENDS: Lazy<u32> =
+    Lazy::new(|| read_env_var("RELAYER_MAX_FAILED_SENDS", 5));
+
+/// The node of L1 and the relayer account.
+pub trait L1Client: Send + Sync + Debug {
+    /// Nonce of the next transaction of the account, counting the pending ones.
+    fn pending_nonce(&self) -> anyhow::Result<u64>;
+
+    /// Current gas price in wei.
+    fn gas_price(&self) -> anyhow::Result<u128>;
+
+    fn block_number(&self) -> anyhow::Result<u64>;
+
+    /// Sign and send a transaction to the verifier contract, returns its hash.
+    fn send(&self, tx: &L1Transaction) -> anyhow::Result<H256>;
+
+    /// Receipt of the transaction, None if not in a block.
+    fn receipt(&self, tx_hash: H256) -> anyhow::Result<Option<L1Receipt>>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct L1Transaction {
+    pub nonce: u64,
+    /// In wei.
+    pub gas_price: u128,
+    pub calldata: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct L1Receipt {
+    pub tx_hash: H256,
+    pub block_number: u64,
+    pub gas_used: u64,
+    /// False if the transaction reverted.
+    pub success: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionState {
+    /// Not sent yet, or sent and not in a block.
+    Pending,
+    /// In a block, not deep enough to be final.
+    Included,
+    Confirmed,
+    Reverted,
+    /// Given up after failing sends.
+    Failed,
+}
+
+impl SubmissionState {
+    /// Whether the submission is over, it won't be sent or checked anymore.
+    pub fn is_final(&self) -> bool {
+        matches!(self, Self::Confirmed | Self::Reverted | Self::Failed)
+    }
+}
+
+/// Submission of the proof of a job.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Submission {
+    pub state: SubmissionState,
+    pub nonce: Option<u64>,
+    /// Gas price in wei of the last transaction sent.
+    pub gas_price: u128,
+    /// Transactions sent at the nonce, the last one with the highest gas price.
+    pub tx_hashes: Vec<H256>,
+    /// Receipt of the transaction included.
+    pub receipt: Option<L1Receipt>,
+    /// Sends which failed in a row.
+    #[serde(default)]
+    pub failed_sends: u32,
+    /// Last error of a send.
+    pub error: Option<String>,
+}
+
+impl Default for Submission {
+    fn default() -> Self {
+        Self {
+            state: SubmissionState::Pending,
+            nonce: None,
+            gas_price: 0,
+            tx_hashes: vec![],
+            receipt: None,
+            failed_sends: 0,
+            error: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RelayerConfig {
+    pub confirmations: u64,
+    pub bump_after: Duration,
+    pub gas_bump_percent: u64,
+    /// In wei, `None` for no limit.
+    pub max_gas_price: Option<u128>,
+    pub poll_interval: Duration,
+    pub max_failed_sends: u32,
+}
+
+impl Default for RelayerConfig {
+    fn default() -> Self {
+        Self {
+            confirmations: *RELAYER_CONFIRMATIONS,
+            bump_after: Duration::from_secs(*RELAYER_BUMP_AFTER_SECS),
+            gas_bump_percent: *RELAYER_GAS_BUMP_PERCENT,
+            max_gas_price: (*RELAYER_MAX_GAS_PRICE_GWEI != 0)
+                .then(|| *RELAYER_MAX_GAS_PRICE_GWEI as u128 * 1_000_000_000),
+            poll_interval: Duration::from_secs(*RELAYER_POLL_SECS),
+            max_failed_sends: *RELAYER_MAX_FAILED_SENDS,
+        }
+    }
+}
+
+struct InFlight {
+    id: JobId,
+    calldata: Vec<u8>,
+    submission: Submission,
+    /// When the last transaction was sent.
+    sent_at: Instant,
+}
+
+pub struct Relayer {
+    client: Arc<dyn L1Client>,
+    config: RelayerConfig,
+    /// Nonce of the next submission, fetched again after a failed send.
+    next_nonce: Option<u64>,
+    /// Lowest nonce not taken by a resumed submission.
+    min_nonce: u64,
+    in_flight: Vec<InFlight>,
+}
+
+impl Relayer {
+    pub fn new(client: Arc<dyn L1Client>, config: RelayerConfig) -> Self {
+        Self {
+            client,
+            config,
+            next_nonce: None,
+            min_nonce: 0,
+            in_flight: vec![],
+        }
+    }
+
+    pub fn config(&self) -> &RelayerConfig {
+        &self.config
+    }
+
+    /// Number of submissions not over.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Send the calldata of the proof of a job, returns its submission.
+    pub fn submit(&mut self, id: JobId, calldata: Vec<u8>) -> Submission {
+        let mut in_flight = InFlight {
+            id,
+            calldata,
+            submission: Submission::default(),
+            sent_at: Instant::now(),
+        };
+        self.send_first(&mut in_flight);
+        let submission = in_flight.submission.clone();
+        if !submission.state.is_final() {
+            self.in_flight.push(in_flight);
+        }
+        submission
+    }
+
+    /// Keep checking a submission not over, e.g. from the history after a restart.
+    pub fn resume(&mut self, id: JobId, calldata: Vec<u8>, submission: Submission) {
+        if let Some(nonce) = submission.nonce {
+            self.min_nonce = self.min_nonce.max(nonce + 1);
+        }
+        self.in_flight.push(InFlight {
+            id,
+            calldata,
+            submission,
+            sent_at: Instant::now(),
+        });
+    }
+
+    /// Check the submissions in flight: send those not sent yet, bump those
+    /// pending for too long, and confirm those deep enough. Returns the
+    /// submissions which changed.
+    pub fn poll(&mut self) -> Vec<(JobId, Submission)> {
+        let head = match self.client.block_number() {
+            Ok(head) => head,
+            Err(e) => {
+                log::warn!("relayer: failed to get the block number: {:#}", e);
+                return vec![];
+            }
+        };
+        let mut in_flight = std::mem::take(&mut self.in_flight);
+        let mut changed = vec![];
+        for f in &mut in_flight {
+            let before = f.submission.clone();
+            if f.submission.tx_hashes.is_empty() {
+                self.send_first(f);
+            } else {
+                self.check(f, head);
+            }
+            if f.submission != before {
+                changed.push((f.id, f.submission.clone()));
+            }
+        }
+        in_flight.retain(|f| !f.submission.state.is_final());
+        self.in_flight = in_flight;
+        changed
+    }
+
+    fn send_first(&mut self, f: &mut InFlight) {
+        let result = self.take_nonce().and_then(|nonce| {
+            let gas_price = self.capped(self.client.gas_price()?);
+            let tx_hash = self.client.send(&L1Transaction {
+                nonce,
+                gas_price,
+                calldata: f.calldata.clone(),
+            })?;
+            Ok((nonce, gas_price, tx_hash))
+        });
+        let s = &mut f.submission;
+        match result {
+            Ok((nonce, gas_price, tx_hash)) => {
+                log::info!(
+                    "relayer: proof of job {} sent in {:?}, nonce {}, gas price {}",
+                    f.id,
+                    tx_hash,
+                    nonce,
+                    gas_price
+                );
+                s.nonce = Some(nonce);
+                s.gas_price = gas_price;
+                s.tx_hashes.push(tx_hash);
+                s.failed_sends = 0;
+                s.error = None;
+                f.sent_at = Instant::now();
+            }
+            Err(e) => {
+                // the nonce may or may not have been taken by the node
+                self.next_nonce = None;
+                s.failed_sends += 1;
+                s.error = Some(format!("{e:#}"));
+                log::warn!(
+                    "relayer: failed to send the proof of job {} ({} in a row): {:#}",
+                    f.id,
+                    s.failed_sends,
+                    e
+                );
+                if s.failed_sends >= self.config.max_failed_sends {
+                    s.state = SubmissionState::Failed;
+                }
+            }
+        }
+    }
+
+    fn check(&self, f: &mut InFlight, head: u64) {
+        let mut receipt = None;
+        // the latest replacement is the most likely to be included
+        for tx_hash in f.submission.tx_hashes.iter().rev() {
+            match self.client.receipt(*tx_hash) {
+                Ok(Some(r)) => {
+                    receipt = Some(r);
+                    break;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!(
+                        "relayer: failed to get the receipt of {:?}: {:#}",
+                        tx_hash,
+                        e
+                    );
+                    return;
+                }
+            }
+        }
+
+        let s = &mut f.submission;
+        match receipt {
+            Some(r) => {
+                let depth = head.saturating_sub(r.block_number) + 1;
+                s.state = if depth < self.config.confirmations {
+                    SubmissionState::Included
+                } else if r.success {
+                    SubmissionState::Confirmed
+                } else {
+                    SubmissionState::Reverted
+                };
+                if s.state.is_final() {
+                    log::info!(
+                        "relayer: proof of job {} {:?} in block {}, gas used {}",
+                        f.id,
+                        s.state,
+                        r.block_number,
+                        r.gas_used
+                    );
+                }
+                s.receipt = Some(r);
+            }
+            None => {
+                if s.state == SubmissionState::Included {
+                    log::warn!("relayer: proof of job {} reorged out", f.id);
+                    s.state = SubmissionState::Pending;
+                    s.receipt = None;
+                }
+                if f.sent_at.elapsed() >= self.config.bump_after {
+                    self.bump(f);
+                }
+            }
+        }
+    }
+
+    /// Replace the pending transaction by one with a higher gas price.
+    fn bump(&self, f: &mut InFlight) {
+        let s = &mut f.submission;
+        let current = match self.client.gas_price() {
+            Ok(current) => current,
+            Err(e) => {
+                log::warn!("relayer: failed to get the gas price: {:#}", e);
+                return;
+            }
+        };
+        let bumped = s.gas_price * (100 + self.config.gas_bump_percent as u128) / 100;
+        let gas_price = self.capped(bumped.max(current));
+        if gas_price <= s.gas_price {
+            log::warn!(
+                "relayer: proof of job {} pending at the max gas price {}",
+                f.id,
+                s.gas_price
+            );
+            return;
+        }
+        let tx = L1Transaction {
+            nonce: s.nonce.expect("sent without a nonce"),
+            gas_price,
+            calldata: f.calldata.clone(),
+        };
+        match self.client.send(&tx) {
+            Ok(tx_hash) => {
+                log::info!(
+                    "relayer: proof of job {} resent in {:?}, gas price {} -> {}",
+                    f.id,
+                    tx_hash,
+                    s.gas_price,
+                    gas_price
+                );
+                s.gas_price = gas_price;
+                s.tx_hashes.push(tx_hash);
+                f.sent_at = Instant::now();
+            }
+            // e.g. nonce too low, a transaction of the nonce being in a block
+            // which the next poll gets the receipt of
+            Err(e) => log::warn!(
+                "relayer: failed to resend the proof of job {}: {:#}",
+                f.id,
+                e
+            ),
+        }
+    }
+
+    fn take_nonce(&mut self) -> anyhow::Result<u64> {
+        let nonce = match self.next_nonce {
+            Some(nonce) => nonce,
+            None => self.client.pending_nonce()?.max(self.min_nonce),
+        };
+        self.next_nonce = Some(nonce + 1);
+        Ok(nonce)
+    }
+
+    fn capped(&self, gas_price: u128) -> u128 {
+        self.config
+            .max_gas_price
+            .map_or(gas_price, |max| gas_price.min(max))
+    }
+}
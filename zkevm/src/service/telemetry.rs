@@ -0,0 +1,183 @@
+//! Spans of the proving jobs, for distributed tracing.
+//!
+//! A job is a span from its submission to its end, with a child span per phase of
+//! its timeline. A job submitted with the W3C `traceparent` of the caller is a
+//! child of the caller's span, so that proving shows up in the same trace as the
+//! sequencer and relayer spans. The spans of a job are exported once it ends, as
+//! an OTLP `ExportTraceServiceRequest` in the JSON encoding.
+
+use super::{JobPhase, JobState, JobStatus};
+use serde_json::{json, Value};
+use std::fmt::Debug;
+
+/// Sends the spans to a collector.
+pub trait SpanExporter: Send + Sync + Debug {
+    /// Export an OTLP/JSON `ExportTraceServiceRequest`.
+    fn export(&self, request: &Value) -> anyhow::Result<()>;
+
+    /// `service.name` of the spans.
+    fn service_name(&self) -> &str {
+        "zkevm-prover"
+    }
+}
+
+/// The trace a job belongs to and the id of its span.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    /// Span of the caller which submitted the job.
+    pub parent_span_id: Option<[u8; 8]>,
+}
+
+impl TraceContext {
+    /// A span in the trace of the `traceparent`, or the root of a new trace if
+    /// there is none or it is invalid.
+    pub fn new(traceparent: Option<&str>) -> Self {
+        let parent = traceparent.and_then(parse_traceparent);
+        Self {
+            trace_id: parent.map_or_else(rand::random, |(trace_id, _)| trace_id),
+            span_id: rand::random(),
+            parent_span_id: parent.map(|(_, span_id)| span_id),
+        }
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        hex::encode(self.trace_id)
+    }
+}
+
+/// `00-<trace id>-<parent span id>-<flags>`
+fn parse_traceparent(traceparent: &str) -> Option<([u8; 16], [u8; 8])> {
+    let mut parts = traceparent.trim().split('-');
+    let (version, trace_id, span_id) = (parts.next()?, parts.next()?, parts.next()?);
+    if version.len() != 2 || version == "ff" {
+        return None;
+    }
+    let trace_id: [u8; 16] = hex::decode(trace_id).ok()?.try_into().ok()?;
+    let span_id: [u8; 8] = hex::decode(span_id).ok()?.try_into().ok()?;
+    (trace_id != [0; 16] && span_id != [0; 8]).then_some((trace_id, span_id))
+}
+
+/// The export request of the spans of an ended job: the job span, and a span
+/// per phase from its event to the next one.
+pub fn job_spans(service_name: &str, status: &JobStatus, ctx: &TraceContext) -> Value {
+    let timeline = &status.timeline;
+    let (start, end) = match (timeline.first(), timeline.last()) {
+        (Some(first), Some(last)) => (first.at, last.at),
+        _ => (0, 0),
+    };
+    let failed = matches!(status.state, JobState::Failed | JobState::TimedOut);
+
+    let block_numbers: Vec<Value> = status.block_numbers.iter().map(|n| int(*n)).collect();
+    let mut attributes = vec![
+        attribute("job.id", int(status.id)),
+        attribute("job.state", string(&format!("{:?}", status.state))),
+        attribute("job.num_blocks", int(status.num_blocks as u64)),
+        attribute(
+            "job.block_numbers",
+            json!({ "arrayValue": { "values": block_numbers } }),
+        ),
+        attribute("job.estimated_memory", int(status.estimated_memory)),
+    ];
+    if let Some(generation) = status.prover_generation {
+        attributes.push(attribute("job.prover_generation", int(generation)));
+    }
+    if let Some(worker) = status.worker {
+        attributes.push(attribute("job.worker", int(worker as u64)));
+    }
+    if let Some(remote_worker) = &status.remote_worker {
+        attributes.push(attribute("job.remote_worker", string(remote_worker)));
+    }
+    let mut job_span = span(
+        ctx,
+        ctx.span_id,
+        ctx.parent_span_id,
+        "prove job",
+        (start, end),
+        attributes,
+    );
+    job_span["status"] = match (&status.error, failed) {
+        (Some(error), true) => json!({ "code": 2, "message": error }),
+        (None, true) => json!({ "code": 2 }),
+        _ => json!({ "code": 1 }),
+    };
+
+    let mut spans = vec![job_span];
+    for (event, next) in timeline.iter().zip(timeline.iter().skip(1)) {
+        if let Some(name) = phase_name(event.phase, next.phase) {
+            spans.push(span(
+                ctx,
+                rand::random(),
+                Some(ctx.span_id),
+                name,
+                (event.at, next.at),
+                vec![],
+            ));
+        }
+    }
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", string(service_name))],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "zkevm::service" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// The span of the phase a job enters with the event, none for the last ones.
+/// The phase after tells witness generation on a coordinator from proving.
+fn phase_name(phase: JobPhase, next: JobPhase) -> Option<&'static str> {
+    match phase {
+        JobPhase::Submitted => Some("queued"),
+        JobPhase::Started if next == JobPhase::WitnessGenerated => Some("witness generation"),
+        JobPhase::Started => Some("inner circuit proving"),
+        JobPhase::WitnessGenerated => Some("waiting for a worker"),
+        JobPhase::Leased => Some("remote proving"),
+        JobPhase::InnerCircuitProved => Some("agg circuit proving"),
+        JobPhase::AggCircuitProved => Some("proof writing"),
+        JobPhase::Done | JobPhase::Failed | JobPhase::TimedOut => None,
+    }
+}
+
+fn span(
+    ctx: &TraceContext,
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    name: &str,
+    (start_ms, end_ms): (u64, u64),
+    attributes: Vec<Value>,
+) -> Value {
+    let mut span = json!({
+        "traceId": ctx.trace_id_hex(),
+        "spanId": hex::encode(span_id),
+        "name": name,
+        // internal
+        "kind": 1,
+        "startTimeUnixNano": (start_ms * 1_000_000).to_string(),
+        "endTimeUnixNano": (end_ms * 1_000_000).to_string(),
+        "attributes": attributes,
+    });
+    if let Some(parent_span_id) = parent_span_id {
+        span["parentSpanId"] = hex::encode(parent_span_id).into();
+    }
+    span
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn string(value: &str) -> Value {
+    json!({ "stringValue": value })
+}
+
+/// int64 values are strings in OTLP/JSON.
+fn int(value: u64) -> Value {
+    json!({ "intValue": value.to_string() })
+}
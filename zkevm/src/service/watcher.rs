@@ -0,0 +1,281 @@
+//! Proving triggered by the batches committed to L1.
+//!
+//! The watcher follows the `CommitBatch` events of the rollup contract, once
+//! `confirmations` blocks deep so that reorged commitments aren't proved. The
+//! chunks of every batch committed are decoded from the calldata of its
+//! `commitBatch`, see `chunk::chunk_block_numbers`, the traces of their blocks
+//! fetched from L2 and a proving job enqueued per chunk. With a relayer, the
+//! proofs are then submitted as the jobs are done, from the commitment of a batch
+//! to its proof without an external orchestrator.
+//!
+//! The L1 blocks scanned, the chunks waiting to be enqueued, e.g. while the queue
+//! is full, and the jobs of every batch are kept in a state file, so that a
+//! restarted watcher carries on where it stopped.
+
+use super::{AdmissionError, JobId};
+use crate::chunk::chunk_block_numbers;
+use crate::utils::read_env_var;
+use anyhow::{anyhow, Context};
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use types::eth::BlockTrace;
+
+pub use eth_types::H256;
+
+/// L1 blocks on top of the one of a commitment, it included, for it to be proved.
+pub static L1_WATCH_CONFIRMATIONS: Lazy<u64> =
+    Lazy::new(|| read_env_var("L1_WATCH_CONFIRMATIONS", 6));
+/// Seconds between the scans of L1.
+pub static L1_WATCH_POLL_SECS: Lazy<u64> = Lazy::new(|| read_env_var("L1_WATCH_POLL_SECS", 12));
+/// L1 blocks scanned by a query of the events, nodes cap the range of `eth_getLogs`.
+pub static L1_WATCH_MAX_RANGE: Lazy<u64> = Lazy::new(|| read_env_var("L1_WATCH_MAX_RANGE", 1000));
+/// L1 block the first scan starts at, without a state file.
+pub static L1_WATCH_START_BLOCK: Lazy<u64> = Lazy::new(|| read_env_var("L1_WATCH_START_BLOCK", 0));
+
+/// The rollup contract on L1.
+pub trait RollupEvents: Send + Sync + Debug {
+    fn block_number(&self) -> anyhow::Result<u64>;
+
+    /// The batches committed in the L1 blocks `from..=to`, in order.
+    fn committed_batches(&self, from: u64, to: u64) -> anyhow::Result<Vec<CommittedBatch>>;
+}
+
+/// The L2 node serving the traces of its blocks.
+pub trait TraceSource: Send + Sync + Debug {
+    fn block_trace(&self, number: u64) -> anyhow::Result<BlockTrace>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommittedBatch {
+    pub batch_index: u64,
+    pub batch_hash: H256,
+    /// L1 block of the commitment.
+    pub l1_block: u64,
+    /// The chunks of the `commitBatch` calldata, encoded.
+    pub chunks: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WatcherConfig {
+    pub confirmations: u64,
+    pub poll_interval: Duration,
+    pub max_range: u64,
+    pub start_block: u64,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            confirmations: *L1_WATCH_CONFIRMATIONS,
+            poll_interval: Duration::from_secs(*L1_WATCH_POLL_SECS),
+            max_range: (*L1_WATCH_MAX_RANGE).max(1),
+            start_block: *L1_WATCH_START_BLOCK,
+        }
+    }
+}
+
+/// A chunk of a committed batch, by the numbers of its first and last blocks.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CommittedChunk {
+    pub batch_index: u64,
+    pub chunk_index: usize,
+    pub first_block: u64,
+    pub last_block: u64,
+    /// The proving job, once enqueued.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<JobId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WatcherState {
+    /// Next L1 block to scan.
+    pub next_block: u64,
+    /// Chunks not enqueued yet, in order.
+    pub pending: VecDeque<CommittedChunk>,
+    /// Chunks enqueued, by batch index.
+    pub batches: BTreeMap<u64, Vec<CommittedChunk>>,
+}
+
+pub struct L1Watcher {
+    events: Arc<dyn RollupEvents>,
+    traces: Arc<dyn TraceSource>,
+    config: WatcherConfig,
+    state: WatcherState,
+    state_path: PathBuf,
+}
+
+impl L1Watcher {
+    /// A watcher resuming from the state file if there is one.
+    pub fn new(
+        events: Arc<dyn RollupEvents>,
+        traces: Arc<dyn TraceSource>,
+        config: WatcherConfig,
+        state_path: impl Into<PathBuf>,
+    ) -> io::Result<Self> {
+        let state_path = state_path.into();
+        let state = match std::fs::read(&state_path) {
+            Ok(buf) => serde_json::from_slice(&buf)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => WatcherState {
+                next_block: config.start_block,
+                ..Default::default()
+            },
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            events,
+            traces,
+            config,
+            state,
+            state_path,
+        })
+    }
+
+    pub fn config(&self) -> &WatcherConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> &WatcherState {
+        &self.state
+    }
+
+    pub fn state_path(&self) -> &Path {
+        &self.state_path
+    }
+
+    /// Scan the L1 blocks confirmed since the last poll for commitments, then
+    /// enqueue the chunks pending with `submit`, returns the jobs enqueued. A
+    /// chunk turned down for a full queue is retried on the next poll.
+    pub fn poll(
+        &mut self,
+        submit: &mut dyn FnMut(Vec<BlockTrace>) -> Result<JobId, AdmissionError>,
+    ) -> anyhow::Result<Vec<JobId>> {
+        let result = self.scan().and_then(|()| self.enqueue(submit));
+        self.save()
+            .with_context(|| format!("failed to save {}", self.state_path.display()))?;
+        result
+    }
+
+    fn scan(&mut self) -> anyhow::Result<()> {
+        let head = self.events.block_number()?;
+        // a block is `head - block + 1` blocks deep, the blocks before `end` deep enough
+        let end = (head + 2).saturating_sub(self.config.confirmations.max(1));
+        while self.state.next_block < end {
+            let from = self.state.next_block;
+            let to = (from + self.config.max_range).min(end) - 1;
+            for batch in self.events.committed_batches(from, to)? {
+                self.add_batch(&batch);
+            }
+            self.state.next_block = to + 1;
+        }
+        Ok(())
+    }
+
+    fn add_batch(&mut self, batch: &CommittedBatch) {
+        let known = self.state.batches.contains_key(&batch.batch_index)
+            || self
+                .state
+                .pending
+                .iter()
+                .any(|chunk| chunk.batch_index == batch.batch_index);
+        if known {
+            return;
+        }
+        let mut chunks = Vec::with_capacity(batch.chunks.len());
+        for (chunk_index, chunk) in batch.chunks.iter().enumerate() {
+            let numbers = match chunk_block_numbers(chunk) {
+                Ok(numbers) => numbers,
+                // a batch the watcher can't make sense of isn't retried forever
+                Err(e) => {
+                    log::error!(
+                        "l1 watcher: batch {} left unproved, chunk {}: {}",
+                        batch.batch_index,
+                        chunk_index,
+                        e
+                    );
+                    return;
+                }
+            };
+            chunks.push(CommittedChunk {
+                batch_index: batch.batch_index,
+                chunk_index,
+                first_block: numbers[0],
+                last_block: numbers[numbers.len() - 1],
+                job: None,
+            });
+        }
+        log::info!(
+            "l1 watcher: batch {} {:?} committed in L1 block {}, {} chunks",
+            batch.batch_index,
+            batch.batch_hash,
+            batch.l1_block,
+            chunks.len()
+        );
+        self.state.pending.extend(chunks);
+    }
+
+    fn enqueue(
+        &mut self,
+        submit: &mut dyn FnMut(Vec<BlockTrace>) -> Result<JobId, AdmissionError>,
+    ) -> anyhow::Result<Vec<JobId>> {
+        let mut jobs = vec![];
+        while let Some(chunk) = self.state.pending.front() {
+            let mut block_traces = vec![];
+            for number in chunk.first_block..=chunk.last_block {
+                let trace = self.traces.block_trace(number)?;
+                if trace.header.number.map(|n| n.as_u64()) != Some(number) {
+                    return Err(anyhow!("trace of block {} isn't of that block", number));
+                }
+                block_traces.push(trace);
+            }
+            let mut chunk = chunk.clone();
+            match submit(block_traces) {
+                Ok(id) => {
+                    log::info!(
+                        "l1 watcher: job {} proves chunk {} of batch {}, blocks {}..={}",
+                        id,
+                        chunk.chunk_index,
+                        chunk.batch_index,
+                        chunk.first_block,
+                        chunk.last_block
+                    );
+                    chunk.job = Some(id);
+                    jobs.push(id);
+                }
+                Err(e @ AdmissionError::QueueFull { .. }) => {
+                    log::info!(
+                        "l1 watcher: {}, {} chunks pending",
+                        e,
+                        self.state.pending.len()
+                    );
+                    break;
+                }
+                // never to fit, left unproved
+                Err(e @ AdmissionError::MemoryExceeded { .. }) => log::error!(
+                    "l1 watcher: chunk {} of batch {} turned down: {}",
+                    chunk.chunk_index,
+                    chunk.batch_index,
+                    e
+                ),
+            }
+            self.state.pending.pop_front();
+            self.state
+                .batches
+                .entry(chunk.batch_index)
+                .or_default()
+                .push(chunk);
+        }
+        Ok(jobs)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let tmp_path = self.state_path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(&self.state)?)?;
+        std::fs::rename(&tmp_path, &self.state_path)
+    }
+}
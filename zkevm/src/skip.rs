@@ -0,0 +1,237 @@
+//! Opcodes and precompiles the circuits don't support yet, and what to do with
+//! the blocks using them.
+//!
+//! The skip list is read from the JSON file at `SKIP_LIST`, e.g.
+//! `{"rules": [{"opcode": "SELFDESTRUCT", "action": "skip"}, {"precompile": 9, "action": "error"}]}`.
+//! A block hitting a `skip` rule is left out: being unable to prove one tx means
+//! being unable to prove the post state root of its block, so the batch is cut
+//! before the block, as with `AUTO_TRUNCATE`. A block hitting an `error` rule
+//! fails the batch. Both decisions are recorded in the skip report.
+
+use crate::error::TraceError;
+use crate::utils::read_env_var;
+use eth_types::evm_types::OpcodeId;
+use ethers_core::types::{Address, H256, U256};
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use types::eth::{BlockTrace, ExecStep};
+
+/// Addresses `0x01..=PRECOMPILE_COUNT` are precompiles.
+const PRECOMPILE_COUNT: u64 = 9;
+
+/// The skip list at `SKIP_LIST`, empty if unset.
+pub static SKIP_LIST: Lazy<SkipList> =
+    Lazy::new(
+        || match read_env_var("SKIP_LIST", "".to_string()).as_str() {
+            "" => SkipList::default(),
+            path => SkipList::from_file(path)
+                .unwrap_or_else(|e| panic!("failed to read SKIP_LIST {path}: {e}")),
+        },
+    );
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipTarget {
+    Opcode(OpcodeId),
+    /// The precompile at this address.
+    Precompile(u8),
+}
+
+impl fmt::Display for SkipTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Opcode(op) => write!(f, "opcode {op:?}"),
+            Self::Precompile(address) => write!(f, "precompile {address:#04x}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipAction {
+    /// Leave the block out of the batch.
+    Skip,
+    /// Fail the batch.
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SkipRule {
+    #[serde(flatten)]
+    pub target: SkipTarget,
+    pub action: SkipAction,
+}
+
+/// Where a target is used. The step is `None` for a tx calling a precompile directly.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SkipLocation {
+    pub block_number: Option<u64>,
+    pub tx_index: usize,
+    pub tx_hash: H256,
+    pub step_index: Option<usize>,
+    pub pc: Option<u64>,
+    pub depth: Option<isize>,
+}
+
+impl fmt::Display for SkipLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block {:?} tx {} ({:?})",
+            self.block_number, self.tx_index, self.tx_hash
+        )?;
+        if let (Some(step), Some(pc)) = (self.step_index, self.pc) {
+            write!(f, " step {step} pc {pc}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SkipEntry {
+    pub target: SkipTarget,
+    pub action: SkipAction,
+    pub location: SkipLocation,
+}
+
+/// The rules hit by a batch, and the blocks left out because of them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SkipReport {
+    pub entries: Vec<SkipEntry>,
+    /// The first skipped block and those after it, cut from the batch.
+    pub skipped_blocks: Vec<Option<u64>>,
+}
+
+impl SkipReport {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SkipList {
+    pub rules: Vec<SkipRule>,
+}
+
+impl SkipList {
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let f = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    fn action(&self, target: SkipTarget) -> Option<SkipAction> {
+        self.rules
+            .iter()
+            .find(|rule| rule.target == target)
+            .map(|rule| rule.action)
+    }
+
+    /// The rules hit by the block, in the order of its txs and steps.
+    pub fn scan(&self, block_trace: &BlockTrace) -> Vec<SkipEntry> {
+        if self.rules.is_empty() {
+            return vec![];
+        }
+        let block_number = block_trace.header.number.map(|n| n.as_u64());
+        let mut entries = vec![];
+        for (tx_index, (tx, result)) in block_trace
+            .transactions
+            .iter()
+            .zip(&block_trace.execution_results)
+            .enumerate()
+        {
+            let mut hit = |target, step: Option<(usize, &ExecStep)>| {
+                if let Some(action) = self.action(target) {
+                    entries.push(SkipEntry {
+                        target,
+                        action,
+                        location: SkipLocation {
+                            block_number,
+                            tx_index,
+                            tx_hash: tx.tx_hash,
+                            step_index: step.map(|(i, _)| i),
+                            pc: step.map(|(_, s)| s.pc),
+                            depth: step.map(|(_, s)| s.depth),
+                        },
+                    });
+                }
+            };
+            if let Some(address) = tx.to.and_then(|to| precompile_of_address(&to)) {
+                hit(SkipTarget::Precompile(address), None);
+            }
+            for (i, step) in result.exec_steps.iter().enumerate() {
+                hit(SkipTarget::Opcode(step.op), Some((i, step)));
+                if let Some(address) = called_precompile(step) {
+                    hit(SkipTarget::Precompile(address), Some((i, step)));
+                }
+            }
+        }
+        entries
+    }
+
+    /// Cut the batch before its first block hitting a `skip` rule. Fails on an
+    /// `error` rule hit up to that block, or if no block is left.
+    pub fn apply(&self, block_traces: &mut Vec<BlockTrace>) -> Result<SkipReport, TraceError> {
+        let mut report = SkipReport::default();
+        let mut cut = block_traces.len();
+        for (idx, block_trace) in block_traces.iter().enumerate() {
+            let entries = self.scan(block_trace);
+            if let Some(entry) = entries.iter().find(|e| e.action == SkipAction::Error) {
+                return Err(TraceError::Unsupported {
+                    target: entry.target.to_string(),
+                    location: entry.location.to_string(),
+                });
+            }
+            let skipped = !entries.is_empty();
+            report.entries.extend(entries);
+            if skipped {
+                cut = idx;
+                break;
+            }
+        }
+        if cut == block_traces.len() {
+            return Ok(report);
+        }
+
+        report.skipped_blocks = block_traces[cut..]
+            .iter()
+            .map(|b| b.header.number.map(|n| n.as_u64()))
+            .collect();
+        let entry = &report.entries[0];
+        log::warn!(
+            "skip blocks {:?}: {} at {}",
+            report.skipped_blocks,
+            entry.target,
+            entry.location
+        );
+        if cut == 0 {
+            return Err(TraceError::Skipped {
+                target: entry.target.to_string(),
+                location: entry.location.to_string(),
+            });
+        }
+        block_traces.truncate(cut);
+        Ok(report)
+    }
+}
+
+fn precompile_of_address(address: &Address) -> Option<u8> {
+    precompile_of(U256::from_big_endian(address.as_bytes()))
+}
+
+fn precompile_of(address: U256) -> Option<u8> {
+    (address >= U256::one() && address <= PRECOMPILE_COUNT.into()).then(|| address.as_u32() as u8)
+}
+
+/// The precompile called by a call step, the callee being the second item from
+/// the top of the stack. Steps of traces without stacks are not detected.
+fn called_precompile(step: &ExecStep) -> Option<u8> {
+    if !matches!(
+        step.op,
+        OpcodeId::CALL | OpcodeId::CALLCODE | OpcodeId::DELEGATECALL | OpcodeId::STATICCALL
+    ) {
+        return None;
+    }
+    let stack = step.stack.as_ref()?;
+    precompile_of(stack[stack.len().checked_sub(2)?])
+}
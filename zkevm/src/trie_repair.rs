@@ -0,0 +1,159 @@
+//! Repair of the storage traces missing proofs of the state they touch.
+//!
+//! Some traces arrive with an incomplete `storageTrace`: an account or a storage
+//! slot touched by the block, i.e. named by the `from`, `to`, `accountAfter`,
+//! `accountCreated` and `proofList` wrappers of its execution results, has no
+//! zktrie proof, and the witness of the whole block fails to build. With a
+//! `TrieProofSource`, e.g. the `eth_getProof` of an l2geth node, the missing proofs
+//! are fetched in the state before the block and added to the storage trace, so
+//! that only a block whose proofs can't be fetched still fails.
+//!
+//! The proofs fetched are checked against `rootBefore` as the ones of the trace
+//! are, by the witness building.
+
+use crate::error::{Result, TraceError};
+use ethers_core::types::Bytes;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use types::eth::{AccountProofWrapper, BlockTrace};
+
+pub use eth_types::{Address, Word};
+
+/// A node serving the zktrie proofs of the state.
+pub trait TrieProofSource: Send + Sync + Debug {
+    /// The proof of the account and of its storage slots `keys` in the state after
+    /// block `block_number`.
+    fn get_proof(
+        &self,
+        address: Address,
+        keys: &[Word],
+        block_number: u64,
+    ) -> anyhow::Result<AccountTrieProof>;
+}
+
+/// The zktrie nodes of the proof of an account and of some of its slots.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccountTrieProof {
+    pub account_proof: Vec<Vec<u8>>,
+    pub storage_proofs: Vec<(Word, Vec<Vec<u8>>)>,
+}
+
+/// The proofs added to the storage trace of a block.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrieRepair {
+    pub accounts: usize,
+    pub slots: usize,
+}
+
+impl TrieRepair {
+    pub fn is_empty(&self) -> bool {
+        self.accounts == 0 && self.slots == 0
+    }
+}
+
+/// Accounts, with their slots, touched by the block without a proof in its storage
+/// trace. An account whose proof is there is listed for its missing slots only.
+pub fn missing_trie_proofs(block_trace: &BlockTrace) -> BTreeMap<Address, BTreeSet<Word>> {
+    let storage_trace = &block_trace.storage_trace;
+    let mut missing: BTreeMap<Address, BTreeSet<Word>> = BTreeMap::new();
+    for wrapper in touched_accounts(block_trace) {
+        let address = match wrapper.address {
+            Some(address) => address,
+            None => continue,
+        };
+        let has_account = storage_trace
+            .proofs
+            .as_ref()
+            .map_or(false, |proofs| proofs.contains_key(&address));
+        let key = wrapper.storage.as_ref().and_then(|storage| storage.key);
+        let missing_key = key.filter(|key| {
+            !storage_trace
+                .storage_proofs
+                .get(&address)
+                .map_or(false, |slots| slots.contains_key(key))
+        });
+        if !has_account || missing_key.is_some() {
+            missing.entry(address).or_default().extend(missing_key);
+        }
+    }
+    missing
+}
+
+/// Fetch the proofs missing from the storage trace of the block from `source`.
+pub fn repair_storage_trace(
+    block_trace: &mut BlockTrace,
+    source: &dyn TrieProofSource,
+) -> Result<TrieRepair> {
+    let missing = missing_trie_proofs(block_trace);
+    let mut repair = TrieRepair::default();
+    if missing.is_empty() {
+        return Ok(repair);
+    }
+    let number = block_trace
+        .header
+        .number
+        .ok_or_else(|| TraceError::Invalid("block without number".to_string()))?
+        .as_u64();
+    let parent = number
+        .checked_sub(1)
+        .ok_or_else(|| TraceError::Invalid("genesis block without its state".to_string()))?;
+
+    let storage_trace = &mut block_trace.storage_trace;
+    for (address, keys) in missing {
+        let keys: Vec<_> = keys.into_iter().collect();
+        let proof = source.get_proof(address, &keys, parent).map_err(|e| {
+            TraceError::Invalid(format!(
+                "failed to fetch the proof of {address:?} at block {parent}: {e}"
+            ))
+        })?;
+        let proofs = storage_trace.proofs.get_or_insert_with(Default::default);
+        if !proofs.contains_key(&address) {
+            proofs.insert(address, to_bytes(proof.account_proof));
+            repair.accounts += 1;
+        }
+        let slots = storage_trace.storage_proofs.entry(address).or_default();
+        for (key, nodes) in proof.storage_proofs {
+            if keys.contains(&key) && !slots.contains_key(&key) {
+                slots.insert(key, to_bytes(nodes));
+                repair.slots += 1;
+            }
+        }
+    }
+    log::info!(
+        "block {}: fetched the proofs of {} accounts and {} slots missing from its storage trace",
+        number,
+        repair.accounts,
+        repair.slots
+    );
+    Ok(repair)
+}
+
+fn touched_accounts(block_trace: &BlockTrace) -> impl Iterator<Item = &AccountProofWrapper> {
+    let txs = block_trace
+        .transactions
+        .iter()
+        .filter_map(|tx| tx.account_created.as_ref());
+    let results = block_trace.execution_results.iter().flat_map(|result| {
+        let steps = result
+            .exec_steps
+            .iter()
+            .filter_map(|step| step.extra_data.as_ref())
+            .filter_map(|extra| extra.proof_list.as_ref())
+            .flatten();
+        result
+            .from
+            .iter()
+            .chain(&result.to)
+            .chain(&result.account_created)
+            .chain(&result.account_after)
+            .chain(steps)
+    });
+    std::iter::once(&block_trace.coinbase)
+        .chain(txs)
+        .chain(results)
+}
+
+fn to_bytes(nodes: Vec<Vec<u8>>) -> Vec<Bytes> {
+    nodes.into_iter().map(Bytes::from).collect()
+}
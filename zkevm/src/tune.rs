@@ -0,0 +1,366 @@
+//! Settings of the prover tuned to the host it runs on.
+//!
+//! `profile_host` measures the host with short micro-runs: the cores and how an
+//! MSM scales over them, the memory and its bandwidth, the
+//! throughput of the disk of the params and pks, and the GPUs. `recommend` turns
+//! the profile into `TunedSettings`, which `bin/tune` writes into a `TuneReport`
+//! at `TUNE_FILE`.
+//!
+//! The settings of the report at `TUNE_FILE`, none read unless it is set, are the
+//! defaults of the provers of the host, its hostname checked so that a report
+//! copied from another host is ignored. An env var or a flag set explicitly wins
+//! over them:
+//! - `threads`, the rayon pool unless `RAYON_NUM_THREADS` is set, applied by
+//!   `apply_tuned_threads` at the start of the bins, before the pool is used;
+//! - `jobs` and `max_memory`, of `prove --jobs` and of the service workers;
+//! - `witness_retained_mb` as `WITNESS_RETAINED_MB`, `params_parallel_read` as
+//!   `PARAMS_PARALLEL_READ`.
+//!
+//! No backend of the prover runs on GPUs yet: the GPUs found are reported, and no
+//! GPU batch size is recommended.
+
+use crate::utils::read_env_var;
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// File of the `TuneReport` of the host, none if empty.
+pub static TUNE_FILE: Lazy<String> = Lazy::new(|| read_env_var("TUNE_FILE", String::new()));
+
+/// The settings of `TUNE_FILE` if tuned on this host.
+pub static TUNED_SETTINGS: Lazy<Option<TunedSettings>> = Lazy::new(|| {
+    let path = TUNE_FILE.as_str();
+    if path.is_empty() {
+        return None;
+    }
+    let report = match TuneReport::read(Path::new(path)) {
+        Ok(report) => report,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::error!("failed to read the tune report {}: {}", path, e);
+            return None;
+        }
+    };
+    let hostname = crate::provenance::hostname();
+    let settings = match report.settings_on(&hostname) {
+        Some(settings) => settings.clone(),
+        None => {
+            log::warn!(
+                "tune report {} is of host {}, not {}, ignored",
+                path,
+                report.hostname,
+                hostname
+            );
+            return None;
+        }
+    };
+    log::info!("settings tuned at {} loaded from {}", report.tuned_at, path);
+    Some(settings)
+});
+
+/// Size the rayon pool to the tuned thread count, unless `RAYON_NUM_THREADS` is
+/// set. Called at process start, the pool can't be resized once used.
+pub fn apply_tuned_threads() {
+    let settings = match TUNED_SETTINGS.as_ref() {
+        Some(settings) if std::env::var_os("RAYON_NUM_THREADS").is_none() => settings,
+        _ => return,
+    };
+    if let Err(e) = rayon::ThreadPoolBuilder::new()
+        .num_threads(settings.threads)
+        .build_global()
+    {
+        log::warn!("tuned {} threads not applied: {}", settings.threads, e);
+    }
+}
+
+/// What `profile_host` measured.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HostProfile {
+    /// Threads the host runs in parallel, hyperthreads included.
+    pub cores: usize,
+    /// Memory available when profiled.
+    pub memory_bytes: u64,
+    /// GB/s of a copy between buffers larger than the caches, on all cores. Only
+    /// reported, the MSM scaling already accounts for it.
+    pub memory_bandwidth_gbps: f64,
+    /// Seconds of the MSM micro-run by thread count.
+    pub msm_secs: BTreeMap<usize, f64>,
+    /// MB/s of a sequential write, synced, and of reading it back from the disk.
+    pub disk_write_mbps: f64,
+    pub disk_read_mbps: f64,
+    pub gpus: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TunedSettings {
+    /// Threads of the rayon pool.
+    pub threads: usize,
+    /// Jobs proved in parallel.
+    pub jobs: usize,
+    /// Memory budget of the jobs in flight.
+    pub max_memory: u64,
+    /// Freed witness memory kept for the next job in MB, unlimited if negative.
+    pub witness_retained_mb: i64,
+    pub params_parallel_read: bool,
+    /// Batch size of a GPU backend, none without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_batch_size: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TuneReport {
+    pub hostname: String,
+    /// RFC 3339 time of the profile.
+    pub tuned_at: String,
+    pub host: HostProfile,
+    pub settings: TunedSettings,
+}
+
+impl TuneReport {
+    /// A report of this host, tuned now.
+    pub fn new(host: HostProfile, settings: TunedSettings) -> Self {
+        Self {
+            hostname: crate::provenance::hostname(),
+            tuned_at: chrono::Utc::now().to_rfc3339(),
+            host,
+            settings,
+        }
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp_path, path)
+    }
+
+    /// The settings, if tuned on `hostname`.
+    pub fn settings_on(&self, hostname: &str) -> Option<&TunedSettings> {
+        (self.hostname == hostname).then_some(&self.settings)
+    }
+}
+
+/// Parallel efficiency of the MSM below which a job doesn't get more threads,
+/// the threads left to other jobs instead.
+const MIN_MSM_EFFICIENCY: f64 = 0.7;
+/// Throughput of the disk in MB/s below which the params are read sequentially,
+/// the chunks read in parallel seeking back and forth on a slow disk.
+const PARALLEL_READ_MIN_MBPS: f64 = 1000.0;
+
+/// The settings for the host, proving jobs of `job_memory` bytes each.
+pub fn recommend(host: &HostProfile, job_memory: u64) -> TunedSettings {
+    let cores = host.cores.max(1);
+    // the fewest threads within 5% of the fastest run
+    let fastest = host
+        .msm_secs
+        .values()
+        .copied()
+        .fold(f64::INFINITY, f64::min);
+    let threads = host
+        .msm_secs
+        .iter()
+        .find(|(_, secs)| **secs <= fastest * 1.05)
+        .map_or(cores, |(threads, _)| *threads);
+
+    // a job gets the threads it uses efficiently, the jobs sharing the pool
+    let serial = host.msm_secs.get(&1).copied();
+    let job_threads = host
+        .msm_secs
+        .iter()
+        .filter(|(t, secs)| {
+            **t <= threads
+                && serial.map_or(false, |serial| {
+                    serial / (**t as f64 * **secs) >= MIN_MSM_EFFICIENCY
+                })
+        })
+        .map(|(t, _)| *t)
+        .max()
+        .unwrap_or(threads);
+    let max_memory = host.memory_bytes / 10 * 9;
+    let jobs_in_memory = match job_memory {
+        0 => usize::MAX,
+        bytes => (max_memory / bytes) as usize,
+    };
+    let jobs = (threads / job_threads.max(1)).min(jobs_in_memory).max(1);
+
+    // the pool of freed witness memory takes what the jobs leave, all of it while
+    // a job fits twice
+    let headroom = max_memory.saturating_sub(jobs as u64 * job_memory);
+    let witness_retained_mb = if jobs == 1 && headroom >= job_memory {
+        -1
+    } else {
+        ((headroom / jobs as u64) >> 20) as i64
+    };
+
+    TunedSettings {
+        threads,
+        jobs,
+        max_memory,
+        witness_retained_mb,
+        params_parallel_read: host.disk_read_mbps >= PARALLEL_READ_MIN_MBPS,
+        gpu_batch_size: None,
+    }
+}
+
+/// Bytes of the buffers of the memory and disk micro-runs.
+const PROFILE_BYTES: usize = 256 << 20;
+/// Points of the MSM micro-run.
+const PROFILE_MSM_LEN: usize = 1 << 15;
+
+/// Profile the host, writing the disk micro-run into `dir`. Takes seconds to a
+/// minute, depending on the cores.
+pub fn profile_host(dir: &Path) -> io::Result<HostProfile> {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    // pools of their own, whatever the global one is
+    let all_cores = thread_pool(cores)?;
+    let memory_bandwidth_gbps = all_cores.install(memory_bandwidth);
+    let msm_secs = profile_msm(cores)?;
+    let (disk_write_mbps, disk_read_mbps) = disk_throughput(dir)?;
+    let host = HostProfile {
+        cores,
+        memory_bytes: crate::prover::available_memory(),
+        memory_bandwidth_gbps,
+        msm_secs,
+        disk_write_mbps,
+        disk_read_mbps,
+        gpus: gpus(),
+    };
+    log::info!("host profiled: {:?}", host);
+    Ok(host)
+}
+
+/// Best of 3 copies of `PROFILE_BYTES` on all cores.
+fn memory_bandwidth() -> f64 {
+    use rayon::prelude::*;
+
+    let src = vec![1u8; PROFILE_BYTES];
+    let mut dst = vec![0u8; PROFILE_BYTES];
+    let chunk_len = PROFILE_BYTES / rayon::current_num_threads();
+    let secs = best_of(3, || {
+        dst.par_chunks_mut(chunk_len)
+            .zip(src.par_chunks(chunk_len))
+            .for_each(|(dst, src)| dst.copy_from_slice(src));
+    });
+    // read and written
+    2.0 * PROFILE_BYTES as f64 / secs / 1e9
+}
+
+fn thread_pool(threads: usize) -> io::Result<rayon::ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Seconds of the MSM of halo2 on 1, 2, 4.. threads, up to `cores`.
+fn profile_msm(cores: usize) -> io::Result<BTreeMap<usize, f64>> {
+    use halo2_proofs::arithmetic::best_multiexp;
+    use halo2_proofs::halo2curves::bn256::{Fr, G1Affine, G1};
+    use halo2_proofs::halo2curves::group::ff::Field;
+    use halo2_proofs::halo2curves::group::{Curve, Group};
+    use rand::SeedableRng;
+
+    let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(0);
+    let scalars: Vec<_> = (0..PROFILE_MSM_LEN).map(|_| Fr::random(&mut rng)).collect();
+    let points: Vec<_> =
+        std::iter::successors(Some(G1::generator()), |p| Some(*p + G1::generator()))
+            .take(PROFILE_MSM_LEN)
+            .collect();
+    let mut bases = vec![G1Affine::default(); PROFILE_MSM_LEN];
+    G1::batch_normalize(&points, &mut bases);
+
+    let mut thread_counts: Vec<_> = std::iter::successors(Some(1), |t| Some(t * 2))
+        .take_while(|t| *t < cores)
+        .collect();
+    thread_counts.push(cores);
+    let mut secs = BTreeMap::new();
+    for threads in thread_counts {
+        let pool = thread_pool(threads)?;
+        let run = best_of(2, || {
+            pool.install(|| best_multiexp(&scalars, &bases));
+        });
+        secs.insert(threads, run);
+    }
+    Ok(secs)
+}
+
+/// Write `PROFILE_BYTES` into a file of `dir`, synced, then read them back with
+/// the pages of the file dropped from the page cache, on Linux.
+fn disk_throughput(dir: &Path) -> io::Result<(f64, f64)> {
+    use std::io::{Read, Write};
+    use std::time::Instant;
+
+    let path = dir.join(format!(".tune_{}.tmp", std::process::id()));
+    // not zeros, which some filesystems compress
+    let buf: Vec<u8> = (0..1 << 20)
+        .map(|i: u32| (i.wrapping_mul(2654435761) >> 24) as u8)
+        .collect();
+    let result = (|| -> io::Result<(f64, f64)> {
+        let start = Instant::now();
+        let mut f = std::fs::File::create(&path)?;
+        for _ in 0..PROFILE_BYTES / buf.len() {
+            f.write_all(&buf)?;
+        }
+        f.sync_all()?;
+        let write_secs = start.elapsed().as_secs_f64();
+        drop_page_cache(&f);
+        drop(f);
+
+        let start = Instant::now();
+        let mut f = std::fs::File::open(&path)?;
+        let mut read_buf = vec![0u8; buf.len()];
+        while f.read(&mut read_buf)? != 0 {}
+        let read_secs = start.elapsed().as_secs_f64();
+        let mb = (PROFILE_BYTES >> 20) as f64;
+        Ok((mb / write_secs, mb / read_secs))
+    })();
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+#[cfg(all(feature = "prover", target_os = "linux"))]
+fn drop_page_cache(f: &std::fs::File) {
+    use std::os::unix::io::AsRawFd;
+    // safe: only advises the kernel about the pages of an open file
+    unsafe {
+        libc::posix_fadvise(f.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+}
+
+#[cfg(all(feature = "prover", not(target_os = "linux")))]
+fn drop_page_cache(_f: &std::fs::File) {}
+
+/// The NVIDIA GPUs of the driver, by model.
+fn gpus() -> Vec<String> {
+    let dirs = match std::fs::read_dir("/proc/driver/nvidia/gpus") {
+        Ok(dirs) => dirs,
+        Err(_) => return vec![],
+    };
+    let mut gpus: Vec<_> = dirs
+        .filter_map(|dir| std::fs::read_to_string(dir.ok()?.path().join("information")).ok())
+        .map(|info| {
+            info.lines()
+                .find_map(|line| line.strip_prefix("Model:"))
+                .unwrap_or("unknown")
+                .trim()
+                .to_string()
+        })
+        .collect();
+    gpus.sort();
+    gpus
+}
+
+fn best_of(runs: usize, mut f: impl FnMut()) -> f64 {
+    (0..runs)
+        .map(|_| {
+            let start = std::time::Instant::now();
+            f();
+            start.elapsed().as_secs_f64()
+        })
+        .fold(f64::INFINITY, f64::min)
+}
@@ -1,162 +1,137 @@
-use anyhow::Result;
-use halo2_proofs::arithmetic::Field;
-use halo2_proofs::halo2curves::bn256::{Bn256, Fr};
-use halo2_proofs::halo2curves::FieldExt;
-use halo2_proofs::SerdeFormat;
-
-use halo2_proofs::poly::kzg::commitment::ParamsKZG;
-use rand::rngs::OsRng;
-use std::fs::{self, metadata, File};
-use std::io::{BufReader, Read, Write};
-use std::path::{Path, PathBuf};
+use crate::error::{KeygenError, Result, TraceError};
+#[cfg(feature = "prover")]
+use halo2curves::bn256::Fr;
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 use std::str::FromStr;
-use types::eth::{BlockTrace, BlockTraceJsonRpcResult};
+use types::eth::BlockTrace;
+use types::lenient::{parse_trace, ParseMode, TraceWarning};
+#[cfg(feature = "prover")]
 use zkevm_circuits::witness;
 
-pub(crate) const DEFAULT_SERDE_FORMAT: SerdeFormat = SerdeFormat::RawBytesUnchecked;
-
-/// return setup params by reading from file or generate new one
-pub fn load_or_create_params(params_dir: &str, degree: usize) -> Result<ParamsKZG<Bn256>> {
-    let _path = PathBuf::from(params_dir);
-
-    match metadata(params_dir) {
-        Ok(md) => {
-            if md.is_file() {
-                panic!("{params_dir} should be folder");
-            }
-        }
-        Err(_) => {
-            // not exist
-            fs::create_dir_all(params_dir)?;
-        }
-    };
-
-    let params_path = format!("{params_dir}/params{degree}");
-    log::info!("load_or_create_params {}", params_path);
-    if Path::new(&params_path).exists() {
-        match load_params(&params_path, degree, DEFAULT_SERDE_FORMAT) {
-            Ok(r) => return Ok(r),
-            Err(e) => {
-                log::error!("load params err: {}. Recreating...", e)
-            }
+#[cfg(feature = "prover")]
+mod discovery;
+#[cfg(feature = "prover")]
+mod parallel_read;
+#[cfg(feature = "prover")]
+mod params;
+#[cfg(feature = "prover")]
+mod seed_key;
+#[cfg(feature = "prover")]
+mod shared_params;
+
+#[cfg(feature = "prover")]
+pub use discovery::{discover_block_traces, trace_files};
+#[cfg(feature = "prover")]
+pub use params::*;
+#[cfg(feature = "prover")]
+pub use seed_key::{SeedKey, SEED_KEY_FILE};
+#[cfg(feature = "prover")]
+pub use shared_params::{share_params, PARAMS_SHARED};
+
+/// `strict` fails on unknown fields and coerced values in traces, e.g. in CI.
+pub static TRACE_PARSE_MODE: Lazy<ParseMode> =
+    Lazy::new(|| read_env_var("TRACE_PARSE_MODE", ParseMode::Lenient));
+
+/// get a block-result from file, either a bare trace or a JSON-RPC response,
+/// upgraded to the current schema if emitted by an older l2geth. Files ending
+/// in `.zst` are zstd compressed, e.g. by `download_traces`.
+/// Parsed in the `TRACE_PARSE_MODE`, the schema mismatches are logged.
+pub fn read_block_trace_from_file<P: AsRef<Path>>(path: P) -> Result<BlockTrace, TraceError> {
+    let path_str = path.as_ref().to_string_lossy().to_string();
+    let (trace, warnings) = read_block_trace_with_warnings(path, *TRACE_PARSE_MODE)?;
+    if !warnings.is_empty() {
+        log::warn!(
+            "{} schema mismatches in {}, first: {}",
+            warnings.len(),
+            path_str,
+            warnings[0]
+        );
+        for warning in &warnings {
+            log::debug!("{}: {}", path_str, warning);
         }
     }
-    create_params(&params_path, degree)
+    Ok(trace)
 }
 
-/// load params from file
-pub fn load_params(
-    params_dir: &str,
-    degree: usize,
-    serde_format: SerdeFormat,
-) -> Result<ParamsKZG<Bn256>> {
-    log::info!("start loading params with degree {}", degree);
-    let params_path = if metadata(params_dir)?.is_dir() {
-        // auto load
-        format!("{params_dir}/params{degree}")
-    } else {
-        params_dir.to_string()
-    };
-    let f = File::open(params_path)?;
-
-    // check params file length:
-    //   len: 4 bytes
-    //   g: 2**DEGREE g1 points, each 32 bytes(256bits)
-    //   g_lagrange: 2**DEGREE g1 points, each 32 bytes(256bits)
-    //   g2: g2 point, 64 bytes
-    //   s_g2: g2 point, 64 bytes
-    let file_size = f.metadata()?.len();
-    let g1_num = 2 * (1 << degree);
-    let g2_num = 2;
-    let g1_bytes_len = match serde_format {
-        SerdeFormat::Processed => 32,
-        SerdeFormat::RawBytes | SerdeFormat::RawBytesUnchecked => 64,
-    };
-    let g2_bytes_len = 2 * g1_bytes_len;
-    let expected_len = 4 + g1_num * g1_bytes_len + g2_num * g2_bytes_len;
-    if file_size != expected_len {
-        return Err(anyhow::format_err!("invalid params file len {} for degree {}. check DEGREE or remove the invalid params file", file_size, degree));
-    }
-
-    let p = ParamsKZG::<Bn256>::read_custom::<_>(&mut BufReader::new(f), serde_format)?;
-    log::info!("load params successfully!");
-    Ok(p)
+/// Read a block trace, with the unknown fields and coercions found in it.
+pub fn read_block_trace_with_warnings<P: AsRef<Path>>(
+    path: P,
+    mode: ParseMode,
+) -> Result<(BlockTrace, Vec<TraceWarning>), TraceError> {
+    let path_str = path.as_ref().to_string_lossy().to_string();
+    let compressed = path.as_ref().extension().map_or(false, |ext| ext == "zst");
+    let mut buffer = Vec::new();
+    File::open(&path)
+        .and_then(|mut f| {
+            if compressed {
+                read_zstd(f, &mut buffer)
+            } else {
+                f.read_to_end(&mut buffer)
+            }
+        })
+        .map_err(|source| TraceError::Read {
+            path: path_str.clone(),
+            source,
+        })?;
+
+    parse_trace(&buffer, mode).map_err(|e| TraceError::Parse {
+        path: path_str,
+        reason: e.to_string(),
+    })
 }
 
-/// create params and write it into file
-pub fn create_params(params_path: &str, degree: usize) -> Result<ParamsKZG<Bn256>> {
-    log::info!("start creating params with degree {}", degree);
-    // The params used for production need to be generated from a trusted setup ceremony.
-    // Here we use a deterministic seed to generate params. This method is unsafe for production usage.
-    let seed_str = read_env_var("PARAM_SEED", "bb4b94a1bbef58c4b5fcda6c900629b5".to_string());
-    let seed_fr = if seed_str.is_empty() {
-        log::info!("use OsRng to create params");
-        Fr::random(OsRng)
-    } else {
-        let bytes = &mut [0u8; 64];
-        bytes[..32].clone_from_slice(&seed_str.as_bytes()[..32]);
-        Fr::from_bytes_wide(bytes)
-    };
-    let params: ParamsKZG<Bn256> = ParamsKZG::<Bn256>::unsafe_setup_with_s(degree as u32, seed_fr);
-    let mut params_buf = Vec::new();
-    params.write_custom(&mut params_buf, DEFAULT_SERDE_FORMAT)?;
-
-    let mut params_file = File::create(params_path)?;
-    params_file.write_all(&params_buf[..])?;
-    log::info!("create params successfully!");
-
-    Ok(params)
+#[cfg(feature = "prover")]
+fn read_zstd(f: File, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+    zstd::Decoder::new(f)?.read_to_end(buffer)
 }
 
-/// return random seed by reading from file or generate new one
-pub fn load_or_create_seed(seed_path: &str) -> Result<[u8; 16]> {
-    if Path::new(seed_path).exists() {
-        load_seed(seed_path)
-    } else {
-        create_seed(seed_path)
-    }
+#[cfg(not(feature = "prover"))]
+fn read_zstd(_: File, _: &mut Vec<u8>) -> std::io::Result<usize> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "zstd traces need the prover feature",
+    ))
 }
 
-/// load seed from the file
-pub fn load_seed(seed_path: &str) -> Result<[u8; 16]> {
-    let mut seed_fs = File::open(seed_path)?;
-    let mut seed = [0_u8; 16];
-    seed_fs.read_exact(&mut seed)?;
-    Ok(seed)
+/// get a block-result from file
+/// Panics if the file can't be read, e.g. in tests.
+pub fn get_block_trace_from_file<P: AsRef<Path>>(path: P) -> BlockTrace {
+    read_block_trace_from_file(path).unwrap_or_else(|e| panic!("{}", e))
 }
 
-/// create the seed and write it into file
-pub fn create_seed(seed_path: &str) -> Result<[u8; 16]> {
-    // TODO: use better randomness source
-    const RNG_SEED_BYTES: [u8; 16] = [
-        0x59, 0x62, 0xbe, 0x5d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06, 0xbc,
-        0xe5,
-    ];
-
-    let mut seed_file = File::create(seed_path)?;
-    seed_file.write_all(RNG_SEED_BYTES.as_slice())?;
-    Ok(RNG_SEED_BYTES)
+/// sha256 digest of a serialized vk, in hex.
+pub fn vk_digest(vk: &[u8]) -> String {
+    hex::encode(Sha256::digest(vk))
 }
 
-/// get a block-result from file
-pub fn get_block_trace_from_file<P: AsRef<Path>>(path: P) -> BlockTrace {
-    let mut buffer = Vec::new();
-    let mut f = File::open(&path).unwrap();
-    f.read_to_end(&mut buffer).unwrap();
-
-    serde_json::from_slice::<BlockTrace>(&buffer).unwrap_or_else(|e1| {
-        serde_json::from_slice::<BlockTraceJsonRpcResult>(&buffer)
-            .map_err(|e2| {
-                panic!(
-                    "unable to load BlockTrace from {:?}, {:?}, {:?}",
-                    path.as_ref(),
-                    e1,
-                    e2
-                )
-            })
-            .unwrap()
-            .result
-    })
+/// Check the digest of a serialized vk against the expected one.
+/// An empty `expected` digest disables the check.
+/// On mismatch, returns an error if `strict`, otherwise only warns.
+pub fn check_vk_digest(vk: &[u8], expected: &str, strict: bool) -> Result<(), KeygenError> {
+    if expected.is_empty() {
+        return Ok(());
+    }
+    let actual = vk_digest(vk);
+    if actual == expected.trim_start_matches("0x").to_lowercase() {
+        log::info!("vk digest {} matches", actual);
+        return Ok(());
+    }
+    if strict {
+        return Err(KeygenError::VkDigestMismatch {
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+    log::warn!(
+        "vk digest mismatch: expected {}, actual {}",
+        expected,
+        actual
+    );
+    Ok(())
 }
 
 pub fn read_env_var<T: Clone + FromStr>(var_name: &'static str, default: T) -> T {
@@ -172,6 +147,7 @@ pub struct BatchMetric {
     pub num_step: usize,
 }
 
+#[cfg(feature = "prover")]
 pub fn metric_of_witness_block(block: &witness::Block<Fr>) -> BatchMetric {
     BatchMetric {
         num_block: block.context.ctxs.len(),
@@ -179,3 +155,30 @@ pub fn metric_of_witness_block(block: &witness::Block<Fr>) -> BatchMetric {
         num_step: block.txs.iter().map(|tx| tx.steps.len()).sum::<usize>(),
     }
 }
+
+/// Bytes per row held while proving the super circuit: the advice, fixed and
+/// permutation polynomials in both the lagrange and the extended coset basis.
+#[cfg(feature = "prover")]
+const SUPER_CIRCUIT_BYTES_PER_ROW: u64 = 16 * 1024;
+/// Same for the aggregation circuit, which has far fewer columns.
+#[cfg(feature = "prover")]
+const AGG_CIRCUIT_BYTES_PER_ROW: u64 = 2 * 1024;
+/// Bytes per exec step of the witness generated from the traces.
+#[cfg(feature = "prover")]
+const WITNESS_BYTES_PER_STEP: u64 = 4 * 1024;
+
+/// Rough estimate of the peak memory in bytes of proving the block traces with
+/// the super circuit and aggregating the snark.
+/// The circuits are proved one after another, so the peak is the larger of the
+/// two, on top of the witness which is held during the whole job.
+#[cfg(feature = "prover")]
+pub fn estimate_proving_memory(block_traces: &[BlockTrace]) -> u64 {
+    let num_step: usize = block_traces
+        .iter()
+        .flat_map(|trace| trace.execution_results.iter())
+        .map(|result| result.exec_steps.len())
+        .sum();
+    let super_circuit = (1u64 << *crate::circuit::DEGREE) * SUPER_CIRCUIT_BYTES_PER_ROW;
+    let agg_circuit = (1u64 << *crate::circuit::AGG_DEGREE) * AGG_CIRCUIT_BYTES_PER_ROW;
+    super_circuit.max(agg_circuit) + num_step as u64 * WITNESS_BYTES_PER_STEP
+}
@@ -0,0 +1,90 @@
+//! Discovery of the traces of a batch, e.g. organized by date or by shard: a file,
+//! a dir of traces, searched recursively, or a glob pattern such as
+//! `traces/2023-05-*/**/*.json.zst`. The traces are ordered by the block number
+//! in their header, not by their file name, so that a batch is proved in block
+//! order whatever the layout of its files.
+
+use super::read_block_trace_from_file;
+use crate::error::TraceError;
+use std::path::{Path, PathBuf};
+use types::eth::BlockTrace;
+
+/// The `.json` and `.json.zst` traces under `dir`, recursively, sorted by path.
+pub fn trace_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_trace_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn is_trace_file(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".json") || name.ends_with(".json.zst")
+}
+
+/// The traces of the file, dir or glob pattern, in block order. Dirs matched by
+/// a pattern are searched recursively too.
+pub fn discover_block_traces(pattern: &str) -> Result<Vec<(PathBuf, BlockTrace)>, TraceError> {
+    let read_error = |path: &Path, source| TraceError::Read {
+        path: path.display().to_string(),
+        source,
+    };
+    let mut paths = vec![];
+    let add = |paths: &mut Vec<PathBuf>, path: PathBuf| {
+        if path.is_dir() {
+            paths.extend(trace_files(&path).map_err(|e| read_error(&path, e))?);
+        } else {
+            paths.push(path);
+        }
+        Ok::<_, TraceError>(())
+    };
+    if Path::new(pattern).exists() {
+        add(&mut paths, PathBuf::from(pattern))?;
+    } else {
+        let matches = glob::glob(pattern)
+            .map_err(|e| TraceError::Invalid(format!("invalid trace pattern {pattern}: {e}")))?;
+        for path in matches {
+            let path = path.map_err(|e| read_error(&e.path().to_path_buf(), e.into_error()))?;
+            add(&mut paths, path)?;
+        }
+    }
+    if paths.is_empty() {
+        return Err(TraceError::Invalid(format!("no traces found at {pattern}")));
+    }
+    paths.sort();
+    paths.dedup();
+
+    let mut traces = vec![];
+    for path in paths {
+        let trace = read_block_trace_from_file(&path)?;
+        let number = trace.header.number.ok_or_else(|| {
+            TraceError::Invalid(format!("trace {} without block number", path.display()))
+        })?;
+        traces.push((number.as_u64(), path, trace));
+    }
+    traces.sort_by_key(|(number, _, _)| *number);
+    for pair in traces.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            return Err(TraceError::Invalid(format!(
+                "block {} traced by both {} and {}",
+                pair[0].0,
+                pair[0].1.display(),
+                pair[1].1.display()
+            )));
+        }
+    }
+    Ok(traces
+        .into_iter()
+        .map(|(_, path, trace)| (path, trace))
+        .collect())
+}
@@ -0,0 +1,148 @@
+//! Parallel loading of params files.
+//!
+//! The file is read with positioned reads in parallel chunks, then the points
+//! are decompressed or checked in parallel and laid out in the raw unchecked
+//! format, which halo2 reads back at memcpy speed. The sha256 digest of the file,
+//! if there is a `.sha256` file next to it, is checked concurrently.
+
+use super::params::params_io_error;
+use crate::error::{ParamsError, Result};
+use halo2_proofs::halo2curves::bn256::{Bn256, G1Affine, G2Affine};
+use halo2_proofs::halo2curves::group::GroupEncoding;
+use halo2_proofs::halo2curves::serde::SerdeObject;
+use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+use halo2_proofs::SerdeFormat;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+
+/// Bytes read by a single positioned read.
+const READ_CHUNK_LEN: usize = 64 << 20;
+/// Points decoded by a single task.
+const POINTS_PER_TASK: usize = 1 << 14;
+/// Bytes of the header, the degree as u32.
+const HEADER_LEN: usize = 4;
+
+pub(super) fn read_params(
+    params_path: &str,
+    degree: usize,
+    serde_format: SerdeFormat,
+) -> Result<ParamsKZG<Bn256>> {
+    let f = File::open(params_path).map_err(|e| params_io_error(params_path, e))?;
+    let file_len = f
+        .metadata()
+        .map_err(|e| params_io_error(params_path, e))?
+        .len() as usize;
+    let buf = read_file_parallel(&f, file_len).map_err(|e| params_io_error(params_path, e))?;
+
+    let expected_digest = std::fs::read_to_string(format!("{params_path}.sha256")).ok();
+    let (digest, raw) = rayon::join(
+        || {
+            expected_digest
+                .as_ref()
+                .map(|_| hex::encode(Sha256::digest(&buf)))
+        },
+        || to_raw_unchecked(buf, degree, serde_format),
+    );
+    if let (Some(expected), Some(actual)) = (expected_digest, digest) {
+        if expected.trim() != actual {
+            return Err(ParamsError::DigestMismatch {
+                path: params_path.to_string(),
+                expected: expected.trim().to_string(),
+                actual,
+            }
+            .into());
+        }
+    }
+
+    let raw = raw.map_err(|e| params_io_error(params_path, e))?;
+    let params = ParamsKZG::<Bn256>::read_custom(&mut &raw[..], SerdeFormat::RawBytesUnchecked)
+        .map_err(|e| params_io_error(params_path, e))?;
+    Ok(params)
+}
+
+#[cfg(unix)]
+fn read_file_parallel(f: &File, file_len: usize) -> std::io::Result<Vec<u8>> {
+    use std::os::unix::fs::FileExt;
+
+    let mut buf = vec![0u8; file_len];
+    buf.par_chunks_mut(READ_CHUNK_LEN)
+        .enumerate()
+        .try_for_each(|(i, chunk)| f.read_exact_at(chunk, (i * READ_CHUNK_LEN) as u64))?;
+    Ok(buf)
+}
+
+#[cfg(not(unix))]
+fn read_file_parallel(mut f: &File, file_len: usize) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut buf = Vec::with_capacity(file_len);
+    f.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Re-encode the points of a params file in the raw unchecked format, checking
+/// or decompressing them on the way.
+fn to_raw_unchecked(
+    buf: Vec<u8>,
+    degree: usize,
+    serde_format: SerdeFormat,
+) -> std::io::Result<Vec<u8>> {
+    let g1_len = match serde_format {
+        SerdeFormat::RawBytesUnchecked => return Ok(buf),
+        SerdeFormat::RawBytes => return check_raw(buf),
+        SerdeFormat::Processed => 32,
+    };
+    let g1_num = 2 << degree;
+    let g2_offset = HEADER_LEN + g1_num * g1_len;
+
+    let mut raw = vec![0u8; HEADER_LEN + g1_num * 64 + 2 * 128];
+    raw[..HEADER_LEN].copy_from_slice(&buf[..HEADER_LEN]);
+    let (raw_g1, raw_g2) = raw[HEADER_LEN..].split_at_mut(g1_num * 64);
+    raw_g1
+        .par_chunks_mut(POINTS_PER_TASK * 64)
+        .zip(buf[HEADER_LEN..g2_offset].par_chunks(POINTS_PER_TASK * g1_len))
+        .try_for_each(|(raw, compressed)| {
+            for (raw, compressed) in raw.chunks_mut(64).zip(compressed.chunks(g1_len)) {
+                let mut repr = <G1Affine as GroupEncoding>::Repr::default();
+                repr.as_mut().copy_from_slice(compressed);
+                let point = Option::<G1Affine>::from(G1Affine::from_bytes(&repr))
+                    .ok_or_else(|| invalid_point("g1"))?;
+                raw.copy_from_slice(&point.to_raw_bytes());
+            }
+            Ok(())
+        })?;
+    for (raw, compressed) in raw_g2.chunks_mut(128).zip(buf[g2_offset..].chunks(64)) {
+        let mut repr = <G2Affine as GroupEncoding>::Repr::default();
+        repr.as_mut().copy_from_slice(compressed);
+        let point = Option::<G2Affine>::from(G2Affine::from_bytes(&repr))
+            .ok_or_else(|| invalid_point("g2"))?;
+        raw.copy_from_slice(&point.to_raw_bytes());
+    }
+    Ok(raw)
+}
+
+/// Check the g1 points of a raw params file are on the curve.
+fn check_raw(buf: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let g1_end = buf.len() - 2 * 128;
+    buf[HEADER_LEN..g1_end]
+        .par_chunks(POINTS_PER_TASK * 64)
+        .try_for_each(|points| {
+            points.chunks(64).try_for_each(|point| {
+                G1Affine::from_raw_bytes(point)
+                    .map(|_| ())
+                    .ok_or_else(|| invalid_point("g1"))
+            })
+        })?;
+    for point in buf[g1_end..].chunks(128) {
+        G2Affine::from_raw_bytes(point).ok_or_else(|| invalid_point("g2"))?;
+    }
+    Ok(buf)
+}
+
+fn invalid_point(group: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid {group} point in params"),
+    )
+}
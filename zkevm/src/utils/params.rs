@@ -0,0 +1,472 @@
+//! The KZG params and the seed of the prover rng: created, read in any format and
+//! written, the seed encrypted with a `SeedKey` if any.
+
+use super::parallel_read;
+use super::read_env_var;
+use super::seed_key::{self, SeedKey};
+use crate::error::{ParamsError, Result};
+use halo2_proofs::arithmetic::Field;
+use halo2_proofs::halo2curves::bn256::{Bn256, Fr};
+use halo2_proofs::halo2curves::pairing::Engine;
+use halo2_proofs::halo2curves::FieldExt;
+use halo2_proofs::poly::commitment::Params;
+use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+use halo2_proofs::SerdeFormat;
+use once_cell::sync::Lazy;
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, metadata, File};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub(crate) const DEFAULT_SERDE_FORMAT: SerdeFormat = SerdeFormat::RawBytesUnchecked;
+
+/// Write new params with compressed points, i.e. half the size, read back
+/// transparently by `load_or_create_params`.
+pub static PARAMS_COMPRESSED: Lazy<bool> = Lazy::new(|| read_env_var("PARAMS_COMPRESSED", false));
+
+/// Read params files in parallel chunks, see `parallel_read`. Unless tuned
+/// otherwise, see `tune`.
+pub static PARAMS_PARALLEL_READ: Lazy<bool> = Lazy::new(|| {
+    let tuned = crate::tune::TUNED_SETTINGS.as_ref();
+    read_env_var(
+        "PARAMS_PARALLEL_READ",
+        tuned.map_or(true, |s| s.params_parallel_read),
+    )
+});
+
+/// Format new params are written in.
+pub fn params_serde_format() -> SerdeFormat {
+    if *PARAMS_COMPRESSED {
+        SerdeFormat::Processed
+    } else {
+        DEFAULT_SERDE_FORMAT
+    }
+}
+
+/// return setup params by reading from file or generate new one
+pub fn load_or_create_params(params_dir: &str, degree: usize) -> Result<ParamsKZG<Bn256>> {
+    let _path = PathBuf::from(params_dir);
+
+    match metadata(params_dir) {
+        Ok(md) => {
+            if md.is_file() {
+                return Err(ParamsError::NotADir(params_dir.to_string()).into());
+            }
+        }
+        Err(_) => {
+            // not exist
+            fs::create_dir_all(params_dir).map_err(|e| params_io_error(params_dir, e))?;
+        }
+    };
+
+    let params_path = format!("{params_dir}/params{degree}");
+    log::info!("load_or_create_params {}", params_path);
+    if Path::new(&params_path).exists() {
+        // params of another degree or format are an error, not recreated,
+        // as they are likely a misconfiguration of the degree or of the file
+        return load_params_any_format(&params_path, degree);
+    }
+    create_params(&params_path, degree)
+}
+
+/// Resolve the params file of the degree in a dir, or the file itself.
+fn params_file_path(params_dir: &str, degree: usize) -> Result<String> {
+    let md = metadata(params_dir).map_err(|e| params_io_error(params_dir, e))?;
+    Ok(if md.is_dir() {
+        // auto load
+        format!("{params_dir}/params{degree}")
+    } else {
+        params_dir.to_string()
+    })
+}
+
+/// Expected size of a params file:
+///   len: 4 bytes
+///   g: 2**DEGREE g1 points, each 32 bytes compressed, 64 bytes raw
+///   g_lagrange: 2**DEGREE g1 points
+///   g2: g2 point, twice the size of a g1 point
+///   s_g2: g2 point
+pub fn params_file_len(degree: usize, serde_format: SerdeFormat) -> u64 {
+    let g1_num = 2 * (1 << degree);
+    let g2_num = 2;
+    let g1_bytes_len = match serde_format {
+        SerdeFormat::Processed => 32,
+        SerdeFormat::RawBytes | SerdeFormat::RawBytesUnchecked => 64,
+    };
+    let g2_bytes_len = 2 * g1_bytes_len;
+    4 + g1_num * g1_bytes_len + g2_num * g2_bytes_len
+}
+
+/// Memory taken by params of `degree` once loaded, their points being held
+/// uncompressed.
+pub fn params_memory(degree: usize) -> u64 {
+    params_file_len(degree, SerdeFormat::RawBytes)
+}
+
+/// Degree in the header of a params file.
+pub fn read_params_degree(params_path: &str) -> Result<usize> {
+    let mut header = [0u8; 4];
+    File::open(params_path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map_err(|e| params_io_error(params_path, e))?;
+    Ok(u32::from_le_bytes(header) as usize)
+}
+
+/// Format of a params file of the degree, told apart by its size and the
+/// degree in its header.
+pub fn detect_params_format(params_dir: &str, degree: usize) -> Result<Option<SerdeFormat>> {
+    let params_path = params_file_path(params_dir, degree)?;
+    let file_size = metadata(&params_path)
+        .map_err(|e| params_io_error(&params_path, e))?
+        .len();
+    let file_degree = read_params_degree(&params_path)?;
+    Ok([DEFAULT_SERDE_FORMAT, SerdeFormat::Processed]
+        .into_iter()
+        .find(|format| params_file_len(file_degree, *format) == file_size))
+}
+
+/// Load params in whichever format they were written. Compressed points are
+/// decompressed on load, so only the files on disk are smaller.
+pub fn load_params_any_format(params_dir: &str, degree: usize) -> Result<ParamsKZG<Bn256>> {
+    let serde_format = match detect_params_format(params_dir, degree)? {
+        Some(serde_format) => serde_format,
+        None => {
+            let params_path = params_file_path(params_dir, degree)?;
+            return Err(ParamsError::UnknownFormat {
+                file_degree: read_params_degree(&params_path)?,
+                len: metadata(&params_path)
+                    .map_err(|e| params_io_error(&params_path, e))?
+                    .len(),
+                path: params_path,
+            }
+            .into());
+        }
+    };
+    load_params(params_dir, degree, serde_format)
+}
+
+/// Rewrite the params file of the degree with compressed points, returns
+/// false if it already is.
+pub fn compress_params(params_dir: &str, degree: usize) -> Result<bool> {
+    if matches!(
+        detect_params_format(params_dir, degree)?,
+        Some(SerdeFormat::Processed)
+    ) {
+        return Ok(false);
+    }
+    // all of the file, not downsized to the degree
+    let params_path = params_file_path(params_dir, degree)?;
+    let file_degree = read_params_degree(&params_path)?;
+    let params = load_params(&params_path, file_degree, DEFAULT_SERDE_FORMAT)?;
+    write_params(&params, &params_path, SerdeFormat::Processed)?;
+    Ok(true)
+}
+
+/// Rewrite the params file at `src` into `dst` in the format, downsized to
+/// `degree` if given. `dst` may be `src`. Params written by halo2 before
+/// `SerdeFormat` have compressed points, i.e. are read as `Processed`.
+pub fn convert_params(
+    src: &str,
+    dst: &str,
+    degree: Option<usize>,
+    serde_format: SerdeFormat,
+) -> Result<()> {
+    let file_degree = read_params_degree(src)?;
+    let degree = degree.unwrap_or(file_degree);
+    let params = load_params_any_format(src, degree)?;
+    write_params(&params, dst, serde_format)
+}
+
+/// Check the params file against the sha256 next to it. Returns false if
+/// there is none, and an error if it doesn't match.
+pub fn verify_params_digest(params_path: &str) -> Result<bool> {
+    let expected = match fs::read_to_string(format!("{params_path}.sha256")) {
+        Ok(expected) => expected.trim().to_string(),
+        Err(_) => return Ok(false),
+    };
+    let buf = fs::read(params_path).map_err(|e| params_io_error(params_path, e))?;
+    let actual = hex::encode(Sha256::digest(&buf));
+    if actual != expected {
+        return Err(ParamsError::DigestMismatch {
+            path: params_path.to_string(),
+            expected,
+            actual,
+        }
+        .into());
+    }
+    Ok(true)
+}
+
+/// Check that `samples` random consecutive points of the params are
+/// successive powers of the same secret, `e(g[i + 1], g2) == e(g[i], s_g2)`.
+/// Catches points corrupted on disk or params of another setup, not a
+/// dishonest setup.
+pub fn check_params_powers(params: &ParamsKZG<Bn256>, samples: usize) -> bool {
+    let g = params.get_g();
+    if g.len() < 2 {
+        return true;
+    }
+    let (g2, s_g2) = (params.g2(), params.s_g2());
+    (0..samples).all(|_| {
+        let i = OsRng.gen_range(0..g.len() - 1);
+        Bn256::pairing(&g[i + 1], &g2) == Bn256::pairing(&g[i], &s_g2)
+    })
+}
+
+/// load params from file. Params of a larger degree are downsized to `degree`.
+pub fn load_params(
+    params_dir: &str,
+    degree: usize,
+    serde_format: SerdeFormat,
+) -> Result<ParamsKZG<Bn256>> {
+    log::info!("start loading params with degree {}", degree);
+    let params_path = params_file_path(params_dir, degree)?;
+    let file_degree = read_params_degree(&params_path)?;
+    if file_degree < degree {
+        return Err(ParamsError::DegreeMismatch {
+            path: params_path,
+            expected: degree,
+            actual: file_degree,
+        }
+        .into());
+    }
+    let f = File::open(&params_path).map_err(|e| params_io_error(&params_path, e))?;
+
+    let file_size = f
+        .metadata()
+        .map_err(|e| params_io_error(&params_path, e))?
+        .len();
+    let expected_len = params_file_len(file_degree, serde_format);
+    if file_size != expected_len {
+        return Err(ParamsError::InvalidLength {
+            degree: file_degree,
+            actual: file_size,
+            expected: expected_len,
+        }
+        .into());
+    }
+
+    let mut p = if *PARAMS_PARALLEL_READ {
+        parallel_read::read_params(&params_path, file_degree, serde_format)?
+    } else {
+        ParamsKZG::<Bn256>::read_custom::<_>(&mut BufReader::new(f), serde_format)
+            .map_err(|e| params_io_error(&params_path, e))?
+    };
+    if file_degree > degree {
+        log::info!(
+            "downsize params {} from degree {} to {}",
+            params_path,
+            file_degree,
+            degree
+        );
+        p.downsize(degree as u32);
+    }
+    if *PARAMS_SHARED {
+        share_params(&p);
+    }
+    log::info!("load params successfully!");
+    Ok(p)
+}
+
+/// create params and write it into file
+pub fn create_params(params_path: &str, degree: usize) -> Result<ParamsKZG<Bn256>> {
+    log::info!("start creating params with degree {}", degree);
+    let params: ParamsKZG<Bn256> =
+        ParamsKZG::<Bn256>::unsafe_setup_with_s(degree as u32, params_secret());
+    write_params(&params, params_path, params_serde_format())?;
+    log::info!("create params successfully!");
+
+    Ok(params)
+}
+
+/// Secret of the params created, from `PARAM_SEED`, random if empty.
+fn params_secret() -> Fr {
+    // The params used for production need to be generated from a trusted setup ceremony.
+    // Here we use a deterministic seed to generate params. This method is unsafe for production usage.
+    let seed_str = read_env_var("PARAM_SEED", "bb4b94a1bbef58c4b5fcda6c900629b5".to_string());
+    if seed_str.is_empty() {
+        log::info!("use OsRng to create params");
+        Fr::random(OsRng)
+    } else {
+        let bytes = &mut [0u8; 64];
+        bytes[..32].clone_from_slice(&seed_str.as_bytes()[..32]);
+        Fr::from_bytes_wide(bytes)
+    }
+}
+
+/// Params of degree `AGG_DEGREE` created in memory, once per process, downsized
+/// to `degree`. Only for the small circuits of `test-mode`, nothing is written.
+#[cfg(feature = "test-mode")]
+pub fn dev_params(degree: usize) -> ParamsKZG<Bn256> {
+    static PARAMS: Lazy<ParamsKZG<Bn256>> = Lazy::new(|| {
+        let degree = *crate::circuit::AGG_DEGREE;
+        log::info!("creating dev params of degree {} in memory", degree);
+        ParamsKZG::<Bn256>::unsafe_setup_with_s(degree as u32, params_secret())
+    });
+    assert!(
+        degree <= PARAMS.k() as usize,
+        "dev params of degree {degree} above AGG_DEGREE"
+    );
+    let mut params = PARAMS.clone();
+    if degree < params.k() as usize {
+        params.downsize(degree as u32);
+    }
+    params
+}
+
+/// The params of `degree`, downsized from `params` on first use and kept in
+/// `cache`, so that circuits of different degrees share one setup.
+pub fn params_of_degree<'a>(
+    params: &'a ParamsKZG<Bn256>,
+    cache: &'a mut BTreeMap<u32, ParamsKZG<Bn256>>,
+    degree: u32,
+) -> Result<&'a ParamsKZG<Bn256>, ParamsError> {
+    if degree == params.k() {
+        return Ok(params);
+    }
+    if degree > params.k() {
+        return Err(ParamsError::DegreeTooHigh {
+            degree,
+            params_degree: params.k(),
+        });
+    }
+    Ok(cache.entry(degree).or_insert_with(|| {
+        log::info!("downsizing params of degree {} to {}", params.k(), degree);
+        let mut downsized = params.clone();
+        downsized.downsize(degree);
+        downsized
+    }))
+}
+
+/// Write params into a file, through a temp file so that a crash doesn't leave
+/// truncated params behind. The sha256 of the file is written next to it, and
+/// checked by the parallel reader.
+pub fn write_params(
+    params: &ParamsKZG<Bn256>,
+    params_path: &str,
+    serde_format: SerdeFormat,
+) -> Result<()> {
+    let mut params_buf = Vec::new();
+    params
+        .write_custom(&mut params_buf, serde_format)
+        .map_err(|e| params_io_error(params_path, e))?;
+
+    let tmp_path = format!("{params_path}.tmp");
+    let digest_path = format!("{params_path}.sha256");
+    // a stale digest must not outlive the file it belongs to
+    let _ = fs::remove_file(&digest_path);
+    File::create(&tmp_path)
+        .and_then(|mut f| f.write_all(&params_buf[..]))
+        .and_then(|_| fs::rename(&tmp_path, params_path))
+        .and_then(|_| fs::write(&digest_path, hex::encode(Sha256::digest(&params_buf))))
+        .map_err(|e| params_io_error(params_path, e))?;
+    Ok(())
+}
+
+/// return random seed by reading from file or generate new one, encrypted
+/// with the `SeedKey` of the env if any
+pub fn load_or_create_seed(seed_path: &str) -> Result<[u8; 16]> {
+    if Path::new(seed_path).exists() {
+        load_seed(seed_path)
+    } else {
+        create_seed(seed_path)
+    }
+}
+
+/// load seed from the file, decrypted with the `SeedKey` of the env if encrypted
+pub fn load_seed(seed_path: &str) -> Result<[u8; 16]> {
+    load_seed_with_key(seed_path, seed_key_from_env(seed_path)?.as_ref())
+}
+
+pub fn load_seed_with_key(seed_path: &str, key: Option<&SeedKey>) -> Result<[u8; 16]> {
+    let buf = fs::read(seed_path).map_err(|e| seed_io_error(seed_path, e))?;
+    let buf = if seed_key::is_encrypted(&buf) {
+        let key = key.ok_or_else(|| {
+            seed_key_error(seed_path, "encrypted, set SEED_PASSPHRASE or SEED_KEY_FILE")
+        })?;
+        seed_key::decrypt(&buf, key).map_err(|e| seed_key_error(seed_path, &e))?
+    } else {
+        if key.is_some() {
+            log::warn!(
+                "seed {} is in plaintext, encrypt it with `setup --seed {} --encrypt-seed`",
+                seed_path,
+                seed_path
+            );
+        }
+        buf
+    };
+    let mut seed = [0_u8; 16];
+    (&buf[..])
+        .read_exact(&mut seed)
+        .map_err(|e| seed_io_error(seed_path, e))?;
+    Ok(seed)
+}
+
+/// create the seed and write it into file, encrypted with the `SeedKey` of the env if any
+pub fn create_seed(seed_path: &str) -> Result<[u8; 16]> {
+    create_seed_with_key(seed_path, seed_key_from_env(seed_path)?.as_ref())
+}
+
+/// create a seed from the OS entropy and write it into file, encrypted with `key` if any
+pub fn create_seed_with_key(seed_path: &str, key: Option<&SeedKey>) -> Result<[u8; 16]> {
+    let seed: [u8; 16] = OsRng.gen();
+    write_seed(seed_path, &seed, key)?;
+    Ok(seed)
+}
+
+/// Encrypt a plaintext seed file in place, returns false if already encrypted.
+///
+/// The encrypted seed is written into `<seed>.tmp` then renamed over the seed, so
+/// the plaintext bytes are neither removed nor overwritten: they stay in the freed
+/// blocks of the disk, and in its backups and snapshots. Create the seed encrypted,
+/// with the `SeedKey` set, when that matters, or wipe the disk.
+pub fn encrypt_seed(seed_path: &str, key: &SeedKey) -> Result<bool> {
+    let buf = fs::read(seed_path).map_err(|e| seed_io_error(seed_path, e))?;
+    if seed_key::is_encrypted(&buf) {
+        return Ok(false);
+    }
+    let seed = load_seed_with_key(seed_path, None)?;
+    write_seed(seed_path, &seed, Some(key))?;
+    Ok(true)
+}
+
+fn write_seed(seed_path: &str, seed: &[u8; 16], key: Option<&SeedKey>) -> Result<()> {
+    let buf = match key {
+        Some(key) => seed_key::encrypt(seed, key).map_err(|e| seed_key_error(seed_path, &e))?,
+        None => seed.to_vec(),
+    };
+    let tmp_path = format!("{seed_path}.tmp");
+    File::create(&tmp_path)
+        .and_then(|mut f| f.write_all(&buf))
+        .and_then(|_| fs::rename(&tmp_path, seed_path))
+        .map_err(|e| seed_io_error(seed_path, e))?;
+    Ok(())
+}
+
+fn seed_key_from_env(seed_path: &str) -> Result<Option<SeedKey>> {
+    SeedKey::from_env().map_err(|e| seed_key_error(seed_path, &e).into())
+}
+
+pub(super) fn params_io_error(path: &str, source: std::io::Error) -> ParamsError {
+    ParamsError::Io {
+        path: path.to_string(),
+        source,
+    }
+}
+
+fn seed_io_error(path: &str, source: std::io::Error) -> ParamsError {
+    ParamsError::Seed {
+        path: path.to_string(),
+        source,
+    }
+}
+
+fn seed_key_error(path: &str, reason: &str) -> ParamsError {
+    ParamsError::SeedKey {
+        path: path.to_string(),
+        reason: reason.to_string(),
+    }
+}
@@ -0,0 +1,108 @@
+//! Encryption of the seed file at rest, in the age format (age-encryption.org),
+//! so that the seed isn't in plaintext on shared prover hosts, and can be
+//! decrypted by hand with `age -d`.
+//!
+//! The key is the passphrase in `SEED_PASSPHRASE` (scrypt), or the X25519
+//! identity, `AGE-SECRET-KEY-1...`, in the file at `SEED_KEY_FILE`, e.g. as
+//! written by `age-keygen`. Encrypted seeds are told apart from plaintext ones
+//! by the age header, so that a seed is read alike either way.
+
+use super::read_env_var;
+use age::secrecy::SecretString;
+use once_cell::sync::Lazy;
+use std::fmt;
+use std::io::{Read, Write};
+
+/// File of the age identity encrypting the seed.
+pub static SEED_KEY_FILE: Lazy<String> =
+    Lazy::new(|| read_env_var("SEED_KEY_FILE", "".to_string()));
+
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1\n";
+
+/// The key of an encrypted seed file.
+pub enum SeedKey {
+    Passphrase(SecretString),
+    Identity(age::x25519::Identity),
+}
+
+impl fmt::Debug for SeedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passphrase(_) => write!(f, "SeedKey::Passphrase(..)"),
+            Self::Identity(identity) => write!(f, "SeedKey::Identity({})", identity.to_public()),
+        }
+    }
+}
+
+impl SeedKey {
+    /// The key of `SEED_PASSPHRASE`, read when called so that it isn't kept in
+    /// memory, or else of `SEED_KEY_FILE`, none if neither is set.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("SEED_PASSPHRASE") {
+            Ok(passphrase) if !passphrase.is_empty() => {
+                return Ok(Some(Self::Passphrase(SecretString::new(passphrase))))
+            }
+            _ => {}
+        }
+        if SEED_KEY_FILE.is_empty() {
+            return Ok(None);
+        }
+        Self::from_identity_file(&SEED_KEY_FILE).map(Some)
+    }
+
+    /// The first identity of an age identity file.
+    pub fn from_identity_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read the key file {path}: {e}"))?;
+        content
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+            .ok_or_else(|| format!("no AGE-SECRET-KEY in the key file {path}"))?
+            .parse()
+            .map(Self::Identity)
+            .map_err(|e| format!("invalid key in {path}: {e}"))
+    }
+}
+
+pub(super) fn is_encrypted(buf: &[u8]) -> bool {
+    buf.starts_with(AGE_HEADER)
+}
+
+pub(super) fn encrypt(seed: &[u8], key: &SeedKey) -> Result<Vec<u8>, String> {
+    let encryptor = match key {
+        SeedKey::Passphrase(passphrase) => age::Encryptor::with_user_passphrase(passphrase.clone()),
+        SeedKey::Identity(identity) => {
+            let recipient: Box<dyn age::Recipient + Send> = Box::new(identity.to_public());
+            age::Encryptor::with_recipients(vec![recipient])
+                .ok_or_else(|| "no recipient".to_string())?
+        }
+    };
+    let mut buf = vec![];
+    let mut writer = encryptor.wrap_output(&mut buf).map_err(|e| e.to_string())?;
+    writer.write_all(seed).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+pub(super) fn decrypt(buf: &[u8], key: &SeedKey) -> Result<Vec<u8>, String> {
+    let decryptor = age::Decryptor::new(buf).map_err(|e| e.to_string())?;
+    let mut reader = match (decryptor, key) {
+        (age::Decryptor::Passphrase(d), SeedKey::Passphrase(passphrase)) => {
+            d.decrypt(passphrase, None)
+        }
+        (age::Decryptor::Recipients(d), SeedKey::Identity(identity)) => {
+            d.decrypt(std::iter::once(identity as &dyn age::Identity))
+        }
+        (age::Decryptor::Passphrase(_), _) => {
+            return Err("encrypted with a passphrase, set SEED_PASSPHRASE".to_string())
+        }
+        (age::Decryptor::Recipients(_), _) => {
+            return Err("encrypted to a key, set SEED_KEY_FILE".to_string())
+        }
+    }
+    .map_err(|e| e.to_string())?;
+    let mut seed = vec![];
+    reader.read_to_end(&mut seed).map_err(|e| e.to_string())?;
+    Ok(seed)
+}
@@ -0,0 +1,105 @@
+//! Sharing the memory of the params between the prover processes of a host.
+//!
+//! halo2 keeps the points of the params in vectors it allocates itself, so they
+//! can't be placed into a shared memory segment nor a mapping of the params file.
+//! Instead the memory holding them is marked as mergeable: the kernel (KSM, see
+//! `/sys/kernel/mm/ksm`) then scans it, and keeps a single copy of the pages that
+//! are the same in every process loading the same params, copied again on write,
+//! which never happens to params. The points of every process are laid out at the
+//! same offsets in their pages, as the allocator maps such large vectors alike.
+//!
+//! Where the kernel has `PR_SET_MEMORY_MERGE` (6.4), all the memory of the process
+//! is made mergeable, which also covers the lagrange points, out of reach of the
+//! params API, and the identical proving keys. Otherwise only the points of `g`
+//! are marked. Either way, nothing is merged unless ksmd runs, i.e. with
+//! `echo 1 > /sys/kernel/mm/ksm/run`.
+
+use super::read_env_var;
+use halo2_proofs::halo2curves::bn256::Bn256;
+use halo2_proofs::poly::commitment::Params;
+use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+use once_cell::sync::Lazy;
+
+/// Share the memory of the params loaded with the other processes of the host.
+pub static PARAMS_SHARED: Lazy<bool> = Lazy::new(|| read_env_var("PARAMS_SHARED", false));
+
+static PROCESS_MERGEABLE: Lazy<bool> = Lazy::new(|| {
+    let mergeable = merge_process();
+    if !ksm_running() {
+        log::warn!("params: ksm is not running, the params memory won't be shared");
+    }
+    mergeable
+});
+
+/// Mark the memory of the params as mergeable with identical memory of the other
+/// processes, returns false if the kernel doesn't support it.
+pub fn share_params(params: &ParamsKZG<Bn256>) -> bool {
+    if *PROCESS_MERGEABLE {
+        return true;
+    }
+    let g = params.get_g();
+    let shared = merge_range(g.as_ptr() as usize, std::mem::size_of_val(g));
+    if shared {
+        log::info!(
+            "params: {}MB of points of degree {} shared",
+            std::mem::size_of_val(g) >> 20,
+            params.k()
+        );
+    } else {
+        log::warn!("params: failed to share the points, no ksm in the kernel");
+    }
+    shared
+}
+
+fn ksm_running() -> bool {
+    std::fs::read_to_string("/sys/kernel/mm/ksm/run").map_or(false, |run| run.trim() == "1")
+}
+
+#[cfg(target_os = "linux")]
+fn merge_process() -> bool {
+    // not in libc 0.2 yet
+    const PR_SET_MEMORY_MERGE: libc::c_int = 67;
+    // the variadic args are unsigned longs
+    let (enable, unused): (libc::c_ulong, libc::c_ulong) = (1, 0);
+    // safe: only changes how the kernel backs the anonymous memory of the process
+    let merged = unsafe { libc::prctl(PR_SET_MEMORY_MERGE, enable, unused, unused, unused) == 0 };
+    if merged {
+        log::info!("params: memory of the process made mergeable");
+    }
+    merged
+}
+
+#[cfg(not(target_os = "linux"))]
+fn merge_process() -> bool {
+    false
+}
+
+/// Mark the whole pages within the range.
+#[cfg(target_os = "linux")]
+fn merge_range(addr: usize, len: usize) -> bool {
+    let page = page_size();
+    let start = (addr + page - 1) & !(page - 1);
+    let end = (addr + len) & !(page - 1);
+    if end <= start {
+        return false;
+    }
+    // safe: MADV_MERGEABLE leaves the content of the pages as is
+    unsafe {
+        libc::madvise(
+            start as *mut libc::c_void,
+            end - start,
+            libc::MADV_MERGEABLE,
+        ) == 0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn merge_range(_addr: usize, _len: usize) -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn page_size() -> usize {
+    // safe: reads a constant of the system
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
@@ -1,121 +1,557 @@
-use std::collections::HashMap;
-use std::io::Cursor;
-
-use crate::circuit::{TargetCircuit, AGG_DEGREE, DEGREE};
-use crate::io::load_instances;
-use crate::prover::{AggCircuitProof, TargetCircuitProof};
-use crate::utils::{load_params, DEFAULT_SERDE_FORMAT};
-use anyhow::anyhow;
+mod vk_registry;
+
+pub use vk_registry::{ArchivedVk, VkRegistry, VK_REGISTRY};
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::aggregation::{read_vk, verify_native};
+use crate::attestation::{instance_hash, trace_hash, QuoteVerifier};
+use crate::audit::{audit_log_from_env, AuditLog, AuditOperation, AuditOutcome, AuditRecord};
+use crate::chunk::{BlockHeaderLike, ChunkInfo};
+use crate::circuit::{ChainBoundAggregationCircuit, TargetCircuit, AGG_DEGREE, CHAIN_ID, DEGREE};
+use crate::error::{KeygenError, Result, VerificationError, ZkEvmError};
+use crate::instance::{decode_column, diff_instances, AggInstance};
+use crate::io::serialize_vk;
+use crate::prover::{
+    inner_instance_hash, AggCircuitProof, AggConfig, TargetCircuitProof, AGG_VK_DIGEST,
+    AGG_VK_DIGEST_STRICT,
+};
+use crate::utils::{check_vk_digest, load_params_any_format, params_of_degree, vk_digest};
+use crate::version::CircuitVersion;
 use halo2_proofs::halo2curves::bn256::{Bn256, Fr, G1Affine};
+use halo2_proofs::plonk::keygen_vk;
 use halo2_proofs::plonk::VerifyingKey;
-use halo2_proofs::plonk::{keygen_vk, verify_proof};
-use halo2_proofs::poly::commitment::ParamsProver;
+use halo2_proofs::poly::commitment::{Params, ParamsProver};
 use halo2_proofs::poly::kzg::commitment::ParamsKZG;
-use halo2_proofs::poly::kzg::multiopen::VerifierSHPLONK;
-use halo2_proofs::poly::kzg::strategy::AccumulatorStrategy;
-use halo2_proofs::poly::VerificationStrategy;
-use halo2_proofs::transcript::TranscriptReadBuffer;
-use snark_verifier::system::halo2::transcript::evm::EvmTranscript;
-use snark_verifier_sdk::evm::evm_verify;
-use snark_verifier_sdk::halo2::aggregation::AggregationCircuit;
+use snark_verifier::loader::evm::{Address, ExecutorBuilder};
+use snark_verifier_sdk::evm::{evm_verify, gen_evm_verifier_shplonk};
 use snark_verifier_sdk::halo2::verify_snark_shplonk;
+use types::eth::BlockTrace;
 
 pub struct Verifier {
     params: ParamsKZG<Bn256>,
     agg_params: ParamsKZG<Bn256>,
+    /// The params downsized to the degrees of the circuits below them.
+    degree_params: BTreeMap<u32, ParamsKZG<Bn256>>,
     agg_vk: Option<VerifyingKey<G1Affine>>,
     target_circuit_vks: HashMap<String, VerifyingKey<G1Affine>>,
+    circuit_version: CircuitVersion,
+    audit_log: Option<Arc<AuditLog>>,
+    vk_registry: Option<Arc<VkRegistry>>,
+    /// Keys of the archived versions verified with, see `Verifier::verify_with_version`.
+    archived_keys: Mutex<HashMap<CircuitVersion, Arc<ArchivedKeys>>>,
+}
+
+struct ArchivedKeys {
+    vk: VerifyingKey<G1Affine>,
+    /// The agg params of the version, the ones of the verifier if none.
+    agg_params: Option<ParamsKZG<Bn256>>,
 }
 
 impl Verifier {
+    /// A verifier of the agg vk, which fails to read or to match `AGG_VK_DIGEST`
+    /// with an error.
     pub fn new(
         params: ParamsKZG<Bn256>,
         agg_params: ParamsKZG<Bn256>,
         raw_agg_vk: Option<Vec<u8>>,
-    ) -> Self {
-        let agg_vk = raw_agg_vk.as_ref().map(|k| {
-            VerifyingKey::<G1Affine>::read::<_, AggregationCircuit>(
-                &mut Cursor::new(&k),
-                halo2_proofs::SerdeFormat::Processed,
-            )
-            .unwrap()
-        });
+    ) -> Result<Self, KeygenError> {
+        let agg_vk = match raw_agg_vk {
+            Some(k) => {
+                check_vk_digest(&k, &AGG_VK_DIGEST, *AGG_VK_DIGEST_STRICT)?;
+                Some(read_vk(&k)?)
+            }
+            None => None,
+        };
 
-        Self {
+        Ok(Self {
             params,
             agg_params,
+            degree_params: Default::default(),
             agg_vk,
             target_circuit_vks: Default::default(),
-        }
+            circuit_version: CircuitVersion::current(),
+            audit_log: audit_log_from_env(),
+            vk_registry: VkRegistry::from_env().map(Arc::new),
+            archived_keys: Default::default(),
+        })
+    }
+
+    /// Verify the proofs of other circuit versions with the vks archived in
+    /// `registry`, `VK_REGISTRY` by default.
+    pub fn set_vk_registry(&mut self, registry: Option<Arc<VkRegistry>>) {
+        self.vk_registry = registry;
+        self.archived_keys.lock().unwrap().clear();
+    }
+
+    pub fn vk_registry(&self) -> Option<&VkRegistry> {
+        self.vk_registry.as_deref()
+    }
+
+    /// Archive the agg vk of the verifier as the one of its circuit version, with
+    /// the agg params if `with_params`.
+    pub fn archive_agg_vk(
+        &self,
+        registry: &VkRegistry,
+        agg_config: Option<AggConfig>,
+        with_params: bool,
+    ) -> Result<ArchivedVk> {
+        let vk = self.agg_vk.as_ref().ok_or(VerificationError::MissingVk)?;
+        let raw_vk = serialize_vk(vk);
+        let entry = ArchivedVk {
+            circuit_version: self.circuit_version.clone(),
+            degree: self.params.k(),
+            agg_degree: self.agg_params.k(),
+            vk_digest: vk_digest(&raw_vk),
+            agg_config,
+        };
+        registry.archive(&entry, &raw_vk, with_params.then_some(&self.agg_params))?;
+        Ok(entry)
+    }
+
+    /// Record the verifications into `audit_log`, `AUDIT_LOG` by default.
+    pub fn set_audit_log(&mut self, audit_log: Option<Arc<AuditLog>>) {
+        self.audit_log = audit_log;
+    }
+
+    /// Circuit version of the verification keys.
+    pub fn circuit_version(&self) -> &CircuitVersion {
+        &self.circuit_version
+    }
+
+    /// Whether the proof was created by a compatible circuit version.
+    pub fn can_verify(&self, proof: &AggCircuitProof) -> bool {
+        crate::version::can_verify(proof, self)
     }
 
     pub fn from_params(
         params: ParamsKZG<Bn256>,
         agg_params: ParamsKZG<Bn256>,
         agg_vk: Option<Vec<u8>>,
-    ) -> Self {
+    ) -> Result<Self, KeygenError> {
         Self::new(params, agg_params, agg_vk)
     }
 
-    pub fn from_fpath(params_path: &str, agg_vk: Option<Vec<u8>>) -> Self {
-        let params =
-            load_params(params_path, *DEGREE, DEFAULT_SERDE_FORMAT).expect("failed to init params");
-        let agg_params = load_params(params_path, *AGG_DEGREE, DEFAULT_SERDE_FORMAT)
-            .expect("failed to init params");
-        Self::from_params(params, agg_params, agg_vk)
+    /// A verifier over the params generated in memory by `dev_params`.
+    #[cfg(feature = "test-mode")]
+    pub fn dev(agg_vk: Option<Vec<u8>>) -> Result<Self, KeygenError> {
+        use crate::utils::dev_params;
+        Self::from_params(dev_params(*DEGREE), dev_params(*AGG_DEGREE), agg_vk)
+    }
+
+    pub fn from_fpath(params_path: &str, agg_vk: Option<Vec<u8>>) -> Result<Self> {
+        let params = load_params_any_format(params_path, *DEGREE)?;
+        let agg_params = load_params_any_format(params_path, *AGG_DEGREE)?;
+        Ok(Self::from_params(params, agg_params, agg_vk)?)
+    }
+
+    pub fn verify_agg_circuit_proof(&self, proof: AggCircuitProof) -> Result<bool> {
+        let start = Instant::now();
+        let result = self.verify_agg(&proof);
+        self.record_verify_agg(&proof, &proof.circuit_version, start, &result);
+        result
+    }
+
+    /// Verify an agg proof of `chunks` on the chain `CHAIN_ID`: the chunks are of
+    /// that chain and the instance is the one recomputed from them, then the proof
+    /// is verified. The circuit doesn't constrain the chain id instance, it is the
+    /// public input hashes, whose preimage has the chain id of each chunk, which
+    /// bind the proof to the chain, see `aggregation`. A proof of the same chunks
+    /// on another chain has other hashes and is rejected.
+    pub fn verify_agg_proof_of_chunks(
+        &self,
+        proof: AggCircuitProof,
+        chunks: &[ChunkInfo],
+    ) -> Result<bool> {
+        if let Some(chunk) = chunks.iter().find(|chunk| chunk.chain_id != *CHAIN_ID) {
+            return Err(VerificationError::InstanceMismatch {
+                field: "chain id of the chunks".to_string(),
+                expected: CHAIN_ID.to_string(),
+                actual: chunk.chain_id.to_string(),
+            }
+            .into());
+        }
+        let instance = AggInstance::decode(&proof.instance)?;
+        let expected = AggInstance::of_chunks(instance.accumulator, chunks).map_err(|reason| {
+            VerificationError::Invalid {
+                what: "chunks",
+                reason,
+            }
+        })?;
+        let diffs = diff_instances(&expected.encode(), &proof.instance)?;
+        if !diffs.is_empty() {
+            return Err(VerificationError::TracesMismatch(diffs).into());
+        }
+        self.verify_agg_circuit_proof(proof)
+    }
+
+    /// Verify an agg proof with the vk of circuit `version`: the one of the
+    /// verifier for its own version, the one archived in the vk registry for the
+    /// others, so that the proofs made before a circuit upgrade stay verifiable.
+    /// The archived keys are loaded once per verifier.
+    pub fn verify_with_version(
+        &self,
+        proof: &AggCircuitProof,
+        version: &CircuitVersion,
+    ) -> Result<bool> {
+        let start = Instant::now();
+        let result = self.verify_archived(proof, version);
+        self.record_verify_agg(proof, version, start, &result);
+        result
+    }
+
+    fn verify_archived(&self, proof: &AggCircuitProof, version: &CircuitVersion) -> Result<bool> {
+        if *version == self.circuit_version {
+            return self.verify_agg(proof);
+        }
+        if !proof.circuit_version.is_unknown() && proof.circuit_version != *version {
+            log::warn!(
+                "agg proof of circuit version {} verified with the vk of version {}",
+                proof.circuit_version,
+                version
+            );
+        }
+        let keys = self.archived_keys(version)?;
+        match &keys.agg_params {
+            Some(agg_params) => verify_agg_proof(agg_params, &keys.vk, proof),
+            None => verify_agg_proof(&self.agg_params, &keys.vk, proof),
+        }
+    }
+
+    fn archived_keys(&self, version: &CircuitVersion) -> Result<Arc<ArchivedKeys>> {
+        if let Some(keys) = self.archived_keys.lock().unwrap().get(version) {
+            return Ok(keys.clone());
+        }
+        let unknown = || VerificationError::UnknownVersion(version.to_string());
+        let registry = self.vk_registry.as_ref().ok_or_else(unknown)?;
+        let entry = registry.entry(version)?.ok_or_else(unknown)?;
+        let raw_vk = registry.agg_vk(&entry)?;
+        // the constraint system of the vk is rebuilt from the config of its version
+        let vk = AggConfig::with_applied(entry.agg_config.as_ref(), || read_vk(&raw_vk))??;
+        let agg_params = match registry.agg_params(&entry)? {
+            Some(agg_params) => Some(agg_params),
+            None if entry.agg_degree == self.agg_params.k() => None,
+            None if entry.agg_degree < self.agg_params.k() => {
+                let mut agg_params = self.agg_params.clone();
+                agg_params.downsize(entry.agg_degree);
+                Some(agg_params)
+            }
+            None => {
+                return Err(VerificationError::Invalid {
+                    what: "archived vk",
+                    reason: format!(
+                        "agg degree {} of version {} above the one of the params {}, archive its params",
+                        entry.agg_degree,
+                        version,
+                        self.agg_params.k()
+                    ),
+                }
+                .into())
+            }
+        };
+        let keys = Arc::new(ArchivedKeys { vk, agg_params });
+        self.archived_keys
+            .lock()
+            .unwrap()
+            .insert(version.clone(), keys.clone());
+        Ok(keys)
+    }
+
+    fn record_verify_agg(
+        &self,
+        proof: &AggCircuitProof,
+        version: &CircuitVersion,
+        start: Instant,
+        result: &Result<bool>,
+    ) {
+        if let Some(log) = &self.audit_log {
+            let (outcome, error) = match result {
+                Ok(true) => (AuditOutcome::Success, None),
+                Ok(false) => (AuditOutcome::Failure, None),
+                Err(e) => (AuditOutcome::Error, Some(e.to_string())),
+            };
+            let record = AuditRecord::new(
+                AuditOperation::VerifyAgg,
+                "aggregation",
+                version,
+                &instance_hash(&proof.instance),
+            )
+            .with_proof(&proof.vk, &proof.proof, proof.total_proved_block_count)
+            .ended(start.elapsed(), outcome, error);
+            log.record(&record);
+        }
     }
 
-    pub fn verify_agg_circuit_proof(&self, proof: AggCircuitProof) -> anyhow::Result<bool> {
-        let mut transcript = TranscriptReadBuffer::<_, G1Affine, _>::init(proof.proof.as_slice());
+    fn verify_agg(&self, proof: &AggCircuitProof) -> Result<bool> {
+        if !self.can_verify(proof) {
+            log::warn!(
+                "agg proof of circuit version {} may not be verified by version {}",
+                proof.circuit_version,
+                self.circuit_version
+            );
+        }
+        let vk = self.agg_vk.as_ref().ok_or(VerificationError::MissingVk)?;
+        verify_agg_proof(&self.agg_params, vk, proof)
+    }
 
-        let vk = match self.agg_vk.clone() {
-            Some(p) => p,
-            None => panic!("aggregation verification key is not found"),
+    /// Check the instance of an agg proof commits to the claimed blocks, i.e. that
+    /// the proof is about these blocks. Only the first `total_proved_block_count`
+    /// blocks are covered by the proof. The proof itself is not verified.
+    pub fn check_instances_against_blocks<B: BlockHeaderLike>(
+        &self,
+        proof: &AggCircuitProof,
+        blocks: &[B],
+    ) -> Result<()> {
+        let instance = AggInstance::decode(&proof.instance)?;
+        if instance.chain_id != *CHAIN_ID {
+            return Err(VerificationError::InstanceMismatch {
+                field: "chain id".to_string(),
+                expected: CHAIN_ID.to_string(),
+                actual: instance.chain_id.to_string(),
+            }
+            .into());
+        }
+        if instance.pi_hashes.len() != 1 {
+            return Err(VerificationError::Invalid {
+                what: "aggregation instance",
+                reason: format!(
+                    "{} inner snarks, only a single one is supported",
+                    instance.pi_hashes.len()
+                ),
+            }
+            .into());
+        }
+        let num_blocks = proof.total_proved_block_count;
+        if num_blocks == 0 || num_blocks > blocks.len() {
+            return Err(VerificationError::InstanceMismatch {
+                field: "number of blocks".to_string(),
+                expected: num_blocks.to_string(),
+                actual: blocks.len().to_string(),
+            }
+            .into());
+        }
+        let blocks = &blocks[..num_blocks];
+        for (prev, next) in blocks.iter().zip(blocks.iter().skip(1)) {
+            if next.parent_state_root() != prev.state_root() {
+                return Err(VerificationError::InstanceMismatch {
+                    field: format!("parent state root of block {:?}", next.number()),
+                    expected: format!("{:?}", prev.state_root()),
+                    actual: format!("{:?}", next.parent_state_root()),
+                }
+                .into());
+            }
+        }
+
+        let chunk = ChunkInfo::from_blocks(*CHAIN_ID, blocks)?;
+        let expected = chunk.public_input_hash();
+        if instance.pi_hashes[0] != expected {
+            return Err(VerificationError::InstanceMismatch {
+                field: "public input hash".to_string(),
+                expected: format!("{expected:?}"),
+                actual: format!("{:?}", instance.pi_hashes[0]),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Recompute the instance of an agg proof from the traces it proves and diff it
+    /// against the instance of the proof, to catch proofs stored against the wrong
+    /// traces. Only the first `total_proved_block_count` traces, as a single chunk,
+    /// are covered by the proof. The accumulator isn't derived from the traces and
+    /// the proof itself is not verified.
+    pub fn check_proof_matches_traces(
+        &self,
+        proof: &AggCircuitProof,
+        block_traces: &[BlockTrace],
+    ) -> Result<()> {
+        let num_blocks = proof.total_proved_block_count;
+        if num_blocks == 0 || num_blocks > block_traces.len() {
+            return Err(VerificationError::InstanceMismatch {
+                field: "number of blocks".to_string(),
+                expected: num_blocks.to_string(),
+                actual: block_traces.len().to_string(),
+            }
+            .into());
+        }
+        let instance = AggInstance::decode(&proof.instance)?;
+        let chunk = ChunkInfo::from_blocks(*CHAIN_ID, &block_traces[..num_blocks])?;
+        let expected = AggInstance {
+            accumulator: instance.accumulator,
+            pi_hashes: vec![chunk.public_input_hash()],
+            chain_id: *CHAIN_ID,
         };
+        let diffs = diff_instances(&expected.encode(), &proof.instance)?;
+        if !diffs.is_empty() {
+            return Err(VerificationError::TracesMismatch(diffs).into());
+        }
+        Ok(())
+    }
 
-        // deserialize instances
-        let verify_circuit_instance: Vec<Vec<Vec<Fr>>> = {
-            let instance = proof.instance;
-            load_instances(&instance)
+    /// Check the TEE attestation of an agg proof commits to its instance and vk,
+    /// and to `block_traces` if given, then check the quote with `quote_verifier`.
+    pub fn check_attestation(
+        &self,
+        proof: &AggCircuitProof,
+        block_traces: Option<&[BlockTrace]>,
+        quote_verifier: &dyn QuoteVerifier,
+    ) -> Result<()> {
+        let attestation = proof
+            .attestation
+            .as_ref()
+            .ok_or_else(|| VerificationError::Attestation("proof is not attested".to_string()))?;
+        let mismatch = |field: &str, expected: String, actual: String| {
+            Err(VerificationError::InstanceMismatch {
+                field: format!("attested {field}"),
+                expected,
+                actual,
+            }
+            .into())
         };
-        let verify_circuit_instance1: Vec<Vec<&[Fr]>> = verify_circuit_instance
-            .iter()
-            .map(|x| x.iter().map(|y| &y[..]).collect())
-            .collect();
-        let verify_circuit_instance2: Vec<&[&[Fr]]> =
-            verify_circuit_instance1.iter().map(|x| &x[..]).collect();
-
-        Ok(VerificationStrategy::<_, VerifierSHPLONK<Bn256>>::finalize(
-            verify_proof::<_, VerifierSHPLONK<Bn256>, _, EvmTranscript<_, _, _, _>, _>(
-                &self.agg_params,
-                &vk,
-                AccumulatorStrategy::new(&self.params),
-                &verify_circuit_instance2,
-                &mut transcript,
-            )?,
-        ))
+        let instance = instance_hash(&proof.instance);
+        if attestation.instance_hash != instance {
+            return mismatch(
+                "instance hash",
+                format!("{instance:?}"),
+                format!("{:?}", attestation.instance_hash),
+            );
+        }
+        let vk = vk_digest(&proof.vk);
+        if attestation.vk_digest != vk {
+            return mismatch("vk digest", vk, attestation.vk_digest.clone());
+        }
+        if let Some(block_traces) = block_traces {
+            let traces = trace_hash(block_traces);
+            if attestation.trace_hash != traces {
+                return mismatch(
+                    "trace hash",
+                    format!("{traces:?}"),
+                    format!("{:?}", attestation.trace_hash),
+                );
+            }
+        }
+        if attestation.quoted_report_data() != Some(&attestation.report_data()[..]) {
+            return Err(VerificationError::Attestation(
+                "the quote doesn't commit to the attested hashes".to_string(),
+            )
+            .into());
+        }
+        quote_verifier.verify(attestation.kind, &attestation.quote)?;
+        Ok(())
     }
 
     pub fn verify_target_circuit_proof<C: TargetCircuit>(
         &mut self,
         proof: &TargetCircuitProof,
-    ) -> anyhow::Result<()> {
-        let verifier_params = self.params.verifier_params();
-        let vk = self.target_circuit_vks.entry(C::name()).or_insert_with(|| {
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = self.verify_target::<C>(proof);
+        if let Some(log) = &self.audit_log {
+            let (outcome, error) = match &result {
+                Ok(()) => (AuditOutcome::Success, None),
+                Err(ZkEvmError::Verification(VerificationError::Failed { .. })) => {
+                    (AuditOutcome::Failure, None)
+                }
+                Err(e) => (AuditOutcome::Error, Some(e.to_string())),
+            };
+            let record = AuditRecord::new(
+                AuditOperation::VerifyTarget,
+                &C::name(),
+                &proof.circuit_version,
+                &inner_instance_hash(std::slice::from_ref(proof)),
+            )
+            .with_proof(&proof.vk, &proof.snark.proof, proof.num_of_proved_blocks)
+            .ended(start.elapsed(), outcome, error);
+            log.record(&record);
+        }
+        result
+    }
+
+    fn verify_target<C: TargetCircuit>(&mut self, proof: &TargetCircuitProof) -> Result<()> {
+        let params = params_of_degree(&self.params, &mut self.degree_params, C::degree())?;
+        if !self.target_circuit_vks.contains_key(&C::name()) {
             let circuit = C::dummy_inner_circuit();
-            keygen_vk(&self.params, &circuit)
-                .unwrap_or_else(|_| panic!("failed to generate {} vk", C::name()))
-        });
+            let vk = keygen_vk(params, &circuit).map_err(|e| KeygenError::Generate {
+                circuit: C::name(),
+                key: "vk",
+                reason: format!("{e:?}"),
+            })?;
+            self.target_circuit_vks.insert(C::name(), vk);
+        }
+        let vk = &self.target_circuit_vks[&C::name()];
+        let verifier_params = params.verifier_params();
         if verify_snark_shplonk::<C::Inner>(verifier_params, proof.snark.clone(), vk) {
             Ok(())
         } else {
-            Err(anyhow!("snark verification failed".to_string()))
+            Err(VerificationError::Failed { circuit: C::name() }.into())
         }
     }
 
+    /// EVM bytecode of the verifier contract of agg proofs with `num_instance` instances.
+    pub fn agg_evm_verifier_bytecode(&self, num_instance: usize) -> Result<Vec<u8>> {
+        let vk = self.agg_vk.as_ref().ok_or(VerificationError::MissingVk)?;
+        Ok(gen_evm_verifier_shplonk::<ChainBoundAggregationCircuit>(
+            &self.agg_params,
+            vk,
+            vec![num_instance],
+            None,
+        ))
+    }
+
     /// Verifies the proof with EVM byte code.
     /// Panics if verification fails.
     pub fn evm_verify(bytecode: Vec<u8>, instances: Vec<Vec<Fr>>, proof: Vec<u8>) {
         evm_verify(bytecode, instances, proof)
     }
+
+    /// Deploys the verifier contract in revm and calls it with the calldata, e.g.
+    /// `AggCircuitProof::encode_calldata`. Returns whether the call succeeded.
+    pub fn evm_verify_calldata(bytecode: Vec<u8>, calldata: Vec<u8>) -> bool {
+        let mut evm = ExecutorBuilder::default()
+            .with_gas_limit(u64::MAX.into())
+            .build();
+        let caller = Address::from_low_u64_be(0xfe);
+        let verifier = match evm.deploy(caller, bytecode.into(), 0.into()).address {
+            Some(verifier) => verifier,
+            None => return false,
+        };
+        let result = evm.call_raw(caller, verifier, calldata.into(), 0.into());
+        log::debug!("evm verification, gas used: {}", result.gas_used);
+        !result.reverted
+    }
+}
+
+/// Verify an agg proof natively with the aggregation vk, checking the chain id it
+/// claims.
+pub(crate) fn verify_agg_proof(
+    agg_params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    proof: &AggCircuitProof,
+) -> Result<bool> {
+    verify_native(agg_params, vk, &proof.instance, &proof.proof, *CHAIN_ID)
+}
+
+/// Verify an agg proof in revm with the verifier contract of the aggregation vk.
+pub(crate) fn evm_verify_agg_proof(
+    agg_params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    proof: &AggCircuitProof,
+) -> Result<()> {
+    let instances = decode_column(&proof.instance)?;
+    let bytecode = gen_evm_verifier_shplonk::<ChainBoundAggregationCircuit>(
+        agg_params,
+        vk,
+        vec![instances.len()],
+        None,
+    );
+    // the calldata handed to the contract on chain
+    if !Verifier::evm_verify_calldata(bytecode, proof.encode_calldata()?) {
+        return Err(VerificationError::Failed {
+            circuit: "aggregation (evm)".to_string(),
+        }
+        .into());
+    }
+    Ok(())
 }
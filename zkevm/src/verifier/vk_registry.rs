@@ -0,0 +1,202 @@
+//! Archive of the agg vks of past circuit versions, so that the proofs made before
+//! a circuit upgrade stay verifiable, see `Verifier::verify_with_version`.
+//!
+//! Every version gets a dir of the registry:
+//! - `entry.json`, the `ArchivedVk` describing the vk;
+//! - `agg.vk`, the vk serialized as by the prover;
+//! - `params<agg_degree>`, the agg params, only if archived with the vk. Params
+//!   are a universal setup, so without them the ones of the verifier are
+//!   downsized to the degree of the version.
+//!
+//! An archived version is never overwritten by another vk.
+
+use crate::error::{Result, VerificationError};
+use crate::prover::AggConfig;
+use crate::utils::{load_params_any_format, read_env_var, vk_digest, write_params};
+use crate::version::CircuitVersion;
+use halo2_proofs::halo2curves::bn256::Bn256;
+use halo2_proofs::poly::commitment::Params;
+use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+use halo2_proofs::SerdeFormat;
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const ENTRY_FILE: &str = "entry.json";
+const VK_FILE: &str = "agg.vk";
+
+/// Dir of the vk registry of new verifiers, none if empty.
+pub static VK_REGISTRY: Lazy<String> = Lazy::new(|| read_env_var("VK_REGISTRY", String::new()));
+
+/// The agg vk of a circuit version.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchivedVk {
+    pub circuit_version: CircuitVersion,
+    pub degree: u32,
+    pub agg_degree: u32,
+    pub vk_digest: String,
+    /// Config of the agg circuit of the vk, the one at `VERIFY_CONFIG` if none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agg_config: Option<AggConfig>,
+}
+
+#[derive(Debug)]
+pub struct VkRegistry {
+    root: PathBuf,
+}
+
+impl VkRegistry {
+    pub fn open(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    /// The registry at `VK_REGISTRY`, if set.
+    pub fn from_env() -> Option<Self> {
+        let dir = VK_REGISTRY.as_str();
+        if dir.is_empty() {
+            return None;
+        }
+        Self::open(dir)
+            .map_err(|e| log::error!("failed to open the vk registry {}: {}", dir, e))
+            .ok()
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Archive the vk of `entry`, and the agg params if given. Archiving the same
+    /// vk again is a no-op, another vk of an archived version an error.
+    pub fn archive(
+        &self,
+        entry: &ArchivedVk,
+        agg_vk: &[u8],
+        agg_params: Option<&ParamsKZG<Bn256>>,
+    ) -> Result<()> {
+        let invalid = |reason: String| VerificationError::Invalid {
+            what: "archived vk",
+            reason,
+        };
+        let actual = vk_digest(agg_vk);
+        if actual != entry.vk_digest {
+            return Err(invalid(format!(
+                "digest {} of the vk differs from the one of the entry {}",
+                actual, entry.vk_digest
+            ))
+            .into());
+        }
+        if let Some(params) = agg_params {
+            if params.k() != entry.agg_degree {
+                return Err(invalid(format!(
+                    "params of degree {}, the vk is of degree {}",
+                    params.k(),
+                    entry.agg_degree
+                ))
+                .into());
+            }
+        }
+        let dir = self.version_dir(&entry.circuit_version)?;
+        if let Some(archived) = self.entry(&entry.circuit_version)? {
+            if archived.vk_digest != entry.vk_digest {
+                return Err(invalid(format!(
+                    "version {} is archived with the vk {}",
+                    entry.circuit_version, archived.vk_digest
+                ))
+                .into());
+            }
+        } else {
+            // written aside, so that an entry is either complete or missing
+            let tmp_dir = self.root.join(format!(".{}.tmp", entry.circuit_version.0));
+            let _ = fs::remove_dir_all(&tmp_dir);
+            fs::create_dir_all(&tmp_dir)?;
+            fs::write(tmp_dir.join(VK_FILE), agg_vk)?;
+            fs::write(tmp_dir.join(ENTRY_FILE), serde_json::to_vec_pretty(entry)?)?;
+            fs::rename(&tmp_dir, &dir)?;
+        }
+        if let Some(params) = agg_params {
+            let path = params_path(&dir, entry.agg_degree);
+            if !path.exists() {
+                write_params(params, &path.to_string_lossy(), SerdeFormat::Processed)?;
+            }
+        }
+        log::info!(
+            "vk {} of circuit version {} archived",
+            entry.vk_digest,
+            entry.circuit_version
+        );
+        Ok(())
+    }
+
+    /// The archived versions, sorted.
+    pub fn versions(&self) -> io::Result<Vec<CircuitVersion>> {
+        let mut versions = vec![];
+        for dir in fs::read_dir(&self.root)? {
+            let dir = dir?.path();
+            let name = dir.file_name().unwrap_or_default().to_string_lossy();
+            if !name.starts_with('.') && dir.join(ENTRY_FILE).exists() {
+                versions.push(CircuitVersion(name.to_string()));
+            }
+        }
+        versions.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(versions)
+    }
+
+    pub fn entry(&self, version: &CircuitVersion) -> Result<Option<ArchivedVk>> {
+        let path = self.version_dir(version)?.join(ENTRY_FILE);
+        match fs::read(&path) {
+            Ok(buf) => Ok(Some(serde_json::from_slice(&buf)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The serialized vk of the version, checked against the digest of its entry.
+    pub fn agg_vk(&self, entry: &ArchivedVk) -> Result<Vec<u8>> {
+        let vk = fs::read(self.version_dir(&entry.circuit_version)?.join(VK_FILE))?;
+        let actual = vk_digest(&vk);
+        if actual != entry.vk_digest {
+            return Err(VerificationError::InstanceMismatch {
+                field: format!("digest of the archived vk of {}", entry.circuit_version),
+                expected: entry.vk_digest.clone(),
+                actual,
+            }
+            .into());
+        }
+        Ok(vk)
+    }
+
+    /// The agg params archived with the vk, if any.
+    pub fn agg_params(&self, entry: &ArchivedVk) -> Result<Option<ParamsKZG<Bn256>>> {
+        let dir = self.version_dir(&entry.circuit_version)?;
+        if !params_path(&dir, entry.agg_degree).exists() {
+            return Ok(None);
+        }
+        let params = load_params_any_format(&dir.to_string_lossy(), entry.agg_degree as usize)?;
+        Ok(Some(params))
+    }
+
+    fn version_dir(&self, version: &CircuitVersion) -> Result<PathBuf> {
+        let name = &version.0;
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c));
+        if !valid {
+            return Err(VerificationError::Invalid {
+                what: "circuit version",
+                reason: format!("{version} can't name a dir of the vk registry"),
+            }
+            .into());
+        }
+        Ok(self.root.join(name))
+    }
+}
+
+fn params_path(dir: &Path, degree: u32) -> PathBuf {
+    dir.join(format!("params{degree}"))
+}
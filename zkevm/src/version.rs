@@ -0,0 +1,83 @@
+//! Circuit versions, used to route proofs to compatible verifiers when several
+//! circuit versions are deployed side by side during an upgrade.
+
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "prover")]
+use crate::circuit::{AGG_DEGREE, CIRCUIT_DEGREES, DEGREE};
+#[cfg(feature = "prover")]
+use crate::prover::{AggCircuitProof, TargetCircuitProof};
+#[cfg(feature = "prover")]
+use crate::utils::read_env_var;
+#[cfg(feature = "prover")]
+use crate::verifier::Verifier;
+#[cfg(feature = "prover")]
+use once_cell::sync::Lazy;
+
+/// The circuit version of this build.
+/// Defaults to the crate version and the circuit degrees, and can be overridden by the
+/// `CIRCUIT_VERSION` env, e.g. with the tag of the deployed verifier.
+#[cfg(feature = "prover")]
+pub static CIRCUIT_VERSION: Lazy<CircuitVersion> = Lazy::new(|| {
+    let mut default = format!(
+        "v{}-k{}-agg{}",
+        env!("CARGO_PKG_VERSION"),
+        *DEGREE,
+        *AGG_DEGREE
+    );
+    for (name, degree) in CIRCUIT_DEGREES.iter() {
+        default.push_str(&format!("-{name}{degree}"));
+    }
+    CircuitVersion(read_env_var("CIRCUIT_VERSION", default))
+});
+
+/// Identifier of a circuit version.
+/// Proofs and keys of different versions are not interchangeable.
+/// Params are a universal setup and do not depend on the circuit version.
+///
+/// An empty version stands for artifacts created before versions were introduced.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CircuitVersion(pub String);
+
+impl CircuitVersion {
+    #[cfg(feature = "prover")]
+    pub fn current() -> Self {
+        CIRCUIT_VERSION.clone()
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Artifacts of unknown version are not compatible with anything.
+    pub fn is_compatible(&self, other: &CircuitVersion) -> bool {
+        !self.is_unknown() && self == other
+    }
+}
+
+impl fmt::Display for CircuitVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_unknown() {
+            write!(f, "<unknown>")
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// Whether the verifier is able to verify the agg proof.
+#[cfg(feature = "prover")]
+pub fn can_verify(proof: &AggCircuitProof, verifier: &Verifier) -> bool {
+    proof
+        .circuit_version
+        .is_compatible(verifier.circuit_version())
+}
+
+/// Whether the verifier is able to verify the target circuit proof.
+#[cfg(feature = "prover")]
+pub fn can_verify_target(proof: &TargetCircuitProof, verifier: &Verifier) -> bool {
+    proof
+        .circuit_version
+        .is_compatible(verifier.circuit_version())
+}
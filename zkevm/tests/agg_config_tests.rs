@@ -0,0 +1,34 @@
+use zkevm::error::ParamsError;
+use zkevm::prover::AggConfig;
+
+#[test]
+fn test_agg_config_validation() {
+    let config = AggConfig::from_file("./configs/verify_circuit.config").unwrap();
+    config.validate(26).unwrap();
+    assert!(matches!(
+        config.validate(25),
+        Err(ParamsError::InvalidAggConfig(_))
+    ));
+
+    let invalid = [
+        AggConfig {
+            lookup_bits: 26,
+            ..config.clone()
+        },
+        AggConfig {
+            num_advice: vec![],
+            ..config.clone()
+        },
+        AggConfig {
+            num_lookup_advice: vec![1, 1],
+            ..config.clone()
+        },
+        AggConfig {
+            num_limbs: 2,
+            ..config.clone()
+        },
+    ];
+    for config in invalid {
+        assert!(config.validate(26).is_err(), "{config:?}");
+    }
+}
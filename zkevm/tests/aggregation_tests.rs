@@ -1,4 +1,3 @@
-use std::time::Instant;
 use halo2_proofs::poly::commitment::Params;
 use rand::SeedableRng;
 use rand_xorshift::XorShiftRng;
@@ -7,10 +6,12 @@ use snark_verifier_sdk::evm::gen_evm_proof_shplonk;
 use snark_verifier_sdk::gen_pk;
 use snark_verifier_sdk::halo2::aggregation::AggregationCircuit;
 use snark_verifier_sdk::CircuitExt;
+use std::time::Instant;
 use test_util::init;
 use test_util::load_block_traces_for_test;
-use zkevm::circuit::SuperCircuit;
-use zkevm::prover::Prover;
+use zkevm::circuit::{ChainBoundAggregationCircuit, SuperCircuit, CHAIN_ID};
+use zkevm::io::serialize_fr_tensor;
+use zkevm::prover::{AggCircuitProof, Prover};
 use zkevm::verifier::Verifier;
 
 mod mock_plonk;
@@ -72,10 +73,15 @@ fn test_aggregation_api() {
 
     // 3. build an aggregation circuit proof
     let now = Instant::now();
-    let agg_circuit =
-        AggregationCircuit::new(&params_outer, [super_circuit_proof.snark.clone()], &mut rng);
+    let agg_circuit = ChainBoundAggregationCircuit::new(
+        AggregationCircuit::new(&params_outer, [super_circuit_proof.snark.clone()], &mut rng),
+        *CHAIN_ID,
+    );
     let pk_outer = gen_pk(&params_outer, &agg_circuit, None);
-    log::info!("finish generating aggregation public parameters, elapsed: {:?}", now.elapsed());
+    log::info!(
+        "finish generating aggregation public parameters, elapsed: {:?}",
+        now.elapsed()
+    );
 
     let instances = agg_circuit.instances();
 
@@ -87,7 +93,11 @@ fn test_aggregation_api() {
         instances.clone(),
         &mut rng,
     );
-    log::info!("finish aggregating proof, elapsed: {:?}, proof size:{:?}", now.elapsed(), proof.len());
+    log::info!(
+        "finish aggregating proof, elapsed: {:?}, proof size:{:?}",
+        now.elapsed(),
+        proof.len()
+    );
     log::info!("finished aggregation generation");
 
     // 4. generate bytecode for evm to verify aggregation circuit proof
@@ -96,7 +106,22 @@ fn test_aggregation_api() {
 
     // 5. validate the proof with evm bytecode
     let now = Instant::now();
-    Verifier::evm_verify(deployment_code, instances, proof);
+    Verifier::evm_verify(deployment_code.clone(), instances.clone(), proof.clone());
     log::info!("finish verifying proof, elapsed: {:?}", now.elapsed());
+
+    // 6. the calldata of the proof verifies as is, and not once tampered with
+    let agg_proof = AggCircuitProof {
+        proof,
+        instance: serde_json::to_vec(&serialize_fr_tensor(&[instances])).unwrap(),
+        ..Default::default()
+    };
+    let mut calldata = agg_proof.encode_calldata().unwrap();
+    assert!(Verifier::evm_verify_calldata(
+        deployment_code.clone(),
+        calldata.clone()
+    ));
+    // a limb of the accumulator
+    calldata[31] ^= 1;
+    assert!(!Verifier::evm_verify_calldata(deployment_code, calldata));
     log::info!("end to end test completed");
 }
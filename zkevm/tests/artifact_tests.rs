@@ -0,0 +1,128 @@
+use std::time::Duration;
+use zkevm::artifact::content::{ContentStore, DIR_DIGEST_FILE};
+use zkevm::artifact::publish::{CommandPublisher, Publisher};
+use zkevm::artifact::{ArtifactKind, ArtifactStore, RetentionPolicy};
+
+#[test]
+fn test_artifact_gc() {
+    let root = std::env::temp_dir().join(format!("artifact_gc_{}", std::process::id()));
+    let store = ArtifactStore::new(&root).unwrap();
+
+    for name in ["a", "b", "c"] {
+        let bundle = store.path(ArtifactKind::Proof, name);
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(bundle.join("proof.data"), [0u8; 10]).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    std::fs::write(store.path(ArtifactKind::Debug, "dump.json"), [0u8; 10]).unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    // keep at most 2 bundles worth of proofs, debug dumps are dropped right away
+    let report = store
+        .gc(|kind| match kind {
+            ArtifactKind::Proof => RetentionPolicy {
+                max_age: None,
+                max_bytes: Some(25),
+            },
+            ArtifactKind::Debug => RetentionPolicy {
+                max_age: Some(Duration::ZERO),
+                max_bytes: None,
+            },
+            ArtifactKind::Snark => RetentionPolicy::default(),
+        })
+        .unwrap();
+
+    assert_eq!(report.reclaimed_bytes(), 20);
+    assert!(!store.path(ArtifactKind::Proof, "a").exists());
+    assert!(store.path(ArtifactKind::Proof, "b").exists());
+    assert!(store.path(ArtifactKind::Proof, "c").exists());
+    assert!(!store.path(ArtifactKind::Debug, "dump.json").exists());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_content_store() {
+    let root = std::env::temp_dir().join(format!("artifact_content_{}", std::process::id()));
+    let store = ArtifactStore::new(&root).unwrap();
+
+    // two bundles sharing a vk
+    let mut digests = vec![];
+    for (name, proof) in [("a", [1u8; 10]), ("b", [2u8; 10])] {
+        let bundle = store.path(ArtifactKind::Proof, name);
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(bundle.join("proof.data"), proof).unwrap();
+        std::fs::write(bundle.join("vk.data"), [7u8; 10]).unwrap();
+        digests.push(store.content().put_dir(&bundle).unwrap());
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let manifest = store.content().get_dir(&digests[0]).unwrap().unwrap();
+    let vk_digest = &manifest.files["vk.data"];
+    assert_eq!(store.content().refs(vk_digest), 2);
+    assert_eq!(
+        store.content().get(vk_digest).unwrap().unwrap(),
+        vec![7u8; 10]
+    );
+    assert!(store.content().get("../refs.json").unwrap().is_none());
+
+    // the gc of a bundle releases its content
+    store
+        .gc(|kind| RetentionPolicy {
+            max_bytes: (kind == ArtifactKind::Proof).then_some(100),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(!store.path(ArtifactKind::Proof, "a").exists());
+    assert_eq!(store.content().refs(vk_digest), 1);
+    assert!(store
+        .content()
+        .get(&manifest.files["proof.data"])
+        .unwrap()
+        .is_none());
+    assert!(store.content().get_dir(&digests[0]).unwrap().is_none());
+    let remaining = store.path(ArtifactKind::Proof, "b").join(DIR_DIGEST_FILE);
+    assert_eq!(std::fs::read_to_string(remaining).unwrap(), digests[1]);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_content_store_shared_root() {
+    let root = std::env::temp_dir().join(format!("artifact_shared_{}", std::process::id()));
+    // e.g. the service and a gc run on the same root
+    let (service, gc) = (
+        ContentStore::open(&root).unwrap(),
+        ContentStore::open(&root).unwrap(),
+    );
+    let digest = service.put(b"vk").unwrap();
+    gc.put(b"vk").unwrap();
+    let proof = service.put(b"proof").unwrap();
+    assert_eq!(service.refs(&digest), 2);
+
+    // neither overwrites the counts of the other
+    assert!(!gc.release(&digest).unwrap());
+    assert!(service.release(&digest).unwrap());
+    assert!(gc.get(&digest).unwrap().is_none());
+    assert_eq!(gc.refs(&proof), 1);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_command_publisher() {
+    let publisher = CommandPublisher {
+        cmd: "sh".to_string(),
+        args: vec![
+            "-c".to_string(),
+            "echo adding; echo cid-$(basename $0)".to_string(),
+        ],
+    };
+    let cid = publisher.publish("proofs/7".as_ref()).unwrap();
+    assert_eq!(cid, "cid-7");
+
+    let failing = CommandPublisher {
+        cmd: "false".to_string(),
+        args: vec![],
+    };
+    assert!(failing.publish("proofs/7".as_ref()).is_err());
+}
@@ -0,0 +1,65 @@
+use halo2_proofs::halo2curves::bn256::Bn256;
+use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use zkevm::attestation::{
+    instance_hash, report_data, trace_hash, Attestation, QuoteVerifier, TeeKind,
+};
+use zkevm::error::VerificationError;
+use zkevm::prover::AggCircuitProof;
+use zkevm::utils::{get_block_trace_from_file, vk_digest};
+use zkevm::verifier::Verifier;
+
+struct AcceptAll;
+
+impl QuoteVerifier for AcceptAll {
+    fn verify(&self, _: TeeKind, _: &[u8]) -> Result<(), VerificationError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_check_attestation() {
+    let traces = [get_block_trace_from_file("./tests/traces/bridge/01.json")];
+    let mut proof = AggCircuitProof {
+        instance: vec![1, 2, 3],
+        vk: vec![4, 5, 6],
+        ..Default::default()
+    };
+    let (trace_hash, instance_hash, vk_digest) = (
+        trace_hash(&traces),
+        instance_hash(&proof.instance),
+        vk_digest(&proof.vk),
+    );
+    // an sgx quote: header, report body with the report data at its end
+    let mut quote = vec![0u8; 48 + 384];
+    quote[48 + 320..].copy_from_slice(&report_data(&trace_hash, &instance_hash, &vk_digest));
+    proof.attestation = Some(Attestation {
+        kind: TeeKind::Sgx,
+        quote,
+        trace_hash,
+        instance_hash,
+        vk_digest,
+    });
+
+    let params = ParamsKZG::<Bn256>::setup(4, XorShiftRng::from_seed([0u8; 16]));
+    let verifier = Verifier::new(params.clone(), params, None).unwrap();
+    verifier
+        .check_attestation(&proof, Some(&traces), &AcceptAll)
+        .unwrap();
+
+    let other_traces = [get_block_trace_from_file("./tests/traces/bridge/02.json")];
+    assert!(verifier
+        .check_attestation(&proof, Some(&other_traces), &AcceptAll)
+        .is_err());
+
+    proof.instance.push(7);
+    assert!(verifier
+        .check_attestation(&proof, None, &AcceptAll)
+        .is_err());
+
+    proof.attestation = None;
+    assert!(verifier
+        .check_attestation(&proof, None, &AcceptAll)
+        .is_err());
+}
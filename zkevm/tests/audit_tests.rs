@@ -0,0 +1,73 @@
+use eth_types::H256;
+use std::time::Duration;
+use zkevm::audit::{AuditLog, AuditOperation, AuditOutcome, AuditRecord};
+use zkevm::error::{Result, ZkEvmError};
+use zkevm::version::CircuitVersion;
+
+#[test]
+fn test_audit_log() {
+    let path = std::env::temp_dir().join(format!("audit_{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let version = CircuitVersion("v0.4.0".to_string());
+
+    let log = AuditLog::open(&path).unwrap();
+    let proved: Result<(Vec<u8>, Vec<u8>)> = Ok((vec![1, 2], vec![3, 4, 5]));
+    let record = AuditRecord::new(
+        AuditOperation::ProveTarget,
+        "super",
+        &version,
+        &H256([1; 32]),
+    )
+    .proved(Duration::from_millis(1500), &proved, |(vk, proof)| {
+        (&vk[..], &proof[..], 3)
+    });
+    log.append(&record).unwrap();
+    let failed: Result<(Vec<u8>, Vec<u8>)> = Err(ZkEvmError::from(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "out of memory",
+    )));
+    log.record(
+        &AuditRecord::new(
+            AuditOperation::ProveAgg,
+            "aggregation",
+            &version,
+            &H256([2; 32]),
+        )
+        .proved(Duration::ZERO, &failed, |(vk, proof)| {
+            (&vk[..], &proof[..], 0)
+        }),
+    );
+    drop(log);
+
+    // reopened logs are appended to
+    AuditLog::open(&path).unwrap().record(
+        &AuditRecord::new(
+            AuditOperation::VerifyAgg,
+            "aggregation",
+            &version,
+            &H256([3; 32]),
+        )
+        .with_proof(&[1, 2], &[3, 4, 5], 3)
+        .ended(Duration::ZERO, AuditOutcome::Failure, None),
+    );
+
+    let records = AuditLog::read(&path).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].operation, AuditOperation::ProveTarget);
+    assert_eq!(records[0].outcome, AuditOutcome::Success);
+    assert_eq!(records[0].input_hash, hex::encode([1; 32]));
+    assert_eq!(records[0].proved_blocks, Some(3));
+    assert_eq!(records[0].duration_ms, 1500);
+    assert_eq!(records[0].proof_digest, records[2].proof_digest);
+    assert_eq!(records[1].outcome, AuditOutcome::Error);
+    assert!(records[1].error.as_ref().unwrap().contains("out of memory"));
+    assert_eq!(records[1].vk_digest, None);
+    assert_eq!(records[2].outcome, AuditOutcome::Failure);
+
+    let line = std::fs::read_to_string(&path).unwrap();
+    let first: serde_json::Value = serde_json::from_str(line.lines().next().unwrap()).unwrap();
+    assert_eq!(first["operation"], "prove_target");
+    assert_eq!(first["outcome"], "success");
+    assert!(first.get("error").is_none());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,112 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use zkevm::service::auth::{
+    Access, ApiKey, AuthConfig, AuthError, Authenticator, Identity, RateLimit, Scope,
+};
+
+fn jwt(secret: &str, claims: &str) -> String {
+    let encode = |b: &[u8]| base64::encode_config(b, base64::URL_SAFE_NO_PAD);
+    let signing_input = format!(
+        "{}.{}",
+        encode(br#"{"alg":"HS256","typ":"JWT"}"#),
+        encode(claims.as_bytes())
+    );
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(signing_input.as_bytes());
+    format!("{}.{}", signing_input, encode(&mac.finalize().into_bytes()))
+}
+
+#[test]
+fn test_auth() {
+    let auth = Authenticator::new(AuthConfig {
+        api_keys: vec![ApiKey {
+            name: "coordinator".to_string(),
+            sha256: hex::encode(Sha256::digest(b"secret-key")),
+            rate_limit: Some(RateLimit {
+                per_minute: 0,
+                burst: 2,
+            }),
+            scopes: vec![Scope::Submit, Scope::Worker],
+        }],
+        jwt_secret: Some("jwt-secret".to_string()),
+        rate_limit: RateLimit::default(),
+    });
+
+    let submit = Access::limited(Scope::Submit);
+    let coordinator = Identity {
+        name: "key:coordinator".to_string(),
+        scopes: vec![Scope::Submit, Scope::Worker],
+    };
+    assert_eq!(
+        auth.authorize(None, Some("secret-key"), submit),
+        Ok(coordinator.clone())
+    );
+    assert_eq!(
+        auth.authorize(Some("Bearer secret-key"), None, submit),
+        Ok(coordinator.clone())
+    );
+    // polling isn't charged
+    for _ in 0..3 {
+        assert_eq!(
+            auth.authorize(None, Some("secret-key"), Access::unlimited(Scope::Worker)),
+            Ok(coordinator.clone())
+        );
+    }
+    assert!(matches!(
+        auth.authorize(None, Some("secret-key"), Access::limited(Scope::Admin)),
+        Err(AuthError::Forbidden { .. })
+    ));
+    assert!(matches!(
+        auth.authorize(None, Some("secret-key"), submit),
+        Err(AuthError::RateLimited { .. })
+    ));
+    assert!(matches!(
+        auth.authorize(None, Some("wrong-key"), submit),
+        Err(AuthError::Unauthorized(_))
+    ));
+    assert!(matches!(
+        auth.authorize(None, None, submit),
+        Err(AuthError::Unauthorized(_))
+    ));
+
+    // the JWT of the name of the key has its own bucket
+    let token = jwt("jwt-secret", r#"{"sub":"coordinator"}"#);
+    assert_eq!(
+        auth.authorize(Some(&format!("Bearer {token}")), None, submit),
+        Ok(Identity {
+            name: "jwt:coordinator".to_string(),
+            scopes: vec![Scope::Submit],
+        })
+    );
+    assert!(matches!(
+        auth.authorize(
+            Some(&format!("Bearer {token}")),
+            None,
+            Access::unlimited(Scope::Worker)
+        ),
+        Err(AuthError::Forbidden { .. })
+    ));
+    let admin = jwt("jwt-secret", r#"{"sub":"ops","scope":"admin"}"#);
+    assert!(auth
+        .authorize(
+            Some(&format!("Bearer {admin}")),
+            None,
+            Access::limited(Scope::Admin)
+        )
+        .is_ok());
+    let unknown = jwt("jwt-secret", r#"{"sub":"ops","scope":"root"}"#);
+    assert!(matches!(
+        auth.authorize(Some(&format!("Bearer {unknown}")), None, submit),
+        Err(AuthError::Unauthorized(_))
+    ));
+    let forged = jwt("other-secret", r#"{"sub":"relayer"}"#);
+    assert!(matches!(
+        auth.authorize(Some(&format!("Bearer {forged}")), None, submit),
+        Err(AuthError::Unauthorized(_))
+    ));
+    let expired = jwt("jwt-secret", r#"{"sub":"relayer","exp":1}"#);
+    assert!(matches!(
+        auth.authorize(Some(&format!("Bearer {expired}")), None, submit),
+        Err(AuthError::Unauthorized(_))
+    ));
+}
@@ -0,0 +1,26 @@
+use zkevm::chunk::ChunkInfo;
+use zkevm::utils::get_block_trace_from_file;
+
+#[test]
+fn test_chunk_info() {
+    let traces: Vec<_> = (1..=3)
+        .map(|n| get_block_trace_from_file(format!("./tests/traces/bridge/{n:02}.json")))
+        .collect();
+    let info = ChunkInfo::from_block_traces(&traces).unwrap();
+    assert_eq!(info.chain_id, traces[0].chain_id.as_u64());
+    assert_eq!(info.prev_state_root, traces[0].storage_trace.root_before);
+    assert_eq!(info.post_state_root, traces[2].storage_trace.root_after);
+
+    // the data hash commits to every block of the chunk
+    let prefix = ChunkInfo::from_block_traces(&traces[..2]).unwrap();
+    assert_ne!(prefix.data_hash, info.data_hash);
+    assert_eq!(ChunkInfo::from_block_traces(&traces).unwrap(), info);
+
+    let other_chain = ChunkInfo {
+        chain_id: info.chain_id + 1,
+        ..info.clone()
+    };
+    assert_ne!(other_chain.public_input_hash(), info.public_input_hash());
+
+    assert!(ChunkInfo::from_block_traces(&[]).is_err());
+}
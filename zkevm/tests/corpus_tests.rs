@@ -0,0 +1,54 @@
+use zkevm::circuit::{
+    calculate_row_usage_of_batch, calculate_row_usage_of_trace, is_empty_block, SUB_CIRCUIT_NAMES,
+};
+use zkevm::corpus::{trace_files, write_csv, BlockUtilization};
+use zkevm::utils::get_block_trace_from_file;
+
+#[test]
+fn test_corpus_csv() {
+    let files = trace_files(std::path::Path::new("./tests/traces/bridge")).unwrap();
+    assert!(files.len() >= 10);
+    let block = BlockUtilization::of_file(&files[0]);
+    assert_eq!(block.error, None);
+    assert_eq!(block.rows.len(), SUB_CIRCUIT_NAMES.len());
+
+    let failed = BlockUtilization::of_file(std::path::Path::new("./no_such_trace.json"));
+    assert!(failed.error.is_some());
+
+    let mut csv = vec![];
+    write_csv(&mut csv, &[block, failed]).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    let num_columns = lines[0].split(',').count();
+    assert_eq!(num_columns, 4 + SUB_CIRCUIT_NAMES.len() + 5);
+    assert!(lines[0].starts_with("path,block,num_txs,calldata_len,evm,"));
+    assert_eq!(lines[1].split(',').count(), num_columns);
+}
+
+#[test]
+fn test_batch_row_usage_dedups_bytecode() {
+    let trace = get_block_trace_from_file("./tests/traces/erc20/multiple.json");
+    let block = calculate_row_usage_of_batch(std::slice::from_ref(&trace)).unwrap();
+    let batch = calculate_row_usage_of_batch(&[trace.clone(), trace]).unwrap();
+    let bytecode = SUB_CIRCUIT_NAMES
+        .iter()
+        .position(|c| *c == "bytecode")
+        .unwrap();
+    assert_eq!(batch[bytecode], block[bytecode]);
+    assert_eq!(batch[0], 2 * block[0]);
+}
+
+#[test]
+fn test_empty_block_row_usage() {
+    let empty = get_block_trace_from_file("./tests/traces/empty.json");
+    assert!(is_empty_block(&empty));
+    assert!(!is_empty_block(&get_block_trace_from_file(
+        "./tests/traces/native_transfer.json"
+    )));
+
+    let block = calculate_row_usage_of_trace(&empty).unwrap();
+    let batch = calculate_row_usage_of_batch(&[empty.clone(), empty]).unwrap();
+    let expected: Vec<usize> = block.iter().map(|rows| 2 * rows).collect();
+    assert_eq!(batch, expected);
+}
@@ -0,0 +1,24 @@
+use halo2_proofs::halo2curves::bn256::Bn256;
+use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use zkevm::error::{KeygenError, ParamsError, TraceError, ZkEvmError};
+use zkevm::utils::{check_vk_digest, load_params, read_block_trace_from_file, vk_digest};
+use zkevm::verifier::Verifier;
+
+#[test]
+fn test_error_categories() {
+    let err = load_params("./no_such_params", 20, halo2_proofs::SerdeFormat::RawBytes).unwrap_err();
+    assert!(matches!(err, ZkEvmError::Params(ParamsError::Io { .. })));
+
+    let err = read_block_trace_from_file("./no_such_trace.json").unwrap_err();
+    assert!(matches!(err, TraceError::Read { .. }));
+
+    let err = check_vk_digest(b"vk", &vk_digest(b"other vk"), true).unwrap_err();
+    assert!(matches!(err, KeygenError::VkDigestMismatch { .. }));
+    assert!(check_vk_digest(b"vk", &vk_digest(b"other vk"), false).is_ok());
+
+    let params = ParamsKZG::<Bn256>::setup(4, XorShiftRng::from_seed([0u8; 16]));
+    let err = Verifier::new(params.clone(), params, Some(b"not a vk".to_vec())).unwrap_err();
+    assert!(matches!(err, KeygenError::InvalidVk { .. }));
+}
@@ -0,0 +1,224 @@
+use eth_types::H256;
+use halo2_proofs::halo2curves::bn256::{Bn256, Fr};
+use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use zkevm::chunk::{BlockHeader, ChunkInfo};
+use zkevm::circuit::CHAIN_ID;
+use zkevm::error::{VerificationError, ZkEvmError};
+use zkevm::instance::{
+    diff_instances, hash_to_halves, AggInstance, InstanceField, InstanceLayout, ACCUMULATOR_LIMBS,
+};
+use zkevm::io::serialize_fr_tensor;
+use zkevm::prover::AggCircuitProof;
+use zkevm::utils::get_block_trace_from_file;
+use zkevm::verifier::Verifier;
+
+fn agg_proof_of(blocks: &[BlockHeader], chain_id: u64) -> AggCircuitProof {
+    let chunk = ChunkInfo::from_blocks(*CHAIN_ID, blocks).unwrap();
+    let mut column: Vec<Fr> = (0..ACCUMULATOR_LIMBS as u64).map(Fr::from).collect();
+    column.extend(hash_to_halves(chunk.public_input_hash()));
+    column.push(Fr::from(chain_id));
+    AggCircuitProof {
+        instance: serde_json::to_vec(&serialize_fr_tensor(&[vec![column]])).unwrap(),
+        total_proved_block_count: blocks.len(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_check_instances_against_blocks() {
+    let blocks: Vec<BlockHeader> = (1..=3)
+        .map(|n| get_block_trace_from_file(format!("./tests/traces/bridge/{n:02}.json")))
+        .map(|trace| BlockHeader::from(&trace))
+        .collect();
+    let proof = agg_proof_of(&blocks, *CHAIN_ID);
+
+    let decoded = AggInstance::decode(&proof.instance).unwrap();
+    let chunk = ChunkInfo::from_blocks(*CHAIN_ID, &blocks).unwrap();
+    assert_eq!(decoded.pi_hashes, vec![chunk.public_input_hash()]);
+    assert_eq!(decoded.chain_id, *CHAIN_ID);
+
+    let rng = XorShiftRng::from_seed([0u8; 16]);
+    let params = ParamsKZG::<Bn256>::setup(4, rng);
+    let verifier = Verifier::new(params.clone(), params, None).unwrap();
+    verifier
+        .check_instances_against_blocks(&proof, &blocks)
+        .unwrap();
+
+    let mut forged = blocks.clone();
+    forged[2].state_root = Default::default();
+    assert!(verifier
+        .check_instances_against_blocks(&proof, &forged)
+        .is_err());
+    // blocks which don't follow each other
+    let gap = [blocks[0].clone(), blocks[2].clone()];
+    assert!(verifier
+        .check_instances_against_blocks(&agg_proof_of(&gap, *CHAIN_ID), &gap)
+        .is_err());
+    // a proof for another chain
+    assert!(verifier
+        .check_instances_against_blocks(&agg_proof_of(&blocks, *CHAIN_ID + 1), &blocks)
+        .is_err());
+
+    assert!(AggInstance::decode(b"[[[[1, 2]]]]").is_err());
+}
+
+#[test]
+fn test_check_proof_matches_traces() {
+    let traces: Vec<_> = (1..=3)
+        .map(|n| get_block_trace_from_file(format!("./tests/traces/bridge/{n:02}.json")))
+        .collect();
+    let blocks: Vec<BlockHeader> = traces.iter().map(BlockHeader::from).collect();
+    let mut proof = agg_proof_of(&blocks[..2], *CHAIN_ID);
+
+    let rng = XorShiftRng::from_seed([0u8; 16]);
+    let params = ParamsKZG::<Bn256>::setup(4, rng);
+    let verifier = Verifier::new(params.clone(), params, None).unwrap();
+    // only the proved blocks are covered
+    verifier
+        .check_proof_matches_traces(&proof, &traces)
+        .unwrap();
+
+    let err = verifier
+        .check_proof_matches_traces(&proof, &traces[1..])
+        .unwrap_err();
+    match err {
+        ZkEvmError::Verification(VerificationError::TracesMismatch(diffs)) => {
+            let names: Vec<_> = diffs.iter().map(|diff| diff.name.as_str()).collect();
+            assert_eq!(names, ["snark[0].pi_hash"]);
+        }
+        e => panic!("unexpected error {e}"),
+    }
+    assert!(verifier
+        .check_proof_matches_traces(&proof, &traces[..1])
+        .is_err());
+    proof.total_proved_block_count = 3;
+    assert!(verifier
+        .check_proof_matches_traces(&proof, &traces)
+        .is_err());
+}
+
+#[test]
+fn test_instance_layout() {
+    let instance = AggInstance {
+        accumulator: (0..ACCUMULATOR_LIMBS as u64).map(Fr::from).collect(),
+        pi_hashes: vec![H256::repeat_byte(1), H256::repeat_byte(2)],
+        chain_id: *CHAIN_ID,
+    };
+    let layout = instance.layout();
+    assert_eq!(layout, InstanceLayout::new(2));
+    let column = instance.to_column();
+    assert_eq!(column.len(), layout.column_len());
+    assert_eq!(InstanceLayout::of_column_len(column.len()), Ok(layout));
+    assert!(InstanceLayout::of_column_len(column.len() + 1).is_err());
+
+    assert_eq!(column[layout.chain_id()], Fr::from(*CHAIN_ID));
+    assert_eq!(
+        column[layout.snark(1)],
+        hash_to_halves(H256::repeat_byte(2))
+    );
+    assert_eq!(
+        layout.field(layout.chain_id()),
+        Some(InstanceField::ChainId)
+    );
+    assert_eq!(
+        layout.field(ACCUMULATOR_LIMBS + 3).unwrap().to_string(),
+        "snark[1].pi_hash.lo"
+    );
+    assert_eq!(layout.field(7).unwrap().to_string(), "accumulator.rhs.x[1]");
+    assert_eq!(layout.field(layout.column_len()), None);
+
+    assert_eq!(AggInstance::from_column(&column).unwrap(), instance);
+    assert_eq!(AggInstance::decode(&instance.encode()).unwrap(), instance);
+}
+
+#[test]
+fn test_agg_instance_of_chunks() {
+    let blocks = vec![BlockHeader {
+        number: 1,
+        ..Default::default()
+    }];
+    let chunk = ChunkInfo::from_blocks(*CHAIN_ID, &blocks).unwrap();
+    let accumulator: Vec<Fr> = (0..ACCUMULATOR_LIMBS as u64).map(Fr::from).collect();
+    let instance = AggInstance::of_chunks(accumulator.clone(), &[chunk.clone()]).unwrap();
+    assert_eq!(instance.encode(), agg_proof_of(&blocks, *CHAIN_ID).instance);
+
+    let other_chain = ChunkInfo {
+        chain_id: *CHAIN_ID + 1,
+        ..chunk.clone()
+    };
+    assert!(AggInstance::of_chunks(accumulator.clone(), &[chunk.clone(), other_chain]).is_err());
+    assert!(AggInstance::of_chunks(accumulator, &[]).is_err());
+    assert!(AggInstance::of_chunks(vec![], &[chunk]).is_err());
+}
+
+#[test]
+fn test_verify_agg_proof_of_chunks() {
+    let blocks = vec![BlockHeader {
+        number: 1,
+        ..Default::default()
+    }];
+    let chunk = ChunkInfo::from_blocks(*CHAIN_ID, &blocks).unwrap();
+    let testnet_chunk = ChunkInfo {
+        chain_id: *CHAIN_ID + 1,
+        ..chunk.clone()
+    };
+    let rng = XorShiftRng::from_seed([0u8; 16]);
+    let params = ParamsKZG::<Bn256>::setup(4, rng);
+    let verifier = Verifier::new(params.clone(), params, None).unwrap();
+
+    // the instance matches, only the proof is left to verify, without a vk here
+    assert!(matches!(
+        verifier.verify_agg_proof_of_chunks(agg_proof_of(&blocks, *CHAIN_ID), &[chunk.clone()]),
+        Err(ZkEvmError::Verification(VerificationError::MissingVk))
+    ));
+    // a proof of the testnet chunk replayed with the chain id instance set to ours
+    let accumulator: Vec<Fr> = (0..ACCUMULATOR_LIMBS as u64).map(Fr::from).collect();
+    let mut replayed = AggInstance::of_chunks(accumulator, &[testnet_chunk.clone()]).unwrap();
+    replayed.chain_id = *CHAIN_ID;
+    let proof = AggCircuitProof {
+        instance: replayed.encode(),
+        total_proved_block_count: 1,
+        ..Default::default()
+    };
+    assert!(matches!(
+        verifier.verify_agg_proof_of_chunks(proof, &[chunk]),
+        Err(ZkEvmError::Verification(VerificationError::TracesMismatch(
+            _
+        )))
+    ));
+    assert!(matches!(
+        verifier.verify_agg_proof_of_chunks(agg_proof_of(&blocks, *CHAIN_ID), &[testnet_chunk]),
+        Err(ZkEvmError::Verification(
+            VerificationError::InstanceMismatch { .. }
+        ))
+    ));
+}
+
+#[test]
+fn test_diff_instances() {
+    // accumulator, a pi hash, chain id
+    let column: Vec<Fr> = (0..ACCUMULATOR_LIMBS as u64 + 3).map(Fr::from).collect();
+    let serialized = serde_json::to_vec(&serialize_fr_tensor(&[vec![column.clone()]])).unwrap();
+    // the same instance as calldata
+    let calldata: Vec<u8> = column
+        .iter()
+        .flat_map(|fr| fr.to_bytes().into_iter().rev())
+        .collect();
+    assert!(diff_instances(&serialized, &calldata).unwrap().is_empty());
+
+    let mut other = column.clone();
+    other[1] = Fr::from(100);
+    other.splice(
+        ACCUMULATOR_LIMBS + 2..ACCUMULATOR_LIMBS + 2,
+        [Fr::zero(), Fr::one()],
+    );
+    let other = serde_json::to_vec(&serialize_fr_tensor(&[vec![other]])).unwrap();
+    let diffs = diff_instances(&serialized, &other).unwrap();
+    let names: Vec<_> = diffs.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, ["accumulator.lhs.x[1]", "snark[1].pi_hash"]);
+    assert_eq!(diffs[1].left, None);
+
+    assert!(diff_instances(&serialized, b"not an instance").is_err());
+}
@@ -10,7 +10,7 @@ use zkevm::{
 };
 
 mod test_util;
-use test_util::{init, load_block_traces_for_test, PARAMS_DIR, SEED_PATH};
+use test_util::{init, load_block_traces_for_test, new_prover, new_verifier, PARAMS_DIR};
 
 use once_cell::sync::Lazy;
 use zkevm::utils::read_env_var;
@@ -54,6 +54,83 @@ fn estimate_circuit_rows() {
     log::info!("super circuit: {:?}", rows);
 }
 
+#[test]
+fn test_split_block_trace() {
+    use zkevm::circuit::split_block_trace;
+
+    init();
+
+    let (_, block_traces) = load_block_traces_for_test();
+    let block_trace = &block_traces[0];
+    let partitions = split_block_trace(block_trace).unwrap();
+    log::info!("split block into {} partitions", partitions.len());
+
+    assert_eq!(
+        partitions
+            .iter()
+            .map(|p| p.transactions.len())
+            .sum::<usize>(),
+        block_trace.transactions.len()
+    );
+    assert_eq!(
+        partitions[0].storage_trace.root_before,
+        block_trace.storage_trace.root_before
+    );
+    assert_eq!(
+        partitions.last().unwrap().storage_trace.root_after,
+        block_trace.storage_trace.root_after
+    );
+    for (prev, next) in partitions.iter().zip(partitions.iter().skip(1)) {
+        assert_eq!(
+            prev.storage_trace.root_after,
+            next.storage_trace.root_before
+        );
+    }
+}
+
+#[cfg(feature = "prove_verify")]
+#[test]
+fn test_mock_prove_block_partition() {
+    use zkevm::circuit::{calculate_row_usage_of_trace, split_block_trace_with_capacity};
+    use zkevm::utils::get_block_trace_from_file;
+
+    init();
+
+    // 8 erc20 transfers, the later ones reading the balances the earlier write
+    let block_trace = get_block_trace_from_file("./tests/traces/erc20/multiple.json");
+    let rows = calculate_row_usage_of_trace(&block_trace).unwrap();
+    // the whole block doesn't fit
+    let capacity = *rows.iter().max().unwrap();
+    let partitions = split_block_trace_with_capacity(&block_trace, capacity).unwrap();
+    assert!(partitions.len() >= 2);
+    assert_ne!(
+        partitions[1].storage_trace.root_before,
+        block_trace.storage_trace.root_before
+    );
+    Prover::mock_prove_target_circuit::<SuperCircuit>(&partitions[1]).unwrap();
+}
+
+#[test]
+fn test_check_witness_block() {
+    use zkevm::circuit::{block_traces_to_witness_block, check_witness_block};
+
+    init();
+
+    let (_, mut block_traces) = load_block_traces_for_test();
+    let witness_block = block_traces_to_witness_block(&block_traces).unwrap();
+    assert_eq!(check_witness_block(&block_traces, &witness_block), vec![]);
+
+    let block = block_traces
+        .iter_mut()
+        .find(|trace| !trace.execution_results.is_empty())
+        .expect("no tx in the traces");
+    block.execution_results[0].gas += 1;
+    let divergences = check_witness_block(&block_traces, &witness_block);
+    assert_eq!(divergences.len(), 1);
+    assert_eq!(divergences[0].tx, Some(0));
+    assert_eq!(divergences[0].field, "gas used");
+}
+
 #[cfg(feature = "prove_verify")]
 #[test]
 fn test_mock_prove() {
@@ -72,6 +149,16 @@ fn test_prove_verify() {
     test_target_circuit_prove_verify::<SuperCircuit>();
 }
 
+#[cfg(feature = "prove_verify")]
+#[test]
+fn test_prove_and_verify_agg() {
+    init();
+    let block_traces = load_block_traces_for_test().1;
+    let mut prover = new_prover();
+    let proof = prover.prove_and_verify_agg(&block_traces, true).unwrap();
+    assert!(proof.total_proved_block_count > 0);
+}
+
 #[cfg(feature = "prove_verify")]
 #[test]
 fn test_vk_same() {
@@ -117,8 +204,6 @@ fn test_vk_same() {
 fn test_target_circuit_prove_verify<C: TargetCircuit>() {
     use std::time::Instant;
 
-    use zkevm::verifier::Verifier;
-
     init();
     let mut rng = XorShiftRng::from_seed([0u8; 16]);
 
@@ -126,7 +211,7 @@ fn test_target_circuit_prove_verify<C: TargetCircuit>() {
 
     log::info!("start generating {} proof", C::name());
     let now = Instant::now();
-    let mut prover = Prover::from_fpath(PARAMS_DIR, SEED_PATH);
+    let mut prover = new_prover();
     let proof = prover
         .create_target_circuit_proof_batch::<C>(&block_traces, &mut rng)
         .unwrap();
@@ -143,7 +228,7 @@ fn test_target_circuit_prove_verify<C: TargetCircuit>() {
 
     log::info!("start verifying proof");
     let now = Instant::now();
-    let mut verifier = Verifier::from_fpath(PARAMS_DIR, None);
+    let mut verifier = new_verifier(None);
     assert!(verifier.verify_target_circuit_proof::<C>(&proof).is_ok());
     log::info!("finish verifying proof, elapsed: {:?}", now.elapsed());
 }
@@ -0,0 +1,56 @@
+use std::sync::mpsc;
+use std::time::Duration;
+use zkevm::prover::{BufferPool, MemoryGate};
+
+#[test]
+fn test_memory_gate() {
+    let gate = MemoryGate::new(10);
+    let first = gate.acquire(6);
+    assert_eq!(gate.in_use(), 6);
+
+    // a second job over what the first leaves waits for it
+    let (started, waiting) = mpsc::channel();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let _second = gate.acquire(6);
+            started.send(()).unwrap();
+        });
+        assert!(waiting.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(first);
+        waiting.recv_timeout(Duration::from_secs(10)).unwrap();
+    });
+    assert_eq!(gate.in_use(), 0);
+
+    // a job over the whole budget runs alone
+    let oversize = gate.acquire(20);
+    assert_eq!(gate.in_use(), 20);
+    drop(oversize);
+
+    let unlimited = MemoryGate::new(0);
+    let _permits: Vec<_> = (0..4).map(|_| unlimited.acquire(u64::MAX / 8)).collect();
+    assert_eq!(unlimited.in_use(), u64::MAX / 2);
+}
+
+#[test]
+fn test_buffer_pool() {
+    let pool = BufferPool::new(Some(100));
+    pool.give(Vec::with_capacity(60));
+    pool.give(Vec::with_capacity(30));
+    assert_eq!(pool.retained_bytes(), 90);
+
+    // over the cap, the smallest buffers are dropped
+    pool.give(Vec::with_capacity(20));
+    assert_eq!(pool.retained_bytes(), 90);
+    pool.give(Vec::with_capacity(50));
+    assert_eq!(pool.retained_bytes(), 60);
+    let mut buf = pool.take();
+    assert!(buf.is_empty());
+    assert_eq!(buf.capacity(), 60);
+    buf.extend([1; 10]);
+    pool.give(buf);
+    assert!(pool.take().is_empty());
+
+    let none_kept = BufferPool::new(Some(0));
+    none_kept.give(Vec::with_capacity(10));
+    assert_eq!(none_kept.retained_bytes(), 0);
+}
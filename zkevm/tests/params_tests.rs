@@ -0,0 +1,216 @@
+use halo2_proofs::poly::commitment::{Params, ParamsProver};
+use halo2_proofs::SerdeFormat;
+use zkevm::error::{ParamsError, ZkEvmError};
+use zkevm::utils::{
+    check_params_powers, compress_params, convert_params, create_seed_with_key,
+    detect_params_format, encrypt_seed, load_or_create_params, load_params, load_params_any_format,
+    load_seed_with_key, params_file_len, params_of_degree, read_params_degree,
+    verify_params_digest, SeedKey,
+};
+
+#[test]
+fn test_compressed_params() {
+    let dir = std::env::temp_dir().join(format!("compressed_params_{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    let degree = 4;
+
+    let params = load_or_create_params(dir, degree).unwrap();
+    assert!(matches!(
+        detect_params_format(dir, degree).unwrap(),
+        Some(SerdeFormat::RawBytesUnchecked)
+    ));
+
+    assert!(compress_params(dir, degree).unwrap());
+    assert!(!compress_params(dir, degree).unwrap());
+    let file_len = std::fs::metadata(format!("{dir}/params{degree}"))
+        .unwrap()
+        .len();
+    assert_eq!(file_len, params_file_len(degree, SerdeFormat::Processed));
+
+    // read back transparently
+    let compressed = load_or_create_params(dir, degree).unwrap();
+    let (mut expected, mut actual) = (vec![], vec![]);
+    params
+        .write_custom(&mut expected, SerdeFormat::RawBytes)
+        .unwrap();
+    compressed
+        .write_custom(&mut actual, SerdeFormat::RawBytes)
+        .unwrap();
+    assert_eq!(expected, actual);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_params_digest() {
+    let dir = std::env::temp_dir().join(format!("params_digest_{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    let degree = 4;
+
+    let params = load_or_create_params(dir, degree).unwrap();
+    let digest_path = format!("{dir}/params{degree}.sha256");
+    assert!(std::path::Path::new(&digest_path).exists());
+    let loaded = load_params(dir, degree, SerdeFormat::RawBytes).unwrap();
+    assert_eq!(loaded.k(), params.k());
+
+    std::fs::write(&digest_path, "00").unwrap();
+    assert!(load_params(dir, degree, SerdeFormat::RawBytesUnchecked).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_params_degree_mismatch() {
+    let dir = std::env::temp_dir().join(format!("params_degree_{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    load_or_create_params(dir, 4).unwrap();
+    let params4 = format!("{dir}/params4");
+
+    // smaller than requested
+    let err = load_params(&params4, 5, SerdeFormat::RawBytesUnchecked).unwrap_err();
+    assert!(matches!(
+        err,
+        ZkEvmError::Params(ParamsError::DegreeMismatch {
+            expected: 5,
+            actual: 4,
+            ..
+        })
+    ));
+    // larger ones are downsized
+    assert_eq!(load_params_any_format(&params4, 3).unwrap().k(), 3);
+
+    // neither raw nor compressed
+    let truncated = format!("{dir}/truncated");
+    let bytes = std::fs::read(&params4).unwrap();
+    std::fs::write(&truncated, &bytes[..bytes.len() - 1]).unwrap();
+    let err = load_params_any_format(&truncated, 4).unwrap_err();
+    assert!(matches!(
+        err,
+        ZkEvmError::Params(ParamsError::UnknownFormat { file_degree: 4, .. })
+    ));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_convert_and_truncate_params() {
+    let dir = std::env::temp_dir().join(format!("convert_params_{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    let params = load_or_create_params(dir, 5).unwrap();
+    let params5 = format!("{dir}/params5");
+    assert!(verify_params_digest(&params5).unwrap());
+    assert!(check_params_powers(&params, 8));
+
+    let compressed = format!("{dir}/compressed");
+    convert_params(&params5, &compressed, None, SerdeFormat::Processed).unwrap();
+    assert!(matches!(
+        detect_params_format(&compressed, 5).unwrap(),
+        Some(SerdeFormat::Processed)
+    ));
+    assert!(verify_params_digest(&compressed).unwrap());
+
+    let truncated = format!("{dir}/truncated");
+    convert_params(&compressed, &truncated, Some(3), SerdeFormat::RawBytes).unwrap();
+    assert_eq!(read_params_degree(&truncated).unwrap(), 3);
+    let params3 = load_params_any_format(&truncated, 3).unwrap();
+    assert!(check_params_powers(&params3, 4));
+
+    let mut corrupted = std::fs::read(&params5).unwrap();
+    corrupted[100] ^= 1;
+    std::fs::write(&params5, &corrupted).unwrap();
+    assert!(verify_params_digest(&params5).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_encrypted_seed() {
+    use age::secrecy::{ExposeSecret, SecretString};
+
+    let dir = std::env::temp_dir().join(format!("encrypted_seed_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("seed");
+    let path = path.to_str().unwrap();
+    let passphrase = || SeedKey::Passphrase(SecretString::new("correct horse".to_string()));
+
+    // plaintext seeds are read alike, then encrypted in place
+    let seed = create_seed_with_key(path, None).unwrap();
+    assert_eq!(load_seed_with_key(path, Some(&passphrase())).unwrap(), seed);
+    assert!(encrypt_seed(path, &passphrase()).unwrap());
+    assert!(!encrypt_seed(path, &passphrase()).unwrap());
+    assert!(!std::fs::read(path).unwrap().starts_with(&seed));
+    assert_eq!(load_seed_with_key(path, Some(&passphrase())).unwrap(), seed);
+
+    let wrong = SeedKey::Passphrase(SecretString::new("wrong".to_string()));
+    for key in [None, Some(&wrong)] {
+        assert!(matches!(
+            load_seed_with_key(path, key),
+            Err(ZkEvmError::Params(ParamsError::SeedKey { .. }))
+        ));
+    }
+
+    // to an age identity, as written by age-keygen
+    let identity = age::x25519::Identity::generate();
+    let key_path = dir.join("key.txt");
+    std::fs::write(
+        &key_path,
+        format!("# created: now\n{}\n", identity.to_string().expose_secret()),
+    )
+    .unwrap();
+    let key = SeedKey::from_identity_file(key_path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(path).unwrap();
+    let seed = create_seed_with_key(path, Some(&key)).unwrap();
+    assert_eq!(load_seed_with_key(path, Some(&key)).unwrap(), seed);
+    assert!(load_seed_with_key(path, Some(&passphrase())).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_params_of_degree() {
+    use std::collections::BTreeMap;
+    use zkevm::circuit::parse_circuit_degrees;
+
+    let dir = std::env::temp_dir().join(format!("params_of_degree_{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    let params = load_or_create_params(dir, 6).unwrap();
+    let mut cache = BTreeMap::new();
+
+    // one setup at every degree
+    let downsized = params_of_degree(&params, &mut cache, 4).unwrap();
+    assert_eq!(downsized.k(), 4);
+    assert_eq!(downsized.get_g()[0], params.get_g()[0]);
+    assert_eq!(
+        downsized.verifier_params().s_g2(),
+        params.verifier_params().s_g2()
+    );
+    assert_eq!(params_of_degree(&params, &mut cache, 6).unwrap().k(), 6);
+    assert_eq!(cache.keys().collect::<Vec<_>>(), [&4]);
+    assert!(matches!(
+        params_of_degree(&params, &mut cache, 7),
+        Err(ParamsError::DegreeTooHigh {
+            degree: 7,
+            params_degree: 6
+        })
+    ));
+
+    let degrees = parse_circuit_degrees(" state=4, poseidon = 5,").unwrap();
+    assert_eq!(degrees["state"], 4);
+    assert_eq!(degrees["poseidon"], 5);
+    assert!(parse_circuit_degrees("state").is_err());
+    assert!(parse_circuit_degrees("state=99").is_err());
+    assert!(parse_circuit_degrees("super=4").is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_load_prover_without_creating_params() {
+    let dir = std::env::temp_dir().join(format!("missing_params_{}", std::process::id()));
+    let seed = dir.with_extension("seed");
+    assert!(zkevm::prover::Prover::try_load_from_fpath(
+        dir.to_str().unwrap(),
+        seed.to_str().unwrap()
+    )
+    .is_err());
+    assert!(!dir.exists());
+}
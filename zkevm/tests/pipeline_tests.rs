@@ -0,0 +1,59 @@
+#[cfg(feature = "prove_verify")]
+mod test_util;
+
+#[cfg(feature = "prove_verify")]
+#[test]
+fn test_pipeline_resume() {
+    use test_util::{init, PARAMS_DIR, SEED_PATH};
+    use zkevm::prover::Prover;
+    use zkevm::utils::get_block_trace_from_file;
+
+    init();
+    let trace = |n: u64| get_block_trace_from_file(format!("./tests/traces/bridge/{n:02}.json"));
+    let batches = vec![vec![vec![trace(1), trace(2)], vec![trace(3), trace(4)]]];
+    let dir = std::env::temp_dir().join(format!("pipeline_{}", std::process::id()));
+
+    let mut prover = Prover::from_fpath(PARAMS_DIR, SEED_PATH);
+    let output = prover.prove_pipeline(&batches, true, &dir).unwrap();
+    let bundle = output.bundle.unwrap();
+    assert_eq!((bundle.block_range.first, bundle.block_range.last), (1, 4));
+    assert_eq!(output.batches[0].chunks.len(), 2);
+    for name in ["chunk_1_2", "chunk_3_4", "batch_1_4", "bundle_1_4"] {
+        assert!(dir.join(format!("{name}.json")).exists(), "{name}");
+    }
+
+    // a second run resumes from the files, without proving
+    let resumed = prover.prove_pipeline(&batches, true, &dir).unwrap();
+    assert_eq!(resumed.bundle.unwrap().proof.proof, bundle.proof.proof);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "prove_verify")]
+#[test]
+fn test_batch_padding() {
+    use test_util::{init, PARAMS_DIR, SEED_PATH};
+    use zkevm::error::{CapacityError, ZkEvmError};
+    use zkevm::prover::Prover;
+    use zkevm::utils::get_block_trace_from_file;
+
+    init();
+    let trace = |n: u64| get_block_trace_from_file(format!("./tests/traces/bridge/{n:02}.json"));
+    let mut prover = Prover::from_fpath(PARAMS_DIR, SEED_PATH);
+    prover.set_max_chunks_per_batch(Some(3));
+    let chunks = [
+        prover.prove_chunk(&[trace(1)]).unwrap(),
+        prover.prove_chunk(&[trace(2)]).unwrap(),
+    ];
+
+    let one = prover.prove_batch(&chunks[..1]).unwrap();
+    let two = prover.prove_batch(&chunks).unwrap();
+    assert_eq!((one.num_dummy_chunks, two.num_dummy_chunks), (2, 1));
+    assert_eq!(one.vk, two.vk);
+
+    prover.set_max_chunks_per_batch(Some(1));
+    let err = prover.prove_batch(&chunks).unwrap_err();
+    assert!(matches!(
+        err,
+        ZkEvmError::Capacity(CapacityError::TooManyChunks { num_chunks: 2, .. })
+    ));
+}
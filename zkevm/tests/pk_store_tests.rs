@@ -0,0 +1,83 @@
+use halo2_proofs::SerdeFormat;
+use mock_plonk::StandardPlonk;
+use snark_verifier_sdk::gen_pk;
+use std::io::Write;
+use zkevm::provenance::DigestWriter;
+use zkevm::prover::{MappedPk, PkStore};
+use zkevm::utils::load_or_create_params;
+use zkevm::version::CircuitVersion;
+
+#[allow(dead_code)]
+mod mock_plonk;
+
+#[test]
+fn test_pk_store() {
+    let root = std::env::temp_dir().join(format!("pk_store_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let params = load_or_create_params(root.join("params").to_str().unwrap(), 8).unwrap();
+    let pk = gen_pk(&params, &StandardPlonk::default(), None);
+    let store = PkStore::open(root.join("pks")).unwrap();
+    let version = CircuitVersion("v1".to_string());
+
+    assert!(store
+        .load::<StandardPlonk>("plonk", 8, &version, "")
+        .unwrap()
+        .is_none());
+    let (path, pk_digest) = store.save("plonk", &version, "", &pk).unwrap();
+    assert_eq!(path, store.path(&version, "plonk"));
+
+    // the commitments are read in place
+    let mapped = MappedPk::open(&path).unwrap();
+    assert_eq!(mapped.header().degree, 8);
+    assert_eq!(
+        &mapped.fixed_commitments().unwrap(),
+        pk.get_vk().fixed_commitments()
+    );
+    assert_eq!(
+        &mapped.permutation_commitments().unwrap(),
+        pk.get_vk().permutation().commitments()
+    );
+    drop(mapped);
+
+    let serialized = |pk: &halo2_proofs::plonk::ProvingKey<_>| {
+        let mut buf = vec![];
+        pk.write(&mut buf, SerdeFormat::RawBytes).unwrap();
+        buf
+    };
+    let (loaded, loaded_digest) = store
+        .load::<StandardPlonk>("plonk", 8, &version, "")
+        .unwrap()
+        .unwrap();
+    assert_eq!(serialized(&loaded), serialized(&pk));
+    // the digest recorded at save is the one of the pk section
+    assert_eq!(loaded_digest, pk_digest);
+    let mut section_digest = DigestWriter::default();
+    section_digest
+        .write_all(MappedPk::open(&path).unwrap().section("pk").unwrap())
+        .unwrap();
+    assert_eq!(section_digest.finish(), pk_digest);
+    // another config or version is generated again
+    assert!(store
+        .load::<StandardPlonk>("plonk", 8, &version, "other")
+        .unwrap()
+        .is_none());
+    // so is another degree
+    assert!(store
+        .load::<StandardPlonk>("plonk", 9, &version, "")
+        .unwrap()
+        .is_none());
+    let other = CircuitVersion("v2".to_string());
+    assert!(store
+        .load::<StandardPlonk>("plonk", 8, &other, "")
+        .unwrap()
+        .is_none());
+
+    // a truncated file is rejected
+    let len = std::fs::metadata(&path).unwrap().len();
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len / 2).unwrap();
+    assert!(store
+        .load::<StandardPlonk>("plonk", 8, &version, "")
+        .is_err());
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,71 @@
+use zkevm::proof::to_canonical_json;
+use zkevm::prover::{AggCircuitProof, CoordinatorProof};
+use zkevm::version::CircuitVersion;
+
+#[test]
+fn test_coordinator_json() {
+    let proof = AggCircuitProof {
+        proof: vec![0xde, 0xad],
+        instance: vec![0xbe, 0xef],
+        vk: vec![0x01],
+        total_proved_block_count: 3,
+        ..Default::default()
+    };
+    let json = proof.to_coordinator_json().unwrap();
+    assert_eq!(
+        json,
+        r#"{"proof":"0xdead","instance":"0xbeef","vk":"0x01","block_count":3}"#
+    );
+
+    let decoded: CoordinatorProof = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.proof, proof.proof);
+    assert_eq!(decoded.instance, proof.instance);
+    assert_eq!(decoded.vk, proof.vk);
+    assert_eq!(decoded.block_count, proof.total_proved_block_count);
+}
+
+#[test]
+fn test_circuit_version_compatibility() {
+    let v1 = CircuitVersion("v0.3.0-k20-agg26".to_string());
+    let v2 = CircuitVersion("v0.4.0-k20-agg26".to_string());
+    assert!(v1.is_compatible(&v1.clone()));
+    assert!(!v1.is_compatible(&v2));
+    assert!(!CircuitVersion::default().is_compatible(&CircuitVersion::default()));
+
+    // proofs created before versions were introduced deserialize with an unknown version
+    let proof: AggCircuitProof =
+        serde_json::from_str(r#"{"proof":"","instance":"","vk":"","total_proved_block_count":1}"#)
+            .unwrap();
+    assert!(proof.circuit_version.is_unknown());
+}
+
+#[test]
+fn test_canonical_json() {
+    let value = serde_json::json!({"b": 1, "a": [true, null, "\"\u{1}\u{e9}\n"], "B": -2});
+    assert_eq!(
+        to_canonical_json(&value).unwrap(),
+        "{\"B\":-2,\"a\":[true,null,\"\\\"\\u0001\u{e9}\\n\"],\"b\":1}"
+    );
+    assert!(to_canonical_json(&serde_json::json!({"a": 0.5})).is_err());
+
+    let proof = AggCircuitProof {
+        proof: vec![0xde, 0xad],
+        instance: vec![0xbe, 0xef],
+        vk: vec![0x01],
+        total_proved_block_count: 3,
+        circuit_version: CircuitVersion("v0.3.0-k20-agg26".to_string()),
+        ..Default::default()
+    };
+    let json = proof.to_canonical_json().unwrap();
+    assert_eq!(
+        json,
+        r#"{"circuit_version":"v0.3.0-k20-agg26","instance":"vu8=","proof":"3q0=","total_proved_block_count":3,"vk":"AQ=="}"#
+    );
+    // stable across a round trip, whatever the order of the fields read
+    let decoded: AggCircuitProof = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.to_canonical_json().unwrap(), json);
+    assert_eq!(
+        decoded.canonical_digest().unwrap(),
+        proof.canonical_digest().unwrap()
+    );
+}
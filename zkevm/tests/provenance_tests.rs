@@ -0,0 +1,47 @@
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use zkevm::proof::AggCircuitProof;
+use zkevm::provenance::{BuildInfo, DigestWriter, ProvenanceManifest, MANIFEST_FILE};
+
+#[test]
+fn test_provenance_manifest() {
+    let mut writer = DigestWriter::default();
+    writer.write_all(b"params").unwrap();
+    writer.write_all(b" and keys").unwrap();
+    assert_eq!(
+        writer.finish(),
+        hex::encode(Sha256::digest(b"params and keys"))
+    );
+
+    let build = BuildInfo::current();
+    assert_eq!(build.crate_version, env!("CARGO_PKG_VERSION"));
+    assert!(!build.git_commit.is_empty());
+
+    let dir = std::env::temp_dir().join(format!("provenance_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut proof = AggCircuitProof {
+        vk: vec![1, 2, 3],
+        ..Default::default()
+    };
+    // no manifest, no file
+    proof.write_to_dir(&mut dir.clone());
+    assert!(!dir.join(MANIFEST_FILE).exists());
+
+    let manifest = ProvenanceManifest {
+        params_digest: "11".repeat(32),
+        pk_digests: [("super".to_string(), "22".repeat(32))].into(),
+        build,
+        hostname: "prover-1".to_string(),
+        started_at: "2023-05-01T00:00:00+00:00".to_string(),
+        finished_at: "2023-05-01T00:10:00+00:00".to_string(),
+        ..Default::default()
+    };
+    proof.manifest = Some(manifest.clone());
+    proof.write_to_dir(&mut dir.clone());
+    assert_eq!(ProvenanceManifest::read_from_dir(&dir).unwrap(), manifest);
+    let full: AggCircuitProof =
+        serde_json::from_reader(std::fs::File::open(dir.join("full_proof.data")).unwrap()).unwrap();
+    assert_eq!(full.manifest, Some(manifest));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
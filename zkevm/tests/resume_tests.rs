@@ -0,0 +1,78 @@
+#[cfg(feature = "prove_verify")]
+mod test_util;
+
+#[cfg(feature = "prove_verify")]
+#[test]
+fn test_resume_agg_from_dir() {
+    use halo2_proofs::halo2curves::bn256::Fr;
+    use rand::{Rng, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+    use test_util::{init, PARAMS_DIR, SEED_PATH};
+    use zkevm::attestation::trace_hash;
+    use zkevm::circuit::SuperCircuit;
+    use zkevm::error::{ProvingError, VerificationError, ZkEvmError};
+    use zkevm::prover::{rng_from_seed, AggResumeState, Prover};
+    use zkevm::utils::get_block_trace_from_file;
+    use zkevm::verifier::Verifier;
+
+    init();
+    let traces = [get_block_trace_from_file("./tests/traces/bridge/01.json")];
+    let dir = std::env::temp_dir().join(format!("agg_resume_{}", std::process::id()));
+    let mut prover = Prover::from_fpath(PARAMS_DIR, SEED_PATH);
+    let mut rng = XorShiftRng::from_seed([0u8; 16]);
+
+    // the state a crashed aggregation leaves behind
+    let inner_seed = rng.gen();
+    let inner_proof = prover
+        .prove_inner_circuit::<SuperCircuit>(&traces, &mut rng_from_seed(inner_seed))
+        .unwrap();
+    let mut state = AggResumeState {
+        trace_hash: trace_hash(&traces),
+        inner_seed,
+        agg_seed: rng.gen(),
+        inner_proofs: vec![inner_proof],
+    };
+    std::fs::create_dir_all(&dir).unwrap();
+    let write_state = |state: &AggResumeState| {
+        serde_json::to_writer(
+            std::fs::File::create(dir.join("agg_resume.json")).unwrap(),
+            state,
+        )
+        .unwrap()
+    };
+
+    // an inner proof which doesn't verify isn't aggregated
+    state.inner_proofs[0].snark.instances[0][0] += Fr::one();
+    write_state(&state);
+    assert!(matches!(
+        prover.resume_agg_from_dir(&dir, &traces),
+        Err(ZkEvmError::Verification(VerificationError::Failed { .. }))
+    ));
+    state.inner_proofs[0].snark.instances[0][0] -= Fr::one();
+    write_state(&state);
+    assert!(Prover::load_agg_resume_state(&dir).unwrap().is_some());
+
+    // not resumed for other traces
+    let other = [get_block_trace_from_file("./tests/traces/empty.json")];
+    assert!(matches!(
+        prover.resume_agg_from_dir(&dir, &other),
+        Err(ZkEvmError::Proving(
+            ProvingError::ResumeTracesMismatch { .. }
+        ))
+    ));
+
+    let proof = prover.resume_agg_from_dir(&dir, &traces).unwrap();
+    assert!(Prover::load_agg_resume_state(&dir).unwrap().is_none());
+    assert!(prover.resume_agg_from_dir(&dir, &traces).is_err());
+
+    let verifier = Verifier::from_fpath(PARAMS_DIR, Some(proof.vk.clone())).unwrap();
+    assert!(verifier.verify_agg_circuit_proof(proof).unwrap());
+
+    // a finished aggregation doesn't leave its state behind
+    prover.set_resume_dir(Some(dir.clone()));
+    prover
+        .create_agg_circuit_proof_batch(&traces, &mut rng)
+        .unwrap();
+    assert!(Prover::load_agg_resume_state(&dir).unwrap().is_none());
+    std::fs::remove_dir_all(dir).unwrap();
+}
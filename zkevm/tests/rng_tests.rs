@@ -0,0 +1,18 @@
+use rand::RngCore;
+use zkevm::prover::{derive_rng, prover_rng, rng_from_seed};
+
+#[test]
+fn test_prover_rng() {
+    let mut a = rng_from_seed([7; 32]);
+    let mut b = rng_from_seed([7; 32]);
+    assert_eq!(a.next_u64(), b.next_u64());
+    assert_ne!(derive_rng(&mut a).next_u64(), derive_rng(&mut a).next_u64());
+
+    // the seed file only makes the proofs reproducible in deterministic tests
+    let (mut a, mut b) = (prover_rng(Some([1; 16])), prover_rng(Some([1; 16])));
+    if cfg!(feature = "deterministic-tests") {
+        assert_eq!(a.next_u64(), b.next_u64());
+    } else {
+        assert_ne!(a.next_u64(), b.next_u64());
+    }
+}
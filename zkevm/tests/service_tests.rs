@@ -0,0 +1,413 @@
+use std::time::Duration;
+use zkevm::error::ProvingError;
+use zkevm::prover::{AllocStats, Deadline, LeakCheck};
+use zkevm::service::history::JobHistory;
+use zkevm::service::{JobEvent, JobFilter, JobPhase, JobState, JobStatus};
+
+fn job(id: u64, state: JobState, block_number: u64) -> JobStatus {
+    JobStatus {
+        id,
+        state,
+        num_blocks: 1,
+        block_numbers: vec![block_number],
+        estimated_memory: 0,
+        prover_generation: None,
+        output_dir: None,
+        content_digest: None,
+        cid: None,
+        worker: None,
+        remote_worker: None,
+        trace_id: None,
+        submission: None,
+        error: None,
+        timeline: vec![JobEvent::now(JobPhase::Submitted)],
+    }
+}
+
+#[test]
+fn test_job_history() {
+    let path = std::env::temp_dir().join(format!("job_history_{}.jsonl", std::process::id()));
+    {
+        let (history, jobs) = JobHistory::open(&path).unwrap();
+        assert!(jobs.is_empty());
+        history.record(&job(0, JobState::Queued, 100));
+        let mut done = job(0, JobState::Done, 100);
+        done.output_dir = Some("proofs/0".to_string());
+        history.record(&done);
+        history.record(&job(1, JobState::Proving, 101));
+    }
+
+    let (_, jobs) = JobHistory::open(&path).unwrap();
+    assert_eq!(jobs.len(), 2);
+    assert_eq!(jobs[0].state, JobState::Done);
+    assert_eq!(jobs[0].output_dir.as_deref(), Some("proofs/0"));
+    // not finished before the restart
+    assert_eq!(jobs[1].state, JobState::Failed);
+    assert_eq!(jobs[1].timeline.last().unwrap().phase, JobPhase::Failed);
+
+    let filter = JobFilter {
+        block_number: Some(101),
+        ..Default::default()
+    };
+    assert!(!filter.matches(&jobs[0]));
+    assert!(filter.matches(&jobs[1]));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_deadline() {
+    assert!(Deadline::never().check("proving").is_ok());
+    assert!(Deadline::after(Duration::from_secs(3600))
+        .check("proving")
+        .is_ok());
+    assert!(matches!(
+        Deadline::after(Duration::ZERO).check("proving"),
+        Err(ProvingError::Timeout { .. })
+    ));
+
+    let deadline = Deadline::never();
+    deadline.clone().cancel();
+    assert!(matches!(
+        deadline.check("proving"),
+        Err(ProvingError::Cancelled { .. })
+    ));
+}
+
+#[test]
+fn test_job_spans() {
+    use zkevm::service::telemetry::{job_spans, TraceContext};
+
+    let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    let ctx = TraceContext::new(Some(traceparent));
+    assert_eq!(ctx.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert!(ctx.parent_span_id.is_some());
+    assert_eq!(TraceContext::new(Some("garbage")).parent_span_id, None);
+
+    let mut status = job(7, JobState::Done, 100);
+    status.worker = Some(1);
+    for phase in [
+        JobPhase::Started,
+        JobPhase::InnerCircuitProved,
+        JobPhase::AggCircuitProved,
+        JobPhase::Done,
+    ] {
+        status.timeline.push(JobEvent::now(phase));
+    }
+    let request = job_spans("prover", &status, &ctx);
+    let spans = request["resourceSpans"][0]["scopeSpans"][0]["spans"]
+        .as_array()
+        .unwrap();
+    // the job and its 4 phases
+    assert_eq!(spans.len(), 5);
+    assert_eq!(spans[0]["parentSpanId"], "00f067aa0ba902b7");
+    assert!(spans[1..]
+        .iter()
+        .all(|span| span["parentSpanId"] == spans[0]["spanId"]
+            && span["traceId"] == "4bf92f3577b34da6a3ce929d0e0e4736"));
+    assert_eq!(spans[0]["status"]["code"], 1);
+}
+
+#[test]
+fn test_leak_check() {
+    let stats = |allocated| AllocStats {
+        allocated,
+        active: allocated * 2,
+        ..Default::default()
+    };
+    assert_eq!(stats(100).fragmentation(), 0.5);
+
+    let mut leak_check = LeakCheck::new(50);
+    // the first job is the baseline
+    assert_eq!(leak_check.check(&stats(1000)), None);
+    assert_eq!(leak_check.check(&stats(1040)), None);
+    assert_eq!(leak_check.check(&stats(900)), None);
+    assert_eq!(leak_check.check(&stats(1100)), Some(100));
+}
+
+#[test]
+fn test_relayer() {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use zkevm::service::relayer::{
+        L1Client, L1Receipt, L1Transaction, Relayer, RelayerConfig, SubmissionState, H256,
+    };
+
+    #[derive(Debug, Default)]
+    struct MockL1 {
+        head: Mutex<u64>,
+        sent: Mutex<Vec<L1Transaction>>,
+        receipts: Mutex<HashMap<H256, L1Receipt>>,
+    }
+
+    impl L1Client for MockL1 {
+        fn pending_nonce(&self) -> anyhow::Result<u64> {
+            Ok(3)
+        }
+        fn gas_price(&self) -> anyhow::Result<u128> {
+            Ok(100)
+        }
+        fn block_number(&self) -> anyhow::Result<u64> {
+            Ok(*self.head.lock().unwrap())
+        }
+        fn send(&self, tx: &L1Transaction) -> anyhow::Result<H256> {
+            let mut sent = self.sent.lock().unwrap();
+            sent.push(tx.clone());
+            Ok(H256::from_low_u64_be(sent.len() as u64))
+        }
+        fn receipt(&self, tx_hash: H256) -> anyhow::Result<Option<L1Receipt>> {
+            Ok(self.receipts.lock().unwrap().get(&tx_hash).cloned())
+        }
+    }
+
+    let l1 = Arc::new(MockL1::default());
+    let config = RelayerConfig {
+        confirmations: 2,
+        bump_after: Duration::ZERO,
+        gas_bump_percent: 10,
+        max_gas_price: Some(115),
+        poll_interval: Duration::ZERO,
+        max_failed_sends: 5,
+    };
+    let mut relayer = Relayer::new(l1.clone(), config);
+    let first = relayer.submit(0, vec![1]);
+    let second = relayer.submit(1, vec![2]);
+    assert_eq!((first.nonce, second.nonce), (Some(3), Some(4)));
+    assert_eq!(first.gas_price, 100);
+
+    // not included, both are bumped, then capped
+    let changed = relayer.poll();
+    assert_eq!(changed.len(), 2);
+    assert_eq!(changed[0].1.gas_price, 110);
+    assert_eq!(changed[0].1.tx_hashes.len(), 2);
+    relayer.poll();
+    let sent = l1.sent.lock().unwrap().clone();
+    assert_eq!(sent.len(), 6);
+    assert_eq!(sent[4].gas_price, 115);
+    assert_eq!(sent[4].nonce, 3);
+    assert!(relayer.poll().is_empty());
+
+    // the first replacement of job 0 is included, then final
+    let receipt = L1Receipt {
+        tx_hash: H256::from_low_u64_be(3),
+        block_number: 10,
+        gas_used: 300_000,
+        success: true,
+    };
+    l1.receipts
+        .lock()
+        .unwrap()
+        .insert(receipt.tx_hash, receipt.clone());
+    *l1.head.lock().unwrap() = 10;
+    let changed = relayer.poll();
+    assert_eq!(changed[0].1.state, SubmissionState::Included);
+    *l1.head.lock().unwrap() = 11;
+    let changed = relayer.poll();
+    assert_eq!(changed[0].0, 0);
+    assert_eq!(changed[0].1.state, SubmissionState::Confirmed);
+    assert_eq!(changed[0].1.receipt, Some(receipt));
+    assert_eq!(relayer.in_flight(), 1);
+}
+
+#[test]
+fn test_coordinator() {
+    use halo2_proofs::halo2curves::bn256::Bn256;
+    use halo2_proofs::poly::kzg::commitment::ParamsKZG;
+    use rand::rngs::OsRng;
+    use zkevm::artifact::ArtifactStore;
+    use zkevm::prover::WitnessArtifact;
+    use zkevm::service::dispatch::DispatchError;
+    use zkevm::service::{ProverService, ServiceConfig};
+    use zkevm::utils::get_block_trace_from_file;
+    use zkevm::verifier::Verifier;
+
+    let root = std::env::temp_dir().join(format!("coordinator_{}", std::process::id()));
+    // only failures are sent, which are not verified
+    let params = ParamsKZG::<Bn256>::setup(4, OsRng);
+    let verifier = Verifier::new(params.clone(), params, None).unwrap();
+    let service = ProverService::coordinator(
+        ServiceConfig {
+            artifacts: ArtifactStore::new(&root).unwrap(),
+            workers: 1,
+            max_queued_jobs: 0,
+            max_memory: 0,
+            job_timeout: None,
+            publisher: None,
+            exporter: None,
+            relayer: None,
+        },
+        verifier,
+    );
+    assert!(service.is_coordinator());
+    assert_eq!(service.prover_generation(), None);
+    let trace = get_block_trace_from_file("./tests/traces/native_transfer.json");
+    let id = service.submit(vec![trace]).unwrap();
+
+    let started = std::time::Instant::now();
+    while service.witnesses_waiting() == 0 {
+        assert!(started.elapsed() < Duration::from_secs(600));
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let lease = service.lease_witness("worker-a").unwrap().unwrap();
+    assert_eq!(lease.id, id);
+    assert_eq!(
+        WitnessArtifact::decode(&lease.witness)
+            .unwrap()
+            .block_traces
+            .len(),
+        1
+    );
+    assert!(service.lease_witness("worker-b").unwrap().is_none());
+    assert_eq!(
+        service.complete_witness(id, "worker-b", Err(String::new())),
+        Err(DispatchError::NotLeaseHolder {
+            id,
+            worker: "worker-b".to_string()
+        })
+    );
+
+    service
+        .complete_witness(id, "worker-a", Err("out of memory".to_string()))
+        .unwrap();
+    let status = service.status(id).unwrap();
+    assert_eq!(status.state, JobState::Failed);
+    assert_eq!(status.remote_worker.as_deref(), Some("worker-a"));
+    let phases: Vec<_> = status.timeline.iter().map(|e| e.phase).collect();
+    assert_eq!(
+        phases,
+        [
+            JobPhase::Submitted,
+            JobPhase::Started,
+            JobPhase::WitnessGenerated,
+            JobPhase::Leased,
+            JobPhase::Failed
+        ]
+    );
+    assert_eq!(
+        service.complete_witness(id, "worker-b", Err(String::new())),
+        Err(DispatchError::NotDispatched { id })
+    );
+    service.shutdown();
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_l1_watcher() {
+    use std::sync::{Arc, Mutex};
+    use types::eth::BlockTrace;
+    use zkevm::chunk::{chunk_block_numbers, BLOCK_CONTEXT_LEN};
+    use zkevm::service::watcher::{
+        CommittedBatch, L1Watcher, RollupEvents, TraceSource, WatcherConfig, H256,
+    };
+    use zkevm::service::AdmissionError;
+    use zkevm::utils::get_block_trace_from_file;
+
+    #[derive(Debug, Default)]
+    struct MockRollup {
+        head: Mutex<u64>,
+        batches: Mutex<Vec<CommittedBatch>>,
+    }
+
+    impl RollupEvents for MockRollup {
+        fn block_number(&self) -> anyhow::Result<u64> {
+            Ok(*self.head.lock().unwrap())
+        }
+        fn committed_batches(&self, from: u64, to: u64) -> anyhow::Result<Vec<CommittedBatch>> {
+            let batches = self.batches.lock().unwrap();
+            Ok(batches
+                .iter()
+                .filter(|batch| (from..=to).contains(&batch.l1_block))
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[derive(Debug)]
+    struct MockL2(BlockTrace);
+
+    impl TraceSource for MockL2 {
+        fn block_trace(&self, number: u64) -> anyhow::Result<BlockTrace> {
+            let mut trace = self.0.clone();
+            trace.header.number = Some(number.into());
+            Ok(trace)
+        }
+    }
+
+    let chunk = |blocks: std::ops::RangeInclusive<u64>| {
+        let mut chunk = vec![blocks.clone().count() as u8];
+        for number in blocks {
+            let mut context = [0u8; BLOCK_CONTEXT_LEN];
+            context[..8].copy_from_slice(&number.to_be_bytes());
+            chunk.extend(context);
+        }
+        chunk
+    };
+    assert_eq!(chunk_block_numbers(&chunk(5..=7)).unwrap(), vec![5, 6, 7]);
+    assert!(chunk_block_numbers(&chunk(5..=7)[..100]).is_err());
+
+    let batch = |batch_index: u64, l1_block: u64, chunks| CommittedBatch {
+        batch_index,
+        batch_hash: H256::from_low_u64_be(batch_index),
+        l1_block,
+        chunks,
+    };
+    let rollup = Arc::new(MockRollup::default());
+    rollup.batches.lock().unwrap().extend([
+        batch(1, 10, vec![chunk(1..=2), chunk(3..=3)]),
+        batch(2, 12, vec![chunk(4..=5)]),
+    ]);
+    let l2 = Arc::new(MockL2(get_block_trace_from_file(
+        "./tests/traces/bridge/01.json",
+    )));
+    let config = WatcherConfig {
+        confirmations: 3,
+        poll_interval: Duration::ZERO,
+        max_range: 2,
+        start_block: 8,
+    };
+    let state_path = std::env::temp_dir().join(format!("l1_watcher_{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&state_path);
+    let new_watcher =
+        || L1Watcher::new(rollup.clone(), l2.clone(), config.clone(), &state_path).unwrap();
+
+    // a queue taking two jobs, of the numbers of their blocks
+    let submitted = Mutex::new(vec![]);
+    let mut submit = |traces: Vec<BlockTrace>| {
+        let mut submitted = submitted.lock().unwrap();
+        if submitted.len() == 2 {
+            return Err(AdmissionError::QueueFull { max_queued_jobs: 2 });
+        }
+        submitted.push(
+            traces
+                .iter()
+                .map(|t| t.header.number.unwrap().as_u64())
+                .collect::<Vec<_>>(),
+        );
+        Ok(submitted.len() as u64)
+    };
+
+    // the commitment in block 10 is 2 blocks deep only
+    *rollup.head.lock().unwrap() = 11;
+    let mut watcher = new_watcher();
+    assert!(watcher.poll(&mut submit).unwrap().is_empty());
+    assert_eq!(watcher.state().next_block, 10);
+
+    *rollup.head.lock().unwrap() = 14;
+    assert_eq!(watcher.poll(&mut submit).unwrap(), vec![1, 2]);
+    assert_eq!(watcher.state().next_block, 13);
+    assert_eq!(watcher.state().batches[&1].len(), 2);
+    // the queue is full, the chunk of batch 2 waits
+    assert_eq!(watcher.state().pending.len(), 1);
+    assert_eq!(watcher.state().pending[0].first_block, 4);
+
+    // resumed from the state file, batch 1 isn't enqueued again
+    drop(watcher);
+    submitted.lock().unwrap().remove(0);
+    let mut watcher = new_watcher();
+    assert_eq!(watcher.state().next_block, 13);
+    assert_eq!(watcher.poll(&mut submit).unwrap(), vec![2]);
+    assert!(watcher.state().pending.is_empty());
+    assert_eq!(watcher.state().batches[&2][0].job, Some(2));
+    assert_eq!(*submitted.lock().unwrap(), vec![vec![3], vec![4, 5]]);
+    std::fs::remove_file(&state_path).unwrap();
+}
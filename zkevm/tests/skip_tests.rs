@@ -0,0 +1,45 @@
+use zkevm::error::TraceError;
+use zkevm::skip::{SkipAction, SkipList, SkipTarget};
+use zkevm::utils::get_block_trace_from_file;
+
+fn skip_list(json: &str) -> SkipList {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn test_skip_list() {
+    let transfer = get_block_trace_from_file("./tests/traces/native_transfer.json");
+    let erc20 = get_block_trace_from_file("./tests/traces/erc20/multiple.json");
+
+    let skip = skip_list(r#"{"rules": [{"opcode": "STATICCALL", "action": "skip"}]}"#);
+    assert!(skip.scan(&transfer).is_empty());
+    let entries = skip.scan(&erc20);
+    assert!(!entries.is_empty());
+    assert!(entries.iter().all(|e| e.action == SkipAction::Skip
+        && matches!(e.target, SkipTarget::Opcode(_))
+        && e.location.step_index.is_some()));
+
+    // the batch is cut before the block
+    let mut batch = vec![transfer.clone(), erc20.clone(), transfer.clone()];
+    let report = skip.apply(&mut batch).unwrap();
+    assert_eq!(batch.len(), 1);
+    assert_eq!(report.skipped_blocks.len(), 2);
+    assert_eq!(report.entries.len(), entries.len());
+
+    let mut batch = vec![erc20.clone()];
+    assert!(matches!(
+        skip.apply(&mut batch),
+        Err(TraceError::Skipped { .. })
+    ));
+
+    let error = skip_list(r#"{"rules": [{"opcode": "DELEGATECALL", "action": "error"}]}"#);
+    let mut batch = vec![transfer.clone(), erc20];
+    assert!(matches!(
+        error.apply(&mut batch),
+        Err(TraceError::Unsupported { .. })
+    ));
+
+    let mut batch = vec![transfer];
+    assert!(SkipList::default().apply(&mut batch).unwrap().is_empty());
+    assert_eq!(batch.len(), 1);
+}
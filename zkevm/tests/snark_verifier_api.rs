@@ -159,7 +159,7 @@ fn test_partial_aggregation_api() {
     log::info!("finished inner circuit snark generation");
 
     // sanity check: the inner proof is correct
-    let mut verifier = Verifier::new(params_inner, params_outer.clone(), None);
+    let mut verifier = Verifier::new(params_inner, params_outer.clone(), None).unwrap();
     for i in 0..num_snarks {
         verifier
             .verify_target_circuit_proof::<MockPlonkCircuit>(&target_circuit_proof[i])
@@ -2,8 +2,10 @@ use git_version::git_version;
 use glob::glob;
 use std::sync::Once;
 use types::eth::BlockTrace;
+use zkevm::prover::Prover;
 use zkevm::utils::get_block_trace_from_file;
 use zkevm::utils::read_env_var;
+use zkevm::verifier::Verifier;
 
 pub const GIT_VERSION: &str = git_version!();
 pub const PARAMS_DIR: &str = "./test_params";
@@ -19,6 +21,28 @@ pub fn init() {
     });
 }
 
+/// A prover over the params in `PARAMS_DIR`, or with `test-mode` over the dev
+/// params generated in memory.
+#[cfg(not(feature = "test-mode"))]
+pub fn new_prover() -> Prover {
+    Prover::from_fpath(PARAMS_DIR, SEED_PATH)
+}
+
+#[cfg(feature = "test-mode")]
+pub fn new_prover() -> Prover {
+    Prover::dev()
+}
+
+#[cfg(not(feature = "test-mode"))]
+pub fn new_verifier(agg_vk: Option<Vec<u8>>) -> Verifier {
+    Verifier::from_fpath(PARAMS_DIR, agg_vk).unwrap()
+}
+
+#[cfg(feature = "test-mode")]
+pub fn new_verifier(agg_vk: Option<Vec<u8>>) -> Verifier {
+    Verifier::dev(agg_vk).unwrap()
+}
+
 pub fn load_batch_traces(batch_dir: &str) -> (Vec<String>, Vec<types::eth::BlockTrace>) {
     let file_names: Vec<String> = glob(&format!("{batch_dir}/**/*.json"))
         .unwrap()
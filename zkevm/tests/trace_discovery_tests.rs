@@ -0,0 +1,43 @@
+use std::fs;
+use zkevm::utils::discover_block_traces;
+
+#[test]
+fn test_discover_block_traces() {
+    let root = std::env::temp_dir().join(format!("trace_discovery_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    // names in the reverse order of the blocks, spread over nested dirs
+    for (n, name) in [
+        (1, "2023-05-02/b/z"),
+        (2, "2023-05-02/a"),
+        (3, "2023-05-01/y"),
+    ] {
+        let path = root.join(format!("{name}.json"));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::copy(format!("./tests/traces/bridge/{n:02}.json"), &path).unwrap();
+    }
+    fs::write(root.join("2023-05-01/notes.txt"), "not a trace").unwrap();
+
+    let numbers = |pattern: &str| -> Vec<u64> {
+        discover_block_traces(pattern)
+            .unwrap()
+            .iter()
+            .map(|(_, trace)| trace.header.number.unwrap().as_u64())
+            .collect()
+    };
+    let all = numbers(root.to_str().unwrap());
+    assert_eq!(all.len(), 3);
+    assert!(all.windows(2).all(|pair| pair[0] < pair[1]));
+    let pattern = format!("{}/2023-05-02/**/*.json", root.display());
+    assert_eq!(numbers(&pattern), all[..2]);
+    // dirs matched by a pattern are searched too
+    assert_eq!(numbers(&format!("{}/2023-05-0*", root.display())), all);
+
+    assert!(discover_block_traces(&format!("{}/2024-*", root.display())).is_err());
+    fs::copy(
+        root.join("2023-05-01/y.json"),
+        root.join("2023-05-02/y.json"),
+    )
+    .unwrap();
+    assert!(discover_block_traces(root.to_str().unwrap()).is_err());
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,75 @@
+use types::eth::BlockTrace;
+use types::lenient::{parse_trace, ParseError, ParseMode, TraceWarning};
+use types::migrate::{migrate_trace, trace_from_slice, TraceVersion};
+use zkevm::utils::get_block_trace_from_file;
+
+fn read_json(path: &str) -> serde_json::Value {
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn test_migrate_v1_trace() {
+    let legacy = read_json("./tests/traces/greeter.json");
+    let (trace, version) = migrate_trace(legacy).unwrap();
+    assert_eq!(version, TraceVersion::V1);
+    assert!(trace.get("mptwitness").is_none());
+    assert_eq!(TraceVersion::detect(&trace), TraceVersion::CURRENT);
+
+    let trace: BlockTrace = serde_json::from_value(trace).unwrap();
+    assert!(trace.coinbase.code_hash.is_some());
+    assert!(trace
+        .execution_results
+        .iter()
+        .all(|result| result.code_hash.is_some()));
+
+    // an upgraded trace is left as is
+    let (again, version) = migrate_trace(serde_json::to_value(&trace).unwrap()).unwrap();
+    assert_eq!(version, TraceVersion::CURRENT);
+    assert_eq!(again, serde_json::to_value(&trace).unwrap());
+}
+
+#[test]
+fn test_migrate_current_trace() {
+    // a JSON-RPC response
+    let current = read_json("./tests/traces/erc20/multiple.json")["result"].clone();
+    assert_eq!(TraceVersion::detect(&current), TraceVersion::CURRENT);
+    let response = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": current.clone() });
+    let (trace, version) = migrate_trace(response).unwrap();
+    assert_eq!(version, TraceVersion::CURRENT);
+    assert_eq!(trace, current);
+
+    let trace = trace_from_slice(&serde_json::to_vec(&trace).unwrap()).unwrap();
+    let from_file = get_block_trace_from_file("./tests/traces/erc20/multiple.json");
+    assert_eq!(trace.header.number, from_file.header.number);
+    assert!(trace.coinbase.code_hash.is_some());
+
+    assert!(migrate_trace(serde_json::json!([1, 2])).is_err());
+}
+
+#[test]
+fn test_lenient_trace_parsing() {
+    let mut trace = read_json("./tests/traces/greeter.json");
+    trace["futureField"] = serde_json::json!(1);
+    trace["executionResults"][0]["futureField"] = serde_json::json!("x");
+    let gas = trace["executionResults"][0]["structLogs"][0]["gas"]
+        .as_u64()
+        .unwrap();
+    trace["executionResults"][0]["structLogs"][0]["gas"] = format!("{gas:#x}").into();
+    let buf = serde_json::to_vec(&trace).unwrap();
+
+    let (parsed, warnings) = parse_trace(&buf, ParseMode::Lenient).unwrap();
+    assert_eq!(parsed.execution_results[0].exec_steps[0].gas, gas);
+    assert!(warnings.contains(&TraceWarning::UnknownField("futureField".to_string())));
+    assert!(warnings.contains(&TraceWarning::UnknownField(
+        "executionResults.0.futureField".to_string()
+    )));
+    assert!(warnings.contains(&TraceWarning::Coerced {
+        path: "executionResults.0.structLogs.0.gas".to_string(),
+        from: format!("{gas:#x}"),
+    }));
+
+    match parse_trace(&buf, ParseMode::Strict) {
+        Err(ParseError::Strict(strict)) => assert_eq!(strict, warnings),
+        other => panic!("expect strict mode to fail, got {other:?}"),
+    }
+}
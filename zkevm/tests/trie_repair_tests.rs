@@ -0,0 +1,91 @@
+use std::sync::Mutex;
+use zkevm::trie_repair::{
+    missing_trie_proofs, repair_storage_trace, AccountTrieProof, Address, TrieProofSource,
+    TrieRepair, Word,
+};
+use zkevm::utils::get_block_trace_from_file;
+
+#[derive(Debug, Default)]
+struct MockSource {
+    proof: AccountTrieProof,
+    requests: Mutex<Vec<(Address, Vec<Word>, u64)>>,
+}
+
+impl TrieProofSource for MockSource {
+    fn get_proof(
+        &self,
+        address: Address,
+        keys: &[Word],
+        block_number: u64,
+    ) -> anyhow::Result<AccountTrieProof> {
+        let mut requests = self.requests.lock().unwrap();
+        requests.push((address, keys.to_vec(), block_number));
+        Ok(self.proof.clone())
+    }
+}
+
+#[test]
+fn test_repair_storage_trace() {
+    let trace = get_block_trace_from_file("./tests/traces/bridge/05.json");
+    assert!(missing_trie_proofs(&trace).is_empty());
+    let source = MockSource::default();
+    let mut repaired = trace.clone();
+    assert_eq!(
+        repair_storage_trace(&mut repaired, &source).unwrap(),
+        TrieRepair::default()
+    );
+    assert!(source.requests.lock().unwrap().is_empty());
+
+    // drop the proofs of an account and of its slots
+    let address = *trace
+        .storage_trace
+        .storage_proofs
+        .keys()
+        .next()
+        .expect("trace without storage proofs");
+    let mut broken = trace.clone();
+    let account_proof = broken
+        .storage_trace
+        .proofs
+        .as_mut()
+        .unwrap()
+        .remove(&address)
+        .unwrap();
+    let slots = broken
+        .storage_trace
+        .storage_proofs
+        .remove(&address)
+        .unwrap();
+    let missing = missing_trie_proofs(&broken);
+    let missing_keys = missing[&address].clone();
+    assert!(missing_keys.iter().all(|key| slots.contains_key(key)));
+
+    let source = MockSource {
+        proof: AccountTrieProof {
+            account_proof: account_proof.iter().map(|node| node.to_vec()).collect(),
+            storage_proofs: slots
+                .iter()
+                .map(|(key, nodes)| (*key, nodes.iter().map(|node| node.to_vec()).collect()))
+                .collect(),
+        },
+        ..Default::default()
+    };
+    let repair = repair_storage_trace(&mut broken, &source).unwrap();
+    assert_eq!(
+        repair,
+        TrieRepair {
+            accounts: 1,
+            slots: missing_keys.len(),
+        }
+    );
+    assert!(missing_trie_proofs(&broken).is_empty());
+    let number = trace.header.number.unwrap().as_u64();
+    let requests = source.requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    let keys: Vec<_> = missing_keys.into_iter().collect();
+    assert_eq!(requests[0], (address, keys, number - 1));
+    assert_eq!(
+        broken.storage_trace.proofs.as_ref().unwrap()[&address],
+        account_proof
+    );
+}
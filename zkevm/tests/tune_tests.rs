@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+use zkevm::tune::{recommend, HostProfile, TuneReport};
+
+fn host(msm_secs: &[(usize, f64)], memory_gb: u64) -> HostProfile {
+    HostProfile {
+        cores: 64,
+        memory_bytes: memory_gb << 30,
+        memory_bandwidth_gbps: 100.0,
+        msm_secs: msm_secs.iter().copied().collect::<BTreeMap<_, _>>(),
+        disk_write_mbps: 2000.0,
+        disk_read_mbps: 3000.0,
+        gpus: vec![],
+    }
+}
+
+#[test]
+fn test_recommend() {
+    // scales up to 16 threads, no faster on the hyperthreads
+    let msm_secs = [
+        (1, 16.0),
+        (2, 8.0),
+        (4, 4.0),
+        (8, 2.0),
+        (16, 1.0),
+        (32, 0.8),
+        (64, 0.81),
+    ];
+    let job = 100 << 30;
+    let settings = recommend(&host(&msm_secs, 1024), job);
+    assert_eq!(settings.threads, 32);
+    assert_eq!(settings.jobs, 2);
+    assert_eq!(settings.max_memory, (1024u64 << 30) / 10 * 9);
+    assert!(settings.params_parallel_read);
+    assert_eq!(settings.gpu_batch_size, None);
+    // the jobs bounded by the memory, the witness memory by what they leave
+    let settings = recommend(&host(&msm_secs, 128), job);
+    assert_eq!(settings.jobs, 1);
+    assert_eq!(
+        settings.witness_retained_mb,
+        (((128u64 << 30) / 10 * 9 - job) >> 20) as i64
+    );
+    // a single job keeps all of it with room for two
+    assert_eq!(
+        recommend(&host(&[(1, 1.0), (64, 1.0 / 64.0)], 1024), job).witness_retained_mb,
+        -1
+    );
+}
+
+#[test]
+fn test_tune_report() {
+    let path = std::env::temp_dir().join(format!("tune_{}.json", std::process::id()));
+    let host = host(&[(1, 1.0)], 16);
+    let report = TuneReport::new(host.clone(), recommend(&host, 1 << 30));
+    report.write(&path).unwrap();
+    let read = TuneReport::read(&path).unwrap();
+    assert_eq!(read, report);
+    assert!(read.settings_on(&report.hostname).is_some());
+    assert!(read.settings_on("another-host").is_none());
+    std::fs::remove_file(&path).unwrap();
+}
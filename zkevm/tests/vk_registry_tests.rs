@@ -0,0 +1,62 @@
+use zkevm::error::{VerificationError, ZkEvmError};
+use zkevm::prover::AggConfig;
+use zkevm::utils::vk_digest;
+use zkevm::verifier::{ArchivedVk, VkRegistry};
+use zkevm::version::CircuitVersion;
+
+fn entry_of(version: &str, vk: &[u8]) -> ArchivedVk {
+    ArchivedVk {
+        circuit_version: CircuitVersion(version.to_string()),
+        degree: 20,
+        agg_degree: 22,
+        vk_digest: vk_digest(vk),
+        agg_config: Some(AggConfig::test_mode()),
+    }
+}
+
+#[test]
+fn test_vk_registry() {
+    let root = std::env::temp_dir().join(format!("vk_registry_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let registry = VkRegistry::open(&root).unwrap();
+    let (old_vk, new_vk) = (vec![1u8; 64], vec![2u8; 64]);
+
+    let old = entry_of("v0.3.0-k20-agg22", &old_vk);
+    registry.archive(&old, &old_vk, None).unwrap();
+    // again is a no-op
+    registry.archive(&old, &old_vk, None).unwrap();
+    let new = entry_of("v0.4.0-k20-agg22", &new_vk);
+    registry.archive(&new, &new_vk, None).unwrap();
+
+    assert_eq!(
+        registry.versions().unwrap(),
+        vec![old.circuit_version.clone(), new.circuit_version.clone()]
+    );
+    let archived = registry.entry(&old.circuit_version).unwrap().unwrap();
+    assert_eq!(archived, old);
+    assert_eq!(registry.agg_vk(&archived).unwrap(), old_vk);
+    assert!(registry.agg_params(&archived).unwrap().is_none());
+    let unknown = CircuitVersion("v0.2.0".to_string());
+    assert!(registry.entry(&unknown).unwrap().is_none());
+
+    // an archived version keeps its vk
+    let overwrite = entry_of("v0.3.0-k20-agg22", &new_vk);
+    assert!(registry.archive(&overwrite, &new_vk, None).is_err());
+    assert_eq!(registry.agg_vk(&archived).unwrap(), old_vk);
+    // the vk must be the one of the entry
+    assert!(registry
+        .archive(&entry_of("v0.5.0", &old_vk), &new_vk, None)
+        .is_err());
+    for version in ["", "../v0.3.0", ".hidden"] {
+        assert!(matches!(
+            registry.archive(&entry_of(version, &old_vk), &old_vk, None),
+            Err(ZkEvmError::Verification(VerificationError::Invalid { .. }))
+        ));
+    }
+
+    // a vk corrupted on disk isn't handed out
+    let vk_path = root.join("v0.4.0-k20-agg22").join("agg.vk");
+    std::fs::write(vk_path, [3u8; 64]).unwrap();
+    assert!(registry.agg_vk(&new).is_err());
+    std::fs::remove_dir_all(&root).unwrap();
+}
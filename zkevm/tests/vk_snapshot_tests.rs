@@ -0,0 +1,88 @@
+// The digests of the vks of every target circuit and of the aggregation
+// circuit, at the degrees pinned in `vk_snapshots.json`, one section for the
+// deployed degrees and one for the small circuits of `test-mode`. The deployed
+// verifier contract is bound to the agg vk, so an unintended circuit change
+// fails here. `UPDATE_VK_SNAPSHOTS=true` records the digests of an intended
+// change, or the first ones.
+#[cfg(feature = "prove_verify")]
+mod test_util;
+
+#[cfg(feature = "prove_verify")]
+#[test]
+fn test_vk_snapshots() {
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+    use test_util::{init, new_prover};
+    use zkevm::circuit::{SuperCircuit, TargetCircuit, AGG_DEGREE, DEGREE};
+    use zkevm::io::serialize_vk;
+    use zkevm::utils::{read_env_var, vk_digest};
+
+    const SNAPSHOT_PATH: &str = "./tests/vk_snapshots.json";
+    let section = if cfg!(feature = "test-mode") {
+        "test-mode"
+    } else {
+        "default"
+    };
+
+    init();
+    let mut snapshots: Value =
+        serde_json::from_reader(std::fs::File::open(SNAPSHOT_PATH).unwrap()).unwrap();
+    let pinned = &snapshots[section];
+    // pinned before the degrees are read, an agg config not pinned is the one of
+    // the mode
+    for (var, key) in [
+        ("DEGREE", "degree"),
+        ("AGG_DEGREE", "agg_degree"),
+        ("VERIFY_CONFIG", "agg_config"),
+        ("PARAM_SEED", "param_seed"),
+        ("CIRCUIT_DEGREES", "circuit_degrees"),
+    ] {
+        match &pinned[key] {
+            Value::Null => std::env::remove_var(var),
+            Value::String(value) => std::env::set_var(var, value),
+            value => std::env::set_var(var, value.to_string()),
+        }
+    }
+    assert_eq!(Some(*DEGREE as u64), pinned["degree"].as_u64());
+    assert_eq!(Some(*AGG_DEGREE as u64), pinned["agg_degree"].as_u64());
+
+    let mut prover = new_prover();
+    prover.warm_up(false).unwrap();
+    // the super circuit is the only target circuit, the sub-circuits are proved
+    // inside it; any pk of another one added to the warm-up is pinned as well
+    assert!(prover
+        .target_circuit_pks
+        .contains_key(&SuperCircuit::name()));
+    // the vks depend on the params too
+    let srs = vk_digest(format!("{:?}", prover.params.s_g2()).as_bytes());
+    let mut vks: BTreeMap<String, String> = prover
+        .target_circuit_pks
+        .iter()
+        .map(|(name, pk)| (name.clone(), vk_digest(&serialize_vk(pk.get_vk()))))
+        .collect();
+    vks.insert(
+        "aggregation".to_string(),
+        vk_digest(&serialize_vk(prover.agg_pk.as_ref().unwrap().get_vk())),
+    );
+
+    if read_env_var("UPDATE_VK_SNAPSHOTS", false) {
+        snapshots[section]["srs"] = srs.into();
+        snapshots[section]["vks"] = serde_json::to_value(&vks).unwrap();
+        let json = serde_json::to_string_pretty(&snapshots).unwrap();
+        std::fs::write(SNAPSHOT_PATH, json + "\n").unwrap();
+        log::info!("recorded {} vk snapshots {:?}", section, vks);
+        return;
+    }
+    let pinned = &snapshots[section];
+    assert_eq!(
+        pinned["srs"].as_str(),
+        Some(srs.as_str()),
+        "no {section} vk snapshots of these params, record them with UPDATE_VK_SNAPSHOTS=true"
+    );
+    let recorded: BTreeMap<String, String> = serde_json::from_value(pinned["vks"].clone()).unwrap();
+    assert_eq!(
+        recorded, vks,
+        "vks changed, the deployed verifier would reject the new proofs; \
+         UPDATE_VK_SNAPSHOTS=true records them if intended"
+    );
+}
@@ -0,0 +1,69 @@
+#[cfg(feature = "prove_verify")]
+mod test_util;
+
+use zkevm::circuit::SuperCircuit;
+use zkevm::prover::{WitnessArtifact, WITNESS_FORMAT_VERSION, WITNESS_MAGIC};
+use zkevm::utils::get_block_trace_from_file;
+
+#[test]
+fn test_witness_artifact() {
+    let traces: Vec<_> = (1..=2)
+        .map(|n| get_block_trace_from_file(format!("./tests/traces/bridge/{n:02}.json")))
+        .collect();
+    let artifact = WitnessArtifact::generate::<SuperCircuit>(&traces).unwrap();
+    assert_eq!(artifact.total_num_of_blocks, 2);
+    assert_eq!(artifact.block_traces.len(), 2);
+
+    let path = std::env::temp_dir().join(format!("witness_{}.zkwt", std::process::id()));
+    let digest = artifact.write(&path).unwrap();
+    let read = WitnessArtifact::read(&path).unwrap();
+    assert_eq!(read.instance, artifact.instance);
+    assert_eq!(read.circuit, artifact.circuit);
+    std::fs::remove_file(path).unwrap();
+
+    let buf = artifact.encode().unwrap();
+    assert_eq!(&buf[..4], WITNESS_MAGIC);
+    assert_eq!(hex::encode(&buf[buf.len() - 32..]), digest);
+    assert_eq!(
+        WitnessArtifact::decode(&buf).unwrap().encode().unwrap(),
+        buf
+    );
+
+    let mut corrupted = buf.clone();
+    corrupted[buf.len() / 2] ^= 1;
+    assert!(WitnessArtifact::decode(&corrupted).is_err());
+    let mut newer = buf.clone();
+    newer[4..8].copy_from_slice(&(WITNESS_FORMAT_VERSION + 1).to_le_bytes());
+    assert!(WitnessArtifact::decode(&newer).is_err());
+    assert!(WitnessArtifact::decode(&buf[..buf.len() - 1]).is_err());
+}
+
+#[cfg(feature = "prove_verify")]
+#[test]
+fn test_prove_from_witness() {
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use test_util::{init, PARAMS_DIR, SEED_PATH};
+    use zkevm::prover::Prover;
+
+    init();
+    let trace = get_block_trace_from_file("./tests/traces/native_transfer.json");
+    let mut prover = Prover::from_fpath(PARAMS_DIR, SEED_PATH);
+    let artifact = prover
+        .generate_witness::<SuperCircuit>(std::slice::from_ref(&trace))
+        .unwrap();
+    let mut rng = XorShiftRng::from_seed([0u8; 16]);
+    let proof = prover
+        .prove_from_witness::<SuperCircuit>(&artifact, &mut rng)
+        .unwrap();
+    assert_eq!(proof.num_of_proved_blocks, 1);
+
+    let mut other = artifact.clone();
+    other.circuit = "other".to_string();
+    assert!(prover
+        .prove_from_witness::<SuperCircuit>(&other, &mut rng)
+        .is_err());
+    prover
+        .create_agg_circuit_proof_impl(&[proof], &mut rng)
+        .unwrap();
+}